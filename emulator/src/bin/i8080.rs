@@ -0,0 +1,766 @@
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process;
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use emulator::core::devices::console::Console;
+use emulator::core::devices::disk::DRIVES;
+use emulator::core::emulator::{Cpu, EResult, Emulator, Target};
+use emulator::core::hex::HexImage;
+use emulator::core::machines::altair::Altair;
+use emulator::core::machines::cpm::{Cpm, CpmExit};
+use emulator::core::machines::cpmsim::CpmSim;
+use emulator::core::ram::LinearRam;
+use emulator::kreator::assembler::Assembler;
+use emulator::kreator::dialect::Dialect;
+use emulator::kreator::include::FileSystem;
+use emulator::terminator::gdb::GdbStub;
+use emulator::terminator::disassembler::Disassembler;
+use emulator::terminator::monitor::Monitor;
+use emulator::terminator::profile::{annotate, folded, report};
+use emulator::terminator::symbols::SymbolTable;
+
+const USAGE: &str = "\
+Usage: i8080 [OPTIONS] [PROGRAM]
+
+Assembles or loads PROGRAM and runs it with the console on stdin/stdout.
+
+Options:
+  -m, --machine NAME   plain, altair, cpmsim or cpm (default plain)
+      --memory SIZE    memory size, e.g. 16K or 4000H (default 64K)
+      --cpu NAME       8080 or 8085 (default 8080)
+      --dialect NAME   assembler dialect: 8080, z80 or 8085
+      --format NAME    bin, hex, com or asm (default from the file extension)
+      --load ADDRESS   load address of binaries (default 0, 100H for .COM)
+      --start ADDRESS  entry point (default the load address)
+      --limit COUNT    stop after COUNT instructions
+      --console PORT   console base port of the plain machine (default 0)
+      --disk IMAGE     disk image for the next cpmsim drive, boots without PROGRAM
+      --monitor        debug the program in the monitor instead of running it
+      --gdb PORT       wait for gdb on localhost PORT and let it debug the program
+      --profile FILE   write the cycles per function, symbol and address and
+                       a listing of the program with hit counts to FILE
+      --folded FILE    write the call stacks in the folded flame graph format
+  -h, --help           print this help
+
+Exit status: 0 after HLT or a CP/M warm boot, 1 on errors,
+2 on invalid arguments, 3 when the instruction limit was reached
+";
+
+const EXIT_HALTED: i32 = 0;
+const EXIT_ERROR: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_LIMIT: i32 = 3;
+
+const BATCH: u64 = 10000;
+const REPORT_LINES: usize = 20;
+
+/*
+ * Memory image as (address, bytes) chunks
+ */
+type Chunks = Vec<(u16, Vec<u8>)>;
+const MEMORY_SIZE: usize = 0x10000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Profile {
+    Plain,
+    Altair,
+    CpmSim,
+    Cpm,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Binary,
+    Hex,
+    Com,
+    Assembly,
+}
+
+impl Format {
+    fn from_name(name: &str) -> Option<Format> {
+        match name.to_lowercase().as_str() {
+            "bin" | "rom" => Some(Format::Binary),
+            "hex" | "ihx" => Some(Format::Hex),
+            "com" => Some(Format::Com),
+            "asm" | "s" | "mac" => Some(Format::Assembly),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    program: Option<String>,
+    profile: Profile,
+    memory: usize,
+    cpu: Cpu,
+    dialect: Dialect,
+    format: Option<Format>,
+    load: Option<u16>,
+    start: Option<u16>,
+    limit: Option<u64>,
+    console: u8,
+    disks: Vec<String>,
+    monitor: bool,
+    gdb: Option<u16>,
+    profile_report: Option<String>,
+    folded: Option<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            program: None,
+            profile: Profile::Plain,
+            memory: MEMORY_SIZE,
+            cpu: Cpu::I8080,
+            dialect: Dialect::default(),
+            format: None,
+            load: None,
+            start: None,
+            limit: None,
+            console: 0,
+            disks: Vec::new(),
+            monitor: false,
+            gdb: None,
+            profile_report: None,
+            folded: None,
+        }
+    }
+}
+
+/*
+ * Why the program stopped
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Exit {
+    Halted,
+    Terminated,
+    Limit,
+}
+
+/*
+ * Decimal, 0x1234 or 1234H, with K for memory sizes
+ */
+fn parse_number(text: &str) -> Result<u64, String> {
+    let upper = text.trim().to_uppercase();
+    let (digits, scale) = match upper.strip_suffix('K') {
+        Some(digits) => (digits, 1024),
+        None => (upper.as_str(), 1),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0X") {
+        u64::from_str_radix(hex, 16)
+    } else if let Some(hex) = digits.strip_suffix('H') {
+        u64::from_str_radix(hex, 16)
+    } else {
+        digits.parse()
+    };
+    value
+        .ok()
+        .and_then(|value| value.checked_mul(scale))
+        .ok_or_else(|| format!("Invalid number {}", text))
+}
+
+fn parse_address(text: &str) -> Result<u16, String> {
+    match parse_number(text)? {
+        value if value <= 0xffff => Ok(value as u16),
+        _ => Err(format!("Address {} is out of range", text)),
+    }
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with('-') {
+            if options.program.replace(arg.clone()).is_some() {
+                return Err(String::from("Only one program can be run"));
+            }
+            continue;
+        }
+        if arg == "-h" || arg == "--help" {
+            return Err(String::new());
+        }
+        if arg == "--monitor" {
+            options.monitor = true;
+            continue;
+        }
+        let value = args.next().ok_or_else(|| format!("Missing value for {}", arg))?;
+        match arg.as_str() {
+            "-m" | "--machine" => {
+                options.profile = match value.to_lowercase().as_str() {
+                    "plain" => Profile::Plain,
+                    "altair" => Profile::Altair,
+                    "cpmsim" => Profile::CpmSim,
+                    "cpm" => Profile::Cpm,
+                    _ => return Err(format!("Unknown machine {}", value)),
+                }
+            }
+            "--memory" => {
+                let size = parse_number(value)? as usize;
+                if !size.is_power_of_two() || size > MEMORY_SIZE {
+                    return Err(String::from("Memory size must be a power of two up to 64K"));
+                }
+                options.memory = size;
+            }
+            "--cpu" => options.cpu = Cpu::from_name(value)?,
+            "--dialect" => options.dialect = Dialect::from_name(value)?,
+            "--format" => {
+                options.format = Some(Format::from_name(value).ok_or_else(|| format!("Unknown format {}", value))?)
+            }
+            "--load" => options.load = Some(parse_address(value)?),
+            "--start" => options.start = Some(parse_address(value)?),
+            "--limit" => options.limit = Some(parse_number(value)?),
+            "--console" => {
+                options.console = match parse_address(value)? {
+                    port if port < 0xff => port as u8,
+                    _ => return Err(String::from("Console port must be below 0FFH")),
+                }
+            }
+            "--disk" if options.disks.len() == DRIVES => return Err(format!("At most {} disks", DRIVES)),
+            "--disk" => options.disks.push(value.clone()),
+            "--gdb" => match parse_number(value)? {
+                port if port > 0 && port <= 0xffff => options.gdb = Some(port as u16),
+                _ => return Err(String::from("Invalid TCP port")),
+            },
+            "--profile" => options.profile_report = Some(value.clone()),
+            "--folded" => options.folded = Some(value.clone()),
+            _ => return Err(format!("Unknown option {}", arg)),
+        }
+    }
+    if options.profile == Profile::Cpm && options.memory != MEMORY_SIZE {
+        return Err(String::from("The cpm machine needs 64K of memory"));
+    }
+    if !options.disks.is_empty() && options.profile != Profile::CpmSim {
+        return Err(String::from("Disks can only be inserted into the cpmsim machine"));
+    }
+    if options.monitor && options.gdb.is_some() {
+        return Err(String::from("Use either the monitor or gdb"));
+    }
+    if options.program.is_none() && options.disks.is_empty() {
+        return Err(String::from("No program given"));
+    }
+    Ok(options)
+}
+
+/*
+ * Memory image of a program, its entry point and the
+ * symbols of assembled programs for the monitor
+ */
+struct Program {
+    chunks: Chunks,
+    start: u16,
+    symbols: SymbolTable,
+}
+
+fn load_program(path: &str, options: &Options) -> Result<Program, String> {
+    let extension = Path::new(path).extension().and_then(|extension| extension.to_str()).unwrap_or("");
+    let format = options.format.or_else(|| Format::from_name(extension)).unwrap_or(Format::Binary);
+    let default_load = if format == Format::Com || options.profile == Profile::Cpm { 0x100 } else { 0 };
+    let load = options.load.unwrap_or(default_load);
+    let error = |error: io::Error| format!("{}: {}", path, error);
+
+    let mut symbols = SymbolTable::new();
+    let (chunks, start) = match format {
+        Format::Binary | Format::Com => (vec![(load, fs::read(path).map_err(error)?)], None),
+        Format::Hex => {
+            let image = HexImage::parse(&fs::read_to_string(path).map_err(error)?)?;
+            (image.chunks, image.start)
+        }
+        Format::Assembly => {
+            let source = fs::read_to_string(path).map_err(error)?;
+            let directory = Path::new(path).parent().unwrap_or_else(|| Path::new("."));
+            let assembler = Assembler::new(&source)
+                .with_resolver(FileSystem::new(directory))
+                .with_dialect(options.dialect);
+            let bytes = assembler.assemble()?;
+            symbols = assembler.get_symbols()?;
+            (place(&bytes, &assembler.get_origins(), load), None)
+        }
+    };
+    let start = options
+        .start
+        .or(start)
+        .or_else(|| chunks.first().map(|(address, _)| *address))
+        .unwrap_or(load);
+    Ok(Program { chunks, start, symbols })
+}
+
+/*
+ * Split assembled code at its ORG directives, code before
+ * the first ORG goes to the load address
+ */
+fn place(bytes: &[u8], origins: &[(u16, u16)], load: u16) -> Chunks {
+    let mut starts = vec![(0, load)];
+    starts.extend(origins.iter().copied());
+    let mut chunks = Vec::new();
+    for (index, (offset, address)) in starts.iter().enumerate() {
+        let end = starts.get(index + 1).map_or(bytes.len(), |(next, _)| *next as usize);
+        if end > *offset as usize {
+            chunks.push((*address, bytes[*offset as usize..end].to_vec()));
+        }
+    }
+    chunks
+}
+
+enum Machine {
+    Plain(Emulator, Rc<RefCell<Console>>),
+    Altair(Altair),
+    CpmSim(CpmSim),
+    Cpm(Cpm),
+}
+
+/*
+ * Result of running a batch of instructions
+ */
+enum Status {
+    Running,
+    Halted,
+    Terminated,
+    WaitingForInput,
+}
+
+impl Machine {
+    fn new(options: &Options) -> io::Result<Machine> {
+        let mut machine = match options.profile {
+            Profile::Plain => {
+                let mut emulator = Emulator::with_ram(Box::new(LinearRam::new(options.memory)));
+                let console = Rc::new(RefCell::new(Console::new(options.console)));
+                for port in console.borrow().ports() {
+                    emulator.register_input_device(console.clone(), port as usize).unwrap();
+                    emulator.register_output_device(console.clone(), port as usize).unwrap();
+                }
+                Machine::Plain(emulator, console)
+            }
+            Profile::Altair => Machine::Altair(Altair::with_memory(options.memory)),
+            Profile::CpmSim => {
+                let mut sim = CpmSim::with_memory(options.memory);
+                for (drive, path) in options.disks.iter().enumerate() {
+                    sim.insert_disk(drive, Path::new(path), false)?;
+                }
+                Machine::CpmSim(sim)
+            }
+            Profile::Cpm => Machine::Cpm(Cpm::new(Path::new("."))),
+        };
+        machine.emulator_mut().set_cpu(options.cpu);
+        Ok(machine)
+    }
+
+    fn load(&mut self, chunks: Chunks, start: u16) {
+        if let Machine::Cpm(cpm) = self {
+            // Sets up the stack so that RET terminates the program
+            cpm.load(Vec::new());
+        }
+        let emulator = self.emulator_mut();
+        for (address, bytes) in chunks {
+            emulator.load_ram(bytes, address);
+        }
+        emulator.set_pc(start);
+    }
+
+    fn boot(&mut self) -> EResult<()> {
+        match self {
+            Machine::CpmSim(sim) => sim.boot(),
+            _ => Err("Only the cpmsim machine can boot from disk"),
+        }
+    }
+
+    fn run_batch(&mut self, max_instructions: u64) -> EResult<Status> {
+        let max_instructions = max_instructions as usize;
+        match self {
+            Machine::Cpm(cpm) => Ok(match cpm.run(max_instructions)? {
+                CpmExit::Terminated => Status::Terminated,
+                CpmExit::Halted => Status::Halted,
+                CpmExit::WaitingForInput => Status::WaitingForInput,
+                CpmExit::InstructionLimit => Status::Running,
+            }),
+            _ => {
+                let emulator = self.emulator_mut();
+                let mut executed = 0;
+                while emulator.is_running() && executed < max_instructions {
+                    emulator.step()?;
+                    executed += 1;
+                }
+                Ok(if emulator.is_running() { Status::Running } else { Status::Halted })
+            }
+        }
+    }
+}
+
+impl Target for Machine {
+    fn emulator(&self) -> &Emulator {
+        match self {
+            Machine::Plain(emulator, _) => emulator,
+            Machine::Altair(altair) => altair.emulator(),
+            Machine::CpmSim(sim) => sim.emulator(),
+            Machine::Cpm(cpm) => cpm.emulator(),
+        }
+    }
+
+    fn emulator_mut(&mut self) -> &mut Emulator {
+        match self {
+            Machine::Plain(emulator, _) => emulator,
+            Machine::Altair(altair) => altair.emulator_mut(),
+            Machine::CpmSim(sim) => sim.emulator_mut(),
+            Machine::Cpm(cpm) => cpm.emulator_mut(),
+        }
+    }
+
+    /*
+     * CP/M programs step through the host BDOS
+     */
+    fn step(&mut self) -> EResult<()> {
+        match self {
+            Machine::Cpm(cpm) => match cpm.run(1)? {
+                CpmExit::WaitingForInput => Err("Program is waiting for console input"),
+                CpmExit::Terminated => Err("Program terminated"),
+                _ => Ok(()),
+            },
+            _ => self.emulator_mut().step(),
+        }
+    }
+
+    fn send_input(&mut self, bytes: &[u8]) {
+        match self {
+            Machine::Plain(_, console) => console.borrow_mut().send(bytes),
+            Machine::Altair(altair) => altair.console().borrow_mut().send(bytes),
+            Machine::CpmSim(sim) => sim.send_input(&String::from_utf8_lossy(bytes)),
+            Machine::Cpm(cpm) => cpm.send_input(&String::from_utf8_lossy(bytes)),
+        }
+    }
+
+    fn take_output(&mut self) -> Vec<u8> {
+        match self {
+            Machine::Plain(_, console) => console.borrow_mut().take_output(),
+            Machine::Altair(altair) => altair.take_output().into_bytes(),
+            Machine::CpmSim(sim) => sim.take_output().into_bytes(),
+            Machine::Cpm(cpm) => cpm.take_output().into_bytes(),
+        }
+    }
+}
+
+/*
+ * Run in batches, passing console input along in between,
+ * until the program stops or the instruction limit is reached
+ */
+fn execute(
+    machine: &mut Machine,
+    input: &Receiver<Vec<u8>>,
+    output: &mut dyn Write,
+    limit: Option<u64>,
+) -> Result<Exit, String> {
+    let mut executed = 0;
+    loop {
+        while let Ok(bytes) = input.try_recv() {
+            machine.send_input(&bytes);
+        }
+        let batch = limit.map_or(BATCH, |limit| (limit - executed).min(BATCH));
+        if batch == 0 {
+            return Ok(Exit::Limit);
+        }
+        let status = machine.run_batch(batch);
+        output
+            .write_all(&machine.take_output())
+            .and_then(|_| output.flush())
+            .map_err(|error| error.to_string())?;
+        match status? {
+            Status::Running => executed += batch,
+            Status::Halted => return Ok(Exit::Halted),
+            Status::Terminated => return Ok(Exit::Terminated),
+            Status::WaitingForInput => match input.recv() {
+                Ok(bytes) => machine.send_input(&bytes),
+                Err(_) => return Err(String::from("Console input exhausted")),
+            },
+        }
+    }
+}
+
+/*
+ * Console input is read on its own thread so that
+ * programs keep running while nothing is typed
+ */
+fn spawn_stdin_reader() -> Receiver<Vec<u8>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buffer = [0; 256];
+        let mut stdin = io::stdin();
+        while let Ok(count) = stdin.read(&mut buffer) {
+            if count == 0 || sender.send(buffer[..count].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+fn run(options: &Options) -> Result<Exit, String> {
+    let mut machine = Machine::new(options).map_err(|error| error.to_string())?;
+    let mut symbols = SymbolTable::new();
+    let mut ranges = Vec::new();
+    match &options.program {
+        Some(path) => {
+            let program = load_program(path, options)?;
+            ranges = program.chunks.iter().map(|(address, bytes)| (*address, bytes.len())).collect();
+            machine.load(program.chunks, program.start);
+            symbols = program.symbols;
+        }
+        None => machine.boot()?,
+    }
+    if options.profile_report.is_some() || options.folded.is_some() {
+        machine.emulator_mut().start_profiling();
+    }
+    let exit = session(&mut machine, options, &symbols);
+    write_profile(machine.emulator(), &symbols, &ranges, options)?;
+    exit
+}
+
+fn session(machine: &mut Machine, options: &Options, symbols: &SymbolTable) -> Result<Exit, String> {
+    if options.monitor {
+        let mut monitor = Monitor::new().with_symbols(symbols.clone());
+        if let Some(limit) = options.limit {
+            monitor = monitor.with_limit(limit as usize);
+        }
+        return debug(machine, &mut monitor).map_err(|error| error.to_string());
+    }
+    if let Some(port) = options.gdb {
+        eprintln!("Waiting for gdb on 127.0.0.1:{}", port);
+        GdbStub::new().listen(machine, ("127.0.0.1", port)).map_err(|error| error.to_string())?;
+        return Ok(Exit::Halted);
+    }
+    execute(machine, &spawn_stdin_reader(), &mut io::stdout(), options.limit)
+}
+
+/*
+ * Profile report followed by the loaded ranges with hit counts,
+ * and the folded call stacks
+ */
+fn write_profile(
+    emulator: &Emulator,
+    symbols: &SymbolTable,
+    ranges: &[(u16, usize)],
+    options: &Options,
+) -> Result<(), String> {
+    let profile = match emulator.get_profile() {
+        Some(profile) => profile,
+        None => return Ok(()),
+    };
+    if let Some(path) = &options.profile_report {
+        let mut text = report(profile, symbols, REPORT_LINES);
+        for (address, length) in ranges {
+            let listing = Disassembler::from_ram(emulator.get_ram(), *address, *length)
+                .with_symbols(symbols.clone())
+                .listing();
+            text.push_str(&format!("\n{}\n", annotate(profile, &listing)));
+        }
+        fs::write(path, text).map_err(|error| format!("{}: {}", path, error))?;
+    }
+    if let Some(path) = &options.folded {
+        fs::write(path, folded(profile, symbols)).map_err(|error| format!("{}: {}", path, error))?;
+    }
+    Ok(())
+}
+
+/*
+ * Monitor session on stdin/stdout until quit or end of input
+ */
+fn debug(machine: &mut Machine, monitor: &mut Monitor) -> io::Result<Exit> {
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    writeln!(stdout, "{}", monitor.registers(machine.emulator()))?;
+    loop {
+        write!(stdout, "- ")?;
+        stdout.flush()?;
+        let mut line = String::new();
+        if stdin.read_line(&mut line)? == 0 {
+            return Ok(Exit::Halted);
+        }
+        match monitor.execute(machine, &line) {
+            Ok(Some(output)) if output.is_empty() => {}
+            Ok(Some(output)) => writeln!(stdout, "{}", output)?,
+            Ok(None) => return Ok(Exit::Halted),
+            Err(message) => writeln!(stdout, "Error: {}", message)?,
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = match parse_options(&args) {
+        Ok(options) => options,
+        Err(message) if message.is_empty() => {
+            print!("{}", USAGE);
+            process::exit(EXIT_HALTED);
+        }
+        Err(message) => {
+            eprintln!("i8080: {}\n\n{}", message, USAGE);
+            process::exit(EXIT_USAGE);
+        }
+    };
+    let status = match run(&options) {
+        Ok(Exit::Halted) | Ok(Exit::Terminated) => EXIT_HALTED,
+        Ok(Exit::Limit) => {
+            eprintln!("i8080: instruction limit reached");
+            EXIT_LIMIT
+        }
+        Err(message) => {
+            eprintln!("i8080: {}", message);
+            EXIT_ERROR
+        }
+    };
+    process::exit(status);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    fn plain(code: Vec<u8>) -> Machine {
+        let mut machine = Machine::new(&Options::default()).expect("Fuck");
+        machine.load(vec![(0, code)], 0);
+        machine
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(parse_number("42"), Ok(42));
+        assert_eq!(parse_number("0x4000"), Ok(0x4000));
+        assert_eq!(parse_number("0FFh"), Ok(0xff));
+        assert_eq!(parse_number("16K"), Ok(0x4000));
+        assert!(parse_number("G").is_err());
+        assert!(parse_address("64K").is_err());
+        assert!(parse_number("99999999999999999K").is_err());
+        assert!(parse_options(&args("--memory 99999999999999999K a.bin")).is_err());
+    }
+
+    #[test]
+    fn options() {
+        let options = parse_options(&args("-m altair --memory 16K --cpu 8085 --limit 1000 --start 100H prog.hex"))
+            .expect("Fuck");
+        assert_eq!(options.profile, Profile::Altair);
+        assert_eq!(options.memory, 0x4000);
+        assert_eq!(options.cpu, Cpu::I8085);
+        assert_eq!(options.limit, Some(1000));
+        assert_eq!(options.start, Some(0x100));
+        assert_eq!(options.program.as_deref(), Some("prog.hex"));
+
+        assert_eq!(parse_options(&args("--help")), Err(String::new()));
+        assert!(parse_options(&args("")).is_err());
+        assert!(parse_options(&args("--memory 3K a.bin")).is_err());
+        assert!(parse_options(&args("-m cpm --memory 32K a.com")).is_err());
+        assert!(parse_options(&args("--disk a.dsk")).is_err());
+        let disks = "-m cpmsim --disk a --disk b --disk c --disk d";
+        assert!(parse_options(&args(disks)).is_ok());
+        assert_eq!(parse_options(&args(&format!("{} --disk e", disks))), Err(String::from("At most 4 disks")));
+        assert!(parse_options(&args("--limit")).is_err());
+        assert!(parse_options(&args("--gdb 0 prog.com")).is_err());
+        assert!(parse_options(&args("--gdb 1234 --monitor prog.com")).is_err());
+        assert!(parse_options(&args("a.bin b.bin")).is_err());
+    }
+
+    #[test]
+    fn origins() {
+        let chunks = place(&[1, 2, 3, 4], &[(1, 0x100), (3, 0x200)], 0x10);
+        assert_eq!(chunks, vec![(0x10, vec![1]), (0x100, vec![2, 3]), (0x200, vec![4])]);
+        assert_eq!(place(&[1, 2], &[(0, 0x100)], 0), vec![(0x100, vec![1, 2])]);
+    }
+
+    #[test]
+    fn echo_until_halt() {
+        // IN 0 / ORA A / JZ 0 / IN 1 / CPI '.' / JZ HALT / OUT 1 / JMP 0 / HALT: HLT
+        let mut machine = plain(vec![
+            0xdb, 0x00, 0xb7, 0xca, 0x00, 0x00, 0xdb, 0x01, 0xfe, 0x2e, 0xca, 0x13, 0x00, 0xd3, 0x01, 0xc3, 0x00,
+            0x00, 0x00, 0x76,
+        ]);
+        let (sender, receiver) = mpsc::channel();
+        sender.send(b"hi.".to_vec()).expect("Fuck");
+        let mut output = Vec::new();
+        assert_eq!(execute(&mut machine, &receiver, &mut output, Some(1000)), Ok(Exit::Halted));
+        assert_eq!(output, b"hi");
+    }
+
+    #[test]
+    fn instruction_limit() {
+        // JMP 0
+        let mut machine = plain(vec![0xc3, 0x00, 0x00]);
+        let (_sender, receiver) = mpsc::channel();
+        assert_eq!(execute(&mut machine, &receiver, &mut Vec::new(), Some(25000)), Ok(Exit::Limit));
+        assert!(machine.emulator_mut().is_running());
+    }
+
+    #[test]
+    fn cpm_input() {
+        let options = Options {
+            profile: Profile::Cpm,
+            ..Options::default()
+        };
+        let mut machine = Machine::new(&options).expect("Fuck");
+        // MVI C,1 / CALL 5 / RET
+        machine.load(vec![(0x100, vec![0x0e, 0x01, 0xcd, 0x05, 0x00, 0xc9])], 0x100);
+        let (sender, receiver) = mpsc::channel();
+        let mut output = Vec::new();
+        sender.send(b"x".to_vec()).expect("Fuck");
+        assert_eq!(execute(&mut machine, &receiver, &mut output, None), Ok(Exit::Terminated));
+        assert_eq!(output, b"x");
+
+        machine.load(vec![(0x100, vec![0x0e, 0x01, 0xcd, 0x05, 0x00, 0xc9])], 0x100);
+        drop(sender);
+        assert!(execute(&mut machine, &receiver, &mut output, None).is_err());
+    }
+
+    #[test]
+    fn monitor_cpm() {
+        let options = Options {
+            profile: Profile::Cpm,
+            ..Options::default()
+        };
+        let mut machine = Machine::new(&options).expect("Fuck");
+        // MVI C,2 / MVI E,'A' / CALL 5 / RET
+        machine.load(vec![(0x100, vec![0x0e, 0x02, 0x1e, b'A', 0xcd, 0x05, 0x00, 0xc9])], 0x100);
+        let mut monitor = Monitor::new();
+        monitor.execute(&mut machine, "b 107").expect("Fuck");
+        let output = monitor.execute(&mut machine, "c").expect("Fuck").expect("Fuck");
+        assert!(output.contains("Breakpoint at 0107"));
+        assert!(output.contains('A'));
+        let output = monitor.execute(&mut machine, "c").expect("Fuck").expect("Fuck");
+        assert!(output.contains("Program terminated"));
+    }
+
+    #[test]
+    fn assemble_program() {
+        let path = std::env::temp_dir().join("i8080_assemble_program.asm");
+        fs::write(&path, "ORG 100H\nMVI A,'!'\nOUT 1\nHLT\nEND\n").expect("Fuck");
+        let options = parse_options(&args(path.to_str().unwrap())).expect("Fuck");
+        let program = load_program(path.to_str().unwrap(), &options).expect("Fuck");
+        fs::remove_file(&path).expect("Fuck");
+        assert_eq!(program.chunks, vec![(0x100, vec![0x3e, b'!', 0xd3, 0x01, 0x76])]);
+        assert_eq!(program.start, 0x100);
+    }
+
+    #[test]
+    fn profile_files() {
+        let directory = std::env::temp_dir();
+        let source = directory.join("i8080_profile_files.asm");
+        let report = directory.join("i8080_profile_files.txt");
+        let stacks = directory.join("i8080_profile_files.folded");
+        fs::write(&source, "ORG 100H\nMAIN: CALL PRINT\nHLT\nPRINT: RET\nEND\n").expect("Fuck");
+        let line = format!("--profile {} --folded {} {}", report.display(), stacks.display(), source.display());
+        assert_eq!(run(&parse_options(&args(&line)).expect("Fuck")), Ok(Exit::Halted));
+
+        let text = fs::read_to_string(&report).expect("Fuck");
+        assert!(text.starts_with("Instructions 3, cycles 34\n"));
+        assert!(text.contains("      1         17  0100  CD 04 01  MAIN:       CALL PRINT"));
+        assert_eq!(fs::read_to_string(&stacks).expect("Fuck"), "MAIN 24\nPRINT 10\n");
+        for path in [source, report, stacks] {
+            fs::remove_file(path).expect("Fuck");
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::VecDeque;
+
+use crate::core::io::{InputDevice, OutputDevice};
+
+// Status register bits
+const RDRF: u8 = 0x01; // Receive data register full
+const TDRE: u8 = 0x02; // Transmit data register empty
+const IRQ: u8 = 0x80;
+
+// Control register bits
+const MASTER_RESET: u8 = 0x03;
+const TX_INTERRUPT: u8 = 0x20;
+const TX_CONTROL: u8 = 0x60;
+const RX_INTERRUPT: u8 = 0x80;
+
+/*
+ * Motorola 6850 ACIA as used on the Altair 88-2SIO board
+ *
+ * base port:     read status / write control
+ * base port + 1: read received byte / write byte to transmit
+ *
+ * The serial line is modelled by two host side buffers:
+ * bytes queued with send() are received one by one,
+ * transmitted bytes are collected until take_output() is called.
+ */
+pub struct Acia6850 {
+    base_port: u8,
+    control: u8,
+    receive: VecDeque<u8>,
+    transmit: Vec<u8>,
+}
+
+impl Acia6850 {
+    pub fn new(base_port: u8) -> Self {
+        Self {
+            base_port,
+            control: MASTER_RESET,
+            receive: VecDeque::new(),
+            transmit: Vec::new(),
+        }
+    }
+
+    pub fn ports(&self) -> [u8; 2] {
+        [self.base_port, self.base_port.wrapping_add(1)]
+    }
+
+    /*
+     * Queue bytes coming in from the terminal
+     */
+    pub fn send(&mut self, bytes: &[u8]) {
+        self.receive.extend(bytes);
+    }
+
+    /*
+     * Drain everything the CPU transmitted so far
+     */
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.transmit)
+    }
+
+    pub fn status(&self) -> u8 {
+        let mut status = TDRE;
+        if !self.receive.is_empty() {
+            status |= RDRF;
+        }
+        if self.interrupt_pending() {
+            status |= IRQ;
+        }
+        status
+    }
+
+    pub fn interrupt_pending(&self) -> bool {
+        if self.control & MASTER_RESET == MASTER_RESET {
+            return false;
+        }
+        let rx = self.control & RX_INTERRUPT != 0 && !self.receive.is_empty();
+        let tx = self.control & TX_CONTROL == TX_INTERRUPT;
+        rx || tx
+    }
+}
+
+impl InputDevice for Acia6850 {
+    fn read(&mut self, port: u8) -> u8 {
+        if port == self.base_port {
+            self.status()
+        } else {
+            self.receive.pop_front().unwrap_or(0)
+        }
+    }
+}
+
+impl OutputDevice for Acia6850 {
+    fn write(&mut self, port: u8, byte: u8) {
+        if port == self.base_port {
+            self.control = byte;
+        } else {
+            self.transmit.push(byte);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn receive_transmit() {
+        let mut acia = Acia6850::new(0x10);
+        assert_eq!(acia.ports(), [0x10, 0x11]);
+        assert_eq!(acia.read(0x10) & RDRF, 0);
+        assert_eq!(acia.read(0x10) & TDRE, TDRE);
+
+        acia.send(b"HI");
+        assert_eq!(acia.read(0x10) & RDRF, RDRF);
+        assert_eq!(acia.read(0x11), b'H');
+        assert_eq!(acia.read(0x11), b'I');
+        assert_eq!(acia.read(0x10) & RDRF, 0);
+
+        acia.write(0x11, b'O');
+        acia.write(0x11, b'K');
+        assert_eq!(acia.take_output(), b"OK".to_vec());
+        assert!(acia.take_output().is_empty());
+    }
+
+    #[test]
+    fn interrupts() {
+        let mut acia = Acia6850::new(0x10);
+        acia.send(b"X");
+        // Interrupts are off after master reset
+        assert!(!acia.interrupt_pending());
+
+        // 8 bits, no parity, 1 stop bit, /16, receive interrupt enabled
+        acia.write(0x10, 0x95);
+        assert!(acia.interrupt_pending());
+        assert_eq!(acia.read(0x10) & IRQ, IRQ);
+
+        acia.read(0x11);
+        assert!(!acia.interrupt_pending());
+
+        // Transmit interrupt enabled
+        acia.write(0x10, 0x35);
+        assert!(acia.interrupt_pending());
+    }
+}
@@ -0,0 +1,78 @@
+use std::collections::VecDeque;
+
+use crate::core::io::{InputDevice, OutputDevice};
+
+/*
+ * Console in the style of the z80pack/simh cpmsim machines
+ *
+ * base port:     status, 0FFH if a character is available, 0 otherwise
+ * base port + 1: read/write a character
+ */
+pub struct Console {
+    base_port: u8,
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+}
+
+impl Console {
+    pub fn new(base_port: u8) -> Self {
+        Self {
+            base_port,
+            input: VecDeque::new(),
+            output: Vec::new(),
+        }
+    }
+
+    pub fn ports(&self) -> [u8; 2] {
+        [self.base_port, self.base_port.wrapping_add(1)]
+    }
+
+    pub fn send(&mut self, bytes: &[u8]) {
+        self.input.extend(bytes);
+    }
+
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+}
+
+impl InputDevice for Console {
+    fn read(&mut self, port: u8) -> u8 {
+        if port == self.base_port {
+            if self.input.is_empty() {
+                0x00
+            } else {
+                0xff
+            }
+        } else {
+            self.input.pop_front().unwrap_or(0)
+        }
+    }
+}
+
+impl OutputDevice for Console {
+    fn write(&mut self, port: u8, byte: u8) {
+        if port != self.base_port {
+            self.output.push(byte);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn console() {
+        let mut console = Console::new(0);
+        assert_eq!(console.read(0), 0);
+        console.send(b"A");
+        assert_eq!(console.read(0), 0xff);
+        assert_eq!(console.read(1), b'A');
+        assert_eq!(console.read(0), 0);
+
+        console.write(1, b'B');
+        console.write(0, b'X');
+        assert_eq!(console.take_output(), b"B".to_vec());
+    }
+}
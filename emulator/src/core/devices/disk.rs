@@ -0,0 +1,316 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::core::io::{InputDevice, OutputDevice};
+use crate::core::ram::RAM;
+
+// IBM 3740: 8" single sided, single density
+pub const TRACKS: usize = 77;
+pub const SECTORS: usize = 26;
+pub const SECTOR_SIZE: usize = 128;
+pub const IMAGE_SIZE: usize = TRACKS * SECTORS * SECTOR_SIZE;
+
+pub const DRIVES: usize = 4;
+
+// Register offsets from the base port
+const DRIVE: u8 = 0;
+const TRACK: u8 = 1;
+const SECTOR: u8 = 2;
+const COMMAND: u8 = 3;
+const STATUS: u8 = 4;
+const DMA_LOW: u8 = 5;
+const DMA_HIGH: u8 = 6;
+const SECTOR_HIGH: u8 = 7;
+
+// Status codes
+pub const STATUS_OK: u8 = 0;
+pub const STATUS_ILLEGAL_DRIVE: u8 = 1;
+pub const STATUS_ILLEGAL_TRACK: u8 = 2;
+pub const STATUS_ILLEGAL_SECTOR: u8 = 3;
+pub const STATUS_READ_ERROR: u8 = 5;
+pub const STATUS_WRITE_ERROR: u8 = 6;
+pub const STATUS_ILLEGAL_COMMAND: u8 = 7;
+
+/*
+ * .dsk image of a 77 track, 26 sector disk
+ * Writes go to memory and are written through to the file (if any)
+ */
+pub struct DiskImage {
+    data: Vec<u8>,
+    path: Option<PathBuf>,
+    read_only: bool,
+}
+
+impl DiskImage {
+    pub fn open(path: &Path, read_only: bool) -> io::Result<Self> {
+        let mut data = fs::read(path)?;
+        if data.len() > IMAGE_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not an IBM 3740 disk image"));
+        }
+        data.resize(IMAGE_SIZE, 0xe5);
+        Ok(Self {
+            data,
+            path: Some(path.to_path_buf()),
+            read_only,
+        })
+    }
+
+    /*
+     * Freshly formatted disk that only lives in memory
+     */
+    pub fn blank() -> Self {
+        Self {
+            data: vec![0xe5; IMAGE_SIZE],
+            path: None,
+            read_only: false,
+        }
+    }
+
+    pub fn from_bytes(mut data: Vec<u8>) -> Self {
+        data.resize(IMAGE_SIZE, 0xe5);
+        Self {
+            data,
+            path: None,
+            read_only: false,
+        }
+    }
+
+    fn offset(track: usize, sector: usize) -> usize {
+        (track * SECTORS + sector - 1) * SECTOR_SIZE
+    }
+
+    pub fn read_sector(&self, track: usize, sector: usize) -> &[u8] {
+        let offset = Self::offset(track, sector);
+        &self.data[offset..offset + SECTOR_SIZE]
+    }
+
+    pub fn write_sector(&mut self, track: usize, sector: usize, bytes: &[u8]) -> io::Result<()> {
+        if self.read_only {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "Disk is write protected"));
+        }
+        let offset = Self::offset(track, sector);
+        self.data[offset..offset + SECTOR_SIZE].copy_from_slice(bytes);
+        if let Some(path) = &self.path {
+            let mut f = OpenOptions::new().write(true).open(path)?;
+            f.seek(SeekFrom::Start(offset as u64))?;
+            f.write_all(bytes)?;
+        }
+        Ok(())
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/*
+ * Floppy disk controller with the port interface of the
+ * z80pack/simh cpmsim machines (ports 10-17 decimal by default)
+ *
+ * base + 0: drive       base + 4: status (read)
+ * base + 1: track       base + 5: DMA address low
+ * base + 2: sector      base + 6: DMA address high
+ * base + 3: command     base + 7: sector high
+ *
+ * Writing 0 (read) or 1 (write) to the command port transfers one
+ * sector between the selected drive and memory at the DMA address.
+ */
+pub struct DiskController {
+    base_port: u8,
+    drives: [Option<DiskImage>; DRIVES],
+    drive: u8,
+    track: u8,
+    sector: u16,
+    dma: u16,
+    status: u8,
+    command: Option<u8>,
+}
+
+impl DiskController {
+    pub fn new(base_port: u8) -> Self {
+        Self {
+            base_port,
+            drives: [None, None, None, None],
+            drive: 0,
+            track: 0,
+            sector: 1,
+            dma: 0x0080,
+            status: STATUS_OK,
+            command: None,
+        }
+    }
+
+    pub fn ports(&self) -> Vec<u8> {
+        (0..8).map(|offset| self.base_port.wrapping_add(offset)).collect()
+    }
+
+    pub fn insert(&mut self, drive: usize, disk: DiskImage) -> Result<(), &'static str> {
+        let slot = self.drives.get_mut(drive).ok_or("Illegal drive")?;
+        *slot = Some(disk);
+        Ok(())
+    }
+
+    pub fn eject(&mut self, drive: usize) -> Option<DiskImage> {
+        self.drives.get_mut(drive).and_then(Option::take)
+    }
+
+    pub fn disk(&self, drive: usize) -> Option<&DiskImage> {
+        self.drives.get(drive).and_then(Option::as_ref)
+    }
+
+    pub fn status(&self) -> u8 {
+        self.status
+    }
+
+    fn execute(&mut self, command: u8, ram: &mut dyn RAM) -> u8 {
+        let (track, sector, dma) = (self.track as usize, self.sector as usize, self.dma);
+        let disk = match self.drives.get_mut(self.drive as usize) {
+            Some(Some(disk)) => disk,
+            _ => return STATUS_ILLEGAL_DRIVE,
+        };
+        if track >= TRACKS {
+            return STATUS_ILLEGAL_TRACK;
+        }
+        if sector == 0 || sector > SECTORS {
+            return STATUS_ILLEGAL_SECTOR;
+        }
+        match command {
+            0 => {
+                ram.load_vec(disk.read_sector(track, sector).to_vec(), dma);
+                STATUS_OK
+            }
+            1 => {
+                let bytes: Vec<u8> = (0..SECTOR_SIZE as u16)
+                    .map(|i| ram[dma.wrapping_add(i)])
+                    .collect();
+                match disk.write_sector(track, sector, &bytes) {
+                    Ok(_) => STATUS_OK,
+                    Err(_) => STATUS_WRITE_ERROR,
+                }
+            }
+            _ => STATUS_ILLEGAL_COMMAND,
+        }
+    }
+}
+
+impl InputDevice for DiskController {
+    fn read(&mut self, port: u8) -> u8 {
+        match port.wrapping_sub(self.base_port) {
+            DRIVE => self.drive,
+            TRACK => self.track,
+            SECTOR => self.sector as u8,
+            STATUS => self.status,
+            DMA_LOW => self.dma as u8,
+            DMA_HIGH => (self.dma >> 8) as u8,
+            SECTOR_HIGH => (self.sector >> 8) as u8,
+            _ => 0,
+        }
+    }
+}
+
+impl OutputDevice for DiskController {
+    fn write(&mut self, port: u8, byte: u8) {
+        match port.wrapping_sub(self.base_port) {
+            DRIVE => self.drive = byte,
+            TRACK => self.track = byte,
+            SECTOR => self.sector = (self.sector & 0xff00) | byte as u16,
+            COMMAND => self.command = Some(byte),
+            DMA_LOW => self.dma = (self.dma & 0xff00) | byte as u16,
+            DMA_HIGH => self.dma = (self.dma & 0x00ff) | (byte as u16) << 8,
+            SECTOR_HIGH => self.sector = (self.sector & 0x00ff) | (byte as u16) << 8,
+            _ => (),
+        }
+    }
+
+    fn transfer(&mut self, ram: &mut dyn RAM) {
+        if let Some(command) = self.command.take() {
+            self.status = self.execute(command, ram);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::ram::LinearRam;
+
+    fn select(fdc: &mut DiskController, drive: u8, track: u8, sector: u8, dma: u16) {
+        fdc.write(10, drive);
+        fdc.write(11, track);
+        fdc.write(12, sector);
+        fdc.write(15, dma as u8);
+        fdc.write(16, (dma >> 8) as u8);
+    }
+
+    fn command(fdc: &mut DiskController, ram: &mut LinearRam, command: u8) -> u8 {
+        fdc.write(13, command);
+        fdc.transfer(ram);
+        fdc.read(14)
+    }
+
+    #[test]
+    fn read_write() {
+        let mut ram = LinearRam::new(0x10000);
+        let mut fdc = DiskController::new(10);
+        fdc.insert(0, DiskImage::blank()).expect("Fuck");
+
+        ram.load_vec(vec![0x42; SECTOR_SIZE], 0x1000);
+        select(&mut fdc, 0, 76, 26, 0x1000);
+        assert_eq!(command(&mut fdc, &mut ram, 1), STATUS_OK);
+        assert_eq!(fdc.disk(0).unwrap().data()[IMAGE_SIZE - 1], 0x42);
+        assert_eq!(fdc.disk(0).unwrap().data()[IMAGE_SIZE - SECTOR_SIZE - 1], 0xe5);
+
+        select(&mut fdc, 0, 76, 26, 0x2000);
+        assert_eq!(command(&mut fdc, &mut ram, 0), STATUS_OK);
+        assert_eq!(ram[0x2000], 0x42);
+        assert_eq!(ram[0x207f], 0x42);
+        assert_eq!(ram[0x2080], 0x00);
+        assert_eq!(fdc.read(16), 0x20);
+    }
+
+    #[test]
+    fn errors() {
+        let mut ram = LinearRam::new(0x10000);
+        let mut fdc = DiskController::new(10);
+        fdc.insert(0, DiskImage::blank()).expect("Fuck");
+        assert_eq!(fdc.insert(DRIVES, DiskImage::blank()), Err("Illegal drive"));
+        assert!(fdc.eject(DRIVES).is_none());
+        assert!(fdc.disk(DRIVES).is_none());
+
+        select(&mut fdc, 1, 0, 1, 0);
+        assert_eq!(command(&mut fdc, &mut ram, 0), STATUS_ILLEGAL_DRIVE);
+        select(&mut fdc, 0, 77, 1, 0);
+        assert_eq!(command(&mut fdc, &mut ram, 0), STATUS_ILLEGAL_TRACK);
+        select(&mut fdc, 0, 0, 0, 0);
+        assert_eq!(command(&mut fdc, &mut ram, 0), STATUS_ILLEGAL_SECTOR);
+        select(&mut fdc, 0, 0, 27, 0);
+        assert_eq!(command(&mut fdc, &mut ram, 0), STATUS_ILLEGAL_SECTOR);
+        select(&mut fdc, 0, 0, 1, 0);
+        assert_eq!(command(&mut fdc, &mut ram, 2), STATUS_ILLEGAL_COMMAND);
+    }
+
+    #[test]
+    fn image_file() -> io::Result<()> {
+        let path = std::env::temp_dir().join("disk_controller_image_file.dsk");
+        fs::write(&path, vec![0xe5; IMAGE_SIZE])?;
+
+        let mut ram = LinearRam::new(0x10000);
+        let mut fdc = DiskController::new(10);
+        fdc.insert(0, DiskImage::open(&path, false)?).expect("Fuck");
+        fdc.insert(1, DiskImage::open(&path, true)?).expect("Fuck");
+
+        ram.load_vec(vec![0x11; SECTOR_SIZE], 0);
+        select(&mut fdc, 0, 2, 3, 0);
+        assert_eq!(command(&mut fdc, &mut ram, 1), STATUS_OK);
+        let written = fs::read(&path)?;
+        let offset = (2 * SECTORS + 2) * SECTOR_SIZE;
+        assert_eq!(written[offset], 0x11);
+        assert_eq!(written[offset - 1], 0xe5);
+
+        select(&mut fdc, 1, 2, 3, 0);
+        assert_eq!(command(&mut fdc, &mut ram, 1), STATUS_WRITE_ERROR);
+
+        fs::remove_file(&path)
+    }
+}
@@ -0,0 +1,6 @@
+pub mod acia;
+pub mod console;
+pub mod disk;
+pub mod pit;
+pub mod ppi;
+pub mod sense_switches;
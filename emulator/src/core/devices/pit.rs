@@ -0,0 +1,632 @@
+use crate::core::io::{ClockedDevice, InputDevice, OutputDevice};
+
+// Control word fields
+const SELECT_SHIFT: u8 = 6;
+const ACCESS_SHIFT: u8 = 4;
+const MODE_SHIFT: u8 = 1;
+const BCD: u8 = 0x01;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Access {
+    Lsb,
+    Msb,
+    Word,
+}
+
+/*
+ * A single 16 bit down counter
+ *
+ * Counts are kept in binary internally, BCD only matters
+ * when the CPU reads or writes them. A count of 0 stands
+ * for 65536 (binary) or 10000 (BCD).
+ */
+struct Counter {
+    mode: u8,
+    bcd: bool,
+    access: Access,
+    reload: u32,
+    count: u32,
+    write_low: Option<u8>,
+    latch: Option<u16>,
+    read_high: bool,
+    gate: bool,
+    trigger: bool,
+    load: bool,
+    counting: bool,
+    expired: bool,
+    out: bool,
+    interrupt: Option<u8>,
+}
+
+impl Counter {
+    fn new() -> Self {
+        Self {
+            mode: 0,
+            bcd: false,
+            access: Access::Lsb,
+            reload: 0,
+            count: 0,
+            write_low: None,
+            latch: None,
+            read_high: false,
+            gate: true,
+            trigger: false,
+            load: false,
+            counting: false,
+            expired: false,
+            out: false,
+            interrupt: None,
+        }
+    }
+
+    fn modulus(&self) -> u32 {
+        if self.bcd {
+            10000
+        } else {
+            0x10000
+        }
+    }
+
+    fn program(&mut self, access: Access, mode: u8, bcd: bool) {
+        self.access = access;
+        self.mode = mode;
+        self.bcd = bcd;
+        self.write_low = None;
+        self.latch = None;
+        self.read_high = false;
+        self.trigger = false;
+        self.load = false;
+        self.counting = false;
+        self.expired = false;
+        self.out = mode != 0;
+    }
+
+    fn latch_count(&mut self) {
+        if self.latch.is_none() {
+            self.latch = Some(self.current());
+            self.read_high = false;
+        }
+    }
+
+    /*
+     * Count as seen by the CPU
+     */
+    fn current(&self) -> u16 {
+        let value = self.count % self.modulus();
+        if self.bcd {
+            to_bcd(value)
+        } else {
+            value as u16
+        }
+    }
+
+    fn read(&mut self) -> u16 {
+        self.latch.unwrap_or_else(|| self.current())
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let value = self.read();
+        match self.access {
+            Access::Lsb => {
+                self.latch = None;
+                value as u8
+            }
+            Access::Msb => {
+                self.latch = None;
+                (value >> 8) as u8
+            }
+            Access::Word if !self.read_high => {
+                self.read_high = true;
+                value as u8
+            }
+            Access::Word => {
+                self.read_high = false;
+                self.latch = None;
+                (value >> 8) as u8
+            }
+        }
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        let value = match self.access {
+            Access::Lsb => byte as u16,
+            Access::Msb => (byte as u16) << 8,
+            Access::Word => match self.write_low.take() {
+                Some(low) => ((byte as u16) << 8) | low as u16,
+                None => {
+                    self.write_low = Some(byte);
+                    // Writing the first byte stops the count in mode 0
+                    if self.mode == 0 {
+                        self.counting = false;
+                    }
+                    return;
+                }
+            },
+        };
+        self.set_count(value);
+    }
+
+    fn set_count(&mut self, value: u16) {
+        let value = if self.bcd { from_bcd(value) } else { value as u32 };
+        self.reload = if value == 0 { self.modulus() } else { value };
+        match self.mode {
+            0 => {
+                self.out = false;
+                self.load = true;
+            }
+            2 | 3 if self.counting => {
+                // The new count is picked up at the next reload
+            }
+            2..=4 => self.load = true,
+            _ => {
+                // Modes 1 and 5 wait for a gate trigger
+            }
+        }
+    }
+
+    fn set_gate(&mut self, gate: bool) {
+        if gate && !self.gate {
+            match self.mode {
+                1 | 5 => self.trigger = true,
+                2 | 3 if self.reload != 0 => self.load = true,
+                _ => {}
+            }
+        }
+        if !gate && (self.mode == 2 || self.mode == 3) {
+            self.out = true;
+        }
+        self.gate = gate;
+    }
+
+    fn decrement(&mut self, by: u32) {
+        let modulus = self.modulus();
+        self.count = (self.count + modulus - by % modulus) % modulus;
+    }
+
+    /*
+     * Square wave half periods, odd counts make the high half one clock longer
+     */
+    fn half_period(&self, high: bool) -> u32 {
+        match (self.reload & 1 == 1, high) {
+            (true, true) => self.reload + 1,
+            (true, false) => self.reload - 1,
+            _ => self.reload,
+        }
+    }
+
+    /*
+     * Advance by one input clock, returns true on a rising edge of OUT
+     */
+    fn clock(&mut self) -> bool {
+        let before = self.out;
+        match self.mode {
+            0 => {
+                if self.load {
+                    self.load = false;
+                    self.count = self.reload;
+                    self.counting = true;
+                    self.expired = false;
+                } else if self.counting && self.gate {
+                    self.decrement(1);
+                    if self.count == 0 && !self.expired {
+                        self.expired = true;
+                        self.out = true;
+                    }
+                }
+            }
+            1 | 5 => {
+                if self.mode == 5 && !self.out {
+                    self.out = true;
+                }
+                if self.trigger {
+                    self.trigger = false;
+                    if self.reload != 0 {
+                        self.count = self.reload;
+                        self.counting = true;
+                        self.expired = false;
+                        if self.mode == 1 {
+                            self.out = false;
+                        }
+                    }
+                } else if self.counting {
+                    self.decrement(1);
+                    if self.count == 0 && !self.expired {
+                        self.expired = true;
+                        self.out = self.mode == 1;
+                    }
+                }
+            }
+            2 => {
+                if self.load {
+                    self.load = false;
+                    self.count = self.reload;
+                    self.counting = true;
+                    self.out = true;
+                } else if self.counting && self.gate {
+                    self.decrement(1);
+                    if self.count == 0 {
+                        self.count = self.reload;
+                        self.out = true;
+                    } else if self.count == 1 {
+                        self.out = false;
+                    }
+                }
+            }
+            3 => {
+                if self.load {
+                    self.load = false;
+                    self.out = true;
+                    self.count = self.half_period(true);
+                    self.counting = true;
+                } else if self.counting && self.gate {
+                    self.count = self.count.saturating_sub(2);
+                    if self.count == 0 {
+                        self.out = !self.out;
+                        self.count = self.half_period(self.out);
+                    }
+                }
+            }
+            _ => {
+                // Mode 4, software triggered strobe
+                if !self.out {
+                    self.out = true;
+                }
+                if self.load {
+                    self.load = false;
+                    self.count = self.reload;
+                    self.counting = true;
+                    self.expired = false;
+                } else if self.counting && self.gate {
+                    self.decrement(1);
+                    if self.count == 0 && !self.expired {
+                        self.expired = true;
+                        self.out = false;
+                    }
+                }
+            }
+        }
+        !before && self.out
+    }
+}
+
+fn to_bcd(value: u32) -> u16 {
+    let mut result = 0;
+    for digit in 0..4 {
+        result |= (((value / 10u32.pow(digit)) % 10) as u16) << (digit * 4);
+    }
+    result
+}
+
+fn from_bcd(value: u16) -> u32 {
+    (0..4).map(|digit| ((value >> (digit * 4)) & 0xf) as u32 * 10u32.pow(digit)).sum()
+}
+
+/*
+ * Intel 8253 programmable interval timer
+ *
+ * base port + 0..2: counter 0-2
+ * base port + 3:    control word (write only)
+ *
+ * The counters are clocked from the CPU cycle counter, one input
+ * clock every `divider` cycles. GATE inputs default to high and can
+ * be driven by the host. A counter can be wired to the interrupt
+ * line, a rising edge on its OUT pin then requests the given
+ * instruction (usually RST n).
+ */
+pub struct Pit8253 {
+    base_port: u8,
+    divider: u64,
+    phase: u64,
+    counters: [Counter; 3],
+}
+
+impl Pit8253 {
+    pub fn new(base_port: u8, divider: u64) -> Self {
+        Self {
+            base_port,
+            divider: divider.max(1),
+            phase: 0,
+            counters: [Counter::new(), Counter::new(), Counter::new()],
+        }
+    }
+
+    pub fn ports(&self) -> [u8; 4] {
+        [
+            self.base_port,
+            self.base_port.wrapping_add(1),
+            self.base_port.wrapping_add(2),
+            self.base_port.wrapping_add(3),
+        ]
+    }
+
+    pub fn set_gate(&mut self, counter: usize, gate: bool) {
+        self.counters[counter].set_gate(gate);
+    }
+
+    pub fn output(&self, counter: usize) -> bool {
+        self.counters[counter].out
+    }
+
+    /*
+     * Raise an interrupt with the given instruction whenever OUT goes high,
+     * None disconnects the counter from the interrupt line
+     */
+    pub fn connect_interrupt(&mut self, counter: usize, opcode: Option<u8>) {
+        self.counters[counter].interrupt = opcode;
+    }
+
+    /*
+     * Advance all counters by a number of input clocks
+     */
+    pub fn clock(&mut self, clocks: u64) -> Option<u8> {
+        let mut interrupt = None;
+        for _ in 0..clocks {
+            for counter in self.counters.iter_mut() {
+                if counter.clock() && interrupt.is_none() {
+                    interrupt = counter.interrupt;
+                }
+            }
+        }
+        interrupt
+    }
+
+    fn control(&mut self, word: u8) {
+        let select = (word >> SELECT_SHIFT) as usize;
+        if select > 2 {
+            // Read-back is an 8254 extension
+            return;
+        }
+        let counter = &mut self.counters[select];
+        let access = match (word >> ACCESS_SHIFT) & 0x3 {
+            0 => return counter.latch_count(),
+            1 => Access::Lsb,
+            2 => Access::Msb,
+            _ => Access::Word,
+        };
+        let mut mode = (word >> MODE_SHIFT) & 0x7;
+        if mode > 5 {
+            mode -= 4;
+        }
+        counter.program(access, mode, word & BCD != 0);
+    }
+}
+
+impl InputDevice for Pit8253 {
+    fn read(&mut self, port: u8) -> u8 {
+        match port.wrapping_sub(self.base_port) {
+            offset @ 0..=2 => self.counters[offset as usize].read_byte(),
+            _ => 0xff,
+        }
+    }
+}
+
+impl OutputDevice for Pit8253 {
+    fn write(&mut self, port: u8, byte: u8) {
+        match port.wrapping_sub(self.base_port) {
+            offset @ 0..=2 => self.counters[offset as usize].write_byte(byte),
+            3 => self.control(byte),
+            _ => {}
+        }
+    }
+}
+
+impl ClockedDevice for Pit8253 {
+    fn tick(&mut self, cycles: u64) -> Option<u8> {
+        self.phase += cycles;
+        let clocks = self.phase / self.divider;
+        self.phase %= self.divider;
+        self.clock(clocks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::emulator::Emulator;
+    use crate::core::ram::LinearRam;
+    use std::{cell::RefCell, rc::Rc};
+
+    fn setup(control: u8, count: &[u8]) -> Pit8253 {
+        let mut pit = Pit8253::new(0x40, 1);
+        pit.write(0x43, control);
+        for byte in count {
+            pit.write(0x40 + (control >> 6), *byte);
+        }
+        pit
+    }
+
+    #[test]
+    fn mode0_interrupt_on_terminal_count() {
+        // Counter 0, LSB/MSB, mode 0, binary
+        let mut pit = setup(0x30, &[0x05, 0x00]);
+        pit.connect_interrupt(0, Some(0xff));
+        assert!(!pit.output(0));
+
+        // One clock to load, then five to count down
+        assert_eq!(pit.clock(5), None);
+        assert!(!pit.output(0));
+        assert_eq!(pit.clock(1), Some(0xff));
+        assert!(pit.output(0));
+
+        // OUT stays high while the counter wraps around
+        assert_eq!(pit.clock(10), None);
+        assert!(pit.output(0));
+    }
+
+    #[test]
+    fn mode0_gate_pauses() {
+        let mut pit = setup(0x30, &[0x03, 0x00]);
+        pit.clock(2);
+        pit.set_gate(0, false);
+        pit.clock(10);
+        assert!(!pit.output(0));
+        pit.set_gate(0, true);
+        pit.clock(2);
+        assert!(pit.output(0));
+    }
+
+    #[test]
+    fn mode1_one_shot() {
+        // Counter 1, LSB only, mode 1
+        let mut pit = setup(0x52, &[0x03]);
+        pit.set_gate(1, false);
+        pit.clock(10);
+        assert!(pit.output(1));
+
+        pit.set_gate(1, true);
+        pit.clock(1);
+        assert!(!pit.output(1));
+        pit.clock(2);
+        assert!(!pit.output(1));
+        pit.clock(1);
+        assert!(pit.output(1));
+
+        // Retrigger
+        pit.set_gate(1, false);
+        pit.set_gate(1, true);
+        pit.clock(1);
+        assert!(!pit.output(1));
+    }
+
+    #[test]
+    fn mode2_rate_generator() {
+        // Counter 2, LSB only, mode 2
+        let mut pit = setup(0x94, &[0x04]);
+        pit.connect_interrupt(2, Some(0xef));
+        pit.clock(1);
+
+        let mut edges = 0;
+        let mut low = 0;
+        for _ in 0..20 {
+            if pit.clock(1).is_some() {
+                edges += 1;
+            }
+            if !pit.output(2) {
+                low += 1;
+            }
+        }
+        assert_eq!(edges, 5);
+        assert_eq!(low, 5);
+    }
+
+    #[test]
+    fn mode3_square_wave() {
+        // Counter 0, LSB only, mode 3, odd count
+        let mut pit = setup(0x16, &[0x05]);
+        pit.clock(1);
+
+        let mut pattern = Vec::new();
+        for _ in 0..10 {
+            pattern.push(pit.output(0));
+            pit.clock(1);
+        }
+        assert_eq!(
+            pattern,
+            vec![true, true, true, false, false, true, true, true, false, false]
+        );
+    }
+
+    #[test]
+    fn mode4_and_mode5_strobe() {
+        // Counter 0, LSB only, mode 4
+        let mut pit = setup(0x18, &[0x02]);
+        pit.clock(2);
+        assert!(pit.output(0));
+        pit.clock(1);
+        assert!(!pit.output(0));
+        pit.clock(1);
+        assert!(pit.output(0));
+
+        // Counter 1, LSB only, mode 5 waits for the gate
+        let mut pit = setup(0x5a, &[0x02]);
+        pit.clock(10);
+        assert!(pit.output(1));
+        pit.set_gate(1, false);
+        pit.set_gate(1, true);
+        pit.clock(3);
+        assert!(!pit.output(1));
+        pit.clock(1);
+        assert!(pit.output(1));
+    }
+
+    #[test]
+    fn bcd_and_latch() {
+        // Counter 0, LSB/MSB, mode 0, BCD count of 1000
+        let mut pit = setup(0x31, &[0x00, 0x10]);
+        pit.clock(2);
+        assert_eq!(pit.read(0x40), 0x99);
+        assert_eq!(pit.read(0x40), 0x09);
+
+        // Latch, then keep counting
+        pit.write(0x43, 0x00);
+        pit.clock(100);
+        assert_eq!(pit.read(0x40), 0x99);
+        assert_eq!(pit.read(0x40), 0x09);
+        assert_eq!(pit.read(0x40), 0x99);
+        assert_eq!(pit.read(0x40), 0x08);
+
+        // 0 is the maximum count
+        let mut pit = setup(0x31, &[0x00, 0x00]);
+        pit.clock(2);
+        assert_eq!(pit.read(0x40), 0x99);
+        assert_eq!(pit.read(0x40), 0x99);
+    }
+
+    #[test]
+    fn interrupts_wake_halted_cpu() {
+        let mut e = Emulator::with_ram(Box::new(LinearRam::new(0x10000)));
+        let pit = Rc::new(RefCell::new(Pit8253::new(0x40, 2)));
+        pit.borrow_mut().connect_interrupt(0, Some(0xff));
+        for port in pit.borrow().ports().iter() {
+            e.register_output_device(pit.clone(), *port as usize).expect("Fuck");
+        }
+        e.register_clocked_device(pit.clone());
+
+        // MVI A,30H; OUT 43H; MVI A,10; OUT 40H; XRA A; OUT 40H; EI; HLT
+        e.get_ram_mut().load_vec(
+            vec![0x3e, 0x30, 0xd3, 0x43, 0x3e, 10, 0xd3, 0x40, 0xaf, 0xd3, 0x40, 0xfb, 0x76],
+            0,
+        );
+        // RST 7: MVI A,42H; HLT
+        e.get_ram_mut().load_vec(vec![0x3e, 0x42, 0x76], 0x38);
+        e.set_sp(0x1000);
+
+        for _ in 0..8 {
+            e.step().expect("Fuck");
+        }
+        assert!(!e.is_running());
+        assert_eq!(e.get_pc(), 0x0d);
+
+        while e.get_registers()['a'] != 0x42 {
+            e.step().expect("Fuck");
+            assert!(e.get_cycles() < 1000);
+        }
+        assert_eq!(e.get_ram()[0x0fff], 0x00);
+        assert_eq!(e.get_ram()[0x0ffe], 0x0d);
+    }
+
+    #[test]
+    fn interrupt_after_ei_ret() {
+        let mut e = Emulator::with_ram(Box::new(LinearRam::new(0x10000)));
+        let pit = Rc::new(RefCell::new(Pit8253::new(0x40, 1)));
+        pit.borrow_mut().connect_interrupt(0, Some(0xff));
+        for port in pit.borrow().ports().iter() {
+            e.register_output_device(pit.clone(), *port as usize).expect("Fuck");
+        }
+        e.register_clocked_device(pit.clone());
+
+        // Counter 0 fires every 3 cycles, faster than any instruction
+        // MVI A,14H; OUT 43H; MVI A,3; OUT 40H; EI; JMP 0009H
+        e.get_ram_mut().load_vec(vec![0x3e, 0x14, 0xd3, 0x43, 0x3e, 3, 0xd3, 0x40, 0xfb, 0xc3, 0x09, 0x00], 0);
+        // RST 7: INR B; EI; RET
+        e.get_ram_mut().load_vec(vec![0x04, 0xfb, 0xc9], 0x38);
+        e.set_sp(0x1000);
+
+        // The RET runs before the next interrupt, so the ISR never nests
+        for _ in 0..300 {
+            e.step().expect("Fuck");
+            assert!(e.get_sp() >= 0x0ffe);
+        }
+        assert!(e.get_registers()['b'] > 50);
+    }
+}
@@ -0,0 +1,464 @@
+use crate::core::io::{ClockedDevice, InputDevice, OutputDevice};
+
+// Mode set control word
+const MODE_SET: u8 = 0x80;
+const GROUP_A_MODE2: u8 = 0x40;
+const GROUP_A_MODE1: u8 = 0x20;
+const PORT_A_INPUT: u8 = 0x10;
+const PORT_C_UPPER_INPUT: u8 = 0x08;
+const GROUP_B_MODE1: u8 = 0x04;
+const PORT_B_INPUT: u8 = 0x02;
+const PORT_C_LOWER_INPUT: u8 = 0x01;
+
+// Port C handshake lines
+const INTR_B: u8 = 0x01;
+const IBF_B: u8 = 0x02; // OBF_B in output mode
+const INTE_B: u8 = 0x04; // STB_B / ACK_B pin
+const INTR_A: u8 = 0x08;
+const INTE2_A: u8 = 0x10; // STB_A pin
+const IBF_A: u8 = 0x20;
+const INTE1_A: u8 = 0x40; // ACK_A pin
+const OBF_A: u8 = 0x80;
+
+/*
+ * Intel 8255 programmable peripheral interface
+ *
+ * base port + 0..2: port A, B, C
+ * base port + 3:    control word (write only)
+ *
+ * Mode 0 is plain I/O, in mode 1 ports A and B are strobed with
+ * handshake lines on port C and in mode 2 port A is bidirectional.
+ * Control words with bit 7 cleared set or reset a single bit of port C.
+ *
+ * The host side sees the pins: port_a() etc. return what the chip
+ * drives, set_port_a() etc. drive input pins, strobe_a() and
+ * acknowledge_a() play the peripheral side of the handshake.
+ */
+pub struct Ppi8255 {
+    base_port: u8,
+    control: u8,
+    latch: [u8; 3],
+    pins: [u8; 3],
+    input_a: u8,
+    input_b: u8,
+    ibf_a: bool,
+    obf_a: bool,
+    ibf_b: bool,
+    obf_b: bool,
+    irq: bool,
+    interrupt: Option<u8>,
+}
+
+impl Ppi8255 {
+    pub fn new(base_port: u8) -> Self {
+        Self {
+            base_port,
+            // All ports are inputs in mode 0 after reset
+            control: MODE_SET | PORT_A_INPUT | PORT_C_UPPER_INPUT | PORT_B_INPUT | PORT_C_LOWER_INPUT,
+            latch: [0; 3],
+            pins: [0xff; 3],
+            input_a: 0,
+            input_b: 0,
+            ibf_a: false,
+            obf_a: false,
+            ibf_b: false,
+            obf_b: false,
+            irq: false,
+            interrupt: None,
+        }
+    }
+
+    pub fn ports(&self) -> [u8; 4] {
+        [
+            self.base_port,
+            self.base_port.wrapping_add(1),
+            self.base_port.wrapping_add(2),
+            self.base_port.wrapping_add(3),
+        ]
+    }
+
+    pub fn group_a_mode(&self) -> u8 {
+        if self.control & GROUP_A_MODE2 != 0 {
+            2
+        } else if self.control & GROUP_A_MODE1 != 0 {
+            1
+        } else {
+            0
+        }
+    }
+
+    pub fn group_b_mode(&self) -> u8 {
+        if self.control & GROUP_B_MODE1 != 0 {
+            1
+        } else {
+            0
+        }
+    }
+
+    fn a_input(&self) -> bool {
+        self.control & PORT_A_INPUT != 0
+    }
+
+    fn b_input(&self) -> bool {
+        self.control & PORT_B_INPUT != 0
+    }
+
+    /*
+     * Port C bits driven by the output latch in mode 0
+     */
+    fn c_output_mask(&self) -> u8 {
+        let mut mask = 0;
+        if self.control & PORT_C_UPPER_INPUT == 0 {
+            mask |= 0xf0;
+        }
+        if self.control & PORT_C_LOWER_INPUT == 0 {
+            mask |= 0x0f;
+        }
+        mask
+    }
+
+    /*
+     * Port C bits taken over by the handshake of mode 1 and 2
+     */
+    fn c_handshake_mask(&self) -> u8 {
+        let a = match self.group_a_mode() {
+            2 => OBF_A | INTE1_A | IBF_A | INTE2_A | INTR_A,
+            1 if self.a_input() => IBF_A | INTE2_A | INTR_A,
+            1 => OBF_A | INTE1_A | INTR_A,
+            _ => 0,
+        };
+        let b = match self.group_b_mode() {
+            1 => INTR_B | IBF_B | INTE_B,
+            _ => 0,
+        };
+        a | b
+    }
+
+    fn inte(&self, bit: u8) -> bool {
+        self.latch[2] & bit != 0
+    }
+
+    pub fn intr_a(&self) -> bool {
+        match self.group_a_mode() {
+            2 => (self.inte(INTE1_A) && !self.obf_a) || (self.inte(INTE2_A) && self.ibf_a),
+            1 if self.a_input() => self.inte(INTE2_A) && self.ibf_a,
+            1 => self.inte(INTE1_A) && !self.obf_a,
+            _ => false,
+        }
+    }
+
+    pub fn intr_b(&self) -> bool {
+        match self.group_b_mode() {
+            1 if self.b_input() => self.inte(INTE_B) && self.ibf_b,
+            1 => self.inte(INTE_B) && !self.obf_b,
+            _ => false,
+        }
+    }
+
+    /*
+     * Handshake outputs on port C (IBF, OBF and INTR)
+     */
+    fn c_handshake_outputs(&self) -> u8 {
+        let mut value = 0;
+        match self.group_a_mode() {
+            0 => {}
+            mode => {
+                if (mode == 2 || !self.a_input()) && !self.obf_a {
+                    value |= OBF_A;
+                }
+                if (mode == 2 || self.a_input()) && self.ibf_a {
+                    value |= IBF_A;
+                }
+                if self.intr_a() {
+                    value |= INTR_A;
+                }
+            }
+        }
+        if self.group_b_mode() == 1 {
+            let flag = if self.b_input() { self.ibf_b } else { !self.obf_b };
+            if flag {
+                value |= IBF_B;
+            }
+            if self.intr_b() {
+                value |= INTR_B;
+            }
+        }
+        value
+    }
+
+    /*
+     * Port C as read by the CPU, handshake lines are replaced by the status word
+     */
+    fn read_c(&self) -> u8 {
+        let output = self.c_output_mask();
+        let mut value = (self.latch[2] & output) | (self.pins[2] & !output);
+        let handshake = self.c_handshake_mask();
+        let inte = self.latch[2] & (INTE1_A | INTE2_A | INTE_B) & handshake;
+        value &= !handshake;
+        value | ((self.c_handshake_outputs() | inte) & handshake)
+    }
+
+    /*
+     * Pin levels of port A as seen from the outside
+     */
+    pub fn port_a(&self) -> u8 {
+        if self.group_a_mode() != 2 && self.a_input() {
+            self.pins[0]
+        } else {
+            self.latch[0]
+        }
+    }
+
+    pub fn port_b(&self) -> u8 {
+        if self.b_input() {
+            self.pins[1]
+        } else {
+            self.latch[1]
+        }
+    }
+
+    pub fn port_c(&self) -> u8 {
+        let output = self.c_output_mask();
+        let mut value = (self.latch[2] & output) | (self.pins[2] & !output);
+        let handshake = self.c_handshake_mask();
+        // STB and ACK are inputs driven by the peripheral
+        let driven = handshake & !(INTE1_A | INTE2_A | INTE_B);
+        value &= !driven;
+        value | (self.c_handshake_outputs() & driven)
+    }
+
+    /*
+     * Drive the input pins of a port
+     */
+    pub fn set_port_a(&mut self, value: u8) {
+        self.pins[0] = value;
+    }
+
+    pub fn set_port_b(&mut self, value: u8) {
+        self.pins[1] = value;
+    }
+
+    pub fn set_port_c(&mut self, value: u8) {
+        self.pins[2] = value;
+    }
+
+    /*
+     * Peripheral strobes a byte into port A (mode 1 input or mode 2)
+     */
+    pub fn strobe_a(&mut self, byte: u8) {
+        if self.group_a_mode() == 2 || (self.group_a_mode() == 1 && self.a_input()) {
+            self.input_a = byte;
+            self.ibf_a = true;
+        }
+    }
+
+    pub fn strobe_b(&mut self, byte: u8) {
+        if self.group_b_mode() == 1 && self.b_input() {
+            self.input_b = byte;
+            self.ibf_b = true;
+        }
+    }
+
+    /*
+     * Peripheral acknowledges the byte written to port A (mode 1 output or mode 2)
+     */
+    pub fn acknowledge_a(&mut self) -> Option<u8> {
+        if self.obf_a {
+            self.obf_a = false;
+            return Some(self.latch[0]);
+        }
+        None
+    }
+
+    pub fn acknowledge_b(&mut self) -> Option<u8> {
+        if self.obf_b {
+            self.obf_b = false;
+            return Some(self.latch[1]);
+        }
+        None
+    }
+
+    /*
+     * Request an interrupt with the given instruction when INTR A or B goes high
+     */
+    pub fn connect_interrupt(&mut self, opcode: Option<u8>) {
+        self.interrupt = opcode;
+    }
+
+    fn control(&mut self, word: u8) {
+        if word & MODE_SET != 0 {
+            self.control = word;
+            self.latch = [0; 3];
+            self.ibf_a = false;
+            self.obf_a = false;
+            self.ibf_b = false;
+            self.obf_b = false;
+        } else {
+            let bit = 1 << ((word >> 1) & 0x7);
+            if word & 0x01 != 0 {
+                self.latch[2] |= bit;
+            } else {
+                self.latch[2] &= !bit;
+            }
+        }
+    }
+}
+
+impl InputDevice for Ppi8255 {
+    fn read(&mut self, port: u8) -> u8 {
+        match port.wrapping_sub(self.base_port) {
+            0 => match self.group_a_mode() {
+                0 if self.a_input() => self.pins[0],
+                1 if !self.a_input() => self.latch[0],
+                0 => self.latch[0],
+                _ => {
+                    self.ibf_a = false;
+                    self.input_a
+                }
+            },
+            1 => match self.group_b_mode() {
+                1 if self.b_input() => {
+                    self.ibf_b = false;
+                    self.input_b
+                }
+                _ if self.b_input() => self.pins[1],
+                _ => self.latch[1],
+            },
+            2 => self.read_c(),
+            _ => 0xff,
+        }
+    }
+}
+
+impl OutputDevice for Ppi8255 {
+    fn write(&mut self, port: u8, byte: u8) {
+        match port.wrapping_sub(self.base_port) {
+            0 => {
+                self.latch[0] = byte;
+                if self.group_a_mode() == 2 || (self.group_a_mode() == 1 && !self.a_input()) {
+                    self.obf_a = true;
+                }
+            }
+            1 => {
+                self.latch[1] = byte;
+                if self.group_b_mode() == 1 && !self.b_input() {
+                    self.obf_b = true;
+                }
+            }
+            2 => {
+                // Handshake lines can not be overwritten
+                let handshake = self.c_handshake_mask() & !(INTE1_A | INTE2_A | INTE_B);
+                self.latch[2] = (self.latch[2] & handshake) | (byte & !handshake);
+            }
+            3 => self.control(byte),
+            _ => {}
+        }
+    }
+}
+
+impl ClockedDevice for Ppi8255 {
+    fn tick(&mut self, _cycles: u64) -> Option<u8> {
+        let irq = self.intr_a() || self.intr_b();
+        let rising = irq && !self.irq;
+        self.irq = irq;
+        if rising {
+            self.interrupt
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mode0() {
+        let mut ppi = Ppi8255::new(0x80);
+        // A output, B input, C upper output, C lower input
+        ppi.write(0x83, 0x83);
+        ppi.write(0x80, 0x5a);
+        assert_eq!(ppi.port_a(), 0x5a);
+        assert_eq!(ppi.read(0x80), 0x5a);
+
+        ppi.set_port_b(0x3c);
+        assert_eq!(ppi.read(0x81), 0x3c);
+
+        ppi.set_port_c(0x0f);
+        ppi.write(0x82, 0xa5);
+        assert_eq!(ppi.read(0x82), 0xaf);
+        assert_eq!(ppi.port_c(), 0xaf);
+
+        // Mode set clears the output latches
+        ppi.write(0x83, 0x80);
+        assert_eq!(ppi.port_a(), 0x00);
+    }
+
+    #[test]
+    fn bit_set_reset() {
+        let mut ppi = Ppi8255::new(0x80);
+        ppi.write(0x83, 0x80);
+        ppi.write(0x83, 0x0f); // set PC7
+        ppi.write(0x83, 0x01); // set PC0
+        assert_eq!(ppi.port_c(), 0x81);
+        ppi.write(0x83, 0x0e); // reset PC7
+        assert_eq!(ppi.port_c(), 0x01);
+    }
+
+    #[test]
+    fn mode1_input() {
+        let mut ppi = Ppi8255::new(0x80);
+        // Port A mode 1 input
+        ppi.write(0x83, 0xb0);
+        ppi.write(0x83, 0x09); // INTE A (PC4)
+        assert_eq!(ppi.read(0x82) & 0x38, 0x10);
+
+        ppi.strobe_a(0x42);
+        assert!(ppi.intr_a());
+        assert_eq!(ppi.read(0x82) & 0x38, 0x38);
+        assert_eq!(ppi.port_c() & (IBF_A | INTR_A), IBF_A | INTR_A);
+
+        assert_eq!(ppi.read(0x80), 0x42);
+        assert!(!ppi.intr_a());
+        assert_eq!(ppi.read(0x82) & 0x38, 0x10);
+    }
+
+    #[test]
+    fn mode1_output() {
+        let mut ppi = Ppi8255::new(0x80);
+        // Port B mode 1 output
+        ppi.write(0x83, 0x84);
+        ppi.write(0x83, 0x05); // INTE B (PC2)
+        assert!(ppi.intr_b());
+
+        ppi.write(0x81, 0x99);
+        assert!(!ppi.intr_b());
+        // OBF is active low
+        assert_eq!(ppi.port_c() & IBF_B, 0);
+        assert_eq!(ppi.port_b(), 0x99);
+
+        assert_eq!(ppi.acknowledge_b(), Some(0x99));
+        assert_eq!(ppi.acknowledge_b(), None);
+        assert!(ppi.intr_b());
+        assert_eq!(ppi.port_c() & IBF_B, IBF_B);
+    }
+
+    #[test]
+    fn mode2_and_interrupts() {
+        let mut ppi = Ppi8255::new(0x80);
+        ppi.connect_interrupt(Some(0xf7));
+        ppi.write(0x83, 0xc0);
+        ppi.write(0x83, 0x09); // INTE2 (PC4)
+        assert_eq!(ppi.tick(4), None);
+
+        ppi.write(0x80, 0x11);
+        assert_eq!(ppi.acknowledge_a(), Some(0x11));
+        ppi.strobe_a(0x22);
+        assert_eq!(ppi.tick(4), Some(0xf7));
+        assert_eq!(ppi.tick(4), None);
+        assert_eq!(ppi.read(0x82) & 0xf8, OBF_A | IBF_A | INTE2_A | INTR_A);
+        assert_eq!(ppi.read(0x80), 0x22);
+        assert!(!ppi.intr_a());
+    }
+}
@@ -0,0 +1,49 @@
+use crate::core::io::InputDevice;
+
+/*
+ * Altair front panel sense switches (A8-A15), read by IN 0FFH
+ */
+pub struct SenseSwitches {
+    value: u8,
+}
+
+impl SenseSwitches {
+    pub fn new(value: u8) -> Self {
+        Self { value }
+    }
+
+    pub fn set(&mut self, value: u8) {
+        self.value = value;
+    }
+
+    pub fn get(&self) -> u8 {
+        self.value
+    }
+
+    pub fn toggle(&mut self, switch: u8) {
+        self.value ^= 1 << (switch & 0x7);
+    }
+}
+
+impl InputDevice for SenseSwitches {
+    fn read(&mut self, _port: u8) -> u8 {
+        self.value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn switches() {
+        let mut s = SenseSwitches::new(0);
+        s.toggle(0);
+        s.toggle(7);
+        assert_eq!(s.read(0xff), 0x81);
+        s.toggle(0);
+        assert_eq!(s.get(), 0x80);
+        s.set(0xfd);
+        assert_eq!(s.read(0xff), 0xfd);
+    }
+}
@@ -1,12 +1,47 @@
 use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::rc::Rc;
 
 use crate::core::io::*;
+use crate::core::opcodes::{opcode_8085, Opcode, OPCODES};
 use crate::core::ram::*;
 use crate::core::register::RegisterArray;
 
 pub type EResult<T> = Result<T, &'static str>;
 
+pub use self::bus::{Access, AccessWatch, MemoryAccess, MemoryHook};
+pub use self::callstack::{CallKind, Frame, ReturnMismatch};
+pub use self::control::{Breakpoint, Stop, Target};
+pub use self::i8085::Pin;
+pub use self::profiler::Profiler;
+
+/*
+ * CPU the emulator behaves like, the 8085 runs 8080 code
+ * with different cycle counts and adds its own instructions
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cpu {
+    I8080,
+    I8085,
+}
+
+impl Cpu {
+    pub fn from_name(name: &str) -> Result<Cpu, &'static str> {
+        match name.to_lowercase().as_str() {
+            "8080" | "i8080" => Ok(Cpu::I8080),
+            "8085" | "i8085" => Ok(Cpu::I8085),
+            _ => Err("Unknown CPU, expected 8080 or 8085"),
+        }
+    }
+
+    pub fn opcode(&self, opcode: u8) -> Opcode {
+        match self {
+            Cpu::I8080 => OPCODES[opcode as usize],
+            Cpu::I8085 => opcode_8085(opcode),
+        }
+    }
+}
+
 pub struct Emulator {
     pc: u16,
     sp: u16,
@@ -14,74 +49,247 @@ pub struct Emulator {
     reg: RegisterArray,
     input_devices: [Option<Rc<RefCell<dyn InputDevice>>>; 256],
     output_devices: [Option<Rc<RefCell<dyn OutputDevice>>>; 256],
+    clocked_devices: Vec<Rc<RefCell<dyn ClockedDevice>>>,
     running: bool,
     interrupts_enabled: bool,
+    // EI only lets interrupts in after the instruction following it
+    interrupts_delayed: bool,
+    pending_interrupt: Option<u8>,
+    cycles: u64,
+    branch_cycles: u8,
+    cpu: Cpu,
+    pins: i8085::Pins,
+    breakpoints: BTreeMap<u16, Breakpoint>,
+    log: Vec<String>,
+    bus: bus::Bus,
+    calls: callstack::CallStack,
+    profiler: Option<Box<Profiler>>,
 }
 
 impl Emulator {
     pub fn new() -> Self {
+        Self::with_ram(Box::new(DefaultRam::new()))
+    }
+
+    pub fn with_ram(ram: Box<dyn RAM>) -> Self {
         Emulator {
             pc: 0,
             sp: 0,
-            ram: Box::new(DefaultRam::new()),
+            ram,
             reg: RegisterArray::new(),
             input_devices: unsafe { std::mem::zeroed() },
             output_devices: unsafe { std::mem::zeroed() },
+            clocked_devices: Vec::new(),
             running: true,
             interrupts_enabled: true, // INTE
+            interrupts_delayed: false,
+            pending_interrupt: None,
+            cycles: 0,
+            branch_cycles: 0,
+            cpu: Cpu::I8080,
+            pins: i8085::Pins::new(),
+            breakpoints: BTreeMap::new(),
+            log: Vec::new(),
+            bus: bus::Bus::new(),
+            calls: callstack::CallStack::new(),
+            profiler: None,
         }
     }
 
+    pub fn with_cpu(mut self, cpu: Cpu) -> Self {
+        self.cpu = cpu;
+        self
+    }
+
+    pub fn get_cpu(&self) -> Cpu {
+        self.cpu
+    }
+
+    pub fn set_cpu(&mut self, cpu: Cpu) {
+        self.cpu = cpu;
+    }
+
     fn execute_instruction(&mut self, opcode: u8) -> EResult<()> {
+        let info = self.cpu.opcode(opcode);
+        self.cycles += info.cycles as u64;
+        self.branch_cycles = info.cycles_taken - info.cycles;
+        if self.cpu == Cpu::I8085 {
+            if let Some(result) = self.execute_8085(opcode) {
+                return result;
+            }
+        }
         match opcode {
+            0x00 | 0x08 | 0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 => {
+                // NOP (0x08-0x38 are undocumented aliases)
+            }
             0x01 => {
                 // LXI B, D16
                 self.lxi("bc")?;
             }
+            0x02 => {
+                // STAX B
+                self.stax("bc")?;
+            }
+            0x03 => {
+                // INX B
+                self.inx("bc")?;
+            }
+            0x04 | 0x0c | 0x14 | 0x1c | 0x24 | 0x2c | 0x34 | 0x3c => {
+                // INR DST
+                self.inr(opcode)?;
+            }
+            0x05 | 0x0d | 0x15 | 0x1d | 0x25 | 0x2d | 0x35 | 0x3d => {
+                // DCR DST
+                self.dcr(opcode)?;
+            }
             0x06 => {
                 // MVI B, D8
                 self.mvi('b')?;
             }
+            0x07 => {
+                // RLC
+                self.rlc()?;
+            }
+            0x09 => {
+                // DAD B
+                self.dad("bc")?;
+            }
+            0x0a => {
+                // LDAX B
+                self.ldax("bc")?;
+            }
+            0x0b => {
+                // DCX B
+                self.dcx("bc")?;
+            }
             0x0e => {
                 // MVI C, D8
                 self.mvi('c')?;
             }
+            0x0f => {
+                // RRC
+                self.rrc()?;
+            }
             0x11 => {
                 // LXI D, D16
                 self.lxi("de")?;
             }
+            0x12 => {
+                // STAX D
+                self.stax("de")?;
+            }
+            0x13 => {
+                // INX D
+                self.inx("de")?;
+            }
             0x16 => {
                 // MVI D, D8
                 self.mvi('d')?;
             }
+            0x17 => {
+                // RAL
+                self.ral()?;
+            }
+            0x19 => {
+                // DAD D
+                self.dad("de")?;
+            }
+            0x1a => {
+                // LDAX D
+                self.ldax("de")?;
+            }
+            0x1b => {
+                // DCX D
+                self.dcx("de")?;
+            }
             0x1e => {
                 // MVI E, D8
                 self.mvi('e')?;
             }
+            0x1f => {
+                // RAR
+                self.rar()?;
+            }
             0x21 => {
                 // LXI H, D16
                 self.lxi("hl")?;
             }
+            0x22 => {
+                // SHLD adr
+                self.shld()?;
+            }
+            0x23 => {
+                // INX H
+                self.inx("hl")?;
+            }
             0x26 => {
                 // MVI H, D8
                 self.mvi('h')?;
             }
+            0x27 => {
+                // DAA
+                self.daa()?;
+            }
+            0x29 => {
+                // DAD H
+                self.dad("hl")?;
+            }
+            0x2a => {
+                // LHLD adr
+                self.lhld()?;
+            }
+            0x2b => {
+                // DCX H
+                self.dcx("hl")?;
+            }
             0x2e => {
                 // MVI L, D8
                 self.mvi('l')?;
             }
+            0x2f => {
+                // CMA
+                self.cma()?;
+            }
             0x31 => {
                 // LXI SP, D16
                 self.sp = self.read_addr()?;
             }
+            0x32 => {
+                // STA adr
+                self.sta()?;
+            }
+            0x33 => {
+                // INX SP
+                self.inx("sp")?;
+            }
             0x36 => {
                 // MVI M, D8
                 self.mvi_adr()?;
             }
+            0x37 => {
+                // STC
+                self.reg.set_flag("carry", true);
+            }
+            0x39 => {
+                // DAD SP
+                self.dad("sp")?;
+            }
+            0x3a => {
+                // LDA adr
+                self.lda()?;
+            }
+            0x3b => {
+                // DCX SP
+                self.dcx("sp")?;
+            }
             0x3e => {
                 // MVI A, D8
                 self.mvi('a')?;
             }
+            0x3f => {
+                // CMC
+                self.reg.flip_flag("carry");
+            }
             0x40..=0x7f => {
                 if opcode == 0x76 {
                     // HLT
@@ -128,28 +336,28 @@ impl Emulator {
                 self.ret_not("zero")?;
             }
             0xc1 => {
-                // Unimplemented
-                unimplemented!();
+                // POP B
+                self.reg["bc"] = self.pop()?;
             }
             0xc2 => {
                 // JNZ adr
                 self.jmp_not("zero")?;
             }
-            0xc3 => {
-                // JMP adr
+            0xc3 | 0xcb => {
+                // JMP adr (0xcb is an undocumented alias)
                 self.pc = self.read_addr()?;
             }
             0xc4 => {
-                // Unimplemented
-                unimplemented!();
+                // CNZ adr
+                self.call_not("zero")?;
             }
             0xc5 => {
                 // PUSH B
                 self.push_reg("bc")?;
             }
             0xc6 => {
-                // Unimplemented
-                unimplemented!();
+                // ADI D8
+                self.adi(false)?;
             }
             0xc7 => {
                 // RST 0
@@ -159,8 +367,8 @@ impl Emulator {
                 // RZ
                 self.ret_if("zero")?;
             }
-            0xc9 => {
-                // RET
+            0xc9 | 0xd9 => {
+                // RET (0xd9 is an undocumented alias)
                 self.ret()?;
             }
             0xca => {
@@ -171,13 +379,13 @@ impl Emulator {
                 // CZ addr
                 self.call_if("zero")?;
             }
-            0xcd => {
-                // CALL addr
+            0xcd | 0xdd | 0xed | 0xfd => {
+                // CALL addr (0xdd, 0xed and 0xfd are undocumented aliases)
                 self.call_imm()?;
             }
             0xce => {
-                // Unimplemented
-                unimplemented!()
+                // ACI D8
+                self.adi(true)?;
             }
             0xcf => {
                 // RST 1
@@ -210,7 +418,7 @@ impl Emulator {
             }
             0xd6 => {
                 // SUI D8
-                unimplemented!()
+                self.sui(false)?;
             }
             0xd7 => {
                 // RST 2
@@ -220,10 +428,6 @@ impl Emulator {
                 // RC
                 self.ret_if("carry")?;
             }
-            0xd9 => {
-                // no-op
-                unimplemented!()
-            }
             0xda => {
                 // JC adr
                 self.jmp_if("carry")?;
@@ -237,13 +441,9 @@ impl Emulator {
                 // CC adr
                 self.call_if("carry")?;
             }
-            0xdd => {
-                // Unimplemented
-                unimplemented!()
-            }
             0xde => {
-                // Unimplemented
-                unimplemented!()
+                // SBI D8
+                self.sui(true)?;
             }
             0xdf => {
                 // RST 3
@@ -254,28 +454,28 @@ impl Emulator {
                 self.ret_not("parity")?;
             }
             0xe1 => {
-                // Unimplemented
-                unimplemented!()
+                // POP H
+                self.reg["hl"] = self.pop()?;
             }
             0xe2 => {
                 // JPO adr
                 self.jmp_not("parity")?;
             }
             0xe3 => {
-                // Unimplemented
-                unimplemented!()
+                // XTHL
+                self.xthl()?;
             }
             0xe4 => {
                 // CPO adr
                 self.call_not("parity")?;
             }
             0xe5 => {
-                // Unimplemented
-                unimplemented!()
+                // PUSH H
+                self.push_reg("hl")?;
             }
             0xe6 => {
-                // Unimplemented
-                unimplemented!()
+                // ANI D8
+                self.ani()?;
             }
             0xe7 => {
                 // RST 4
@@ -286,28 +486,24 @@ impl Emulator {
                 self.ret_if("parity")?;
             }
             0xe9 => {
-                // Unimplemented
-                unimplemented!()
+                // PCHL
+                self.pc = self.reg["hl"];
             }
             0xea => {
                 // JPE adr
                 self.jmp_if("parity")?;
             }
             0xeb => {
-                // Unimplemented
-                unimplemented!()
+                // XCHG
+                self.xchg()?;
             }
             0xec => {
                 // CPE
                 self.call_if("parity")?;
             }
-            0xed => {
-                // Unimplemented
-                unimplemented!()
-            }
             0xee => {
-                // Unimplemented
-                unimplemented!()
+                // XRI D8
+                self.xri()?;
             }
             0xef => {
                 // RST 5
@@ -318,8 +514,8 @@ impl Emulator {
                 self.ret_not("sign")?;
             }
             0xf1 => {
-                // Unimplemented
-                unimplemented!()
+                // POP PSW
+                self.pop_psw()?;
             }
             0xf2 => {
                 // JP adr
@@ -334,12 +530,12 @@ impl Emulator {
                 self.call_not("sign")?;
             }
             0xf5 => {
-                // Unimplemented
-                unimplemented!()
+                // PUSH PSW
+                self.push_psw()?;
             }
             0xf6 => {
-                // Unimplemented
-                unimplemented!()
+                // ORI D8
+                self.ori()?;
             }
             0xf7 => {
                 // RST 6
@@ -350,8 +546,8 @@ impl Emulator {
                 self.ret_if("sign")?;
             }
             0xf9 => {
-                // Unimplemented
-                unimplemented!()
+                // SPHL
+                self.sp = self.reg["hl"];
             }
             0xfa => {
                 // JM adr
@@ -360,53 +556,146 @@ impl Emulator {
             0xfb => {
                 // EI
                 self.interrupts_enabled = true;
+                self.interrupts_delayed = true;
             }
             0xfc => {
                 // CM adr
                 self.call_if("sign")?;
             }
-            0xfd => {
-                // Unimplemented
-                unimplemented!()
-            }
             0xfe => {
-                // Unimplemented
-                unimplemented!()
+                // CPI D8
+                self.cpi()?;
             }
             0xff => {
                 // RST 7
                 self.call(0x38)?;
             }
-            _ => unimplemented!("Opcode not yet implemented"),
         }
         Ok(())
     }
 
     fn execute_next(&mut self) -> EResult<()> {
-        let opcode = self.ram[self.pc];
-        self.pc += 1;
+        let opcode = self.fetch(self.pc);
+        self.pc = self.pc.wrapping_add(1);
         self.execute_instruction(opcode)
     }
 
+    /*
+     * Execute a single instruction, then clock the devices and
+     * service their interrupt requests
+     * A halted CPU idles for 4 cycles until an interrupt arrives
+     */
+    pub fn step(&mut self) -> EResult<()> {
+        self.bus.clear_hit();
+        self.calls.clear_pending();
+        let start = self.cycles;
+        if self.running {
+            self.begin_sample();
+            self.execute_next()?;
+            self.end_sample(self.cycles - start);
+        } else {
+            self.cycles += 4;
+        }
+        self.tick_devices(self.cycles - start);
+
+        let accepting = self.interrupts_enabled && !std::mem::take(&mut self.interrupts_delayed);
+        if self.cpu == Cpu::I8085 && self.service_8085(accepting)? {
+            return Ok(());
+        }
+        if accepting {
+            if let Some(opcode) = self.pending_interrupt.take() {
+                self.interrupt(opcode)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn tick_devices(&mut self, cycles: u64) {
+        for device in &self.clocked_devices {
+            if let Some(opcode) = device.borrow_mut().tick(cycles) {
+                self.pending_interrupt = Some(opcode);
+            }
+        }
+    }
+
+    pub fn get_cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    pub fn get_pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub fn get_sp(&self) -> u16 {
+        self.sp
+    }
+
+    pub fn set_sp(&mut self, sp: u16) {
+        self.sp = sp;
+    }
+
+    pub fn get_registers(&self) -> &RegisterArray {
+        &self.reg
+    }
+
+    pub fn get_registers_mut(&mut self) -> &mut RegisterArray {
+        &mut self.reg
+    }
+
+    pub fn get_ram(&self) -> &dyn RAM {
+        self.ram.as_ref()
+    }
+
+    pub fn get_ram_mut(&mut self) -> &mut dyn RAM {
+        self.ram.as_mut()
+    }
+
     fn read_byte(&mut self) -> EResult<u8> {
-        if self.pc + 1 > self.ram.size() as u16 {
+        if self.pc as usize + 1 > self.ram.size() {
             return Err("READ_BYTE: Not enough bytes available");
         }
-        self.pc += 1;
-        Ok(self.ram[self.pc - 1])
+        self.pc = self.pc.wrapping_add(1);
+        Ok(self.fetch(self.pc.wrapping_sub(1)))
     }
 
     fn read_addr(&mut self) -> EResult<u16> {
-        if self.pc + 2 > self.ram.size() as u16 {
+        if self.pc as usize + 2 > self.ram.size() {
             return Err("READ_ADDR: Not enough bytes available");
         }
-        let low = self.ram[self.pc] as u16;
-        self.pc += 1;
-        let high = self.ram[self.pc] as u16;
-        self.pc += 1;
+        let low = self.fetch(self.pc) as u16;
+        self.pc = self.pc.wrapping_add(1);
+        let high = self.fetch(self.pc) as u16;
+        self.pc = self.pc.wrapping_add(1);
         Ok((high << 8) | low)
     }
 
+    /*
+     * Operand access for the register field of an opcode, 'm' being (HL)
+     */
+    fn read_operand(&mut self, register: char) -> u8 {
+        if register == 'm' {
+            self.read_memory(self.reg["hl"])
+        } else {
+            self.reg[register]
+        }
+    }
+
+    fn write_operand(&mut self, register: char, value: u8) {
+        if register == 'm' {
+            self.write_memory(self.reg["hl"], value);
+        } else {
+            self.reg[register] = value;
+        }
+    }
+
     pub fn load_ram(&mut self, data: Vec<u8>, start: u16) {
         self.ram.load_vec(data, start)
     }
@@ -414,14 +703,30 @@ impl Emulator {
     pub fn interrupt(&mut self, opcode: u8) -> EResult<()> {
         if self.interrupts_enabled {
             self.interrupts_enabled = false;
-            return self.execute_instruction(opcode);
+            self.running = true;
+            self.calls.begin_interrupt();
+            let result = self.execute_instruction(opcode);
+            self.calls.end_interrupt();
+            return result;
         }
         Err("Interrupts disabled")
     }
+
+    /*
+     * Latch an interrupt request, it is serviced by step() once interrupts are enabled
+     */
+    pub fn request_interrupt(&mut self, opcode: u8) {
+        self.pending_interrupt = Some(opcode);
+    }
 }
 
 mod instructions;
+mod bus;
+mod callstack;
+mod control;
 mod devices;
+mod i8085;
+mod profiler;
 
 #[cfg(test)]
 mod tests {
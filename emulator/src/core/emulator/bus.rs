@@ -0,0 +1,284 @@
+use super::{Emulator, Stop};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    // Instruction fetch, opcode and operand bytes
+    Execute,
+}
+
+/*
+ * A memory access by the CPU, value is the byte read, written or fetched
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub access: Access,
+    pub address: u16,
+    pub value: u8,
+}
+
+/*
+ * Host callback for every memory access, returning true stops the run
+ */
+pub type MemoryHook = Box<dyn FnMut(&MemoryAccess) -> bool>;
+
+/*
+ * Watch for some kinds of accesses to an address range, optionally
+ * only when the byte equals a value or a write changes it
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccessWatch {
+    pub start: u16,
+    pub end: u16,
+    pub accesses: Vec<Access>,
+    pub value: Option<u8>,
+    pub changes: bool,
+}
+
+impl AccessWatch {
+    pub fn new(address: u16, accesses: &[Access]) -> Self {
+        AccessWatch {
+            start: address,
+            end: address,
+            accesses: accesses.to_vec(),
+            value: None,
+            changes: false,
+        }
+    }
+
+    /*
+     * Last address of the range, inclusive
+     */
+    pub fn with_end(mut self, end: u16) -> Self {
+        self.end = end;
+        self
+    }
+
+    pub fn with_value(mut self, value: u8) -> Self {
+        self.value = Some(value);
+        self
+    }
+
+    /*
+     * Only writes that change the byte trigger, they stop with Stop::Watchpoint
+     */
+    pub fn with_changes(mut self) -> Self {
+        self.changes = true;
+        self
+    }
+
+    pub fn matches(&self, access: &MemoryAccess) -> bool {
+        self.accesses.contains(&access.access)
+            && (self.start..=self.end).contains(&access.address)
+            && self.value.is_none_or(|value| value == access.value)
+    }
+}
+
+/*
+ * Observers of the memory accesses, the first access that
+ * triggers a watch or hook during a step is kept as the hit
+ */
+pub(super) struct Bus {
+    watches: Vec<AccessWatch>,
+    hooks: Vec<MemoryHook>,
+    hit: Option<Stop>,
+}
+
+impl Bus {
+    pub(super) fn new() -> Self {
+        Bus {
+            watches: Vec::new(),
+            hooks: Vec::new(),
+            hit: None,
+        }
+    }
+
+    pub(super) fn clear_hit(&mut self) {
+        self.hit = None;
+    }
+
+    /*
+     * old is the byte before a write, the accessed byte otherwise
+     */
+    fn observe(&mut self, access: MemoryAccess, old: u8) {
+        let mut stop = None;
+        for hook in self.hooks.iter_mut() {
+            if hook(&access) {
+                stop = Some(Stop::Access(access));
+            }
+        }
+        // Execute watches are checked against the PC before the instruction runs
+        if access.access != Access::Execute {
+            for watch in self.watches.iter().filter(|watch| watch.matches(&access)) {
+                if !watch.changes {
+                    stop = stop.or(Some(Stop::Access(access)));
+                } else if old != access.value {
+                    let (address, new) = (access.address, access.value);
+                    stop = stop.or(Some(Stop::Watchpoint { address, old, new }));
+                }
+            }
+        }
+        if self.hit.is_none() {
+            self.hit = stop;
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.watches.is_empty() && self.hooks.is_empty()
+    }
+}
+
+impl Emulator {
+    pub(super) fn read_memory(&mut self, address: u16) -> u8 {
+        let value = self.ram[address];
+        self.observe(Access::Read, address, value, value);
+        value
+    }
+
+    pub(super) fn write_memory(&mut self, address: u16, value: u8) {
+        let old = std::mem::replace(&mut self.ram[address], value);
+        self.observe(Access::Write, address, value, old);
+    }
+
+    pub(super) fn fetch(&mut self, address: u16) -> u8 {
+        let value = self.ram[address];
+        self.observe(Access::Execute, address, value, value);
+        value
+    }
+
+    fn observe(&mut self, access: Access, address: u16, value: u8, old: u8) {
+        if !self.bus.is_idle() {
+            self.bus.observe(MemoryAccess { access, address, value }, old);
+        }
+    }
+
+    pub fn add_access_watch(&mut self, watch: AccessWatch) {
+        self.bus.watches.push(watch);
+    }
+
+    pub fn remove_access_watch(&mut self, watch: &AccessWatch) -> bool {
+        let count = self.bus.watches.len();
+        self.bus.watches.retain(|other| other != watch);
+        self.bus.watches.len() != count
+    }
+
+    pub fn get_access_watches(&self) -> &[AccessWatch] {
+        &self.bus.watches
+    }
+
+    pub fn add_memory_hook(&mut self, hook: MemoryHook) {
+        self.bus.hooks.push(hook);
+    }
+
+    pub fn clear_memory_hooks(&mut self) {
+        self.bus.hooks.clear();
+    }
+
+    /*
+     * Stop for the access that triggered a watch or hook in the
+     * last step, or for an execute watch on the instruction at PC
+     */
+    pub(super) fn take_access_hit(&mut self) -> Option<Stop> {
+        if let Some(hit) = self.bus.hit.take() {
+            return Some(hit);
+        }
+        let next = MemoryAccess {
+            access: Access::Execute,
+            address: self.pc,
+            value: self.ram[self.pc],
+        };
+        self.bus.watches.iter().any(|watch| watch.matches(&next)).then_some(Stop::Access(next))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::emulator::{Stop, Target};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // 0000: LXI SP,0200H / 0003: LXI H,0100H / 0006: MOV A,M / 0007: INR M / 0008: PUSH H / 0009: HLT
+    const PROGRAM: [u8; 10] = [0x31, 0x00, 0x02, 0x21, 0x00, 0x01, 0x7e, 0x34, 0xe5, 0x76];
+
+    fn setup() -> Emulator {
+        let mut e = Emulator::new();
+        e.load_ram(PROGRAM.to_vec(), 0);
+        e
+    }
+
+    fn hit(access: Access, address: u16, value: u8) -> Stop {
+        Stop::Access(MemoryAccess { access, address, value })
+    }
+
+    #[test]
+    fn read_and_write_watches() {
+        let mut e = setup();
+        e.add_access_watch(AccessWatch::new(0x0100, &[Access::Read]));
+        assert_eq!(e.run(100), Ok(hit(Access::Read, 0x0100, 0)));
+        assert_eq!(e.get_pc(), 0x0007);
+        // INR M reads before it writes
+        assert_eq!(e.run(100), Ok(hit(Access::Read, 0x0100, 0)));
+        assert!(e.remove_access_watch(&AccessWatch::new(0x0100, &[Access::Read])));
+        assert!(e.get_access_watches().is_empty());
+
+        let mut e = setup();
+        e.add_access_watch(AccessWatch::new(0x0100, &[Access::Write]));
+        assert_eq!(e.run(100), Ok(hit(Access::Write, 0x0100, 1)));
+        assert_eq!(e.get_pc(), 0x0008);
+        assert_eq!(e.run(100), Ok(Stop::Halted));
+    }
+
+    #[test]
+    fn ranges_and_values() {
+        // The stack overwrites 01FE-01FF
+        let mut e = setup();
+        e.add_access_watch(AccessWatch::new(0x01f0, &[Access::Write]).with_end(0x01ff));
+        assert_eq!(e.run(100), Ok(hit(Access::Write, 0x01ff, 0x01)));
+        assert_eq!(e.get_pc(), 0x0009);
+
+        let mut e = setup();
+        e.add_access_watch(AccessWatch::new(0x01f0, &[Access::Write]).with_end(0x01ff).with_value(0x00));
+        assert_eq!(e.run(100), Ok(hit(Access::Write, 0x01fe, 0x00)));
+    }
+
+    #[test]
+    fn change_watches() {
+        // PUSH H writes 01 to 01FF and the unchanged 00 to 01FE
+        let mut e = setup();
+        e.add_access_watch(AccessWatch::new(0x01fe, &[Access::Write]).with_end(0x01ff).with_changes());
+        assert_eq!(e.run(100), Ok(Stop::Watchpoint { address: 0x01ff, old: 0, new: 1 }));
+
+        let mut e = setup();
+        e.add_access_watch(AccessWatch::new(0x01fe, &[Access::Write]).with_changes());
+        assert_eq!(e.run(100), Ok(Stop::Halted));
+    }
+
+    #[test]
+    fn execute_watches() {
+        let mut e = setup();
+        e.add_access_watch(AccessWatch::new(0x0006, &[Access::Execute]).with_end(0x0009).with_value(0x34));
+        assert_eq!(e.run(100), Ok(hit(Access::Execute, 0x0007, 0x34)));
+        assert_eq!(e.get_pc(), 0x0007);
+        assert_eq!(e.run(100), Ok(Stop::Halted));
+    }
+
+    #[test]
+    fn hooks() {
+        let mut e = setup();
+        let accesses = Rc::new(RefCell::new(Vec::new()));
+        let log = Rc::clone(&accesses);
+        e.add_memory_hook(Box::new(move |access| {
+            log.borrow_mut().push(*access);
+            access.access == Access::Write
+        }));
+        assert_eq!(e.run(100), Ok(hit(Access::Write, 0x0100, 1)));
+        let accesses = accesses.borrow();
+        assert_eq!(accesses.len(), 11);
+        assert_eq!(accesses[0], MemoryAccess { access: Access::Execute, address: 0, value: 0x31 });
+        assert_eq!(accesses[7], MemoryAccess { access: Access::Read, address: 0x0100, value: 0 });
+        e.clear_memory_hooks();
+        assert_eq!(e.run(100), Ok(Stop::Halted));
+    }
+}
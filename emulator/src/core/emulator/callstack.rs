@@ -0,0 +1,258 @@
+use super::Emulator;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CallKind {
+    Call,
+    Restart,
+    Interrupt,
+}
+
+/*
+ * A CALL, RST or interrupt that has not returned yet, sp is the
+ * stack pointer right after the return address was pushed
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub kind: CallKind,
+    // Address of the call instruction, the interrupted PC for interrupts
+    pub call_site: u16,
+    pub target: u16,
+    pub return_address: u16,
+    pub sp: u16,
+}
+
+/*
+ * A RET at address that popped found where the matching call left expected
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReturnMismatch {
+    pub address: u16,
+    pub expected: u16,
+    pub found: u16,
+    pub sp: u16,
+}
+
+/*
+ * Shadow copy of the call chain, kept next to the real stack
+ *
+ * Frames are matched to returns by their stack pointer, frames
+ * the program abandoned (e.g. by reloading SP) are dropped as soon
+ * as a call or return happens above them. A RET below the innermost
+ * frame (PUSH H / RET as an indirect jump) is not a return.
+ */
+pub(super) struct CallStack {
+    frames: Vec<Frame>,
+    interrupt: bool,
+    stop_on_mismatch: bool,
+    last_mismatch: Option<ReturnMismatch>,
+    pending: Option<ReturnMismatch>,
+}
+
+impl CallStack {
+    pub(super) fn new() -> Self {
+        CallStack {
+            frames: Vec::new(),
+            interrupt: false,
+            stop_on_mismatch: false,
+            last_mismatch: None,
+            pending: None,
+        }
+    }
+
+    pub(super) fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub(super) fn clear_pending(&mut self) {
+        self.pending = None;
+    }
+
+    /*
+     * Calls made until end_interrupt() are interrupt entries
+     */
+    pub(super) fn begin_interrupt(&mut self) {
+        self.interrupt = true;
+    }
+
+    pub(super) fn end_interrupt(&mut self) {
+        self.interrupt = false;
+    }
+
+    fn drop_abandoned(&mut self, sp: u16) {
+        while self.frames.last().is_some_and(|frame| frame.sp <= sp) {
+            self.frames.pop();
+        }
+    }
+}
+
+impl Emulator {
+    /*
+     * Record a call of target whose instruction is length bytes long,
+     * after the return address has been pushed
+     */
+    pub(super) fn enter_call(&mut self, target: u16, length: u16) {
+        let return_address = self.pc;
+        let (kind, call_site) = match (self.calls.interrupt, length) {
+            (true, _) => (CallKind::Interrupt, return_address),
+            (false, 1) => (CallKind::Restart, return_address.wrapping_sub(1)),
+            (false, _) => (CallKind::Call, return_address.wrapping_sub(length)),
+        };
+        let calls = &mut self.calls;
+        calls.drop_abandoned(self.sp);
+        calls.frames.push(Frame {
+            kind,
+            call_site,
+            target,
+            return_address,
+            sp: self.sp,
+        });
+    }
+
+    /*
+     * Match a RET at address that found the stack pointer at sp
+     */
+    pub(super) fn leave_call(&mut self, address: u16, sp: u16) {
+        let calls = &mut self.calls;
+        calls.drop_abandoned(sp.wrapping_sub(1));
+        if calls.frames.last().is_none_or(|frame| frame.sp != sp) {
+            return;
+        }
+        let frame = calls.frames.pop().unwrap();
+        if frame.return_address != self.pc {
+            let mismatch = ReturnMismatch {
+                address,
+                expected: frame.return_address,
+                found: self.pc,
+                sp,
+            };
+            calls.last_mismatch = Some(mismatch);
+            if calls.stop_on_mismatch {
+                calls.pending = Some(mismatch);
+            }
+        }
+    }
+
+    /*
+     * Open calls, outermost first
+     */
+    pub fn get_call_stack(&self) -> &[Frame] {
+        &self.calls.frames
+    }
+
+    pub fn get_last_mismatch(&self) -> Option<ReturnMismatch> {
+        self.calls.last_mismatch
+    }
+
+    /*
+     * Whether runs stop after a RET that does not return to its call,
+     * off by default since passing arguments inline after a CALL
+     * adjusts the return address on purpose
+     */
+    pub fn set_stop_on_mismatch(&mut self, stop: bool) {
+        self.calls.stop_on_mismatch = stop;
+    }
+
+    pub(super) fn take_mismatch(&mut self) -> Option<ReturnMismatch> {
+        self.calls.pending.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::emulator::{Stop, Target};
+
+    fn setup(program: &[u8]) -> Emulator {
+        let mut e = Emulator::new();
+        e.load_ram(program.to_vec(), 0);
+        e
+    }
+
+    #[test]
+    fn nested_calls() {
+        // 0000: LXI SP,0100H / 0003: CALL 0008H / 0006: HLT / 0007: NOP
+        // 0008: RST 2 / 0009: RET / 0010: HLT
+        let mut e = setup(&[0x31, 0x00, 0x01, 0xcd, 0x08, 0x00, 0x76, 0x00, 0xd7, 0xc9]);
+        e.get_ram_mut()[0x0010] = 0x76;
+        e.run(3).expect("Fuck");
+        let frames = e.get_call_stack();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(
+            frames[0],
+            Frame {
+                kind: CallKind::Call,
+                call_site: 0x0003,
+                target: 0x0008,
+                return_address: 0x0006,
+                sp: 0x00fe
+            }
+        );
+        assert_eq!(
+            (frames[1].kind, frames[1].call_site, frames[1].target),
+            (CallKind::Restart, 0x0008, 0x0010)
+        );
+
+        e.set_pc(0x0009);
+        e.run(1).expect("Fuck");
+        assert_eq!(e.get_call_stack().len(), 1);
+        assert_eq!(e.get_last_mismatch(), None);
+    }
+
+    #[test]
+    fn mismatched_return() {
+        // 0000: LXI SP,0100H / 0003: CALL 000AH / 0006: HLT / 0007: HLT / 0008-0009: NOP
+        // 000A: LXI H,0007H / 000D: XTHL / 000E: RET
+        let program = [
+            0x31, 0x00, 0x01, 0xcd, 0x0a, 0x00, 0x76, 0x76, 0, 0, 0x21, 0x07, 0x00, 0xe3, 0xc9,
+        ];
+        let mut e = setup(&program);
+        e.set_stop_on_mismatch(true);
+        let mismatch = ReturnMismatch {
+            address: 0x000e,
+            expected: 0x0006,
+            found: 0x0007,
+            sp: 0x00fe,
+        };
+        assert_eq!(e.run(100), Ok(Stop::ReturnMismatch(mismatch)));
+        assert!(e.get_call_stack().is_empty());
+
+        let mut e = setup(&program);
+        assert_eq!(e.run(100), Ok(Stop::Halted));
+        assert_eq!(e.get_last_mismatch(), Some(mismatch));
+    }
+
+    #[test]
+    fn indirect_jumps_and_abandoned_frames() {
+        // 0000: LXI SP,0100H / 0003: CALL 0007H / 0006: HLT
+        // 0007: LXI H,0006H / 000A: PUSH H / 000B: RET / (0006: HLT)
+        let program = [0x31, 0x00, 0x01, 0xcd, 0x07, 0x00, 0x76, 0x21, 0x06, 0x00, 0xe5, 0xc9];
+        let mut e = setup(&program);
+        e.set_stop_on_mismatch(true);
+        assert_eq!(e.run(5), Ok(Stop::Limit));
+        assert_eq!(e.get_pc(), 0x0006);
+        assert_eq!(e.get_call_stack().len(), 1);
+        assert_eq!(e.get_last_mismatch(), None);
+
+        // Reloading SP and calling again forgets the old frame
+        e.set_sp(0x0100);
+        e.set_pc(0x0003);
+        e.run(1).expect("Fuck");
+        assert_eq!(e.get_call_stack().len(), 1);
+    }
+
+    #[test]
+    fn interrupts() {
+        let mut e = setup(&[0x31, 0x00, 0x01, 0x00, 0x00]);
+        e.get_ram_mut()[0x0038] = 0xc9;
+        e.run(2).expect("Fuck");
+        e.interrupt(0xff).expect("Fuck");
+        let frame = e.get_call_stack()[0];
+        assert_eq!(
+            (frame.kind, frame.call_site, frame.target),
+            (CallKind::Interrupt, 0x0004, 0x0038)
+        );
+        e.run(1).expect("Fuck");
+        assert!(e.get_call_stack().is_empty());
+        assert_eq!(e.get_pc(), 0x0004);
+    }
+}
@@ -0,0 +1,359 @@
+use super::{Access, AccessWatch, Cpu, EResult, Emulator, MemoryAccess, ReturnMismatch};
+use crate::kreator::parser::{Environment, Expression};
+
+/*
+ * Why a run stopped
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stop {
+    Halted,
+    Breakpoint(u16),
+    // The byte at address changed from old to new
+    Watchpoint { address: u16, old: u8, new: u8 },
+    // An access watch or memory hook triggered
+    Access(MemoryAccess),
+    // A RET did not return to its call, see Emulator::set_stop_on_mismatch
+    ReturnMismatch(ReturnMismatch),
+    // max_instructions have been executed
+    Limit,
+}
+
+/*
+ * A breakpoint stops when its condition holds (or it has none) once
+ * the ignore count is used up, a logpoint adds its message to the
+ * log instead of stopping
+ */
+#[derive(Debug, Default)]
+pub struct Breakpoint {
+    condition: Option<(String, Expression)>,
+    ignore: u32,
+    hits: u32,
+    message: Option<String>,
+}
+
+impl Breakpoint {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /*
+     * Expression in assembler syntax on registers, flags and memory,
+     * e.g. "A == 0FFH AND [HL] > 10", nonzero means true
+     */
+    pub fn with_condition(mut self, condition: &str) -> Result<Self, &'static str> {
+        self.condition = Some((condition.to_string(), Expression::parse(condition)?));
+        Ok(self)
+    }
+
+    pub fn with_ignore_count(mut self, count: u32) -> Self {
+        self.ignore = count;
+        self
+    }
+
+    /*
+     * Message of a logpoint, {EXPRESSION} is replaced by its value in hex
+     */
+    pub fn with_message(mut self, message: &str) -> Self {
+        self.message = Some(message.to_string());
+        self
+    }
+
+    pub fn get_condition(&self) -> Option<&str> {
+        self.condition.as_ref().map(|(text, _)| text.as_str())
+    }
+
+    pub fn get_ignore_count(&self) -> u32 {
+        self.ignore
+    }
+
+    /*
+     * Ignore the next count hits
+     */
+    pub fn set_ignore_count(&mut self, count: u32) {
+        self.ignore = count;
+        self.hits = 0;
+    }
+
+    /*
+     * How often the breakpoint was reached with its condition holding
+     */
+    pub fn get_hits(&self) -> u32 {
+        self.hits
+    }
+
+    pub fn get_message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+}
+
+/*
+ * Anything a debugger can drive: the bare emulator or a machine
+ * that handles some calls on the host (e.g. the CP/M BDOS)
+ */
+pub trait Target {
+    fn emulator(&self) -> &Emulator;
+
+    fn emulator_mut(&mut self) -> &mut Emulator;
+
+    fn step(&mut self) -> EResult<()> {
+        self.emulator_mut().step()
+    }
+
+    /*
+     * Console of the machine, if it has one
+     */
+    fn send_input(&mut self, _bytes: &[u8]) {}
+
+    fn take_output(&mut self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /*
+     * Step until a breakpoint or watchpoint triggers, the CPU halts
+     * or max_instructions have been executed. A breakpoint at the
+     * current PC does not keep the first instruction from running.
+     */
+    fn run(&mut self, max_instructions: usize) -> EResult<Stop> {
+        for _ in 0..max_instructions {
+            self.step()?;
+            if let Some(stop) = self.emulator_mut().check_stop() {
+                return Ok(stop);
+            }
+        }
+        Ok(Stop::Limit)
+    }
+}
+
+impl Target for Emulator {
+    fn emulator(&self) -> &Emulator {
+        self
+    }
+
+    fn emulator_mut(&mut self) -> &mut Emulator {
+        self
+    }
+}
+
+impl Emulator {
+    /*
+     * Plain breakpoint, an existing one at the address is kept
+     */
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.entry(address).or_default();
+    }
+
+    pub fn set_breakpoint(&mut self, address: u16, breakpoint: Breakpoint) {
+        self.breakpoints.insert(address, breakpoint);
+    }
+
+    pub fn get_breakpoint(&self, address: u16) -> Option<&Breakpoint> {
+        self.breakpoints.get(&address)
+    }
+
+    pub fn get_breakpoint_mut(&mut self, address: u16) -> Option<&mut Breakpoint> {
+        self.breakpoints.get_mut(&address)
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address).is_some()
+    }
+
+    pub fn get_breakpoints(&self) -> Vec<u16> {
+        self.breakpoints.keys().copied().collect()
+    }
+
+    /*
+     * Watchpoints trigger when a write changes the byte at the address
+     */
+    pub fn add_watchpoint(&mut self, address: u16) {
+        let watch = AccessWatch::new(address, &[Access::Write]).with_changes();
+        if !self.get_access_watches().contains(&watch) {
+            self.add_access_watch(watch);
+        }
+    }
+
+    /*
+     * Whether a run should stop after the instruction just executed
+     */
+    pub fn check_stop(&mut self) -> Option<Stop> {
+        if let Some(stop) = self.take_access_hit() {
+            return Some(stop);
+        }
+        if let Some(mismatch) = self.take_mismatch() {
+            return Some(Stop::ReturnMismatch(mismatch));
+        }
+        if self.hit_breakpoint() {
+            return Some(Stop::Breakpoint(self.pc));
+        }
+        if !self.running {
+            return Some(Stop::Halted);
+        }
+        None
+    }
+
+    /*
+     * Whether the breakpoint at PC stops, logpoints never do
+     * A condition that cannot be evaluated counts as true
+     */
+    fn hit_breakpoint(&mut self) -> bool {
+        let breakpoint = match self.breakpoints.get(&self.pc) {
+            Some(breakpoint) => breakpoint,
+            None => return false,
+        };
+        if let Some((_, condition)) = &breakpoint.condition {
+            if condition.evaluate(self) == Ok(0) {
+                return false;
+            }
+        }
+        let breakpoint = self.breakpoints.get_mut(&self.pc).unwrap();
+        breakpoint.hits += 1;
+        if breakpoint.hits <= breakpoint.ignore {
+            return false;
+        }
+        match breakpoint.message.clone() {
+            Some(message) => {
+                let text = self.format_message(&message);
+                self.log.push(text);
+                false
+            }
+            None => true,
+        }
+    }
+
+    fn format_message(&self, message: &str) -> String {
+        let mut text = String::new();
+        let mut rest = message;
+        while let Some(start) = rest.find('{') {
+            let end = match rest[start..].find('}') {
+                Some(end) => start + end,
+                None => break,
+            };
+            text.push_str(&rest[..start]);
+            match Expression::parse(&rest[start + 1..end]).and_then(|expression| expression.evaluate(self)) {
+                Ok(value) if value & 0xffff > 0xff => text.push_str(&format!("{:04X}", value & 0xffff)),
+                Ok(value) => text.push_str(&format!("{:02X}", value & 0xff)),
+                Err(_) => text.push('?'),
+            }
+            rest = &rest[end + 1..];
+        }
+        text.push_str(rest);
+        text
+    }
+
+    /*
+     * Messages of the logpoints hit since the last call
+     */
+    pub fn take_log(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.log)
+    }
+}
+
+/*
+ * Registers, flags (0FFFFH when set) and memory for conditions
+ */
+impl Environment for Emulator {
+    fn value(&self, name: &str) -> Option<u16> {
+        let flag = |name| if self.reg.get_flag(name) { 0xffff } else { 0 };
+        let flags = match self.cpu {
+            Cpu::I8080 => self.reg.get_flags(),
+            Cpu::I8085 => self.reg.get_flags_8085(),
+        };
+        Some(match name {
+            "A" | "B" | "C" | "D" | "E" | "H" | "L" => self.reg[name.to_ascii_lowercase().chars().next()?] as u16,
+            "BC" | "DE" | "HL" => self.reg[name.to_ascii_lowercase().as_str()],
+            "M" => self.ram[self.reg["hl"]] as u16,
+            "PSW" => ((self.reg['a'] as u16) << 8) | flags as u16,
+            "SP" => self.sp,
+            "PC" => self.pc,
+            "S" => flag("sign"),
+            "Z" => flag("zero"),
+            "AC" => flag("aux"),
+            "P" => flag("parity"),
+            "CY" => flag("carry"),
+            _ => return None,
+        })
+    }
+
+    fn memory(&self, address: u16) -> Result<u8, &'static str> {
+        Ok(self.ram[address])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 0000: LXI H,0100H / 0003: INR M / 0004: JMP 0003H / 0007: HLT
+    const COUNTER: [u8; 8] = [0x21, 0x00, 0x01, 0x34, 0xc3, 0x03, 0x00, 0x76];
+
+    #[test]
+    fn breakpoints() {
+        let mut e = Emulator::new();
+        e.load_ram(COUNTER.to_vec(), 0);
+        e.add_breakpoint(0x0003);
+        assert_eq!(e.run(100), Ok(Stop::Breakpoint(0x0003)));
+        assert_eq!(e.run(100), Ok(Stop::Breakpoint(0x0003)));
+        assert_eq!(e.get_ram()[0x0100], 1);
+
+        assert!(e.remove_breakpoint(0x0003));
+        assert!(!e.remove_breakpoint(0x0003));
+        assert_eq!(e.run(10), Ok(Stop::Limit));
+        assert!(e.get_breakpoints().is_empty());
+    }
+
+    #[test]
+    fn watchpoints() {
+        let mut e = Emulator::new();
+        e.load_ram(COUNTER.to_vec(), 0);
+        e.add_watchpoint(0x0100);
+        assert_eq!(e.run(100), Ok(Stop::Watchpoint { address: 0x0100, old: 0, new: 1 }));
+        assert_eq!(e.get_pc(), 0x0004);
+
+        // Edits by hand are no writes by the CPU
+        e.get_ram_mut()[0x0100] = 0x41;
+        assert_eq!(e.run(100), Ok(Stop::Watchpoint { address: 0x0100, old: 0x41, new: 0x42 }));
+        e.add_watchpoint(0x0100);
+        assert_eq!(e.get_access_watches().len(), 1);
+    }
+
+    #[test]
+    fn conditions_and_hit_counts() {
+        let mut e = Emulator::new();
+        e.load_ram(COUNTER.to_vec(), 0);
+        let breakpoint = Breakpoint::new().with_condition("[HL] >= 3 AND NOT Z").expect("Fuck");
+        e.set_breakpoint(0x0004, breakpoint.with_ignore_count(1));
+        assert_eq!(e.run(100), Ok(Stop::Breakpoint(0x0004)));
+        assert_eq!(e.get_ram()[0x0100], 4);
+        assert_eq!(e.get_breakpoint(0x0004).map(Breakpoint::get_hits), Some(2));
+
+        e.get_breakpoint_mut(0x0004).unwrap().set_ignore_count(2);
+        assert_eq!(e.run(100), Ok(Stop::Breakpoint(0x0004)));
+        assert_eq!(e.get_ram()[0x0100], 7);
+        e.add_breakpoint(0x0004);
+        assert_eq!(e.get_breakpoint(0x0004).and_then(Breakpoint::get_condition), Some("[HL] >= 3 AND NOT Z"));
+        assert!(Breakpoint::new().with_condition("A ==").is_err());
+    }
+
+    #[test]
+    fn logpoints() {
+        let mut e = Emulator::new();
+        e.load_ram(COUNTER.to_vec(), 0);
+        e.set_breakpoint(0x0003, Breakpoint::new().with_message("count {[HL]} at {HL}, {nonsense"));
+        e.set_breakpoint(0x0004, Breakpoint::new().with_condition("M == 2").expect("Fuck"));
+        assert_eq!(e.run(100), Ok(Stop::Breakpoint(0x0004)));
+        assert_eq!(e.take_log(), vec!["count 00 at 0100, {nonsense", "count 01 at 0100, {nonsense"]);
+        assert!(e.take_log().is_empty());
+        e.set_breakpoint(0x0003, Breakpoint::new().with_message("{FOO}"));
+        e.run(1).expect("Fuck");
+        assert_eq!(e.take_log(), vec!["?"]);
+    }
+
+    #[test]
+    fn halt() {
+        let mut e = Emulator::new();
+        e.load_ram(COUNTER.to_vec(), 0);
+        e.set_pc(0x0007);
+        assert_eq!(e.run(100), Ok(Stop::Halted));
+        assert_eq!(e.run(100), Ok(Stop::Halted));
+    }
+}
@@ -1,12 +1,12 @@
 use std::{cell::RefCell, rc::Rc};
 
-use super::{EResult, Emulator, InputDevice, OutputDevice};
+use super::{ClockedDevice, EResult, Emulator, InputDevice, OutputDevice};
 
 impl Emulator {
 
     pub fn input(&mut self, port: u8) -> EResult<()> {
         match &self.input_devices[port as usize] {
-            Some(device) => self.reg['a'] = device.borrow().read(),
+            Some(device) => self.reg['a'] = device.borrow_mut().read(port),
             None => return Err("No device registered at this port")
         }
         Ok(())
@@ -14,7 +14,11 @@ impl Emulator {
 
     pub fn output(&mut self, port: u8) -> EResult<()> {
         match &self.output_devices[port as usize] {
-            Some(device) => device.borrow_mut().write(self.reg['a']),
+            Some(device) => {
+                let mut device = device.borrow_mut();
+                device.write(port, self.reg['a']);
+                device.transfer(self.ram.as_mut());
+            }
             None => return Err("No device registered at this port")
         }
         Ok(())
@@ -29,6 +33,13 @@ impl Emulator {
         self.output_devices[port] = Some(device);
         Ok(())
     }
+
+    /*
+     * Clocked devices are ticked with the cycles of every executed instruction
+     */
+    pub fn register_clocked_device(&mut self, device: Rc<RefCell<dyn ClockedDevice>>) {
+        self.clocked_devices.push(device);
+    }
 }
 
 #[cfg(test)]
@@ -52,13 +63,13 @@ mod tests {
     }
 
     impl InputDevice for Logger {
-        fn read(&self) -> u8 {
+        fn read(&mut self, _port: u8) -> u8 {
             42
         }
     }
 
     impl OutputDevice for Logger {
-        fn write(&mut self, byte: u8) {
+        fn write(&mut self, _port: u8, byte: u8) {
             self.last = byte;
         }
     }
@@ -0,0 +1,393 @@
+use super::{EResult, Emulator};
+
+const MASK_55: u8 = 0x01;
+const MASK_65: u8 = 0x02;
+const MASK_75: u8 = 0x04;
+
+/*
+ * Input pins of the 8085. TRAP and RST 7.5 are edge triggered and
+ * latched until serviced, RST 6.5 and RST 5.5 are level triggered.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pin {
+    Trap,
+    Rst75,
+    Rst65,
+    Rst55,
+    Sid,
+}
+
+pub struct Pins {
+    trap: bool,
+    trap_level: bool,
+    rst75: bool,
+    rst75_level: bool,
+    rst65: bool,
+    rst55: bool,
+    masks: u8,
+    sid: bool,
+    sod: bool,
+}
+
+impl Pins {
+    pub fn new() -> Self {
+        Pins {
+            trap: false,
+            trap_level: false,
+            rst75: false,
+            rst75_level: false,
+            rst65: false,
+            rst55: false,
+            masks: MASK_55 | MASK_65 | MASK_75,
+            sid: false,
+            sod: false,
+        }
+    }
+}
+
+impl Emulator {
+    pub fn set_pin(&mut self, pin: Pin, level: bool) {
+        let pins = &mut self.pins;
+        match pin {
+            Pin::Trap => {
+                pins.trap |= level && !pins.trap_level;
+                pins.trap_level = level;
+            }
+            Pin::Rst75 => {
+                pins.rst75 |= level && !pins.rst75_level;
+                pins.rst75_level = level;
+            }
+            Pin::Rst65 => pins.rst65 = level,
+            Pin::Rst55 => pins.rst55 = level,
+            Pin::Sid => pins.sid = level,
+        }
+    }
+
+    /*
+     * Serial output, written by SIM
+     */
+    pub fn get_sod(&self) -> bool {
+        self.pins.sod
+    }
+
+    /*
+     * Instructions the 8085 executes instead of the 8080's aliases,
+     * None for every opcode both CPUs share
+     */
+    pub(super) fn execute_8085(&mut self, opcode: u8) -> Option<EResult<()>> {
+        let result = match opcode {
+            0x08 => self.dsub(),
+            0x10 => self.arhl(),
+            0x18 => self.rdel(),
+            0x20 => self.rim(),
+            0x28 => self.ldhi(),
+            0x30 => self.sim(),
+            0x38 => self.ldsi(),
+            0xcb => self.rstv(),
+            0xd9 => self.shlx(),
+            0xdd => self.jmp_not("k"),
+            0xed => self.lhlx(),
+            0xfd => self.jmp_if("k"),
+            _ => return None,
+        };
+        Some(result)
+    }
+
+    /*
+     * Vector to the highest priority interrupt input:
+     * TRAP, RST 7.5, RST 6.5, RST 5.5 and INTR last, only TRAP
+     * gets in while interrupts are not accepted
+     */
+    pub(super) fn service_8085(&mut self, accepting: bool) -> EResult<bool> {
+        let pins = &mut self.pins;
+        let vector = if pins.trap {
+            pins.trap = false;
+            0x24
+        } else if !accepting {
+            return Ok(false);
+        } else if pins.rst75 && pins.masks & MASK_75 == 0 {
+            pins.rst75 = false;
+            0x3c
+        } else if pins.rst65 && pins.masks & MASK_65 == 0 {
+            0x34
+        } else if pins.rst55 && pins.masks & MASK_55 == 0 {
+            0x2c
+        } else {
+            return Ok(false);
+        };
+        self.interrupts_enabled = false;
+        self.running = true;
+        self.cycles += 12;
+        self.calls.begin_interrupt();
+        let result = self.call(vector);
+        self.calls.end_interrupt();
+        result.map(|_| true)
+    }
+
+    /*
+     * Accumulator after RIM:
+     * SID I7.5 I6.5 I5.5 IE M7.5 M6.5 M5.5
+     */
+    fn rim(&mut self) -> EResult<()> {
+        let pins = &self.pins;
+        self.reg['a'] = (pins.sid as u8) << 7
+            | (pins.rst75 as u8) << 6
+            | (pins.rst65 as u8) << 5
+            | (pins.rst55 as u8) << 4
+            | (self.interrupts_enabled as u8) << 3
+            | pins.masks;
+        Ok(())
+    }
+
+    /*
+     * Accumulator for SIM:
+     * SOD SDE - R7.5 MSE M7.5 M6.5 M5.5
+     */
+    fn sim(&mut self) -> EResult<()> {
+        let value = self.reg['a'];
+        let pins = &mut self.pins;
+        if value & 0x08 != 0 {
+            pins.masks = value & (MASK_55 | MASK_65 | MASK_75);
+        }
+        if value & 0x10 != 0 {
+            pins.rst75 = false;
+        }
+        if value & 0x40 != 0 {
+            pins.sod = value & 0x80 != 0;
+        }
+        Ok(())
+    }
+
+    /*
+     * HL = HL - BC, flags are those of the high byte except zero
+     */
+    fn dsub(&mut self) -> EResult<()> {
+        let hl = self.reg["hl"] as u32;
+        let complement = !self.reg["bc"] as u32;
+        let result = hl + complement + 1;
+        let high = (result >> 8) as u8;
+        let overflow = (hl ^ result) & (complement ^ result) & 0x8000 != 0;
+        self.reg.set_flag("zero", result & 0xffff == 0);
+        self.reg.set_flag("sign", result & 0x8000 != 0);
+        self.reg.set_flag("carry", result <= 0xffff);
+        self.reg.set_flag("parity", high.count_ones() & 1 == 0);
+        self.reg.set_flag("aux", (hl & 0x0fff) + (complement & 0x0fff) + 1 > 0x0fff);
+        self.reg.set_flag("overflow", overflow);
+        self.reg.set_flag("k", overflow != self.reg.get_flag("sign"));
+        self.reg["hl"] = result as u16;
+        Ok(())
+    }
+
+    /*
+     * Arithmetic shift right of HL into the carry
+     */
+    fn arhl(&mut self) -> EResult<()> {
+        let hl = self.reg["hl"];
+        self.reg.set_flag("carry", hl & 1 != 0);
+        self.reg["hl"] = ((hl as i16) >> 1) as u16;
+        Ok(())
+    }
+
+    /*
+     * Rotate DE left through the carry
+     */
+    fn rdel(&mut self) -> EResult<()> {
+        let de = self.reg["de"];
+        let result = (de << 1) | self.reg.get_flag("carry") as u16;
+        self.reg.set_flag("carry", de & 0x8000 != 0);
+        self.reg.set_flag("overflow", (de ^ result) & 0x8000 != 0);
+        self.reg["de"] = result;
+        Ok(())
+    }
+
+    fn ldhi(&mut self) -> EResult<()> {
+        let offset = self.read_byte()? as u16;
+        self.reg["de"] = self.reg["hl"].wrapping_add(offset);
+        Ok(())
+    }
+
+    fn ldsi(&mut self) -> EResult<()> {
+        let offset = self.read_byte()? as u16;
+        self.reg["de"] = self.sp.wrapping_add(offset);
+        Ok(())
+    }
+
+    fn rstv(&mut self) -> EResult<()> {
+        if self.reg.get_flag("overflow") {
+            self.cycles += self.branch_cycles as u64;
+            self.call(0x40)?;
+        }
+        Ok(())
+    }
+
+    fn shlx(&mut self) -> EResult<()> {
+        let address = self.reg["de"];
+        self.write_memory(address, self.reg['l']);
+        self.write_memory(address.wrapping_add(1), self.reg['h']);
+        Ok(())
+    }
+
+    fn lhlx(&mut self) -> EResult<()> {
+        let address = self.reg["de"];
+        self.reg['l'] = self.read_memory(address);
+        self.reg['h'] = self.read_memory(address.wrapping_add(1));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Cpu;
+    use super::*;
+
+    fn emulator(code: Vec<u8>) -> Emulator {
+        let mut e = Emulator::new().with_cpu(Cpu::I8085);
+        e.load_ram(code, 0);
+        e.sp = 0x1000;
+        e
+    }
+
+    #[test]
+    fn undocumented_instructions() {
+        // DSUB, ARHL, RDEL, LDHI 5, LDSI 2, SHLX, LHLX
+        let mut e = emulator(vec![0x08, 0x10, 0x18, 0x28, 5, 0x38, 2, 0xd9, 0xed]);
+        e.reg["hl"] = 0x1234;
+        e.reg["bc"] = 0x0235;
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.reg["hl"], 0x0fff);
+        assert!(!e.reg.get_flag("carry"));
+        assert!(!e.reg.get_flag("zero"));
+
+        e.reg["hl"] = 0x8003;
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.reg["hl"], 0xc001);
+        assert!(e.reg.get_flag("carry"));
+
+        e.reg["de"] = 0x4001;
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.reg["de"], 0x8003);
+        assert!(!e.reg.get_flag("carry"));
+        assert!(e.reg.get_flag("overflow"));
+
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.reg["de"], 0xc006);
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.reg["de"], 0x1002);
+
+        e.execute_next().expect("Fuck");
+        assert_eq!((e.ram[0x1002], e.ram[0x1003]), (0x01, 0xc0));
+        e.reg["hl"] = 0;
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.reg["hl"], 0xc001);
+        assert_eq!(e.get_cycles(), 10 + 7 + 10 + 10 + 10 + 10 + 10);
+    }
+
+    #[test]
+    fn k_and_overflow() {
+        // CPI 10H, JNK 0, JK 8, DCX B, RSTV
+        let mut e = emulator(vec![0xfe, 0x10, 0xdd, 0x00, 0x00, 0xfd, 0x08, 0x00, 0x0b, 0xcb]);
+        e.reg['a'] = 0xf0;
+        e.execute_next().expect("Fuck");
+        assert!(e.reg.get_flag("k"));
+        assert!(!e.reg.get_flag("overflow"));
+        assert_eq!(e.reg.get_flags_8085() & 0x20, 0x20);
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.pc, 0x05);
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.pc, 0x08);
+
+        e.reg["bc"] = 0;
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.reg["bc"], 0xffff);
+        assert!(e.reg.get_flag("k"));
+
+        // -128 - 1 overflows
+        e.reg['a'] = 0x80;
+        e.sub_value(1, false).expect("Fuck");
+        assert!(e.reg.get_flag("overflow"));
+        assert!(e.reg.get_flag("k"));
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.pc, 0x40);
+        assert_eq!(e.pop().expect("Fuck"), 0x0a);
+
+        // Only the 8085 pushes K and V
+        e.push_psw().expect("Fuck");
+        assert_eq!(e.pop().expect("Fuck") & 0x22, 0x22);
+        e.cpu = Cpu::I8080;
+        e.push_psw().expect("Fuck");
+        assert_eq!(e.pop().expect("Fuck") & 0x22, 0x02);
+    }
+
+    #[test]
+    fn rim_sim() {
+        // RIM, SIM, RIM
+        let mut e = emulator(vec![0x20, 0x30, 0x20]);
+        e.set_pin(Pin::Sid, true);
+        e.set_pin(Pin::Rst65, true);
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.reg['a'], 0xaf);
+
+        // Set SOD, unmask RST 6.5
+        e.reg['a'] = 0xcd;
+        e.execute_next().expect("Fuck");
+        assert!(e.get_sod());
+        e.interrupts_enabled = false;
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.reg['a'], 0xa5);
+    }
+
+    #[test]
+    fn interrupt_inputs() {
+        // HLT
+        let mut e = emulator(vec![0x76]);
+        e.pins.masks = 0;
+        e.set_pin(Pin::Rst55, true);
+        e.set_pin(Pin::Rst75, true);
+        e.set_pin(Pin::Rst75, false);
+        e.request_interrupt(0xff);
+        e.step().expect("Fuck");
+        assert_eq!(e.pc, 0x3c);
+        assert!(e.is_running());
+
+        // Only TRAP gets through with interrupts disabled
+        e.set_pin(Pin::Trap, true);
+        e.pc = 0x100;
+        e.step().expect("Fuck");
+        assert_eq!(e.pc, 0x24);
+        e.step().expect("Fuck");
+        assert_eq!(e.pc, 0x25);
+
+        e.interrupts_enabled = true;
+        e.step().expect("Fuck");
+        assert_eq!(e.pc, 0x2c);
+
+        // Masked by SIM, INTR comes last
+        e.pins.masks = MASK_55;
+        e.interrupts_enabled = true;
+        e.step().expect("Fuck");
+        assert_eq!(e.pc, 0x38);
+    }
+
+    #[test]
+    fn cycles() {
+        // MOV B,C, JNZ 0, NOP (0x20 is RIM on the 8085)
+        let code = vec![0x41, 0xc2, 0x00, 0x00, 0x20];
+        let mut e = emulator(code.clone());
+        e.reg.set_flag("zero", true);
+        e.execute_next().expect("Fuck");
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.get_cycles(), 4 + 7);
+        e.reg.set_flag("zero", false);
+        e.pc = 1;
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.get_cycles(), 4 + 7 + 10);
+
+        let mut e = Emulator::new();
+        e.load_ram(code, 0);
+        e.reg.set_flag("zero", true);
+        e.execute_next().expect("Fuck");
+        e.execute_next().expect("Fuck");
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.get_cycles(), 5 + 10 + 4);
+    }
+}
@@ -18,24 +18,20 @@ impl Emulator {
 
     fn add_memory(&mut self, use_carry: bool) -> EResult<()> {
         let address = self.reg["hl"];
-        let mut memory_value = self.ram[address] as u16;
-        if use_carry && self.reg.get_flag("carry") {
-            memory_value += 1;
-        }
-        self.add_value(memory_value)
+        let carry = use_carry && self.reg.get_flag("carry");
+        let value = self.read_memory(address);
+        self.add_value(value, carry)
     }
 
     fn add_register(&mut self, register: char, use_carry: bool) -> EResult<()> {
-        let mut register_value = self.reg[register] as u16;
-        if use_carry && self.reg.get_flag("carry") {
-            register_value += 1;
-        }
-        self.add_value(register_value)
+        let carry = use_carry && self.reg.get_flag("carry");
+        self.add_value(self.reg[register], carry)
     }
 
-    fn add_value(&mut self, value: u16) -> EResult<()> {
+    fn add_value(&mut self, value: u8, carry: bool) -> EResult<()> {
         let accumulator = self.reg['a'] as u16;
-        let result = accumulator + value;
+        let value = value as u16;
+        let result = accumulator + value + carry as u16;
         let result_byte = (result & 0xff) as u8;
         self.reg.set_flag("zero", (result & 0xff) == 0);
         self.reg.set_flag("sign", (result & 0x80) != 0);
@@ -43,7 +39,8 @@ impl Emulator {
         self.reg
             .set_flag("parity", result_byte.count_ones() & 1 == 0);
         self.reg
-            .set_flag("aux", ((accumulator & 0x0F) + (value & 0x0F)) > 0x0F);
+            .set_flag("aux", ((accumulator & 0x0F) + (value & 0x0F) + carry as u16) > 0x0F);
+        self.set_overflow_flags((accumulator ^ result) & (value ^ result) & 0x80 != 0);
         self.reg['a'] = result_byte;
         Ok(())
     }
@@ -63,35 +60,135 @@ impl Emulator {
 
     fn sub_memory(&mut self, use_carry: bool) -> EResult<()> {
         let address = self.reg["hl"];
-        let mut memory_value = self.ram[address] as u16;
-        if use_carry && self.reg.get_flag("carry") {
-            memory_value += 1;
-        }
-        self.sub_value(memory_value)
+        let borrow = use_carry && self.reg.get_flag("carry");
+        let value = self.read_memory(address);
+        self.sub_value(value, borrow)
     }
 
     fn sub_register(&mut self, register: char, use_carry: bool) -> EResult<()> {
-        let mut register_value = self.reg[register] as u16;
-        if use_carry && self.reg.get_flag("carry") {
-            register_value += 1;
-        }
-        self.sub_value(register_value)
+        let borrow = use_carry && self.reg.get_flag("carry");
+        self.sub_value(self.reg[register], borrow)
     }
 
-    pub fn sub_value(&mut self, value: u16) -> EResult<()> {
+    /*
+     * Subtraction is an addition of the complement with the carry
+     * inverted, which also yields the 8080's auxiliary carry
+     */
+    pub fn sub_value(&mut self, value: u8, borrow: bool) -> EResult<()> {
         let accumulator = self.reg['a'] as u16;
-        let result = accumulator + (!value & 0xFF) + 1;
+        let complement = !value as u16;
+        let result = accumulator + complement + !borrow as u16;
         let result_byte = (result & 0xff) as u8;
         self.reg.set_flag("zero", (result & 0xff) == 0);
         self.reg.set_flag("sign", (result & 0x80) != 0);
-        self.reg.set_flag("carry", !(result > 0xff));
+        self.reg.set_flag("carry", result <= 0xff);
         self.reg
             .set_flag("parity", result_byte.count_ones() & 1 == 0);
         self.reg
-            .set_flag("aux", ((accumulator & 0x0F) + (!value & 0x0F) + 1) > 0x0F);
+            .set_flag("aux", ((accumulator & 0x0F) + (complement & 0x0F) + !borrow as u16) > 0x0F);
+        self.set_overflow_flags((accumulator ^ result) & (complement ^ result) & 0x80 != 0);
         self.reg['a'] = result_byte;
         Ok(())
     }
+
+    /*
+     * Two's complement overflow and the 8085's K flag (sign XOR overflow),
+     * both are only visible on the 8085
+     */
+    fn set_overflow_flags(&mut self, overflow: bool) {
+        self.reg.set_flag("overflow", overflow);
+        self.reg.set_flag("k", overflow != self.reg.get_flag("sign"));
+    }
+
+    pub fn adi(&mut self, use_carry: bool) -> EResult<()> {
+        let value = self.read_byte()?;
+        let carry = use_carry && self.reg.get_flag("carry");
+        self.add_value(value, carry)
+    }
+
+    pub fn sui(&mut self, use_carry: bool) -> EResult<()> {
+        let value = self.read_byte()?;
+        let borrow = use_carry && self.reg.get_flag("carry");
+        self.sub_value(value, borrow)
+    }
+
+    pub fn inr(&mut self, opcode: u8) -> EResult<()> {
+        let register = REGISTERS[(opcode >> 3) as usize];
+        let result = self.read_operand(register).wrapping_add(1);
+        self.set_inc_dec_flags(result);
+        self.reg.set_flag("aux", (result & 0x0F) == 0);
+        self.set_overflow_flags(result == 0x80);
+        self.write_operand(register, result);
+        Ok(())
+    }
+
+    pub fn dcr(&mut self, opcode: u8) -> EResult<()> {
+        let register = REGISTERS[(opcode >> 3) as usize];
+        let result = self.read_operand(register).wrapping_sub(1);
+        self.set_inc_dec_flags(result);
+        self.reg.set_flag("aux", (result & 0x0F) != 0x0F);
+        self.set_overflow_flags(result == 0x7f);
+        self.write_operand(register, result);
+        Ok(())
+    }
+
+    fn set_inc_dec_flags(&mut self, result: u8) {
+        // INR and DCR leave the carry flag untouched
+        self.reg.set_flag("zero", result == 0);
+        self.reg.set_flag("sign", (result & 0x80) != 0);
+        self.reg.set_flag("parity", result.count_ones() & 1 == 0);
+    }
+
+    /*
+     * INX and DCX set the 8085's K flag when the pair wraps around
+     */
+    pub fn inx(&mut self, pair: &str) -> EResult<()> {
+        let result = if pair == "sp" {
+            self.sp = self.sp.wrapping_add(1);
+            self.sp
+        } else {
+            self.reg[pair] = self.reg[pair].wrapping_add(1);
+            self.reg[pair]
+        };
+        self.reg.set_flag("k", result == 0);
+        Ok(())
+    }
+
+    pub fn dcx(&mut self, pair: &str) -> EResult<()> {
+        let result = if pair == "sp" {
+            self.sp = self.sp.wrapping_sub(1);
+            self.sp
+        } else {
+            self.reg[pair] = self.reg[pair].wrapping_sub(1);
+            self.reg[pair]
+        };
+        self.reg.set_flag("k", result == 0xffff);
+        Ok(())
+    }
+
+    pub fn dad(&mut self, pair: &str) -> EResult<()> {
+        let value = if pair == "sp" { self.sp } else { self.reg[pair] };
+        let (result, carry) = self.reg["hl"].overflowing_add(value);
+        self.reg["hl"] = result;
+        self.reg.set_flag("carry", carry);
+        Ok(())
+    }
+
+    pub fn daa(&mut self) -> EResult<()> {
+        let accumulator = self.reg['a'];
+        let mut correction = 0;
+        let mut carry = self.reg.get_flag("carry");
+        if self.reg.get_flag("aux") || (accumulator & 0x0F) > 9 {
+            correction |= 0x06;
+        }
+        if carry || accumulator > 0x99 {
+            correction |= 0x60;
+            carry = true;
+        }
+        self.add_value(correction, false)?;
+        self.reg.set_flag("carry", carry);
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -274,4 +371,98 @@ mod tests {
 
         assert_eq!(e.reg['a'], 69 - 43);
     }
+
+    #[test]
+    fn immediates() {
+        let mut e = Emulator::new();
+
+        // ADI 10, ACI 10, SUI 5, SBI 5
+        e.ram.load_vec(vec![0xc6, 10, 0xce, 10, 0xd6, 5, 0xde, 5], 0);
+        e.reg['a'] = 42;
+        e.reg.set_flag("carry", true);
+
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.reg['a'], 52);
+        assert_eq!(e.reg.get_flag("carry"), false);
+
+        e.reg.set_flag("carry", true);
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.reg['a'], 63);
+
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.reg['a'], 58);
+
+        e.reg.set_flag("carry", true);
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.reg['a'], 52);
+    }
+
+    #[test]
+    fn inr_dcr() {
+        let mut e = Emulator::new();
+
+        // INR B, DCR B, INR M, DCR A
+        e.ram.load_vec(vec![0x04, 0x05, 0x34, 0x3d], 0);
+        e.reg['b'] = 0xff;
+        e.reg["hl"] = 0x100;
+        e.ram[0x100] = 0x0f;
+        e.reg.set_flag("carry", true);
+
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.reg['b'], 0);
+        assert_eq!(e.reg.get_flag("zero"), true, "Zero bit");
+        assert_eq!(e.reg.get_flag("carry"), true, "Carry bit");
+
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.reg['b'], 0xff);
+        assert_eq!(e.reg.get_flag("sign"), true, "Sign bit");
+
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.ram[0x100], 0x10);
+        assert_eq!(e.reg.get_flag("aux"), true, "Auxiliary Carry bit");
+
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.reg['a'], 0xff);
+    }
+
+    #[test]
+    fn inx_dcx_dad() {
+        let mut e = Emulator::new();
+
+        // INX B, DCX D, INX SP, DAD B, DAD SP
+        e.ram.load_vec(vec![0x03, 0x1b, 0x33, 0x09, 0x39], 0);
+        e.reg["bc"] = 0x00ff;
+        e.reg["hl"] = 0xff00;
+        e.sp = 0x1000;
+
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.reg["bc"], 0x0100);
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.reg["de"], 0xffff);
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.sp, 0x1001);
+
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.reg["hl"], 0x0000);
+        assert_eq!(e.reg.get_flag("carry"), true);
+
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.reg["hl"], 0x1001);
+        assert_eq!(e.reg.get_flag("carry"), false);
+    }
+
+    #[test]
+    fn daa() {
+        let mut e = Emulator::new();
+
+        // DAA with example from manual
+        e.ram.load_vec(vec![0x27], 0);
+        e.reg['a'] = 0x9B;
+
+        e.execute_next().expect("Fuck");
+
+        assert_eq!(e.reg['a'], 0x01);
+        assert_eq!(e.reg.get_flag("carry"), true, "Carry bit");
+        assert_eq!(e.reg.get_flag("aux"), true, "Auxiliary Carry bit");
+    }
 }
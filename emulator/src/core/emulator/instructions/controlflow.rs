@@ -2,39 +2,47 @@ use super::super::{EResult, Emulator};
 
 const REGISTERS: [char; 8] = ['b', 'c', 'd', 'e', 'h', 'l', 'm', 'a'];
 
+/*
+ * Taken branches add the difference between cycles_taken and cycles
+ * of the executed opcode, which is zero for the jumps of the 8080
+ */
 impl Emulator {
     pub fn jmp_not(&mut self, flag: &str) -> EResult<()> {
         if !self.reg.get_flag(flag) {
+            self.cycles += self.branch_cycles as u64;
             self.pc = self.read_addr()?;
         } else {
-            self.pc += 2;
+            self.pc = self.pc.wrapping_add(2);
         }
         Ok(())
     }
 
     pub fn jmp_if(&mut self, flag: &str) -> EResult<()> {
         if self.reg.get_flag(flag) {
+            self.cycles += self.branch_cycles as u64;
             self.pc = self.read_addr()?;
         } else {
-            self.pc += 2;
+            self.pc = self.pc.wrapping_add(2);
         }
         Ok(())
     }
 
     pub fn call_not(&mut self, flag: &str) -> EResult<()> {
         if !self.reg.get_flag(flag) {
+            self.cycles += self.branch_cycles as u64;
             self.call_imm()?;
         } else {
-            self.pc += 2;
+            self.pc = self.pc.wrapping_add(2);
         }
         Ok(())
     }
 
     pub fn call_if(&mut self, flag: &str) -> EResult<()> {
         if self.reg.get_flag(flag) {
+            self.cycles += self.branch_cycles as u64;
             self.call_imm()?;
         } else {
-            self.pc += 2;
+            self.pc = self.pc.wrapping_add(2);
         }
         Ok(())
     }
@@ -42,18 +50,24 @@ impl Emulator {
     pub fn call_imm(&mut self) -> EResult<()> {
         let adr = self.read_addr()?;
         self.push(self.pc)?;
+        self.enter_call(adr, 3);
         self.pc = adr;
         Ok(())
     }
 
+    /*
+     * RST and interrupt entry
+     */
     pub fn call(&mut self, adr: u16) -> EResult<()> {
         self.push(self.pc)?;
+        self.enter_call(adr, 1);
         self.pc = adr;
         Ok(())
     }
 
     pub fn ret_if(&mut self, flag: &str) -> EResult<()> {
         if self.reg.get_flag(flag) {
+            self.cycles += self.branch_cycles as u64;
             self.ret()?;
         }
         Ok(())
@@ -61,13 +75,16 @@ impl Emulator {
 
     pub fn ret_not(&mut self, flag: &str) -> EResult<()> {
         if !self.reg.get_flag(flag) {
+            self.cycles += self.branch_cycles as u64;
             self.ret()?;
         }
         Ok(())
     }
 
     pub fn ret(&mut self) -> EResult<()> {
+        let (address, sp) = (self.pc.wrapping_sub(1), self.sp);
         self.pc = self.pop()?;
+        self.leave_call(address, sp);
         Ok(())
     }
 }
@@ -111,6 +128,18 @@ mod tests {
         }
     }
 
+    #[test]
+    fn not_taken_at_end_of_memory() {
+        let mut e = Emulator::new();
+
+        e.pc = 0xfffe;
+        e.jmp_if("zero").expect("Fuck");
+        assert_eq!(e.pc, 0x0000);
+        e.pc = 0xffff;
+        e.call_if("zero").expect("Fuck");
+        assert_eq!(e.pc, 0x0001);
+    }
+
     #[test]
     fn jmp_not() {
         let mut e = Emulator::new();
@@ -8,7 +8,8 @@ impl Emulator {
         let register = REGISTERS[index];
         if register == 'm' {
             let address = self.reg["hl"];
-            self.and_value(self.ram[address])
+            let value = self.read_memory(address);
+            self.and_value(value)
         } else {
             self.and_value(self.reg[register])
         }
@@ -27,7 +28,8 @@ impl Emulator {
         let register = REGISTERS[index];
         if register == 'm' {
             let address = self.reg["hl"];
-            self.xor_value(self.ram[address])
+            let value = self.read_memory(address);
+            self.xor_value(value)
         } else {
             self.xor_value(self.reg[register])
         }
@@ -46,7 +48,8 @@ impl Emulator {
         let register = REGISTERS[index];
         if register == 'm' {
             let address = self.reg["hl"];
-            self.or_value(self.ram[address])
+            let value = self.read_memory(address);
+            self.or_value(value)
         } else {
             self.or_value(self.reg[register])
         }
@@ -65,7 +68,8 @@ impl Emulator {
         let register = REGISTERS[index];
         if register == 'm' {
             let address = self.reg["hl"];
-            self.cmp_value(self.ram[address])
+            let value = self.read_memory(address);
+            self.cmp_value(value)
         } else {
             self.cmp_value(self.reg[register])
         }
@@ -74,11 +78,66 @@ impl Emulator {
     fn cmp_value(&mut self, value: u8) -> EResult<()> {
         // Perform SUB but restore accumulator afterwards
         let accumulator = self.reg['a'];
-        self.sub_value(value as u16)?;
+        self.sub_value(value, false)?;
         self.reg['a'] = accumulator;
         Ok(())
     }
 
+    pub fn ani(&mut self) -> EResult<()> {
+        let value = self.read_byte()?;
+        self.and_value(value)
+    }
+
+    pub fn xri(&mut self) -> EResult<()> {
+        let value = self.read_byte()?;
+        self.xor_value(value)
+    }
+
+    pub fn ori(&mut self) -> EResult<()> {
+        let value = self.read_byte()?;
+        self.or_value(value)
+    }
+
+    pub fn cpi(&mut self) -> EResult<()> {
+        let value = self.read_byte()?;
+        self.cmp_value(value)
+    }
+
+    pub fn rlc(&mut self) -> EResult<()> {
+        let accumulator = self.reg['a'];
+        self.reg.set_flag("carry", accumulator & 0x80 != 0);
+        self.reg['a'] = accumulator.rotate_left(1);
+        Ok(())
+    }
+
+    pub fn rrc(&mut self) -> EResult<()> {
+        let accumulator = self.reg['a'];
+        self.reg.set_flag("carry", accumulator & 0x01 != 0);
+        self.reg['a'] = accumulator.rotate_right(1);
+        Ok(())
+    }
+
+    pub fn ral(&mut self) -> EResult<()> {
+        let accumulator = self.reg['a'];
+        let carry = self.reg.get_flag("carry") as u8;
+        self.reg.set_flag("carry", accumulator & 0x80 != 0);
+        self.reg['a'] = (accumulator << 1) | carry;
+        Ok(())
+    }
+
+    pub fn rar(&mut self) -> EResult<()> {
+        let accumulator = self.reg['a'];
+        let carry = self.reg.get_flag("carry") as u8;
+        self.reg.set_flag("carry", accumulator & 0x01 != 0);
+        self.reg['a'] = (accumulator >> 1) | (carry << 7);
+        Ok(())
+    }
+
+    pub fn cma(&mut self) -> EResult<()> {
+        self.reg['a'] = !self.reg['a'];
+        Ok(())
+    }
+
     fn set_flags(&mut self, result: u8) {
         self.reg.set_flag("zero", (result & 0xff) == 0);
         self.reg.set_flag("sign", (result & 0x80) != 0);
@@ -191,4 +250,67 @@ mod tests {
         assert_eq!(e.reg.get_flag("carry"), false, "Carry bit");
         assert_eq!(e.reg.get_flag("zero"), false, "Zero bit");
     }
+
+    #[test]
+    fn immediates() {
+        let mut e = Emulator::new();
+
+        // ANI 0FH, XRI 0FFH, ORI 01H, CPI 0F6H
+        e.ram.load_vec(vec![0xe6, 0x0f, 0xee, 0xff, 0xf6, 0x01, 0xfe, 0xf6], 0);
+        e.reg['a'] = 0b1010_1010;
+
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.reg['a'], 0b0000_1010);
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.reg['a'], 0b1111_0101);
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.reg['a'], 0b1111_0101);
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.reg['a'], 0b1111_0101);
+        assert_eq!(e.reg.get_flag("carry"), true, "Carry bit");
+    }
+
+    #[test]
+    fn rotate() {
+        let mut e = Emulator::new();
+
+        // RLC, RRC, RAL, RAR
+        e.ram.load_vec(vec![0x07, 0x0f, 0x17, 0x1f], 0);
+        e.reg['a'] = 0b1111_0010;
+
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.reg['a'], 0b1110_0101);
+        assert_eq!(e.reg.get_flag("carry"), true, "Carry bit");
+
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.reg['a'], 0b1111_0010);
+        assert_eq!(e.reg.get_flag("carry"), true, "Carry bit");
+
+        e.reg['a'] = 0b1011_0101;
+        e.reg.set_flag("carry", false);
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.reg['a'], 0b0110_1010);
+        assert_eq!(e.reg.get_flag("carry"), true, "Carry bit");
+
+        e.reg['a'] = 0b0110_1010;
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.reg['a'], 0b1011_0101);
+        assert_eq!(e.reg.get_flag("carry"), false, "Carry bit");
+    }
+
+    #[test]
+    fn cma_stc_cmc() {
+        let mut e = Emulator::new();
+
+        // CMA, STC, CMC
+        e.ram.load_vec(vec![0x2f, 0x37, 0x3f], 0);
+        e.reg['a'] = 0b0101_0001;
+
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.reg['a'], 0b1010_1110);
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.reg.get_flag("carry"), true, "Carry bit");
+        e.execute_next().expect("Fuck");
+        assert_eq!(e.reg.get_flag("carry"), false, "Carry bit");
+    }
 }
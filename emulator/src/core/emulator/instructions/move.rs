@@ -12,7 +12,7 @@ impl Emulator {
         // Move byte 2 to address in HL
         let byte = self.read_byte()?;
         let adr = self.reg["hl"];
-        self.ram[adr] = byte;
+        self.write_memory(adr, byte);
         Ok(())
     }
 
@@ -21,10 +21,10 @@ impl Emulator {
         let dst_idx = opcode_rel >> 3;
         let src_idx = opcode_rel - (dst_idx << 3);
         if dst_idx == 6 {
-            self.ram[self.reg["hl"]] = self.reg[REGISTERS[src_idx as usize]];
+            self.write_memory(self.reg["hl"], self.reg[REGISTERS[src_idx as usize]]);
         } else {
             if src_idx == 6 {
-                self.reg[REGISTERS[dst_idx as usize]] = self.ram[self.reg["hl"]];
+                self.reg[REGISTERS[dst_idx as usize]] = self.read_memory(self.reg["hl"]);
             } else {
                 self.mov(REGISTERS[dst_idx as usize], REGISTERS[src_idx as usize])?;
             }
@@ -41,6 +41,50 @@ impl Emulator {
         self.reg[dst] = self.read_addr()?;
         Ok(())
     }
+
+    pub fn stax(&mut self, pair: &str) -> EResult<()> {
+        let adr = self.reg[pair];
+        self.write_memory(adr, self.reg['a']);
+        Ok(())
+    }
+
+    pub fn ldax(&mut self, pair: &str) -> EResult<()> {
+        self.reg['a'] = self.read_memory(self.reg[pair]);
+        Ok(())
+    }
+
+    pub fn sta(&mut self) -> EResult<()> {
+        let adr = self.read_addr()?;
+        self.write_memory(adr, self.reg['a']);
+        Ok(())
+    }
+
+    pub fn lda(&mut self) -> EResult<()> {
+        let adr = self.read_addr()?;
+        self.reg['a'] = self.read_memory(adr);
+        Ok(())
+    }
+
+    pub fn shld(&mut self) -> EResult<()> {
+        let adr = self.read_addr()?;
+        self.write_memory(adr, self.reg['l']);
+        self.write_memory(adr.wrapping_add(1), self.reg['h']);
+        Ok(())
+    }
+
+    pub fn lhld(&mut self) -> EResult<()> {
+        let adr = self.read_addr()?;
+        self.reg['l'] = self.read_memory(adr);
+        self.reg['h'] = self.read_memory(adr.wrapping_add(1));
+        Ok(())
+    }
+
+    pub fn xchg(&mut self) -> EResult<()> {
+        let hl = self.reg["hl"];
+        self.reg["hl"] = self.reg["de"];
+        self.reg["de"] = hl;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -103,4 +147,53 @@ mod tests {
         assert_eq!(emu.sp, 0x0408);
         Ok(())
     }
+
+    #[test]
+    fn load_store() {
+        let mut emu = Emulator::new();
+
+        // STA 1000H, LDAX B, SHLD 1002H, LHLD 1000H, STAX D, LDA 1003H
+        emu.load_ram(
+            vec![0x32, 0x00, 0x10, 0x0a, 0x22, 0x02, 0x10, 0x2a, 0x00, 0x10, 0x12, 0x3a, 0x03, 0x10],
+            0,
+        );
+        emu.reg['a'] = 0x42;
+        emu.reg["bc"] = 0x1000;
+        emu.reg["de"] = 0x1004;
+        emu.reg["hl"] = 0xabcd;
+
+        emu.execute_next().expect("Fuck");
+        assert_eq!(emu.ram[0x1000], 0x42);
+
+        emu.reg['a'] = 0;
+        emu.execute_next().expect("Fuck");
+        assert_eq!(emu.reg['a'], 0x42);
+
+        emu.execute_next().expect("Fuck");
+        assert_eq!(emu.ram[0x1002], 0xcd);
+        assert_eq!(emu.ram[0x1003], 0xab);
+
+        emu.execute_next().expect("Fuck");
+        assert_eq!(emu.reg["hl"], 0x0042);
+
+        emu.execute_next().expect("Fuck");
+        assert_eq!(emu.ram[0x1004], 0x42);
+
+        emu.execute_next().expect("Fuck");
+        assert_eq!(emu.reg['a'], 0xab);
+    }
+
+    #[test]
+    fn xchg() {
+        let mut emu = Emulator::new();
+
+        // XCHG
+        emu.load_ram(vec![0xeb], 0);
+        emu.reg["de"] = 0x1234;
+        emu.reg["hl"] = 0x5678;
+
+        emu.execute_next().expect("Fuck");
+        assert_eq!(emu.reg["de"], 0x5678);
+        assert_eq!(emu.reg["hl"], 0x1234);
+    }
 }
@@ -1,16 +1,17 @@
-use super::super::{EResult, Emulator};
+use super::super::{Cpu, EResult, Emulator};
 
 const REGISTERS: [char; 8] = ['b', 'c', 'd', 'e', 'h', 'l', 'm', 'a'];
 
 impl Emulator {
     pub fn push(&mut self, val: u16) -> EResult<()> {
-        if self.sp < 2 {
+        // With a full 64K address space the stack simply wraps around
+        if self.sp < 2 && self.ram.size() < 0x10000 {
             return Err("PUSH: No more stack space");
         }
-        self.sp -= 1;
-        self.ram[self.sp] = (val >> 8) as u8;
-        self.sp -= 1;
-        self.ram[self.sp] = val as u8;
+        self.sp = self.sp.wrapping_sub(1);
+        self.write_memory(self.sp, (val >> 8) as u8);
+        self.sp = self.sp.wrapping_sub(1);
+        self.write_memory(self.sp, val as u8);
         Ok(())
     }
 
@@ -19,15 +20,43 @@ impl Emulator {
     }
 
     pub fn pop(&mut self) -> EResult<u16> {
-        if self.sp + 2 > self.ram.size() as u16 {
+        if self.sp as usize + 2 > self.ram.size() && self.ram.size() < 0x10000 {
             return Err("POP: No return address on the stack");
         }
-        let low = self.ram[self.sp] as u16;
-        self.sp += 1;
-        let high = self.ram[self.sp] as u16;
-        self.sp += 1;
+        let low = self.read_memory(self.sp) as u16;
+        self.sp = self.sp.wrapping_add(1);
+        let high = self.read_memory(self.sp) as u16;
+        self.sp = self.sp.wrapping_add(1);
         Ok((high << 8) | low)
     }
+
+    pub fn push_psw(&mut self) -> EResult<()> {
+        // Accumulator is the high byte on the stack, flags the low byte
+        let flags = match self.cpu {
+            Cpu::I8080 => self.reg.get_flags(),
+            Cpu::I8085 => self.reg.get_flags_8085(),
+        };
+        let psw = ((self.reg['a'] as u16) << 8) | flags as u16;
+        self.push(psw)
+    }
+
+    pub fn pop_psw(&mut self) -> EResult<()> {
+        let psw = self.pop()?;
+        self.reg['a'] = (psw >> 8) as u8;
+        self.reg.set_flags(psw as u8);
+        Ok(())
+    }
+
+    pub fn xthl(&mut self) -> EResult<()> {
+        let low = self.read_memory(self.sp);
+        let high = self.read_memory(self.sp.wrapping_add(1));
+        let sp = self.sp;
+        self.write_memory(sp, self.reg['l']);
+        self.write_memory(sp.wrapping_add(1), self.reg['h']);
+        self.reg['l'] = low;
+        self.reg['h'] = high;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -48,4 +77,40 @@ mod tests {
         e.sp = 0x1;
         assert_eq!(e.push(0x1234), Err("PUSH: No more stack space"));
     }
+
+    #[test]
+    fn push_pop_psw() {
+        let mut e = Emulator::new();
+
+        e.sp = 0x3fff;
+        e.reg['a'] = 0x42;
+        e.reg.set_flag("carry", true);
+        e.reg.set_flag("zero", true);
+        e.push_psw().expect("Push failed");
+
+        // 8080 layout: S Z 0 AC 0 P 1 CY
+        assert_eq!(e.ram[0x3ffe], 0x42);
+        assert_eq!(e.ram[0x3ffd], 0b0100_0011);
+
+        e.reg['a'] = 0;
+        e.reg.set_flags(0);
+        e.pop_psw().expect("Pop failed");
+        assert_eq!(e.reg['a'], 0x42);
+        assert!(e.reg.get_flag("carry"));
+        assert!(e.reg.get_flag("zero"));
+        assert!(!e.reg.get_flag("sign"));
+    }
+
+    #[test]
+    fn xthl() {
+        let mut e = Emulator::new();
+
+        e.sp = 0x3fff;
+        e.push(0xabcd).expect("Push failed");
+        e.reg["hl"] = 0x1234;
+        e.xthl().expect("Fuck");
+
+        assert_eq!(e.reg["hl"], 0xabcd);
+        assert_eq!(e.pop().expect("Fuck"), 0x1234);
+    }
 }
@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+
+use super::Emulator;
+
+/*
+ * Executions and cycles per address, and cycles per call stack
+ *
+ * Stacks are the targets of the open calls, outermost first,
+ * followed by the address of the instruction
+ */
+pub struct Profiler {
+    enabled: bool,
+    hits: Vec<u64>,
+    cycles: Vec<u64>,
+    stacks: HashMap<Vec<u16>, u64>,
+    stack: Vec<u16>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            enabled: true,
+            hits: vec![0; 0x10000],
+            cycles: vec![0; 0x10000],
+            stacks: HashMap::new(),
+            stack: Vec::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn get_hits(&self, address: u16) -> u64 {
+        self.hits[address as usize]
+    }
+
+    pub fn get_cycles(&self, address: u16) -> u64 {
+        self.cycles[address as usize]
+    }
+
+    pub fn get_instructions(&self) -> u64 {
+        self.hits.iter().sum()
+    }
+
+    pub fn get_total_cycles(&self) -> u64 {
+        self.cycles.iter().sum()
+    }
+
+    /*
+     * Executed addresses with their hits and cycles, in address order
+     */
+    pub fn addresses(&self) -> impl Iterator<Item = (u16, u64, u64)> + '_ {
+        (0..=0xffff)
+            .filter(move |&address| self.hits[address as usize] > 0)
+            .map(move |address| (address, self.hits[address as usize], self.cycles[address as usize]))
+    }
+
+    pub fn stacks(&self) -> impl Iterator<Item = (&[u16], u64)> {
+        self.stacks.iter().map(|(stack, cycles)| (stack.as_slice(), *cycles))
+    }
+
+    fn record(&mut self, cycles: u64) {
+        let address = *self.stack.last().unwrap() as usize;
+        self.hits[address] += 1;
+        self.cycles[address] += cycles;
+        match self.stacks.get_mut(self.stack.as_slice()) {
+            Some(total) => *total += cycles,
+            None => {
+                self.stacks.insert(self.stack.clone(), cycles);
+            }
+        }
+    }
+}
+
+impl Emulator {
+    /*
+     * Start or resume collecting a profile
+     */
+    pub fn start_profiling(&mut self) {
+        self.profiler.get_or_insert_with(Box::default).enabled = true;
+    }
+
+    /*
+     * Pause profiling, the profile is kept
+     */
+    pub fn stop_profiling(&mut self) {
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.enabled = false;
+        }
+    }
+
+    pub fn clear_profile(&mut self) {
+        self.profiler = None;
+    }
+
+    pub fn get_profile(&self) -> Option<&Profiler> {
+        self.profiler.as_deref()
+    }
+
+    /*
+     * Remember the call stack and address of the instruction about to run
+     */
+    pub(super) fn begin_sample(&mut self) {
+        if let Some(profiler) = self.profiler.as_mut().filter(|profiler| profiler.enabled) {
+            profiler.stack.clear();
+            profiler.stack.extend(self.calls.frames().iter().map(|frame| frame.target));
+            profiler.stack.push(self.pc);
+        }
+    }
+
+    pub(super) fn end_sample(&mut self, cycles: u64) {
+        if let Some(profiler) = self.profiler.as_mut().filter(|profiler| profiler.enabled) {
+            profiler.record(cycles);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::emulator::Target;
+
+    // 0000: LXI SP,0100H / 0003: MVI B,2
+    // 0005: CALL 000DH / 0008: DCR B / 0009: JNZ 0005H / 000C: HLT / 000D: NOP / 000E: RET
+    const PROGRAM: [u8; 15] = [
+        0x31, 0x00, 0x01, 0x06, 0x02, 0xcd, 0x0d, 0x00, 0x05, 0xc2, 0x05, 0x00, 0x76, 0x00, 0xc9,
+    ];
+
+    #[test]
+    fn counts_and_stacks() {
+        let mut e = Emulator::new();
+        e.load_ram(PROGRAM.to_vec(), 0);
+        assert!(e.get_profile().is_none());
+        e.start_profiling();
+        e.run(1).expect("Fuck");
+        e.stop_profiling();
+        e.run(1).expect("Fuck");
+        e.start_profiling();
+        e.run(100).expect("Fuck");
+
+        let profile = e.get_profile().expect("Fuck");
+        assert_eq!(profile.get_hits(0x0005), 2);
+        assert_eq!(profile.get_cycles(0x0005), 34);
+        assert_eq!(profile.get_hits(0x000e), 2);
+        assert_eq!(profile.get_hits(0x000c), 1);
+        assert_eq!(profile.get_hits(0x0003), 0);
+        assert_eq!(profile.get_instructions(), 12);
+        assert_eq!(profile.addresses().count(), 7);
+        let mut stacks: Vec<(&[u16], u64)> = profile.stacks().collect();
+        stacks.sort();
+        assert_eq!(stacks[0], (&[0x0000][..], 10));
+        assert_eq!(stacks[stacks.len() - 1], (&[0x000d, 0x000e][..], 20));
+        assert_eq!(stacks.iter().map(|(_, cycles)| cycles).sum::<u64>(), profile.get_total_cycles());
+
+        e.clear_profile();
+        assert!(e.get_profile().is_none());
+    }
+}
@@ -0,0 +1,94 @@
+/*
+ * Intel HEX images as produced by most 8080 assemblers
+ *
+ * Data records are merged into contiguous chunks, the start
+ * address comes from a type 03 or 05 record if there is one.
+ */
+#[derive(Debug, Default, PartialEq)]
+pub struct HexImage {
+    pub chunks: Vec<(u16, Vec<u8>)>,
+    pub start: Option<u16>,
+}
+
+const DATA: u8 = 0x00;
+const END_OF_FILE: u8 = 0x01;
+const START_SEGMENT: u8 = 0x03;
+const START_LINEAR: u8 = 0x05;
+
+impl HexImage {
+    pub fn parse(text: &str) -> Result<Self, &'static str> {
+        let mut image = HexImage::default();
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let record = line.strip_prefix(':').ok_or("Intel HEX records must start with ':'")?;
+            let bytes = decode(record)?;
+            if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+                return Err("Intel HEX record length does not match its byte count");
+            }
+            if bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+                return Err("Intel HEX record has a wrong checksum");
+            }
+            let address = u16::from_be_bytes([bytes[1], bytes[2]]);
+            let data = &bytes[4..bytes.len() - 1];
+            match bytes[3] {
+                DATA => image.add(address, data),
+                END_OF_FILE => break,
+                START_SEGMENT if data.len() == 4 => {
+                    let segment = u16::from_be_bytes([data[0], data[1]]);
+                    let offset = u16::from_be_bytes([data[2], data[3]]);
+                    image.start = Some(segment.wrapping_shl(4).wrapping_add(offset));
+                }
+                START_LINEAR if data.len() == 4 => image.start = Some(u16::from_be_bytes([data[2], data[3]])),
+                _ => return Err("Unsupported Intel HEX record"),
+            }
+        }
+        Ok(image)
+    }
+
+    fn add(&mut self, address: u16, data: &[u8]) {
+        if let Some((start, bytes)) = self.chunks.last_mut() {
+            if *start as usize + bytes.len() == address as usize {
+                bytes.extend_from_slice(data);
+                return;
+            }
+        }
+        self.chunks.push((address, data.to_vec()));
+    }
+}
+
+fn decode(record: &str) -> Result<Vec<u8>, &'static str> {
+    if !record.len().is_multiple_of(2) || !record.is_ascii() {
+        return Err("Intel HEX record contains an odd number of digits");
+    }
+    (0..record.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&record[index..index + 2], 16).map_err(|_| "Invalid digit in Intel HEX record"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_image() {
+        let text = "\
+            :03010000C3050133\n\
+            :02010300AABB95\n\
+            :010020007669\n\
+            :0400000500000100F6\n\
+            :00000001FF\n\
+            :010030007659\n";
+        let image = HexImage::parse(text).expect("Fuck");
+        assert_eq!(image.chunks, vec![(0x0100, vec![0xc3, 0x05, 0x01, 0xaa, 0xbb]), (0x0020, vec![0x76])]);
+        assert_eq!(image.start, Some(0x0100));
+    }
+
+    #[test]
+    fn invalid_records() {
+        assert!(HexImage::parse("010020007669").is_err());
+        assert!(HexImage::parse(":010020007668").is_err());
+        assert!(HexImage::parse(":020020007669").is_err());
+        assert!(HexImage::parse(":01002000G669").is_err());
+        assert!(HexImage::parse(":010020027667").is_err());
+    }
+}
@@ -1,23 +1,42 @@
+use crate::core::ram::RAM;
 
+/*
+ * Devices are attached to one or more of the 256 I/O ports.
+ * The port an access was made on is passed along, so a single device
+ * can decode several ports (e.g. status and data register).
+ */
 pub trait InputDevice: {
-    fn read(&self) -> u8;
+    fn read(&mut self, port: u8) -> u8;
 }
 
 pub trait OutputDevice {
-    fn write(&mut self, byte: u8);
+    fn write(&mut self, port: u8, byte: u8);
+
+    /*
+     * Called after every write to the device
+     * Devices with DMA access perform pending memory transfers here
+     */
+    fn transfer(&mut self, _ram: &mut dyn RAM) {}
+}
+
+pub trait ClockedDevice {
+    /*
+     * Advance the device by the given number of CPU cycles
+     * Returns the instruction to put on the data bus (usually RST n)
+     * if the device requests an interrupt
+     */
+    fn tick(&mut self, cycles: u64) -> Option<u8>;
 }
 
 /* Input/Output device that does nothing */
 pub struct DevNull {}
 
 impl InputDevice for DevNull {
-    fn read(&self) -> u8 {
+    fn read(&mut self, _port: u8) -> u8 {
         0
     }
 }
 
 impl OutputDevice for DevNull {
-    fn write(&mut self, byte: u8) {
-        return;
-    }
+    fn write(&mut self, _port: u8, _byte: u8) {}
 }
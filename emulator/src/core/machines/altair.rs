@@ -0,0 +1,190 @@
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+
+use crate::core::devices::acia::Acia6850;
+use crate::core::devices::sense_switches::SenseSwitches;
+use crate::core::emulator::{EResult, Emulator};
+use crate::core::ram::LinearRam;
+
+const MEMORY_SIZE: usize = 0x10000;
+const SIO_CONSOLE_PORT: u8 = 0x10;
+const SIO_AUX_PORT: u8 = 0x12;
+const SENSE_SWITCH_PORT: u8 = 0xff;
+
+/*
+ * MITS Altair 8800 with 64K of RAM, an 88-2SIO serial board
+ * (console on ports 10H/11H, second channel on 12H/13H)
+ * and the front panel sense switches on port 0FFH
+ */
+pub struct Altair {
+    emulator: Emulator,
+    console: Rc<RefCell<Acia6850>>,
+    aux: Rc<RefCell<Acia6850>>,
+    sense_switches: Rc<RefCell<SenseSwitches>>,
+}
+
+impl Altair {
+    pub fn new() -> Self {
+        Self::with_memory(MEMORY_SIZE)
+    }
+
+    /*
+     * Altair with fewer memory boards, e.g. 4K for 4K BASIC
+     */
+    pub fn with_memory(size: usize) -> Self {
+        let mut emulator = Emulator::with_ram(Box::new(LinearRam::new(size)));
+        let console = Rc::new(RefCell::new(Acia6850::new(SIO_CONSOLE_PORT)));
+        let aux = Rc::new(RefCell::new(Acia6850::new(SIO_AUX_PORT)));
+        let sense_switches = Rc::new(RefCell::new(SenseSwitches::new(0)));
+
+        for acia in [&console, &aux] {
+            for port in acia.borrow().ports() {
+                emulator
+                    .register_input_device(acia.clone(), port as usize)
+                    .unwrap();
+                emulator
+                    .register_output_device(acia.clone(), port as usize)
+                    .unwrap();
+            }
+        }
+        emulator
+            .register_input_device(sense_switches.clone(), SENSE_SWITCH_PORT as usize)
+            .unwrap();
+
+        Self {
+            emulator,
+            console,
+            aux,
+            sense_switches,
+        }
+    }
+
+    /*
+     * Load a binary image (e.g. Altair BASIC or a monitor ROM) at start
+     */
+    pub fn load_file(&mut self, path: &str, start: u16) -> io::Result<()> {
+        self.emulator.get_ram_mut().load_file(path, start)
+    }
+
+    pub fn load(&mut self, data: Vec<u8>, start: u16) {
+        self.emulator.load_ram(data, start);
+    }
+
+    pub fn boot(&mut self, address: u16) {
+        self.emulator.set_pc(address);
+    }
+
+    /*
+     * Run until HLT or until max_instructions have been executed
+     * Returns the number of executed instructions
+     */
+    pub fn run(&mut self, max_instructions: usize) -> EResult<usize> {
+        let mut executed = 0;
+        while self.emulator.is_running() && executed < max_instructions {
+            self.emulator.step()?;
+            executed += 1;
+        }
+        Ok(executed)
+    }
+
+    pub fn set_sense_switches(&mut self, value: u8) {
+        self.sense_switches.borrow_mut().set(value);
+    }
+
+    pub fn send_input(&mut self, input: &str) {
+        self.console.borrow_mut().send(input.as_bytes());
+    }
+
+    pub fn take_output(&mut self) -> String {
+        let bytes = self.console.borrow_mut().take_output();
+        bytes.iter().map(|&b| (b & 0x7f) as char).collect()
+    }
+
+    pub fn console(&self) -> Rc<RefCell<Acia6850>> {
+        self.console.clone()
+    }
+
+    pub fn aux(&self) -> Rc<RefCell<Acia6850>> {
+        self.aux.clone()
+    }
+
+    pub fn emulator(&self) -> &Emulator {
+        &self.emulator
+    }
+
+    pub fn emulator_mut(&mut self) -> &mut Emulator {
+        &mut self.emulator
+    }
+}
+
+impl Default for Altair {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    // Echo console input until '.', then print the sense switches and halt
+    const ECHO: [u8; 24] = [
+        0xdb, 0x10, // 0000: IN 10H
+        0xe6, 0x01, // 0002: ANI 01H
+        0xca, 0x00, 0x00, // 0004: JZ 0000H
+        0xdb, 0x11, // 0007: IN 11H
+        0xfe, 0x2e, // 0009: CPI '.'
+        0xca, 0x13, 0x00, // 000B: JZ 0013H
+        0xd3, 0x11, // 000E: OUT 11H
+        0xc3, 0x00, 0x00, // 0010: JMP 0000H
+        0xdb, 0xff, // 0013: IN 0FFH
+        0xd3, 0x11, // 0015: OUT 11H
+        0x76, // 0017: HLT
+    ];
+
+    #[test]
+    fn echo() {
+        let mut altair = Altair::new();
+        altair.load(ECHO.to_vec(), 0);
+        altair.set_sense_switches(b'!');
+        altair.send_input("HELLO.");
+
+        altair.run(1000).expect("Fuck");
+
+        assert!(!altair.emulator().is_running());
+        assert_eq!(altair.take_output(), "HELLO!");
+    }
+
+    #[test]
+    fn instruction_limit() {
+        let mut altair = Altair::new();
+        altair.load(ECHO.to_vec(), 0);
+
+        // Nothing to read, polls forever
+        assert_eq!(altair.run(100), Ok(100));
+        assert!(altair.emulator().is_running());
+    }
+
+    #[test]
+    fn boot_from_file() -> io::Result<()> {
+        let path = std::env::temp_dir().join("altair_boot_from_file.bin");
+        fs::write(&path, ECHO)?;
+
+        let mut altair = Altair::new();
+        altair.load_file(path.to_str().unwrap(), 0xe000)?;
+        fs::remove_file(&path)?;
+
+        // Relocate the jumps of the image to 0E000H
+        for adr in [0xe006u16, 0xe00d, 0xe012] {
+            altair.emulator_mut().get_ram_mut()[adr] = 0xe0;
+        }
+        altair.boot(0xe000);
+        altair.send_input("OK.");
+        altair.run(1000).expect("Fuck");
+
+        assert_eq!(altair.take_output(), "OK\0");
+        Ok(())
+    }
+}
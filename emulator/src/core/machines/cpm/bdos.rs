@@ -0,0 +1,562 @@
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use super::fcb::{Fcb, RECORD_SIZE};
+use super::{Cpm, CpmExit, DEFAULT_DMA};
+use crate::core::emulator::EResult;
+
+const EOF: u8 = 0x1a;
+const CR: u8 = 0x0d;
+const LF: u8 = 0x0a;
+
+// Return codes
+const OK: u8 = 0x00;
+const ERROR: u8 = 0xff;
+const END_OF_DATA: u8 = 0x01;
+const NO_DIRECTORY_SPACE: u8 = 0x02;
+
+impl Cpm {
+    /*
+     * Handle a BDOS call: function number in C, parameter in DE
+     * Results are returned in A = L and B = H
+     * Returns Some(exit) if the run loop has to stop
+     */
+    pub(super) fn bdos(&mut self) -> EResult<Option<CpmExit>> {
+        let function = self.emulator.get_registers()['c'];
+        let param = self.emulator.get_registers()["de"];
+        let arg = param as u8;
+
+        let result: u16 = match function {
+            // System reset
+            0 => return Ok(Some(CpmExit::Terminated)),
+            // Console input
+            1 => match self.input.pop_front() {
+                Some(c) => {
+                    self.output.push(c);
+                    c as u16
+                }
+                None => return Ok(Some(CpmExit::WaitingForInput)),
+            },
+            // Console output, list output
+            2 | 5 => {
+                self.output.push(arg);
+                0
+            }
+            // Reader input
+            3 => self.input.pop_front().unwrap_or(EOF) as u16,
+            // Punch output
+            4 => 0,
+            // Direct console I/O
+            6 => match arg {
+                0xff => self.input.pop_front().unwrap_or(0) as u16,
+                0xfe => self.console_status(),
+                _ => {
+                    self.output.push(arg);
+                    0
+                }
+            },
+            // Get/set IOBYTE
+            7 => self.emulator.get_ram()[0x0003] as u16,
+            8 => {
+                self.emulator.get_ram_mut()[0x0003] = arg;
+                0
+            }
+            // Print string terminated by '$', at most all of memory
+            9 => {
+                for offset in 0..=0xffff {
+                    let c = self.emulator.get_ram()[param.wrapping_add(offset)];
+                    if c == b'$' {
+                        break;
+                    }
+                    self.output.push(c);
+                }
+                0
+            }
+            // Read console buffer
+            10 => {
+                if !self.input.contains(&CR) {
+                    return Ok(Some(CpmExit::WaitingForInput));
+                }
+                self.read_console_buffer(param);
+                0
+            }
+            // Console status
+            11 => self.console_status(),
+            // Version number: CP/M 2.2
+            12 => 0x0022,
+            // Reset disk system
+            13 => {
+                self.dma = DEFAULT_DMA;
+                0
+            }
+            // Select disk, only drive A: exists
+            14 => 0,
+            15 => self.open_file(param),
+            16 => self.close_file(param),
+            17 => self.search_first(param),
+            18 => self.search_next(),
+            19 => self.delete_file(param),
+            20 => self.read_sequential(param),
+            21 => self.write_sequential(param),
+            22 => self.make_file(param),
+            23 => self.rename_file(param),
+            // Login vector: only A:
+            24 => 0x0001,
+            // Current disk
+            25 => 0,
+            // Set DMA address
+            26 => {
+                self.dma = param;
+                0
+            }
+            // Allocation vector, R/O vector, disk parameters: nothing meaningful
+            27 | 29 | 31 => 0,
+            // Write protect disk, set file attributes
+            28 | 30 => 0,
+            // Get/set user code, only user 0 exists
+            32 => 0,
+            33 => self.read_random(param),
+            34 | 40 => self.write_random(param),
+            35 => self.compute_file_size(param),
+            36 => {
+                let fcb = Fcb::new(param);
+                let record = fcb.current_record(self.emulator.get_ram());
+                fcb.set_random_record(self.emulator.get_ram_mut(), record);
+                0
+            }
+            // Reset drive
+            37 => 0,
+            _ => ERROR as u16,
+        };
+
+        let reg = self.emulator.get_registers_mut();
+        reg["hl"] = result;
+        reg['a'] = result as u8;
+        reg['b'] = (result >> 8) as u8;
+        Ok(None)
+    }
+
+    fn console_status(&self) -> u16 {
+        if self.input.is_empty() {
+            0
+        } else {
+            0xff
+        }
+    }
+
+    /*
+     * Buffer layout: max length, returned length, characters
+     */
+    fn read_console_buffer(&mut self, buffer: u16) {
+        let max = self.emulator.get_ram()[buffer] as usize;
+        let mut line: Vec<u8> = Vec::new();
+        while let Some(c) = self.input.pop_front() {
+            match c {
+                CR | LF => break,
+                0x08 | 0x7f if line.pop().is_some() => {
+                    self.output.extend([0x08, b' ', 0x08]);
+                }
+                0x08 | 0x7f => (),
+                _ if line.len() < max => {
+                    line.push(c);
+                    self.output.push(c);
+                }
+                _ => (),
+            }
+        }
+        self.output.push(CR);
+
+        let ram = self.emulator.get_ram_mut();
+        ram[buffer.wrapping_add(1)] = line.len() as u8;
+        ram.load_vec(line, buffer.wrapping_add(2));
+    }
+
+    fn open_file(&mut self, param: u16) -> u16 {
+        let fcb = Fcb::new(param);
+        let name = fcb.name(self.emulator.get_ram());
+        match self.directory.find(&name) {
+            Some(path) => {
+                let size = fs::metadata(&path).map(|m| m.len() as usize).unwrap_or(0);
+                let ram = self.emulator.get_ram_mut();
+                ram[param.wrapping_add(32)] = 0;
+                fcb.set_record_count(ram, records(size));
+                OK as u16
+            }
+            None => ERROR as u16,
+        }
+    }
+
+    fn close_file(&mut self, param: u16) -> u16 {
+        let name = Fcb::new(param).name(self.emulator.get_ram());
+        match self.directory.find(&name) {
+            Some(_) => OK as u16,
+            None => ERROR as u16,
+        }
+    }
+
+    fn make_file(&mut self, param: u16) -> u16 {
+        let fcb = Fcb::new(param);
+        let name = fcb.name(self.emulator.get_ram());
+        if name.contains(&b'?') {
+            return ERROR as u16;
+        }
+        let path = self.directory.find(&name).unwrap_or_else(|| self.directory.path_for(&name));
+        if fs::write(path, []).is_err() {
+            return NO_DIRECTORY_SPACE as u16;
+        }
+        let ram = self.emulator.get_ram_mut();
+        fcb.set_current_record(ram, 0);
+        fcb.set_record_count(ram, 0);
+        OK as u16
+    }
+
+    fn delete_file(&mut self, param: u16) -> u16 {
+        let pattern = Fcb::new(param).name(self.emulator.get_ram());
+        let files = self.directory.search(&pattern);
+        if files.is_empty() {
+            return ERROR as u16;
+        }
+        for (_, path) in files {
+            if fs::remove_file(path).is_err() {
+                return ERROR as u16;
+            }
+        }
+        OK as u16
+    }
+
+    fn rename_file(&mut self, param: u16) -> u16 {
+        let ram = self.emulator.get_ram();
+        let old_name = Fcb::new(param).name(ram);
+        let new_name = Fcb::new(param.wrapping_add(16)).name(ram);
+        if self.directory.find(&new_name).is_some() {
+            return ERROR as u16;
+        }
+        match self.directory.find(&old_name) {
+            Some(path) => match fs::rename(path, self.directory.path_for(&new_name)) {
+                Ok(_) => OK as u16,
+                Err(_) => ERROR as u16,
+            },
+            None => ERROR as u16,
+        }
+    }
+
+    fn search_first(&mut self, param: u16) -> u16 {
+        let pattern = Fcb::new(param).name(self.emulator.get_ram());
+        self.search_results = self
+            .directory
+            .search(&pattern)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        self.search_next()
+    }
+
+    /*
+     * The directory entry is always returned as the first
+     * of the four entries in the DMA buffer
+     */
+    fn search_next(&mut self) -> u16 {
+        let name = match self.search_results.pop_front() {
+            Some(name) => name,
+            None => return ERROR as u16,
+        };
+        let size = self
+            .directory
+            .find(&name)
+            .and_then(|path| fs::metadata(path).ok())
+            .map(|m| m.len() as usize)
+            .unwrap_or(0);
+
+        let mut entry = vec![0u8; 32];
+        entry[1..12].copy_from_slice(&name);
+        entry[15] = records(size).min(128) as u8;
+        self.emulator.get_ram_mut().load_vec(entry, self.dma);
+        OK as u16
+    }
+
+    fn read_sequential(&mut self, param: u16) -> u16 {
+        let fcb = Fcb::new(param);
+        let record = fcb.current_record(self.emulator.get_ram());
+        let result = self.read_record(param, record);
+        if result == OK {
+            fcb.set_current_record(self.emulator.get_ram_mut(), record + 1);
+        }
+        result as u16
+    }
+
+    fn write_sequential(&mut self, param: u16) -> u16 {
+        let fcb = Fcb::new(param);
+        let record = fcb.current_record(self.emulator.get_ram());
+        let result = self.write_record(param, record);
+        if result == OK {
+            fcb.set_current_record(self.emulator.get_ram_mut(), record + 1);
+        }
+        result as u16
+    }
+
+    /*
+     * Random access also moves the sequential position,
+     * the next sequential read returns the same record again
+     */
+    fn read_random(&mut self, param: u16) -> u16 {
+        let fcb = Fcb::new(param);
+        let record = fcb.random_record(self.emulator.get_ram());
+        let result = self.read_record(param, record);
+        fcb.set_current_record(self.emulator.get_ram_mut(), record);
+        result as u16
+    }
+
+    fn write_random(&mut self, param: u16) -> u16 {
+        let fcb = Fcb::new(param);
+        let record = fcb.random_record(self.emulator.get_ram());
+        let result = self.write_record(param, record);
+        fcb.set_current_record(self.emulator.get_ram_mut(), record);
+        result as u16
+    }
+
+    fn compute_file_size(&mut self, param: u16) -> u16 {
+        let fcb = Fcb::new(param);
+        let name = fcb.name(self.emulator.get_ram());
+        match self.directory.find(&name).and_then(|path| fs::metadata(path).ok()) {
+            Some(metadata) => {
+                fcb.set_random_record(self.emulator.get_ram_mut(), records(metadata.len() as usize));
+                OK as u16
+            }
+            None => ERROR as u16,
+        }
+    }
+
+    /*
+     * Copy a record into the DMA buffer, short records are padded with ^Z
+     */
+    fn read_record(&mut self, param: u16, record: usize) -> u8 {
+        let name = Fcb::new(param).name(self.emulator.get_ram());
+        let path = match self.directory.find(&name) {
+            Some(path) => path,
+            None => return ERROR,
+        };
+        let mut buf = vec![EOF; RECORD_SIZE];
+        let read = fs::File::open(path).and_then(|mut f| {
+            f.seek(SeekFrom::Start((record * RECORD_SIZE) as u64))?;
+            let mut total = 0;
+            while total < RECORD_SIZE {
+                let n = f.read(&mut buf[total..])?;
+                if n == 0 {
+                    break;
+                }
+                total += n;
+            }
+            Ok(total)
+        });
+        match read {
+            Ok(0) | Err(_) => END_OF_DATA,
+            Ok(_) => {
+                self.emulator.get_ram_mut().load_vec(buf, self.dma);
+                OK
+            }
+        }
+    }
+
+    fn write_record(&mut self, param: u16, record: usize) -> u8 {
+        let name = Fcb::new(param).name(self.emulator.get_ram());
+        let path = match self.directory.find(&name) {
+            Some(path) => path,
+            None => return ERROR,
+        };
+        let ram = self.emulator.get_ram();
+        let buf: Vec<u8> = (0..RECORD_SIZE as u16)
+            .map(|i| ram[self.dma.wrapping_add(i)])
+            .collect();
+        let written = OpenOptions::new().write(true).open(path).and_then(|mut f| {
+            f.seek(SeekFrom::Start((record * RECORD_SIZE) as u64))?;
+            f.write_all(&buf)
+        });
+        match written {
+            Ok(_) => OK,
+            Err(_) => NO_DIRECTORY_SPACE,
+        }
+    }
+}
+
+fn records(size: usize) -> usize {
+    size.div_ceil(RECORD_SIZE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fcb::parse_name;
+    use super::super::DEFAULT_FCB;
+    use super::*;
+    use std::path::PathBuf;
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("cpm_bdos_{}", name));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn call(cpm: &mut Cpm, function: u8, param: u16) -> u8 {
+        cpm.emulator.get_registers_mut()['c'] = function;
+        cpm.emulator.get_registers_mut()["de"] = param;
+        assert_eq!(cpm.bdos(), Ok(None));
+        cpm.emulator.get_registers()['a']
+    }
+
+    fn set_fcb(cpm: &mut Cpm, address: u16, name: &str) {
+        let fcb = Fcb::new(address);
+        let ram = cpm.emulator.get_ram_mut();
+        fcb.clear(ram);
+        fcb.set_name(ram, &parse_name(name).unwrap());
+    }
+
+    fn dma(cpm: &Cpm) -> Vec<u8> {
+        (0..RECORD_SIZE as u16)
+            .map(|i| cpm.emulator.get_ram()[cpm.dma + i])
+            .collect()
+    }
+
+    #[test]
+    fn console() {
+        let mut cpm = Cpm::new(&std::env::temp_dir());
+
+        assert_eq!(call(&mut cpm, 11, 0), 0);
+        cpm.send_input("ab\x08c\nz");
+        assert_eq!(call(&mut cpm, 11, 0), 0xff);
+
+        cpm.emulator.get_ram_mut()[0x1000] = 10;
+        call(&mut cpm, 10, 0x1000);
+        assert_eq!(cpm.emulator.get_ram()[0x1001], 2);
+        assert_eq!(cpm.emulator.get_ram()[0x1002], b'a');
+        assert_eq!(cpm.emulator.get_ram()[0x1003], b'c');
+
+        assert_eq!(call(&mut cpm, 6, 0xff), b'z');
+        assert_eq!(call(&mut cpm, 6, 0xff), 0);
+        call(&mut cpm, 2, b'!' as u16);
+        assert_eq!(cpm.take_output(), "ab\x08 \x08c\r!");
+
+        assert_eq!(call(&mut cpm, 12, 0), 0x22);
+    }
+
+    #[test]
+    fn unterminated_string() {
+        let mut cpm = Cpm::new(&std::env::temp_dir());
+        for address in 0..=0xffff {
+            cpm.emulator.get_ram_mut()[address] = b'x';
+        }
+        call(&mut cpm, 9, 0x1000);
+        assert_eq!(cpm.take_output().len(), 0x10000);
+    }
+
+    #[test]
+    fn sequential_files() {
+        let dir = TempDir::new("sequential");
+        let mut cpm = Cpm::new(&dir.0);
+
+        set_fcb(&mut cpm, DEFAULT_FCB, "TEST.TXT");
+        assert_eq!(call(&mut cpm, 15, DEFAULT_FCB), ERROR);
+        assert_eq!(call(&mut cpm, 22, DEFAULT_FCB), OK);
+
+        for record in 0..3u8 {
+            cpm.emulator.get_ram_mut().load_vec(vec![record; RECORD_SIZE], DEFAULT_DMA);
+            assert_eq!(call(&mut cpm, 21, DEFAULT_FCB), OK);
+        }
+        assert_eq!(call(&mut cpm, 16, DEFAULT_FCB), OK);
+        assert_eq!(fs::metadata(dir.0.join("TEST.TXT")).unwrap().len(), 384);
+
+        assert_eq!(call(&mut cpm, 15, DEFAULT_FCB), OK);
+        assert_eq!(cpm.emulator.get_ram()[DEFAULT_FCB + 15], 3);
+        for record in 0..3u8 {
+            assert_eq!(call(&mut cpm, 20, DEFAULT_FCB), OK);
+            assert_eq!(dma(&cpm), vec![record; RECORD_SIZE]);
+        }
+        assert_eq!(call(&mut cpm, 20, DEFAULT_FCB), END_OF_DATA);
+    }
+
+    #[test]
+    fn short_record_padding() {
+        let dir = TempDir::new("padding");
+        fs::write(dir.0.join("short.txt"), b"HI").unwrap();
+        let mut cpm = Cpm::new(&dir.0);
+
+        set_fcb(&mut cpm, DEFAULT_FCB, "SHORT.TXT");
+        assert_eq!(call(&mut cpm, 15, DEFAULT_FCB), OK);
+        assert_eq!(call(&mut cpm, 20, DEFAULT_FCB), OK);
+        let record = dma(&cpm);
+        assert_eq!(&record[..3], &[b'H', b'I', EOF]);
+        assert_eq!(record[127], EOF);
+    }
+
+    #[test]
+    fn random_files() {
+        let dir = TempDir::new("random");
+        let mut cpm = Cpm::new(&dir.0);
+
+        set_fcb(&mut cpm, DEFAULT_FCB, "DATA.DAT");
+        call(&mut cpm, 22, DEFAULT_FCB);
+
+        let fcb = Fcb::new(DEFAULT_FCB);
+        fcb.set_random_record(cpm.emulator.get_ram_mut(), 200);
+        cpm.emulator.get_ram_mut().load_vec(vec![0xaa; RECORD_SIZE], DEFAULT_DMA);
+        assert_eq!(call(&mut cpm, 34, DEFAULT_FCB), OK);
+        assert_eq!(fcb.current_record(cpm.emulator.get_ram()), 200);
+
+        assert_eq!(call(&mut cpm, 35, DEFAULT_FCB), OK);
+        assert_eq!(fcb.random_record(cpm.emulator.get_ram()), 201);
+
+        fcb.set_random_record(cpm.emulator.get_ram_mut(), 5);
+        assert_eq!(call(&mut cpm, 33, DEFAULT_FCB), OK);
+        assert_eq!(dma(&cpm), vec![0; RECORD_SIZE]);
+
+        fcb.set_random_record(cpm.emulator.get_ram_mut(), 200);
+        assert_eq!(call(&mut cpm, 33, DEFAULT_FCB), OK);
+        assert_eq!(dma(&cpm), vec![0xaa; RECORD_SIZE]);
+
+        fcb.set_random_record(cpm.emulator.get_ram_mut(), 300);
+        assert_eq!(call(&mut cpm, 33, DEFAULT_FCB), END_OF_DATA);
+
+        // Set random record from the sequential position
+        fcb.set_current_record(cpm.emulator.get_ram_mut(), 42);
+        call(&mut cpm, 36, DEFAULT_FCB);
+        assert_eq!(fcb.random_record(cpm.emulator.get_ram()), 42);
+    }
+
+    #[test]
+    fn directory() {
+        let dir = TempDir::new("directory");
+        for name in ["b.com", "A.COM", "c.txt", "not_a_valid_name.txt"] {
+            fs::write(dir.0.join(name), [0; 300]).unwrap();
+        }
+        let mut cpm = Cpm::new(&dir.0);
+
+        set_fcb(&mut cpm, DEFAULT_FCB, "*.COM");
+        assert_eq!(call(&mut cpm, 17, DEFAULT_FCB), OK);
+        assert_eq!(&dma(&cpm)[1..12], b"A       COM");
+        assert_eq!(dma(&cpm)[15], 3);
+        assert_eq!(call(&mut cpm, 18, 0), OK);
+        assert_eq!(&dma(&cpm)[1..12], b"B       COM");
+        assert_eq!(call(&mut cpm, 18, 0), ERROR);
+
+        // Rename C.TXT to D.TXT
+        set_fcb(&mut cpm, 0x1000, "C.TXT");
+        set_fcb(&mut cpm, 0x1010, "D.TXT");
+        assert_eq!(call(&mut cpm, 23, 0x1000), OK);
+        assert!(dir.0.join("D.TXT").exists());
+        assert_eq!(call(&mut cpm, 23, 0x1000), ERROR);
+
+        // Delete all .COM files
+        assert_eq!(call(&mut cpm, 19, DEFAULT_FCB), OK);
+        assert_eq!(call(&mut cpm, 17, DEFAULT_FCB), ERROR);
+        assert_eq!(call(&mut cpm, 19, DEFAULT_FCB), ERROR);
+    }
+}
@@ -0,0 +1,254 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::core::ram::RAM;
+
+pub const RECORD_SIZE: usize = 128;
+const RECORDS_PER_EXTENT: usize = 128;
+const EXTENTS_PER_MODULE: usize = 32;
+
+// FCB field offsets
+const NAME: u16 = 1;
+const EX: u16 = 12;
+const S2: u16 = 14;
+const RC: u16 = 15;
+const CR: u16 = 32;
+const R0: u16 = 33;
+
+/*
+ * File control block in emulated memory
+ *
+ * dr | f1..f8 | t1..t3 | ex | s1 | s2 | rc | d0..d15 | cr | r0 r1 r2
+ *
+ * The file position lives in the FCB itself (ex/s2/cr for sequential,
+ * r0-r2 for random access), so the host side keeps no open handles.
+ */
+pub struct Fcb {
+    address: u16,
+}
+
+impl Fcb {
+    pub fn new(address: u16) -> Self {
+        Self { address }
+    }
+
+    fn at(&self, offset: u16) -> u16 {
+        self.address.wrapping_add(offset)
+    }
+
+    /*
+     * 8.3 name padded with spaces, attribute bits stripped
+     */
+    pub fn name(&self, ram: &dyn RAM) -> [u8; 11] {
+        let mut name = [b' '; 11];
+        for (i, c) in name.iter_mut().enumerate() {
+            *c = (ram[self.at(NAME + i as u16)] & 0x7f).to_ascii_uppercase();
+        }
+        name
+    }
+
+    pub fn set_name(&self, ram: &mut dyn RAM, name: &[u8; 11]) {
+        for (i, &c) in name.iter().enumerate() {
+            ram[self.at(NAME + i as u16)] = c;
+        }
+    }
+
+    pub fn clear(&self, ram: &mut dyn RAM) {
+        for offset in 0..36 {
+            ram[self.at(offset)] = 0;
+        }
+        for offset in NAME..NAME + 11 {
+            ram[self.at(offset)] = b' ';
+        }
+    }
+
+    /*
+     * Sequential position in records
+     */
+    pub fn current_record(&self, ram: &dyn RAM) -> usize {
+        let s2 = (ram[self.at(S2)] & 0x3f) as usize;
+        let ex = (ram[self.at(EX)] & 0x1f) as usize;
+        let cr = (ram[self.at(CR)] & 0x7f) as usize;
+        (s2 * EXTENTS_PER_MODULE + ex) * RECORDS_PER_EXTENT + cr
+    }
+
+    pub fn set_current_record(&self, ram: &mut dyn RAM, record: usize) {
+        ram[self.at(CR)] = (record % RECORDS_PER_EXTENT) as u8;
+        ram[self.at(EX)] = ((record / RECORDS_PER_EXTENT) % EXTENTS_PER_MODULE) as u8;
+        ram[self.at(S2)] = (record / (RECORDS_PER_EXTENT * EXTENTS_PER_MODULE)) as u8;
+    }
+
+    pub fn random_record(&self, ram: &dyn RAM) -> usize {
+        ram[self.at(R0)] as usize
+            | (ram[self.at(R0 + 1)] as usize) << 8
+            | (ram[self.at(R0 + 2)] as usize) << 16
+    }
+
+    pub fn set_random_record(&self, ram: &mut dyn RAM, record: usize) {
+        ram[self.at(R0)] = record as u8;
+        ram[self.at(R0 + 1)] = (record >> 8) as u8;
+        ram[self.at(R0 + 2)] = (record >> 16) as u8;
+    }
+
+    /*
+     * Record count of the current extent, derived from the file size
+     */
+    pub fn set_record_count(&self, ram: &mut dyn RAM, file_records: usize) {
+        let extent = self.current_record(ram) / RECORDS_PER_EXTENT;
+        let remaining = file_records.saturating_sub(extent * RECORDS_PER_EXTENT);
+        ram[self.at(RC)] = remaining.min(RECORDS_PER_EXTENT) as u8;
+    }
+}
+
+/*
+ * "README.TXT" -> b"README  TXT", None if it is no valid 8.3 name
+ * '*' expands to '?' wildcards for the rest of the field
+ */
+pub fn parse_name(text: &str) -> Option<[u8; 11]> {
+    let text = text.to_ascii_uppercase();
+    let (base, ext) = match text.split_once('.') {
+        Some((base, ext)) => (base, ext),
+        None => (text.as_str(), ""),
+    };
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 {
+        return None;
+    }
+    let mut name = [b' '; 11];
+    for (field, range) in [(base, 0..8), (ext, 8..11)] {
+        for (idx, c) in (range.start..).zip(field.bytes()) {
+            if c == b'*' {
+                for slot in &mut name[idx..range.end] {
+                    *slot = b'?';
+                }
+                break;
+            }
+            if !c.is_ascii_graphic() || b"<>.,;:=[]%|()/\\".contains(&c) {
+                return None;
+            }
+            name[idx] = c;
+        }
+    }
+    Some(name)
+}
+
+/*
+ * b"README  TXT" -> "README.TXT"
+ */
+pub fn host_name(name: &[u8; 11]) -> String {
+    let base = String::from_utf8_lossy(&name[..8]).trim_end().to_string();
+    let ext = String::from_utf8_lossy(&name[8..]).trim_end().to_string();
+    if ext.is_empty() {
+        base
+    } else {
+        format!("{}.{}", base, ext)
+    }
+}
+
+pub fn matches(pattern: &[u8; 11], name: &[u8; 11]) -> bool {
+    pattern
+        .iter()
+        .zip(name.iter())
+        .all(|(&p, &n)| p == b'?' || p == n)
+}
+
+/*
+ * Host directory backing drive A:
+ * File names are matched case insensitively
+ */
+pub struct HostDirectory {
+    root: PathBuf,
+}
+
+impl HostDirectory {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+        }
+    }
+
+    /*
+     * All files with valid 8.3 names, sorted by name
+     */
+    pub fn list(&self) -> Vec<([u8; 11], PathBuf)> {
+        let mut files = Vec::new();
+        if let Ok(entries) = fs::read_dir(&self.root) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if !path.is_file() {
+                    continue;
+                }
+                if let Some(name) = path.file_name().and_then(|n| n.to_str()).and_then(parse_name) {
+                    if !name.contains(&b'?') {
+                        files.push((name, path));
+                    }
+                }
+            }
+        }
+        files.sort();
+        files
+    }
+
+    pub fn find(&self, name: &[u8; 11]) -> Option<PathBuf> {
+        self.list()
+            .into_iter()
+            .find(|(entry, _)| entry == name)
+            .map(|(_, path)| path)
+    }
+
+    pub fn search(&self, pattern: &[u8; 11]) -> Vec<([u8; 11], PathBuf)> {
+        self.list()
+            .into_iter()
+            .filter(|(name, _)| matches(pattern, name))
+            .collect()
+    }
+
+    pub fn path_for(&self, name: &[u8; 11]) -> PathBuf {
+        self.root.join(host_name(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::ram::LinearRam;
+
+    #[test]
+    fn names() {
+        assert_eq!(parse_name("readme.txt"), Some(*b"README  TXT"));
+        assert_eq!(parse_name("MBASIC"), Some(*b"MBASIC     "));
+        assert_eq!(parse_name("*.COM"), Some(*b"????????COM"));
+        assert_eq!(parse_name("A*.*"), Some(*b"A??????????"));
+        assert_eq!(parse_name("toolongname.txt"), None);
+        assert_eq!(parse_name("a.text"), None);
+        assert_eq!(parse_name(".txt"), None);
+
+        assert_eq!(host_name(b"README  TXT"), "README.TXT");
+        assert_eq!(host_name(b"MBASIC     "), "MBASIC");
+
+        assert!(matches(b"????????COM", b"MBASIC  COM"));
+        assert!(!matches(b"????????COM", b"MBASIC  BAS"));
+    }
+
+    #[test]
+    fn positions() {
+        let mut ram = LinearRam::new(0x10000);
+        let fcb = Fcb::new(0x5c);
+
+        fcb.set_current_record(&mut ram, 129);
+        assert_eq!(ram[0x5c + 32], 1);
+        assert_eq!(ram[0x5c + 12], 1);
+        assert_eq!(fcb.current_record(&ram), 129);
+
+        fcb.set_current_record(&mut ram, 4096 + 5);
+        assert_eq!(ram[0x5c + 14], 1);
+        assert_eq!(fcb.current_record(&ram), 4101);
+
+        fcb.set_random_record(&mut ram, 0x012345);
+        assert_eq!(ram[0x5c + 33], 0x45);
+        assert_eq!(fcb.random_record(&ram), 0x012345);
+
+        fcb.set_current_record(&mut ram, 130);
+        fcb.set_record_count(&mut ram, 200);
+        assert_eq!(ram[0x5c + 15], 72);
+    }
+}
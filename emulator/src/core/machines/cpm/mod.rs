@@ -0,0 +1,253 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::core::emulator::{EResult, Emulator};
+use crate::core::ram::LinearRam;
+
+use fcb::{parse_name, Fcb, HostDirectory};
+
+mod bdos;
+pub mod fcb;
+
+const MEMORY_SIZE: usize = 0x10000;
+pub const TPA_START: u16 = 0x0100;
+pub const BDOS_ENTRY: u16 = 0x0005;
+const BDOS_BASE: u16 = 0xfe06;
+const BIOS_BASE: u16 = 0xff00;
+pub const DEFAULT_FCB: u16 = 0x005c;
+const SECOND_FCB: u16 = 0x006c;
+pub const DEFAULT_DMA: u16 = 0x0080;
+
+/*
+ * Why the emulated program stopped running
+ */
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CpmExit {
+    // Warm boot: JMP 0, RET from the TPA or BDOS function 0
+    Terminated,
+    // HLT instruction
+    Halted,
+    // Console input was requested but none is queued, call run() again after send_input()
+    WaitingForInput,
+    InstructionLimit,
+}
+
+/*
+ * CP/M 2.2 machine running .COM files directly in the TPA
+ *
+ * There is no real BDOS or BIOS in memory: calls to 0005H are
+ * intercepted and handled on the host, files are read from and
+ * written to a host directory acting as drive A:.
+ */
+pub struct Cpm {
+    emulator: Emulator,
+    directory: HostDirectory,
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+    dma: u16,
+    search_results: VecDeque<[u8; 11]>,
+}
+
+impl Cpm {
+    pub fn new(directory: &Path) -> Self {
+        let mut cpm = Self {
+            emulator: Emulator::with_ram(Box::new(LinearRam::new(MEMORY_SIZE))),
+            directory: HostDirectory::new(directory),
+            input: VecDeque::new(),
+            output: Vec::new(),
+            dma: DEFAULT_DMA,
+            search_results: VecDeque::new(),
+        };
+        cpm.setup_zero_page();
+        cpm
+    }
+
+    fn setup_zero_page(&mut self) {
+        let ram = self.emulator.get_ram_mut();
+        // 0000H: JMP BIOS warm boot
+        ram.load_vec(vec![0xc3, 0x03, (BIOS_BASE >> 8) as u8], 0x0000);
+        // 0003H: IOBYTE, 0004H: current drive/user
+        ram.load_vec(vec![0x00, 0x00], 0x0003);
+        // 0005H: JMP BDOS, 0006H also tells programs the top of the TPA
+        ram.load_vec(vec![0xc3, BDOS_BASE as u8, (BDOS_BASE >> 8) as u8], BDOS_ENTRY);
+        // Halt if anything ever jumps into the BDOS/BIOS area
+        ram.load_vec(vec![0x76], BDOS_BASE);
+        ram.load_vec(vec![0x76; 0x100], BIOS_BASE);
+
+        self.dma = DEFAULT_DMA;
+        self.set_command_tail("");
+    }
+
+    /*
+     * Load a .COM file at 0100H and prepare the stack so that
+     * a RET from the program returns to the warm boot vector
+     */
+    pub fn load_com(&mut self, path: &str) -> io::Result<()> {
+        let mut f = File::open(path)?;
+        let mut bytes = Vec::new();
+        f.read_to_end(&mut bytes)?;
+        if bytes.len() > (BDOS_BASE - TPA_START) as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Program does not fit into the TPA"));
+        }
+        self.load(bytes);
+        Ok(())
+    }
+
+    pub fn load(&mut self, program: Vec<u8>) {
+        self.emulator.load_ram(program, TPA_START);
+        self.emulator.set_pc(TPA_START);
+        self.emulator.set_sp(BDOS_BASE);
+        self.emulator.push(0x0000).unwrap();
+    }
+
+    /*
+     * Fill the command tail at 0080H and parse the first two
+     * arguments into the default FCBs, like the CCP does
+     */
+    pub fn set_command_tail(&mut self, tail: &str) {
+        let tail = tail.trim().to_ascii_uppercase();
+        let ram = self.emulator.get_ram_mut();
+        let mut bytes = Vec::new();
+        if !tail.is_empty() {
+            bytes.push(b' ');
+            bytes.extend(tail.bytes().take(126));
+        }
+        ram[DEFAULT_DMA] = bytes.len() as u8;
+        ram.load_vec(bytes, DEFAULT_DMA + 1);
+
+        let mut args = tail.split_whitespace();
+        for address in [DEFAULT_FCB, SECOND_FCB] {
+            let fcb = Fcb::new(address);
+            // The second FCB overlaps the first one, only clear its name part
+            if address == DEFAULT_FCB {
+                fcb.clear(ram);
+            } else {
+                ram[address] = 0;
+                fcb.set_name(ram, &[b' '; 11]);
+            }
+            if let Some(arg) = args.next() {
+                let (drive, name) = match arg.split_once(':') {
+                    Some((d, n)) if matches!(d.as_bytes(), [b'A'..=b'P']) => (d.as_bytes()[0] - b'A' + 1, n),
+                    _ => (0, arg),
+                };
+                ram[address] = drive;
+                if let Some(name) = parse_name(name) {
+                    fcb.set_name(ram, &name);
+                }
+            }
+        }
+    }
+
+    /*
+     * Run until the program exits, halts, needs input
+     * or max_instructions have been executed
+     */
+    pub fn run(&mut self, max_instructions: usize) -> EResult<CpmExit> {
+        let mut executed = 0;
+        while executed < max_instructions {
+            if !self.emulator.is_running() {
+                return Ok(CpmExit::Halted);
+            }
+            match self.emulator.get_pc() {
+                0x0000 => return Ok(CpmExit::Terminated),
+                BDOS_ENTRY => {
+                    if let Some(exit) = self.bdos()? {
+                        return Ok(exit);
+                    }
+                    self.emulator.ret()?;
+                }
+                _ => self.emulator.step()?,
+            }
+            executed += 1;
+        }
+        Ok(CpmExit::InstructionLimit)
+    }
+
+    /*
+     * Queue console input, line feeds are sent as carriage returns
+     */
+    pub fn send_input(&mut self, input: &str) {
+        self.input
+            .extend(input.bytes().map(|b| if b == b'\n' { b'\r' } else { b }));
+    }
+
+    pub fn take_output(&mut self) -> String {
+        let bytes = std::mem::take(&mut self.output);
+        bytes.iter().map(|&b| (b & 0x7f) as char).collect()
+    }
+
+    pub fn emulator(&self) -> &Emulator {
+        &self.emulator
+    }
+
+    pub fn emulator_mut(&mut self) -> &mut Emulator {
+        &mut self.emulator
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_page() {
+        let mut cpm = Cpm::new(Path::new("."));
+        cpm.set_command_tail("foo.txt b:bar.*");
+        let ram = cpm.emulator().get_ram();
+
+        assert_eq!(ram[0x0005], 0xc3);
+        assert_eq!((ram[0x0007] as u16) << 8 | ram[0x0006] as u16, BDOS_BASE);
+
+        assert_eq!(ram[0x0080], 16);
+        assert_eq!(ram[0x0081], b' ');
+        assert_eq!(ram[0x0082], b'F');
+
+        assert_eq!(ram[DEFAULT_FCB], 0);
+        assert_eq!(Fcb::new(DEFAULT_FCB).name(ram), *b"FOO     TXT");
+        assert_eq!(ram[SECOND_FCB], 2);
+        assert_eq!(Fcb::new(SECOND_FCB).name(ram), *b"BAR     ???");
+
+        // Only A to P are drives
+        cpm.set_command_tail("1:foo @:x");
+        let ram = cpm.emulator().get_ram();
+        assert_eq!(ram[DEFAULT_FCB], 0);
+        assert_eq!(ram[SECOND_FCB], 0);
+        cpm.set_command_tail("p:foo q:x");
+        let ram = cpm.emulator().get_ram();
+        assert_eq!(ram[DEFAULT_FCB], 16);
+        assert_eq!(ram[SECOND_FCB], 0);
+    }
+
+    #[test]
+    fn hello_world() {
+        let mut cpm = Cpm::new(Path::new("."));
+        cpm.load(vec![
+            0x0e, 0x09, // MVI C, 9
+            0x11, 0x09, 0x01, // LXI D, MSG
+            0xcd, 0x05, 0x00, // CALL 5
+            0xc9, // RET
+            b'H', b'E', b'L', b'L', b'O', b'$',
+        ]);
+
+        assert_eq!(cpm.run(1000), Ok(CpmExit::Terminated));
+        assert_eq!(cpm.take_output(), "HELLO");
+    }
+
+    #[test]
+    fn wait_for_input() {
+        let mut cpm = Cpm::new(Path::new("."));
+        cpm.load(vec![
+            0x0e, 0x01, // MVI C, 1
+            0xcd, 0x05, 0x00, // CALL 5
+            0x76, // HLT
+        ]);
+
+        assert_eq!(cpm.run(1000), Ok(CpmExit::WaitingForInput));
+        cpm.send_input("x");
+        assert_eq!(cpm.run(1000), Ok(CpmExit::Halted));
+        assert_eq!(cpm.emulator().get_registers()['a'], b'x');
+        assert_eq!(cpm.take_output(), "x");
+    }
+}
@@ -0,0 +1,183 @@
+use std::cell::RefCell;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::core::devices::console::Console;
+use crate::core::devices::disk::{DiskController, DiskImage, STATUS_OK};
+use crate::core::emulator::{EResult, Emulator};
+use crate::core::io::OutputDevice;
+use crate::core::ram::LinearRam;
+
+const MEMORY_SIZE: usize = 0x10000;
+const CONSOLE_PORT: u8 = 0;
+const FDC_PORT: u8 = 10;
+
+/*
+ * Machine with the I/O layout of the z80pack "cpmsim" system:
+ * console on ports 0/1 and the floppy disk controller on ports 10-17
+ *
+ * boot() loads track 0, sector 1 of drive A: to 0000H and starts it,
+ * which is what the boot loaders of the CP/M disk images expect.
+ */
+pub struct CpmSim {
+    emulator: Emulator,
+    console: Rc<RefCell<Console>>,
+    fdc: Rc<RefCell<DiskController>>,
+}
+
+impl CpmSim {
+    pub fn new() -> Self {
+        Self::with_memory(MEMORY_SIZE)
+    }
+
+    /*
+     * Smaller machine, the size has to be a power of two
+     */
+    pub fn with_memory(size: usize) -> Self {
+        let mut emulator = Emulator::with_ram(Box::new(LinearRam::new(size)));
+        let console = Rc::new(RefCell::new(Console::new(CONSOLE_PORT)));
+        let fdc = Rc::new(RefCell::new(DiskController::new(FDC_PORT)));
+
+        for port in console.borrow().ports() {
+            emulator.register_input_device(console.clone(), port as usize).unwrap();
+            emulator.register_output_device(console.clone(), port as usize).unwrap();
+        }
+        for port in fdc.borrow().ports() {
+            emulator.register_input_device(fdc.clone(), port as usize).unwrap();
+            emulator.register_output_device(fdc.clone(), port as usize).unwrap();
+        }
+
+        Self {
+            emulator,
+            console,
+            fdc,
+        }
+    }
+
+    pub fn insert_disk(&mut self, drive: usize, path: &Path, read_only: bool) -> io::Result<()> {
+        let disk = DiskImage::open(path, read_only)?;
+        self.insert_image(drive, disk).map_err(|message| io::Error::new(io::ErrorKind::InvalidInput, message))
+    }
+
+    pub fn insert_image(&mut self, drive: usize, disk: DiskImage) -> Result<(), &'static str> {
+        self.fdc.borrow_mut().insert(drive, disk)
+    }
+
+    /*
+     * Read the boot sector to 0000H and jump to it
+     */
+    pub fn boot(&mut self) -> EResult<()> {
+        let mut fdc = self.fdc.borrow_mut();
+        for (offset, value) in [(0, 0), (1, 0), (2, 1), (5, 0), (6, 0), (3, 0)] {
+            fdc.write(FDC_PORT + offset, value);
+        }
+        fdc.transfer(self.emulator.get_ram_mut());
+        if fdc.status() != STATUS_OK {
+            return Err("Could not read boot sector");
+        }
+        self.emulator.set_pc(0x0000);
+        Ok(())
+    }
+
+    /*
+     * Run until HLT or until max_instructions have been executed
+     * Returns the number of executed instructions
+     */
+    pub fn run(&mut self, max_instructions: usize) -> EResult<usize> {
+        let mut executed = 0;
+        while self.emulator.is_running() && executed < max_instructions {
+            self.emulator.step()?;
+            executed += 1;
+        }
+        Ok(executed)
+    }
+
+    pub fn send_input(&mut self, input: &str) {
+        let bytes: Vec<u8> = input
+            .bytes()
+            .map(|b| if b == b'\n' { b'\r' } else { b })
+            .collect();
+        self.console.borrow_mut().send(&bytes);
+    }
+
+    pub fn take_output(&mut self) -> String {
+        let bytes = self.console.borrow_mut().take_output();
+        bytes.iter().map(|&b| (b & 0x7f) as char).collect()
+    }
+
+    pub fn disk_controller(&self) -> Rc<RefCell<DiskController>> {
+        self.fdc.clone()
+    }
+
+    pub fn emulator(&self) -> &Emulator {
+        &self.emulator
+    }
+
+    pub fn emulator_mut(&mut self) -> &mut Emulator {
+        &mut self.emulator
+    }
+}
+
+impl Default for CpmSim {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::devices::disk::{IMAGE_SIZE, SECTORS, SECTOR_SIZE};
+
+    // Boot sector: load track 1, sector 1 to 0100H, print its first byte, write it to track 76
+    const BOOT: [u8; 44] = [
+        0x3e, 0x00, 0xd3, 0x0a, // MVI A,0 / OUT 10
+        0x3e, 0x01, 0xd3, 0x0b, // MVI A,1 / OUT 11
+        0x3e, 0x01, 0xd3, 0x0c, // MVI A,1 / OUT 12
+        0x3e, 0x00, 0xd3, 0x0f, // MVI A,0 / OUT 15
+        0x3e, 0x01, 0xd3, 0x10, // MVI A,1 / OUT 16
+        0x3e, 0x00, 0xd3, 0x0d, // MVI A,0 / OUT 13 (read)
+        0x3a, 0x00, 0x01, 0xd3, 0x01, // LDA 0100H / OUT 1
+        0x3e, 0x4c, 0xd3, 0x0b, // MVI A,76 / OUT 11
+        0x3e, 0x01, 0xd3, 0x0d, // MVI A,1 / OUT 13 (write)
+        0xdb, 0x0e, 0xc6, 0x30, 0xd3, 0x01, // IN 14 / ADI '0' / OUT 1
+        0x76, // HLT
+    ];
+
+    #[test]
+    fn boot() {
+        let mut image = vec![0xe5; IMAGE_SIZE];
+        image[..BOOT.len()].copy_from_slice(&BOOT);
+        image[SECTORS * SECTOR_SIZE] = b'C';
+
+        let mut sim = CpmSim::new();
+        sim.insert_image(0, DiskImage::from_bytes(image)).expect("Fuck");
+        sim.boot().expect("Fuck");
+        sim.run(1000).expect("Fuck");
+
+        assert!(!sim.emulator().is_running());
+        assert_eq!(sim.take_output(), "C0");
+
+        let fdc = sim.disk_controller();
+        let fdc = fdc.borrow();
+        let data = fdc.disk(0).unwrap().data();
+        assert_eq!(data[76 * SECTORS * SECTOR_SIZE], b'C');
+    }
+
+    #[test]
+    fn boot_without_disk() {
+        let mut sim = CpmSim::new();
+        assert_eq!(sim.boot(), Err("Could not read boot sector"));
+    }
+
+    #[test]
+    fn illegal_drive() {
+        let path = std::env::temp_dir().join("cpmsim_illegal_drive.dsk");
+        std::fs::write(&path, vec![0xe5; IMAGE_SIZE]).expect("Fuck");
+        let mut sim = CpmSim::new();
+        let error = sim.insert_disk(4, &path, true).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(sim.insert_disk(3, &path, true).is_ok());
+    }
+}
@@ -0,0 +1,4 @@
+pub mod altair;
+pub mod cpm;
+pub mod cpmsim;
+pub mod trainer;
@@ -0,0 +1,110 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::core::devices::ppi::Ppi8255;
+use crate::core::emulator::{EResult, Emulator};
+use crate::core::ram::LinearRam;
+
+const MEMORY_SIZE: usize = 0x10000;
+pub const DEFAULT_PPI_PORT: u8 = 0x80;
+
+/*
+ * Microprocessor lab trainer: 64K of RAM and an 8255 whose pins
+ * are wired to LEDs, 7-segment displays and switches on the host side
+ */
+pub struct Trainer {
+    emulator: Emulator,
+    ppi: Rc<RefCell<Ppi8255>>,
+}
+
+impl Trainer {
+    pub fn new(ppi_port: u8) -> Self {
+        let mut emulator = Emulator::with_ram(Box::new(LinearRam::new(MEMORY_SIZE)));
+        let ppi = Rc::new(RefCell::new(Ppi8255::new(ppi_port)));
+
+        for port in ppi.borrow().ports() {
+            emulator
+                .register_input_device(ppi.clone(), port as usize)
+                .unwrap();
+            emulator
+                .register_output_device(ppi.clone(), port as usize)
+                .unwrap();
+        }
+        emulator.register_clocked_device(ppi.clone());
+
+        Self { emulator, ppi }
+    }
+
+    pub fn load(&mut self, data: Vec<u8>, start: u16) {
+        self.emulator.load_ram(data, start);
+    }
+
+    pub fn boot(&mut self, address: u16) {
+        self.emulator.set_pc(address);
+    }
+
+    /*
+     * Run until HLT or until max_instructions have been executed
+     * Returns the number of executed instructions
+     */
+    pub fn run(&mut self, max_instructions: usize) -> EResult<usize> {
+        let mut executed = 0;
+        while self.emulator.is_running() && executed < max_instructions {
+            self.emulator.step()?;
+            executed += 1;
+        }
+        Ok(executed)
+    }
+
+    pub fn ppi(&self) -> Rc<RefCell<Ppi8255>> {
+        self.ppi.clone()
+    }
+
+    pub fn emulator(&self) -> &Emulator {
+        &self.emulator
+    }
+
+    pub fn emulator_mut(&mut self) -> &mut Emulator {
+        &mut self.emulator
+    }
+}
+
+impl Default for Trainer {
+    fn default() -> Self {
+        Self::new(DEFAULT_PPI_PORT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Copy the switches on port B to the LEDs on port A until PC0 is set
+    const SWITCHES: [u8; 16] = [
+        0x3e, 0x83, // 0000: MVI A,83H
+        0xd3, 0x83, // 0002: OUT 83H
+        0xdb, 0x81, // 0004: IN 81H
+        0xd3, 0x80, // 0006: OUT 80H
+        0xdb, 0x82, // 0008: IN 82H
+        0xe6, 0x01, // 000A: ANI 01H
+        0xca, 0x04, 0x00, // 000C: JZ 0004H
+        0x76, // 000F: HLT
+    ];
+
+    #[test]
+    fn switches_to_leds() {
+        let mut trainer = Trainer::default();
+        trainer.load(SWITCHES.to_vec(), 0);
+        trainer.ppi().borrow_mut().set_port_c(0x00);
+        trainer.ppi().borrow_mut().set_port_b(0xa5);
+
+        assert_eq!(trainer.run(20), Ok(20));
+        assert_eq!(trainer.ppi().borrow().port_a(), 0xa5);
+
+        trainer.ppi().borrow_mut().set_port_b(0x18);
+        trainer.ppi().borrow_mut().set_port_c(0x01);
+        trainer.run(100).expect("Fuck");
+        assert!(!trainer.emulator().is_running());
+        assert_eq!(trainer.ppi().borrow().port_a(), 0x18);
+    }
+}
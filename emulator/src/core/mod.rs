@@ -1,4 +1,8 @@
+pub mod devices;
 pub mod emulator;
+pub mod hex;
 pub mod io;
+pub mod machines;
+pub mod opcodes;
 pub mod ram;
 pub mod register;
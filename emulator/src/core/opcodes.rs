@@ -0,0 +1,590 @@
+use std::fmt;
+
+/*
+ * Flag bits as they are laid out in the PSW
+ */
+pub const SIGN: u8 = 0x80;
+pub const ZERO: u8 = 0x40;
+pub const AUX: u8 = 0x10;
+pub const PARITY: u8 = 0x04;
+pub const CARRY: u8 = 0x01;
+
+/*
+ * Undocumented flags of the 8085, the 8080 always pushes
+ * bit 5 cleared and bit 1 set
+ */
+pub const K: u8 = 0x20;
+pub const OVERFLOW: u8 = 0x02;
+
+const NONE: u8 = 0;
+const SZAP: u8 = SIGN | ZERO | AUX | PARITY;
+const ALL: u8 = SZAP | CARRY;
+const ALL_8085: u8 = ALL | K | OVERFLOW;
+
+const FLAG_NAMES: [(u8, &str); 7] = [
+    (SIGN, "S"),
+    (ZERO, "Z"),
+    (K, "K"),
+    (AUX, "AC"),
+    (PARITY, "P"),
+    (OVERFLOW, "V"),
+    (CARRY, "CY"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operand {
+    Register(&'static str),
+    Vector(u8),
+    Data8,
+    Data16,
+    Address,
+    Port,
+}
+
+const B: Operand = Operand::Register("B");
+const C: Operand = Operand::Register("C");
+const D: Operand = Operand::Register("D");
+const E: Operand = Operand::Register("E");
+const H: Operand = Operand::Register("H");
+const L: Operand = Operand::Register("L");
+const M: Operand = Operand::Register("M");
+const A: Operand = Operand::Register("A");
+const SP: Operand = Operand::Register("SP");
+const PSW: Operand = Operand::Register("PSW");
+const D8: Operand = Operand::Data8;
+const D16: Operand = Operand::Data16;
+const ADR: Operand = Operand::Address;
+const PORT: Operand = Operand::Port;
+
+impl Operand {
+    /*
+     * Registers and RST numbers are encoded in the opcode itself
+     */
+    pub fn is_fixed(&self) -> bool {
+        matches!(self, Operand::Register(_) | Operand::Vector(_))
+    }
+
+    /*
+     * Number of bytes following the opcode
+     */
+    pub fn size(&self) -> usize {
+        match self {
+            Operand::Register(_) | Operand::Vector(_) => 0,
+            Operand::Data8 | Operand::Port => 1,
+            Operand::Data16 | Operand::Address => 2,
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Register(name) => write!(f, "{}", name),
+            Operand::Vector(number) => write!(f, "{}", number),
+            Operand::Data8 => write!(f, "D8"),
+            Operand::Data16 => write!(f, "D16"),
+            Operand::Address => write!(f, "adr"),
+            Operand::Port => write!(f, "port"),
+        }
+    }
+}
+
+/*
+ * Everything known about a single opcode
+ *
+ * Conditional calls and returns take cycles_taken instead of
+ * cycles when the condition holds. Undocumented opcodes are
+ * aliases of documented instructions the CPU executes anyway.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Opcode {
+    pub opcode: u8,
+    pub mnemonic: &'static str,
+    pub operands: &'static [Operand],
+    pub length: usize,
+    pub cycles: u8,
+    pub cycles_taken: u8,
+    pub flags: u8,
+    pub documented: bool,
+}
+
+impl Opcode {
+    /*
+     * Names of the affected flags, e.g. ["S", "Z", "AC", "P"]
+     */
+    pub fn flag_names(&self) -> Vec<&'static str> {
+        FLAG_NAMES
+            .iter()
+            .filter(|(bit, _)| self.flags & bit != 0)
+            .map(|(_, name)| *name)
+            .collect()
+    }
+}
+
+/*
+ * Instruction template, e.g. "LXI B,D16"
+ */
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.mnemonic)?;
+        for (index, operand) in self.operands.iter().enumerate() {
+            write!(f, "{}{}", if index == 0 { " " } else { "," }, operand)?;
+        }
+        Ok(())
+    }
+}
+
+const fn op(opcode: u8, mnemonic: &'static str, operands: &'static [Operand], length: usize, cycles: u8, flags: u8) -> Opcode {
+    Opcode {
+        opcode,
+        mnemonic,
+        operands,
+        length,
+        cycles,
+        cycles_taken: cycles,
+        flags,
+        documented: true,
+    }
+}
+
+const fn branch(opcode: u8, mnemonic: &'static str, operands: &'static [Operand], length: usize, cycles: u8, cycles_taken: u8) -> Opcode {
+    Opcode {
+        cycles_taken,
+        ..op(opcode, mnemonic, operands, length, cycles, NONE)
+    }
+}
+
+const fn hidden(opcode: Opcode) -> Opcode {
+    Opcode {
+        documented: false,
+        ..opcode
+    }
+}
+
+const fn alias(opcode: u8, mnemonic: &'static str, operands: &'static [Operand], length: usize, cycles: u8) -> Opcode {
+    Opcode {
+        documented: false,
+        ..op(opcode, mnemonic, operands, length, cycles, NONE)
+    }
+}
+
+/*
+ * All 256 opcodes of the 8080, indexed by opcode
+ */
+pub static OPCODES: [Opcode; 256] = [
+    op(0x00, "NOP", &[], 1, 4, NONE),
+    op(0x01, "LXI", &[B, D16], 3, 10, NONE),
+    op(0x02, "STAX", &[B], 1, 7, NONE),
+    op(0x03, "INX", &[B], 1, 5, NONE),
+    op(0x04, "INR", &[B], 1, 5, SZAP),
+    op(0x05, "DCR", &[B], 1, 5, SZAP),
+    op(0x06, "MVI", &[B, D8], 2, 7, NONE),
+    op(0x07, "RLC", &[], 1, 4, CARRY),
+    alias(0x08, "NOP", &[], 1, 4),
+    op(0x09, "DAD", &[B], 1, 10, CARRY),
+    op(0x0a, "LDAX", &[B], 1, 7, NONE),
+    op(0x0b, "DCX", &[B], 1, 5, NONE),
+    op(0x0c, "INR", &[C], 1, 5, SZAP),
+    op(0x0d, "DCR", &[C], 1, 5, SZAP),
+    op(0x0e, "MVI", &[C, D8], 2, 7, NONE),
+    op(0x0f, "RRC", &[], 1, 4, CARRY),
+    alias(0x10, "NOP", &[], 1, 4),
+    op(0x11, "LXI", &[D, D16], 3, 10, NONE),
+    op(0x12, "STAX", &[D], 1, 7, NONE),
+    op(0x13, "INX", &[D], 1, 5, NONE),
+    op(0x14, "INR", &[D], 1, 5, SZAP),
+    op(0x15, "DCR", &[D], 1, 5, SZAP),
+    op(0x16, "MVI", &[D, D8], 2, 7, NONE),
+    op(0x17, "RAL", &[], 1, 4, CARRY),
+    alias(0x18, "NOP", &[], 1, 4),
+    op(0x19, "DAD", &[D], 1, 10, CARRY),
+    op(0x1a, "LDAX", &[D], 1, 7, NONE),
+    op(0x1b, "DCX", &[D], 1, 5, NONE),
+    op(0x1c, "INR", &[E], 1, 5, SZAP),
+    op(0x1d, "DCR", &[E], 1, 5, SZAP),
+    op(0x1e, "MVI", &[E, D8], 2, 7, NONE),
+    op(0x1f, "RAR", &[], 1, 4, CARRY),
+    alias(0x20, "NOP", &[], 1, 4),
+    op(0x21, "LXI", &[H, D16], 3, 10, NONE),
+    op(0x22, "SHLD", &[ADR], 3, 16, NONE),
+    op(0x23, "INX", &[H], 1, 5, NONE),
+    op(0x24, "INR", &[H], 1, 5, SZAP),
+    op(0x25, "DCR", &[H], 1, 5, SZAP),
+    op(0x26, "MVI", &[H, D8], 2, 7, NONE),
+    op(0x27, "DAA", &[], 1, 4, ALL),
+    alias(0x28, "NOP", &[], 1, 4),
+    op(0x29, "DAD", &[H], 1, 10, CARRY),
+    op(0x2a, "LHLD", &[ADR], 3, 16, NONE),
+    op(0x2b, "DCX", &[H], 1, 5, NONE),
+    op(0x2c, "INR", &[L], 1, 5, SZAP),
+    op(0x2d, "DCR", &[L], 1, 5, SZAP),
+    op(0x2e, "MVI", &[L, D8], 2, 7, NONE),
+    op(0x2f, "CMA", &[], 1, 4, NONE),
+    alias(0x30, "NOP", &[], 1, 4),
+    op(0x31, "LXI", &[SP, D16], 3, 10, NONE),
+    op(0x32, "STA", &[ADR], 3, 13, NONE),
+    op(0x33, "INX", &[SP], 1, 5, NONE),
+    op(0x34, "INR", &[M], 1, 10, SZAP),
+    op(0x35, "DCR", &[M], 1, 10, SZAP),
+    op(0x36, "MVI", &[M, D8], 2, 10, NONE),
+    op(0x37, "STC", &[], 1, 4, CARRY),
+    alias(0x38, "NOP", &[], 1, 4),
+    op(0x39, "DAD", &[SP], 1, 10, CARRY),
+    op(0x3a, "LDA", &[ADR], 3, 13, NONE),
+    op(0x3b, "DCX", &[SP], 1, 5, NONE),
+    op(0x3c, "INR", &[A], 1, 5, SZAP),
+    op(0x3d, "DCR", &[A], 1, 5, SZAP),
+    op(0x3e, "MVI", &[A, D8], 2, 7, NONE),
+    op(0x3f, "CMC", &[], 1, 4, CARRY),
+    op(0x40, "MOV", &[B, B], 1, 5, NONE),
+    op(0x41, "MOV", &[B, C], 1, 5, NONE),
+    op(0x42, "MOV", &[B, D], 1, 5, NONE),
+    op(0x43, "MOV", &[B, E], 1, 5, NONE),
+    op(0x44, "MOV", &[B, H], 1, 5, NONE),
+    op(0x45, "MOV", &[B, L], 1, 5, NONE),
+    op(0x46, "MOV", &[B, M], 1, 7, NONE),
+    op(0x47, "MOV", &[B, A], 1, 5, NONE),
+    op(0x48, "MOV", &[C, B], 1, 5, NONE),
+    op(0x49, "MOV", &[C, C], 1, 5, NONE),
+    op(0x4a, "MOV", &[C, D], 1, 5, NONE),
+    op(0x4b, "MOV", &[C, E], 1, 5, NONE),
+    op(0x4c, "MOV", &[C, H], 1, 5, NONE),
+    op(0x4d, "MOV", &[C, L], 1, 5, NONE),
+    op(0x4e, "MOV", &[C, M], 1, 7, NONE),
+    op(0x4f, "MOV", &[C, A], 1, 5, NONE),
+    op(0x50, "MOV", &[D, B], 1, 5, NONE),
+    op(0x51, "MOV", &[D, C], 1, 5, NONE),
+    op(0x52, "MOV", &[D, D], 1, 5, NONE),
+    op(0x53, "MOV", &[D, E], 1, 5, NONE),
+    op(0x54, "MOV", &[D, H], 1, 5, NONE),
+    op(0x55, "MOV", &[D, L], 1, 5, NONE),
+    op(0x56, "MOV", &[D, M], 1, 7, NONE),
+    op(0x57, "MOV", &[D, A], 1, 5, NONE),
+    op(0x58, "MOV", &[E, B], 1, 5, NONE),
+    op(0x59, "MOV", &[E, C], 1, 5, NONE),
+    op(0x5a, "MOV", &[E, D], 1, 5, NONE),
+    op(0x5b, "MOV", &[E, E], 1, 5, NONE),
+    op(0x5c, "MOV", &[E, H], 1, 5, NONE),
+    op(0x5d, "MOV", &[E, L], 1, 5, NONE),
+    op(0x5e, "MOV", &[E, M], 1, 7, NONE),
+    op(0x5f, "MOV", &[E, A], 1, 5, NONE),
+    op(0x60, "MOV", &[H, B], 1, 5, NONE),
+    op(0x61, "MOV", &[H, C], 1, 5, NONE),
+    op(0x62, "MOV", &[H, D], 1, 5, NONE),
+    op(0x63, "MOV", &[H, E], 1, 5, NONE),
+    op(0x64, "MOV", &[H, H], 1, 5, NONE),
+    op(0x65, "MOV", &[H, L], 1, 5, NONE),
+    op(0x66, "MOV", &[H, M], 1, 7, NONE),
+    op(0x67, "MOV", &[H, A], 1, 5, NONE),
+    op(0x68, "MOV", &[L, B], 1, 5, NONE),
+    op(0x69, "MOV", &[L, C], 1, 5, NONE),
+    op(0x6a, "MOV", &[L, D], 1, 5, NONE),
+    op(0x6b, "MOV", &[L, E], 1, 5, NONE),
+    op(0x6c, "MOV", &[L, H], 1, 5, NONE),
+    op(0x6d, "MOV", &[L, L], 1, 5, NONE),
+    op(0x6e, "MOV", &[L, M], 1, 7, NONE),
+    op(0x6f, "MOV", &[L, A], 1, 5, NONE),
+    op(0x70, "MOV", &[M, B], 1, 7, NONE),
+    op(0x71, "MOV", &[M, C], 1, 7, NONE),
+    op(0x72, "MOV", &[M, D], 1, 7, NONE),
+    op(0x73, "MOV", &[M, E], 1, 7, NONE),
+    op(0x74, "MOV", &[M, H], 1, 7, NONE),
+    op(0x75, "MOV", &[M, L], 1, 7, NONE),
+    op(0x76, "HLT", &[], 1, 7, NONE),
+    op(0x77, "MOV", &[M, A], 1, 7, NONE),
+    op(0x78, "MOV", &[A, B], 1, 5, NONE),
+    op(0x79, "MOV", &[A, C], 1, 5, NONE),
+    op(0x7a, "MOV", &[A, D], 1, 5, NONE),
+    op(0x7b, "MOV", &[A, E], 1, 5, NONE),
+    op(0x7c, "MOV", &[A, H], 1, 5, NONE),
+    op(0x7d, "MOV", &[A, L], 1, 5, NONE),
+    op(0x7e, "MOV", &[A, M], 1, 7, NONE),
+    op(0x7f, "MOV", &[A, A], 1, 5, NONE),
+    op(0x80, "ADD", &[B], 1, 4, ALL),
+    op(0x81, "ADD", &[C], 1, 4, ALL),
+    op(0x82, "ADD", &[D], 1, 4, ALL),
+    op(0x83, "ADD", &[E], 1, 4, ALL),
+    op(0x84, "ADD", &[H], 1, 4, ALL),
+    op(0x85, "ADD", &[L], 1, 4, ALL),
+    op(0x86, "ADD", &[M], 1, 7, ALL),
+    op(0x87, "ADD", &[A], 1, 4, ALL),
+    op(0x88, "ADC", &[B], 1, 4, ALL),
+    op(0x89, "ADC", &[C], 1, 4, ALL),
+    op(0x8a, "ADC", &[D], 1, 4, ALL),
+    op(0x8b, "ADC", &[E], 1, 4, ALL),
+    op(0x8c, "ADC", &[H], 1, 4, ALL),
+    op(0x8d, "ADC", &[L], 1, 4, ALL),
+    op(0x8e, "ADC", &[M], 1, 7, ALL),
+    op(0x8f, "ADC", &[A], 1, 4, ALL),
+    op(0x90, "SUB", &[B], 1, 4, ALL),
+    op(0x91, "SUB", &[C], 1, 4, ALL),
+    op(0x92, "SUB", &[D], 1, 4, ALL),
+    op(0x93, "SUB", &[E], 1, 4, ALL),
+    op(0x94, "SUB", &[H], 1, 4, ALL),
+    op(0x95, "SUB", &[L], 1, 4, ALL),
+    op(0x96, "SUB", &[M], 1, 7, ALL),
+    op(0x97, "SUB", &[A], 1, 4, ALL),
+    op(0x98, "SBB", &[B], 1, 4, ALL),
+    op(0x99, "SBB", &[C], 1, 4, ALL),
+    op(0x9a, "SBB", &[D], 1, 4, ALL),
+    op(0x9b, "SBB", &[E], 1, 4, ALL),
+    op(0x9c, "SBB", &[H], 1, 4, ALL),
+    op(0x9d, "SBB", &[L], 1, 4, ALL),
+    op(0x9e, "SBB", &[M], 1, 7, ALL),
+    op(0x9f, "SBB", &[A], 1, 4, ALL),
+    op(0xa0, "ANA", &[B], 1, 4, ALL),
+    op(0xa1, "ANA", &[C], 1, 4, ALL),
+    op(0xa2, "ANA", &[D], 1, 4, ALL),
+    op(0xa3, "ANA", &[E], 1, 4, ALL),
+    op(0xa4, "ANA", &[H], 1, 4, ALL),
+    op(0xa5, "ANA", &[L], 1, 4, ALL),
+    op(0xa6, "ANA", &[M], 1, 7, ALL),
+    op(0xa7, "ANA", &[A], 1, 4, ALL),
+    op(0xa8, "XRA", &[B], 1, 4, ALL),
+    op(0xa9, "XRA", &[C], 1, 4, ALL),
+    op(0xaa, "XRA", &[D], 1, 4, ALL),
+    op(0xab, "XRA", &[E], 1, 4, ALL),
+    op(0xac, "XRA", &[H], 1, 4, ALL),
+    op(0xad, "XRA", &[L], 1, 4, ALL),
+    op(0xae, "XRA", &[M], 1, 7, ALL),
+    op(0xaf, "XRA", &[A], 1, 4, ALL),
+    op(0xb0, "ORA", &[B], 1, 4, ALL),
+    op(0xb1, "ORA", &[C], 1, 4, ALL),
+    op(0xb2, "ORA", &[D], 1, 4, ALL),
+    op(0xb3, "ORA", &[E], 1, 4, ALL),
+    op(0xb4, "ORA", &[H], 1, 4, ALL),
+    op(0xb5, "ORA", &[L], 1, 4, ALL),
+    op(0xb6, "ORA", &[M], 1, 7, ALL),
+    op(0xb7, "ORA", &[A], 1, 4, ALL),
+    op(0xb8, "CMP", &[B], 1, 4, ALL),
+    op(0xb9, "CMP", &[C], 1, 4, ALL),
+    op(0xba, "CMP", &[D], 1, 4, ALL),
+    op(0xbb, "CMP", &[E], 1, 4, ALL),
+    op(0xbc, "CMP", &[H], 1, 4, ALL),
+    op(0xbd, "CMP", &[L], 1, 4, ALL),
+    op(0xbe, "CMP", &[M], 1, 7, ALL),
+    op(0xbf, "CMP", &[A], 1, 4, ALL),
+    branch(0xc0, "RNZ",&[], 1, 5, 11),
+    op(0xc1, "POP", &[B], 1, 10, NONE),
+    op(0xc2, "JNZ", &[ADR], 3, 10, NONE),
+    op(0xc3, "JMP", &[ADR], 3, 10, NONE),
+    branch(0xc4, "CNZ",&[ADR], 3, 11, 17),
+    op(0xc5, "PUSH", &[B], 1, 11, NONE),
+    op(0xc6, "ADI", &[D8], 2, 7, ALL),
+    op(0xc7, "RST", &[Operand::Vector(0)], 1, 11, NONE),
+    branch(0xc8, "RZ", &[], 1, 5, 11),
+    op(0xc9, "RET", &[], 1, 10, NONE),
+    op(0xca, "JZ", &[ADR], 3, 10, NONE),
+    alias(0xcb, "JMP", &[ADR], 3, 10),
+    branch(0xcc, "CZ", &[ADR], 3, 11, 17),
+    op(0xcd, "CALL", &[ADR], 3, 17, NONE),
+    op(0xce, "ACI", &[D8], 2, 7, ALL),
+    op(0xcf, "RST", &[Operand::Vector(1)], 1, 11, NONE),
+    branch(0xd0, "RNC",&[], 1, 5, 11),
+    op(0xd1, "POP", &[D], 1, 10, NONE),
+    op(0xd2, "JNC", &[ADR], 3, 10, NONE),
+    op(0xd3, "OUT", &[PORT], 2, 10, NONE),
+    branch(0xd4, "CNC",&[ADR], 3, 11, 17),
+    op(0xd5, "PUSH", &[D], 1, 11, NONE),
+    op(0xd6, "SUI", &[D8], 2, 7, ALL),
+    op(0xd7, "RST", &[Operand::Vector(2)], 1, 11, NONE),
+    branch(0xd8, "RC", &[], 1, 5, 11),
+    alias(0xd9, "RET", &[], 1, 10),
+    op(0xda, "JC", &[ADR], 3, 10, NONE),
+    op(0xdb, "IN", &[PORT], 2, 10, NONE),
+    branch(0xdc, "CC", &[ADR], 3, 11, 17),
+    alias(0xdd, "CALL",&[ADR], 3, 17),
+    op(0xde, "SBI", &[D8], 2, 7, ALL),
+    op(0xdf, "RST", &[Operand::Vector(3)], 1, 11, NONE),
+    branch(0xe0, "RPO",&[], 1, 5, 11),
+    op(0xe1, "POP", &[H], 1, 10, NONE),
+    op(0xe2, "JPO", &[ADR], 3, 10, NONE),
+    op(0xe3, "XTHL", &[], 1, 18, NONE),
+    branch(0xe4, "CPO",&[ADR], 3, 11, 17),
+    op(0xe5, "PUSH", &[H], 1, 11, NONE),
+    op(0xe6, "ANI", &[D8], 2, 7, ALL),
+    op(0xe7, "RST", &[Operand::Vector(4)], 1, 11, NONE),
+    branch(0xe8, "RPE",&[], 1, 5, 11),
+    op(0xe9, "PCHL", &[], 1, 5, NONE),
+    op(0xea, "JPE", &[ADR], 3, 10, NONE),
+    op(0xeb, "XCHG", &[], 1, 4, NONE),
+    branch(0xec, "CPE",&[ADR], 3, 11, 17),
+    alias(0xed, "CALL",&[ADR], 3, 17),
+    op(0xee, "XRI", &[D8], 2, 7, ALL),
+    op(0xef, "RST", &[Operand::Vector(5)], 1, 11, NONE),
+    branch(0xf0, "RP", &[], 1, 5, 11),
+    op(0xf1, "POP", &[PSW], 1, 10, ALL),
+    op(0xf2, "JP", &[ADR], 3, 10, NONE),
+    op(0xf3, "DI", &[], 1, 4, NONE),
+    branch(0xf4, "CP", &[ADR], 3, 11, 17),
+    op(0xf5, "PUSH", &[PSW], 1, 11, NONE),
+    op(0xf6, "ORI", &[D8], 2, 7, ALL),
+    op(0xf7, "RST", &[Operand::Vector(6)], 1, 11, NONE),
+    branch(0xf8, "RM", &[], 1, 5, 11),
+    op(0xf9, "SPHL", &[], 1, 5, NONE),
+    op(0xfa, "JM", &[ADR], 3, 10, NONE),
+    op(0xfb, "EI", &[], 1, 4, NONE),
+    branch(0xfc, "CM", &[ADR], 3, 11, 17),
+    alias(0xfd, "CALL",&[ADR], 3, 17),
+    op(0xfe, "CPI", &[D8], 2, 7, ALL),
+    op(0xff, "RST", &[Operand::Vector(7)], 1, 11, NONE),
+];
+
+/*
+ * Opcodes the 8085 assigns to the undocumented aliases of the 8080,
+ * only RIM and SIM made it into Intel's documentation
+ */
+static EXTENSIONS_8085: [Opcode; 12] = [
+    hidden(op(0x08, "DSUB", &[], 1, 10, ALL_8085)),
+    hidden(op(0x10, "ARHL", &[], 1, 7, CARRY)),
+    hidden(op(0x18, "RDEL", &[], 1, 10, CARRY | OVERFLOW)),
+    op(0x20, "RIM", &[], 1, 4, NONE),
+    hidden(op(0x28, "LDHI", &[D8], 2, 10, NONE)),
+    op(0x30, "SIM", &[], 1, 4, NONE),
+    hidden(op(0x38, "LDSI", &[D8], 2, 10, NONE)),
+    hidden(branch(0xcb, "RSTV", &[], 1, 6, 12)),
+    hidden(op(0xd9, "SHLX", &[], 1, 10, NONE)),
+    hidden(branch(0xdd, "JNK", &[ADR], 3, 7, 10)),
+    hidden(op(0xed, "LHLX", &[], 1, 10, NONE)),
+    hidden(branch(0xfd, "JK", &[ADR], 3, 7, 10)),
+];
+
+/*
+ * Opcode as the 8085 executes it, with its own cycle counts
+ * and the K and overflow flags of its arithmetic instructions
+ */
+pub fn opcode_8085(opcode: u8) -> Opcode {
+    if let Some(extension) = EXTENSIONS_8085.iter().find(|extension| extension.opcode == opcode) {
+        return *extension;
+    }
+    let opcode = OPCODES[opcode as usize];
+    let conditional = opcode.cycles != opcode.cycles_taken;
+    let (cycles, cycles_taken) = match opcode.mnemonic {
+        "MOV" | "INR" | "DCR" if !opcode.operands.contains(&M) => (4, 4),
+        "INX" | "DCX" | "SPHL" | "PCHL" => (6, 6),
+        "JNZ" | "JZ" | "JNC" | "JC" | "JPO" | "JPE" | "JP" | "JM" => (7, 10),
+        "CALL" => (18, 18),
+        mnemonic if conditional && mnemonic.starts_with('C') => (9, 18),
+        mnemonic if conditional && mnemonic.starts_with('R') => (6, 12),
+        "RST" | "PUSH" => (12, 12),
+        "XTHL" => (16, 16),
+        "HLT" => (5, 5),
+        _ => (opcode.cycles, opcode.cycles_taken),
+    };
+    let flags = match opcode.mnemonic {
+        "ADD" | "ADC" | "SUB" | "SBB" | "CMP" | "ADI" | "ACI" | "SUI" | "SBI" | "CPI" | "INR" | "DCR" => {
+            opcode.flags | K | OVERFLOW
+        }
+        "INX" | "DCX" => K,
+        _ => opcode.flags,
+    };
+    Opcode {
+        cycles,
+        cycles_taken,
+        flags,
+        ..opcode
+    }
+}
+
+/*
+ * Documented opcode for a mnemonic and its operands,
+ * registers and RST numbers have to match exactly while
+ * immediate operands accept any expression
+ */
+pub fn find(mnemonic: &str, operands: &[&str]) -> Option<&'static Opcode> {
+    OPCODES.iter().find(|opcode| {
+        opcode.documented
+            && opcode.mnemonic == mnemonic
+            && opcode.operands.len() == operands.len()
+            && opcode
+                .operands
+                .iter()
+                .zip(operands)
+                .all(|(operand, arg)| !operand.is_fixed() || operand.to_string() == *arg)
+    })
+}
+
+/*
+ * Documented 8085 instruction the 8080 does not have, RIM and SIM
+ */
+pub fn find_8085(mnemonic: &str) -> Option<&'static Opcode> {
+    EXTENSIONS_8085
+        .iter()
+        .find(|opcode| opcode.documented && opcode.mnemonic == mnemonic)
+}
+
+/*
+ * All documented opcodes of a mnemonic
+ */
+pub fn variants(mnemonic: &str) -> impl Iterator<Item = &'static Opcode> + '_ {
+    OPCODES
+        .iter()
+        .filter(move |opcode| opcode.documented && opcode.mnemonic == mnemonic)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_is_consistent() {
+        for (index, opcode) in OPCODES.iter().enumerate() {
+            assert_eq!(opcode.opcode as usize, index);
+            let size: usize = opcode.operands.iter().map(Operand::size).sum();
+            assert_eq!(opcode.length, 1 + size, "{:02x}", index);
+            assert!(opcode.cycles_taken >= opcode.cycles);
+        }
+        assert_eq!(OPCODES.iter().filter(|opcode| opcode.documented).count(), 244);
+    }
+
+    #[test]
+    fn documented_opcodes_are_unique() {
+        for opcode in OPCODES.iter().filter(|opcode| opcode.documented) {
+            let operands: Vec<String> = opcode
+                .operands
+                .iter()
+                .map(|operand| if operand.is_fixed() { operand.to_string() } else { String::from("0") })
+                .collect();
+            let operands: Vec<&str> = operands.iter().map(String::as_str).collect();
+            assert_eq!(find(opcode.mnemonic, &operands), Some(opcode));
+        }
+    }
+
+    #[test]
+    fn describe_opcodes() {
+        assert_eq!(OPCODES[0x01].to_string(), "LXI B,D16");
+        assert_eq!(OPCODES[0xd3].to_string(), "OUT port");
+        assert_eq!(OPCODES[0xef].to_string(), "RST 5");
+        assert_eq!(OPCODES[0x34].flag_names(), vec!["S", "Z", "AC", "P"]);
+        assert_eq!(OPCODES[0xc4].cycles_taken, 17);
+        assert!(!OPCODES[0xcb].documented);
+        assert_eq!(find("MOV", &["M", "M"]), None);
+        assert_eq!(find("MVI", &["A", "'x'"]).map(|opcode| opcode.opcode), Some(0x3e));
+    }
+
+    #[test]
+    fn opcodes_8085() {
+        for index in 0..=255u8 {
+            let opcode = opcode_8085(index);
+            assert_eq!(opcode.opcode, index);
+            let size: usize = opcode.operands.iter().map(Operand::size).sum();
+            assert_eq!(opcode.length, 1 + size, "{:02x}", index);
+            assert!(opcode.cycles_taken >= opcode.cycles);
+        }
+        assert_eq!(opcode_8085(0x20).to_string(), "RIM");
+        assert!(opcode_8085(0x30).documented);
+        assert!(!opcode_8085(0xfd).documented);
+        assert_eq!(opcode_8085(0x28).to_string(), "LDHI D8");
+        assert_eq!(opcode_8085(0x41).cycles, 4);
+        assert_eq!(opcode_8085(0x46).cycles, 7);
+        assert_eq!(opcode_8085(0x34).cycles, 10);
+        assert_eq!((opcode_8085(0xc2).cycles, opcode_8085(0xc2).cycles_taken), (7, 10));
+        assert_eq!((opcode_8085(0xc4).cycles, opcode_8085(0xc4).cycles_taken), (9, 18));
+        assert_eq!((opcode_8085(0xc0).cycles, opcode_8085(0xc0).cycles_taken), (6, 12));
+        assert_eq!(opcode_8085(0xcd).cycles, 18);
+        assert_eq!(opcode_8085(0xc9).cycles, 10);
+        assert_eq!(opcode_8085(0x76).cycles, 5);
+        assert_eq!(opcode_8085(0xb8).flag_names(), vec!["S", "Z", "K", "AC", "P", "V", "CY"]);
+        assert_eq!(OPCODES[0xb8].flag_names(), vec!["S", "Z", "AC", "P", "CY"]);
+    }
+}
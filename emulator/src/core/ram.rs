@@ -14,6 +14,14 @@ pub trait RAM: Index<u16, Output=u8> + IndexMut<u16, Output=u8> {
     fn size(&self) -> usize;
 
     fn load_vec(&mut self, vec: Vec<u8>, start: u16);
+
+    fn load_file(&mut self, path: &str, start: u16) -> io::Result<()> {
+        let mut f = File::open(path)?;
+        let mut bytes = Vec::new();
+        f.read_to_end(&mut bytes)?;
+        self.load_vec(bytes, start);
+        Ok(())
+    }
 }
 
 impl RAM for DefaultRam {
@@ -30,6 +38,53 @@ impl RAM for DefaultRam {
     }
 }
 
+/*
+ * Flat, fully populated memory of a power of two size (up to 64K)
+ * Addresses above the size mirror the lower part, like DefaultRam
+ */
+pub struct LinearRam {
+    mem: Vec<u8>,
+    mask: u16,
+}
+
+impl LinearRam {
+    pub fn new(size: usize) -> Self {
+        assert!(size.is_power_of_two() && size <= 0x10000, "RAM size must be a power of two <= 64K");
+        Self {
+            mem: vec![0; size],
+            mask: (size - 1) as u16,
+        }
+    }
+}
+
+impl RAM for LinearRam {
+    fn size(&self) -> usize {
+        self.mem.len()
+    }
+
+    fn load_vec(&mut self, vec: Vec<u8>, start: u16) {
+        let mut idx = start;
+        for byte in vec {
+            self[idx] = byte;
+            idx = idx.wrapping_add(1);
+        }
+    }
+}
+
+impl Index<u16> for LinearRam {
+    type Output = u8;
+
+    fn index(&self, index: u16) -> &Self::Output {
+        &self.mem[(index & self.mask) as usize]
+    }
+}
+
+impl IndexMut<u16> for LinearRam {
+    fn index_mut(&mut self, index: u16) -> &mut Self::Output {
+        &mut self.mem[(index & self.mask) as usize]
+    }
+}
+
 impl DefaultRam {
     /*
      * Struct representing the RAM
@@ -43,13 +98,6 @@ impl DefaultRam {
     pub fn new() -> Self {
         Self { mem: [0; RAM_SIZE] }
     }
-
-    pub fn load_file(&mut self, path: &str, start: u16) -> io::Result<()> {
-        let mut f = File::open(path)?;
-        let mut bytes = Vec::new();
-        f.read_to_end(&mut bytes)?;
-        Ok(())
-    }
 }
 
 impl Index<u16> for DefaultRam {
@@ -92,4 +140,25 @@ mod tests {
         let slice = &r[0..5];
         assert_eq!(slice, &[1, 2, 3, 4, 5]);
     }
+
+    #[test]
+    fn linear_ram() {
+        let mut r = LinearRam::new(0x10000);
+        assert_eq!(r.size(), 0x10000);
+
+        r[0xffff] = 42;
+        r[0x4000] = 69;
+        assert_eq!(r[0xffff], 42);
+        assert_eq!(r[0x4000], 69);
+        assert_eq!(r[0], 0);
+
+        r.load_vec(vec![1, 2, 3], 0xfffe);
+        assert_eq!(r[0xfffe], 1);
+        assert_eq!(r[0xffff], 2);
+        assert_eq!(r[0], 3);
+
+        let mut r = LinearRam::new(0x1000);
+        r[0x1001] = 7;
+        assert_eq!(r[0x0001], 7);
+    }
 }
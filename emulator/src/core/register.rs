@@ -34,11 +34,13 @@ impl RegisterArray {
     pub fn get_flag(&self, flag: &str) -> bool {
         unsafe {
             match flag {
-                "zero" => (self.psw.bytes.1 & 0x80) != 0,
-                "carry" => (self.psw.bytes.1 & 0x40) != 0,
-                "sign" => (self.psw.bytes.1 & 0x20) != 0,
-                "parity" => (self.psw.bytes.1 & 0x10) != 0,
-                "aux" => (self.psw.bytes.1 & 0x8) != 0,
+                "zero" => (self.psw.bytes.1 & 0x40) != 0,
+                "carry" => (self.psw.bytes.1 & 0x01) != 0,
+                "sign" => (self.psw.bytes.1 & 0x80) != 0,
+                "parity" => (self.psw.bytes.1 & 0x04) != 0,
+                "aux" => (self.psw.bytes.1 & 0x10) != 0,
+                "overflow" => (self.psw.bytes.1 & 0x02) != 0,
+                "k" => (self.psw.bytes.1 & 0x20) != 0,
                 _ => panic!("Invalid flag"),
             }
         }
@@ -48,20 +50,24 @@ impl RegisterArray {
         unsafe {
             if value {
                 match flag {
-                    "zero" => self.psw.bytes.1 |= 0x80,
-                    "carry" => self.psw.bytes.1 |= 0x40,
-                    "sign" => self.psw.bytes.1 |= 0x20,
-                    "parity" => self.psw.bytes.1 |= 0x10,
-                    "aux" => self.psw.bytes.1 |= 0x8,
+                    "zero" => self.psw.bytes.1 |= 0x40,
+                    "carry" => self.psw.bytes.1 |= 0x01,
+                    "sign" => self.psw.bytes.1 |= 0x80,
+                    "parity" => self.psw.bytes.1 |= 0x04,
+                    "aux" => self.psw.bytes.1 |= 0x10,
+                    "overflow" => self.psw.bytes.1 |= 0x02,
+                    "k" => self.psw.bytes.1 |= 0x20,
                     _ => panic!("Invalid flag"),
                 }
             } else {
                 match flag {
-                    "zero" => self.psw.bytes.1 &= !0x80,
-                    "carry" => self.psw.bytes.1 &= !0x40,
-                    "sign" => self.psw.bytes.1 &= !0x20,
-                    "parity" => self.psw.bytes.1 &= !0x10,
-                    "aux" => self.psw.bytes.1 &= !0x8,
+                    "zero" => self.psw.bytes.1 &= !0x40,
+                    "carry" => self.psw.bytes.1 &= !0x01,
+                    "sign" => self.psw.bytes.1 &= !0x80,
+                    "parity" => self.psw.bytes.1 &= !0x04,
+                    "aux" => self.psw.bytes.1 &= !0x10,
+                    "overflow" => self.psw.bytes.1 &= !0x02,
+                    "k" => self.psw.bytes.1 &= !0x20,
                     _ => panic!("Invalid flag"),
                 }
             }
@@ -71,11 +77,13 @@ impl RegisterArray {
     pub fn flip_flag(&mut self, flag: &str) {
         unsafe {
             match flag {
-                "zero" => self.psw.bytes.1 ^= 0x80,
-                "carry" => self.psw.bytes.1 ^= 0x40,
-                "sign" => self.psw.bytes.1 ^= 0x20,
-                "parity" => self.psw.bytes.1 ^= 0x10,
-                "aux" => self.psw.bytes.1 ^= 0x8,
+                "zero" => self.psw.bytes.1 ^= 0x40,
+                "carry" => self.psw.bytes.1 ^= 0x01,
+                "sign" => self.psw.bytes.1 ^= 0x80,
+                "parity" => self.psw.bytes.1 ^= 0x04,
+                "aux" => self.psw.bytes.1 ^= 0x10,
+                "overflow" => self.psw.bytes.1 ^= 0x02,
+                "k" => self.psw.bytes.1 ^= 0x20,
                 _ => panic!("Invalid flag"),
             }
         }
@@ -84,6 +92,23 @@ impl RegisterArray {
     pub fn set_flags(&mut self, flags: u8) {
         self.psw.bytes.1 = flags;
     }
+
+    /*
+     * Flag byte in the layout pushed by PUSH PSW:
+     * S Z 0 AC 0 P 1 CY
+     */
+    pub fn get_flags(&self) -> u8 {
+        unsafe { (self.psw.bytes.1 & 0xd5) | 0x02 }
+    }
+
+    /*
+     * Flag byte as the 8085 pushes it, including its
+     * undocumented overflow and K flags:
+     * S Z K AC 0 P V CY
+     */
+    pub fn get_flags_8085(&self) -> u8 {
+        unsafe { self.psw.bytes.1 & 0xf7 }
+    }
 }
 
 impl Index<char> for RegisterArray {
@@ -209,6 +234,11 @@ mod tests {
         
         regs.set_flag("parity", false);
         assert!(!regs.get_flag("parity"));
+
+        regs.set_flag("overflow", true);
+        regs.set_flag("k", true);
+        assert_eq!(regs.get_flags(), 0x93);
+        assert_eq!(regs.get_flags_8085(), 0xb3);
     }
 
 }
@@ -1,26 +1,55 @@
-use super::parser::eval;
-use super::preprocessor::get_preprocessed_code;
+use super::dialect::Dialect;
+use super::include::{FileResolver, FileSystem, MemoryFiles};
+use super::macros::directive;
+use super::parser::{eval, split_operands, strip_comment, string_literal};
+use super::object::{Fixup, Object, Public, Segment, Target};
+use super::preprocessor::{preprocess_module, Layout, Module};
+use crate::core::opcodes::{self, OPCODES};
+use crate::terminator::symbols::SymbolTable;
 use core::fmt;
 use regex::Regex;
+use std::collections::HashSet;
+use std::sync::OnceLock;
 use std::{collections::HashMap, hash::Hash};
 
 pub const LABEL_DECL: &str = r"^( *[a-zA-Z@?][a-zA-Z@?0-9]{0,4}:)";
+const RELOCATION_SHIFT: u16 = 0x0101;
 
+/*
+ * Mnemonics from the opcode table, directives and register names
+ */
 pub fn get_reserved_names() -> Vec<&'static str> {
-    vec![
-        "STC", "CMC", "INR", "DCR", "CMA", "DAA", "NOP", "MOV", "STAX", "LDAX", "ADD", "ADC",
-        "SUB", "SBB", "ANA", "XRA", "ORA", "CMP", "RLC", "RRC", "RAL", "RAR", "PUSH", "POP",
-        "DAD", "INX", "DCX", "XCHG", "XTHL", "SPHL", "LXI", "MVI", "ADI", "ACI", "SUI", "SBI",
-        "ANI", "XRI", "ORI", "CPI", "STA", "LDA", "SHLD", "LHLD", "PCHL", "JMP", "JC", "JNC",
-        "JZ", "JNZ", "JP", "JM", "JPE", "JPO", "CALL", "CC", "CNC", "CZ", "CNZ", "CP", "CM",
-        "CPE", "CPO", "RET", "RC", "RNC", "RZ", "RNZ", "RM", "RP", "RPE", "RPO", "RST", "EI",
-        "DI", "IN", "OUT", "HLT", "ORG", "EQU", "SET", "END", "IF", "ENDIF", "MACRO", "ENDM",
-        "B", "C", "D", "H", "L", "A", "SP", "PSW",
-    ]
+    let mut names: Vec<&'static str> = Vec::new();
+    for opcode in OPCODES.iter().filter(|opcode| opcode.documented) {
+        if !names.contains(&opcode.mnemonic) {
+            names.push(opcode.mnemonic);
+        }
+    }
+    names.extend([
+        "ORG", "EQU", "SET", "END", "IF", "ELSEIF", "ELSE", "ENDIF", "MACRO", "ENDM", "LOCAL", "REPT", "IRP",
+        "IRPC", "EXITM", "INCLUDE", "ASEG", "CSEG", "DSEG", "PUBLIC", "EXTRN", "B", "C", "D", "H", "L", "A", "SP",
+        "PSW",
+    ]);
+    names
+}
+
+/*
+ * Whether the assembler accepts name as a label, the regex and
+ * reserved names are built on the first call
+ */
+pub fn is_label_name(name: &str) -> bool {
+    static LABELS: OnceLock<(Regex, HashSet<&'static str>)> = OnceLock::new();
+    let (regex, reserved) =
+        LABELS.get_or_init(|| (Regex::new(LABEL_DECL).unwrap(), get_reserved_names().into_iter().collect()));
+    let declaration = format!("{}:", name);
+    let valid = regex.find(&declaration).is_some_and(|found| found.as_str() == declaration);
+    valid && !reserved.contains(name.to_uppercase().as_str())
 }
 
 pub struct Assembler {
     code: Vec<String>,
+    resolver: Box<dyn FileResolver>,
+    dialect: Dialect,
 }
 
 impl fmt::Display for Assembler {
@@ -32,44 +61,123 @@ impl fmt::Display for Assembler {
 impl Assembler {
     pub fn new(input_code: &str) -> Self {
         let mut lines = Vec::new();
-        let comment_regex = Regex::new(r";.*").unwrap();
 
         for line in input_code.split("\n") {
-            let line = comment_regex.replace(line, "");
-            let line = line.trim_end();
+            let line = strip_comment(line).trim_end();
             lines.push(String::from(line));
         }
 
-        Self { code: lines }
+        Self {
+            code: lines,
+            resolver: default_resolver(),
+            dialect: Dialect::default(),
+        }
+    }
+
+    /*
+     * Where INCLUDE looks for files
+     */
+    pub fn with_resolver(mut self, resolver: impl FileResolver + 'static) -> Self {
+        self.resolver = Box::new(resolver);
+        self
+    }
+
+    /*
+     * Mnemonics the code is written in, Intel 8080 unless set
+     */
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    fn preprocessed_code(&self) -> Result<Vec<String>, String> {
+        self.preprocess(&Layout::default()).map(|module| module.code)
+    }
+
+    fn preprocess(&self, layout: &Layout) -> Result<Module, String> {
+        let layout = Layout {
+            dialect: self.dialect,
+            ..layout.clone()
+        };
+        preprocess_module(&self.code, self.resolver.as_ref(), &layout)
     }
 
-    pub fn assemble(&self) -> Result<Vec<u8>, &'static str> {
+    pub fn assemble(&self) -> Result<Vec<u8>, String> {
         let label_regex = Regex::new(LABEL_DECL).unwrap();
-        let preprocessed_code = get_preprocessed_code(&self.code)?;
+        let preprocessed_code = self.preprocessed_code()?;
 
         let mut machine_code = Vec::new();
 
         for line in preprocessed_code {
             let line = label_regex.replace(&line, "").trim().to_string();
 
-            if !line.is_empty() && !line.contains("ORG ") {
+            if !line.is_empty() && origin(&line).is_none() && Segment::from_directive(&line).is_none() {
                 machine_code.extend(to_machine_code(line)?);
             }
         }
         Ok(machine_code)
     }
 
+    /*
+     * Labels and EQU constants in the symbol file format
+     */
+    pub fn get_symbols(&self) -> Result<SymbolTable, String> {
+        let mut table = SymbolTable::new();
+        for (name, address) in self.preprocess(&Layout::default())?.symbols {
+            table.insert(address, &name);
+        }
+        Ok(table)
+    }
+
+    /*
+     * Relocatable object for the linker, CSEG and DSEG start at 0 and
+     * external symbols are 0 plus whatever the program adds to them
+     */
+    pub fn assemble_object(&self) -> Result<Object, String> {
+        let layout = Layout {
+            data: Some(0),
+            ..Layout::default()
+        };
+        let module = self.preprocess(&layout)?;
+        let mut object = place(&module.code, &layout)?;
+
+        let mut variants = vec![
+            (Target::Code, Layout { code: RELOCATION_SHIFT, ..layout.clone() }),
+            (Target::Data, Layout { data: Some(RELOCATION_SHIFT), ..layout.clone() }),
+        ];
+        for name in &module.externals {
+            let mut variant = layout.clone();
+            variant.externals.insert(name.clone(), RELOCATION_SHIFT);
+            variants.push((Target::External(name.clone()), variant));
+        }
+        for (target, variant) in variants {
+            let moved = self.preprocess(&variant)?;
+            let fixups = relocations(&object, &place(&moved.code, &variant)?, &target)?;
+            object.fixups.extend(fixups);
+        }
+
+        for name in &module.publics {
+            let offset = *module
+                .symbols
+                .get(name)
+                .ok_or_else(|| format!("PUBLIC symbol {} is not defined", name))?;
+            let segment = module.segments.get(name).copied().unwrap_or(Segment::Absolute);
+            object.publics.push(Public { name: name.clone(), segment, offset });
+        }
+        object.externals = module.externals;
+        Ok(object)
+    }
+
     pub fn get_origins(&self) -> Vec<(u16, u16)> {
         let label_regex = Regex::new(LABEL_DECL).unwrap();
         let mut origins: Vec<(u16, u16)> = Vec::new();
         let mut executed_bytes = 0;
 
-        for line in get_preprocessed_code(&self.code).unwrap() {
-            if line.contains("ORG") {
-                let split = line.split_once(" ").unwrap();
-                origins.push((executed_bytes, evaluate_str(split.1)));
-            } else {
-                let line = label_regex.replace(&line, "").to_string();
+        for line in self.preprocessed_code().unwrap() {
+            let line = label_regex.replace(&line, "").to_string();
+            if let Some(address) = origin(&line) {
+                origins.push((executed_bytes, evaluate_str(address).unwrap()));
+            } else if Segment::from_directive(&line).is_none() {
                 executed_bytes = executed_bytes + to_machine_code(line).unwrap().len() as u16;
             }
         }
@@ -77,371 +185,222 @@ impl Assembler {
     }
 }
 
-fn to_machine_code(instruction: String) -> Result<Vec<u8>, &'static str> {
-    let label_regex = Regex::new(LABEL_DECL).unwrap();
-    let instruction = label_regex.replace(&instruction, "").to_string();
-    let mut args: Vec<&str> = Vec::new();
+/*
+ * Operand of a line whose first word is ORG, labels must be stripped already
+ */
+fn origin(line: &str) -> Option<&str> {
+    let (keyword, operand) = line.trim().split_once(char::is_whitespace)?;
+    keyword.eq_ignore_ascii_case("ORG").then_some(operand)
+}
 
-    match instruction.trim_start().split_once(" ") {
-        Some((opcode, suffix)) => {
-            let dirty_args: Vec<&str> = suffix.split(",").collect();
-            for arg in dirty_args {
-                args.push(arg.trim());
-            }
-            match opcode {
-                "MOV" => return convert_mov_args(args),
-                "STAX" => return convert_stax_args(args),
-                "INX" => return convert_inx_args(args),
-                "INR" => return convert_opcodes_using_all_registers(args, 0x04, true),
-                "DCR" => return convert_opcodes_using_all_registers(args, 0x05, true),
-                "ADD" => return convert_opcodes_using_all_registers(args, 0x80, false),
-                "ADC" => return convert_opcodes_using_all_registers(args, 0x88, false),
-                "SUB" => return convert_opcodes_using_all_registers(args, 0x90, false),
-                "SBB" => return convert_opcodes_using_all_registers(args, 0x98, false),
-                "ANA" => return convert_opcodes_using_all_registers(args, 0xa0, false),
-                "XRA" => return convert_opcodes_using_all_registers(args, 0xa8, false),
-                "ORA" => return convert_opcodes_using_all_registers(args, 0xb0, false),
-                "CMP" => return convert_opcodes_using_all_registers(args, 0xb8, false),
-                "LXI" => return convert_lxi_args(args),
-                "MVI" => return convert_mvi_args(args),
-                "DAD" => return convert_dad_args(args),
-                "DCX" => return convert_dcx_args(args),
-                "POP" => return convert_pop_args(args),
-                "RST" => return convert_rst_args(args),
-                "PUSH" => return convert_push_args(args),
-                "LDAX" => match args[0] {
-                    "B" => return Ok(vec![0x0a]),
-                    "D" => return Ok(vec![0x1a]),
-                    _ => return Err("wrong register!"),
-                },
-                "SHLD" => {
-                    let adr = evaluate_str(args[0]);
-                    return Ok(vec![0x22, adr as u8, (adr >> 8) as u8]);
-                }
-                "LHLD" => {
-                    let adr = evaluate_str(args[0]);
-                    return Ok(vec![0x2a, adr as u8, (adr >> 8) as u8]);
-                }
-                "STA" => {
-                    let adr = evaluate_str(args[0]);
-                    return Ok(vec![0x32, adr as u8, (adr >> 8) as u8]);
-                }
-                "LDA" => {
-                    let adr = evaluate_str(args[0]);
-                    return Ok(vec![0x3a, adr as u8, (adr >> 8) as u8]);
-                }
-                "JMP" => {
-                    let adr = evaluate_str(args[0]);
-                    return Ok(vec![0xc3, adr as u8, (adr >> 8) as u8]);
-                }
-                "JNZ" => {
-                    let adr = evaluate_str(args[0]);
-                    return Ok(vec![0xc2, adr as u8, (adr >> 8) as u8]);
-                }
-                "CNZ" => {
-                    let adr = evaluate_str(args[0]);
-                    return Ok(vec![0xc4, adr as u8, (adr >> 8) as u8]);
-                }
-                "ADI" => {
-                    let adr = evaluate_str(args[0]);
-                    return Ok(vec![0xc6, adr as u8]);
-                }
-                "JZ" => {
-                    let adr = evaluate_str(args[0]);
-                    return Ok(vec![0xca, adr as u8, (adr >> 8) as u8]);
-                }
-                "CZ" => {
-                    let adr = evaluate_str(args[0]);
-                    return Ok(vec![0xcc, adr as u8, (adr >> 8) as u8]);
-                }
-                "CALL" => {
-                    let adr = evaluate_str(args[0]);
-                    return Ok(vec![0xcd, adr as u8, (adr >> 8) as u8]);
-                }
-                "ACI" => {
-                    let adr = evaluate_str(args[0]);
-                    return Ok(vec![0xce, adr as u8]);
-                }
-                "JNC" => {
-                    let adr = evaluate_str(args[0]);
-                    return Ok(vec![0xd2, adr as u8, (adr >> 8) as u8]);
-                }
-                "OUT" => {
-                    let adr = evaluate_str(args[0]);
-                    return Ok(vec![0xd3, adr as u8]);
-                }
-                "CNC" => {
-                    let adr = evaluate_str(args[0]);
-                    return Ok(vec![0xd4, adr as u8, (adr >> 8) as u8]);
-                }
-                "SUI" => {
-                    let adr = evaluate_str(args[0]);
-                    return Ok(vec![0xd6, adr as u8]);
-                }
-                "JC" => {
-                    let adr = evaluate_str(args[0]);
-                    return Ok(vec![0xda, adr as u8, (adr >> 8) as u8]);
-                }
-                "IN" => {
-                    let adr = evaluate_str(args[0]);
-                    return Ok(vec![0xdb, adr as u8]);
-                }
-                "CC" => {
-                    let adr = evaluate_str(args[0]);
-                    return Ok(vec![0xdc, adr as u8, (adr >> 8) as u8]);
-                }
-                "SBI" => {
-                    let adr = evaluate_str(args[0]);
-                    return Ok(vec![0xde, adr as u8]);
-                }
-                "JPO" => {
-                    let adr = evaluate_str(args[0]);
-                    return Ok(vec![0xe2, adr as u8, (adr >> 8) as u8]);
-                }
-                "CPO" => {
-                    let adr = evaluate_str(args[0]);
-                    return Ok(vec![0xe4, adr as u8, (adr >> 8) as u8]);
-                }
-                "ANI" => {
-                    let adr = evaluate_str(args[0]);
-                    return Ok(vec![0xe6, adr as u8]);
-                }
-                "JPE" => {
-                    let adr = evaluate_str(args[0]);
-                    return Ok(vec![0xea, adr as u8, (adr >> 8) as u8]);
-                }
-                "CPE" => {
-                    let adr = evaluate_str(args[0]);
-                    return Ok(vec![0xec, adr as u8, (adr >> 8) as u8]);
-                }
-                "XRI" => {
-                    let adr = evaluate_str(args[0]);
-                    return Ok(vec![0xee, adr as u8]);
-                }
-                "JP" => {
-                    let adr = evaluate_str(args[0]);
-                    return Ok(vec![0xf2, adr as u8, (adr >> 8) as u8]);
-                }
-                "CP" => {
-                    let adr = evaluate_str(args[0]);
-                    return Ok(vec![0xf4, adr as u8, (adr >> 8) as u8]);
-                }
-                "ORI" => {
-                    let adr = evaluate_str(args[0]);
-                    return Ok(vec![0xf6, adr as u8]);
-                }
-                "JM" => {
-                    let adr = evaluate_str(args[0]);
-                    return Ok(vec![0xfa, adr as u8, (adr >> 8) as u8]);
-                }
-                "CM" => {
-                    let adr = evaluate_str(args[0]);
-                    return Ok(vec![0xfc, adr as u8, (adr >> 8) as u8]);
-                }
-                "CPI" => {
-                    let adr = evaluate_str(args[0]);
-                    return Ok(vec![0xfe, adr as u8]);
+/*
+ * Bytes of every segment, the same lines assembled with a segment or external
+ * symbol moved by RELOCATION_SHIFT tell which words refer to it
+ */
+fn place(lines: &[String], layout: &Layout) -> Result<Object, &'static str> {
+    let mut object = Object::default();
+    let mut segment = Segment::Code;
+    let mut offset: u16 = 0;
+
+    for line in lines {
+        if let Some(next) = Segment::from_directive(line) {
+            segment = next;
+            continue;
+        }
+        if let Some(address) = origin(line) {
+            let address = evaluate_str(address)?;
+            offset = match segment {
+                Segment::Absolute => {
+                    object.absolute.push((address, Vec::new()));
+                    address
+                }
+                Segment::Code => address.wrapping_sub(layout.code),
+                Segment::Data => address.wrapping_sub(layout.data.unwrap_or(0)),
+            };
+            continue;
+        }
+        let bytes = to_machine_code(line.to_string())?;
+        match segment {
+            Segment::Absolute => {
+                if object.absolute.is_empty() {
+                    object.absolute.push((offset, Vec::new()));
                 }
-                _ => return Err("Could not match instruction"),
+                object.absolute.last_mut().unwrap().1.extend(&bytes);
             }
+            Segment::Code => write_at(&mut object.code, offset, &bytes),
+            Segment::Data => write_at(&mut object.data, offset, &bytes),
         }
-        None => match instruction.trim() {
-            "NOP" => return Ok(vec![0x0]),
-            "RLC" => return Ok(vec![0x7]),
-            "RRC" => return Ok(vec![0x0f]),
-            "RAL" => return Ok(vec![0x17]),
-            "RAR" => return Ok(vec![0x1f]),
-            "CMA" => return Ok(vec![0x2f]),
-            "CMC" => return Ok(vec![0x3f]),
-            "DAA" => return Ok(vec![0x27]),
-            "HLT" => return Ok(vec![0x76]),
-            "RNZ" => return Ok(vec![0xc0]),
-            "STC" => return Ok(vec![0x37]),
-            "RET" => return Ok(vec![0xc9]),
-            "RNC" => return Ok(vec![0xd0]),
-            "RPE" => return Ok(vec![0xe8]),
-            "RPO" => return Ok(vec![0xe0]),
-            "EI" => return Ok(vec![0xfb]),
-            "RM" => return Ok(vec![0xf8]),
-            "RZ" => return Ok(vec![0xc8]),
-            "RC" => return Ok(vec![0xd8]),
-            "DI" => return Ok(vec![0xf3]),
-            "RP" => return Ok(vec![0xf0]),
-            "SPHL" => return Ok(vec![0xf9]),
-            "XCHG" => return Ok(vec![0xeb]),
-            "PCHL" => return Ok(vec![0xe9]),
-            "XTHL" => return Ok(vec![0xe3]),
-            _ => return Err("Could not match instruction"),
-        },
-    };
-}
-
-fn evaluate_str(str: &str) -> u16 {
-    eval(str) as u16
-}
-
-fn convert_mov_args(args: Vec<&str>) -> Result<Vec<u8>, &'static str> {
-    let base_value = 0x40;
-    let registers = "BCDEHLMA";
-
-    match args.len() {
-        0 | 1 => return Err("Missing argument(s) for MOV instruction"),
-        2 => match registers.find(args[0]) {
-            Some(index) => match registers.find(args[1]) {
-                Some(second_index) => {
-                    if index == 6 && second_index == 6 {
-                        return Err("Invalid arguments for MOV instruction (Can't move M into M)");
-                    }
-                    let instruction_value = base_value + (index as u8 * 8) + second_index as u8;
-                    return Ok(vec![instruction_value]);
-                }
-                None => return Err("Invalid second argument for MOV instruction"),
-            },
-            None => return Err("Invalid first argument for MOV instruction"),
-        },
-        _ => return Err("MOV only takes 2 arguments!"),
-    }
-}
-
-fn convert_stax_args(args: Vec<&str>) -> Result<Vec<u8>, &'static str> {
-    if args.len() != 1 {
-        return Err("wrong arg amount!");
-    }
-    match args[0] {
-        "B" => return Ok(vec![0x02]),
-        "D" => return Ok(vec![0x12]),
-        _ => return Err("wrong register!"),
+        offset = offset.wrapping_add(bytes.len() as u16);
     }
+    object.absolute.retain(|(_, bytes)| !bytes.is_empty());
+    Ok(object)
 }
 
-fn convert_inx_args(args: Vec<&str>) -> Result<Vec<u8>, &'static str> {
-    if args.len() != 1 {
-        return Err("wrong arg amount!");
-    }
-    match args[0] {
-        "B" => return Ok(vec![0x03]),
-        "D" => return Ok(vec![0x13]),
-        "H" => return Ok(vec![0x23]),
-        "SP" => return Ok(vec![0x33]),
-        _ => return Err("wrong register!"),
+fn write_at(image: &mut Vec<u8>, offset: u16, bytes: &[u8]) {
+    let start = offset as usize;
+    if image.len() < start + bytes.len() {
+        image.resize(start + bytes.len(), 0);
     }
+    image[start..start + bytes.len()].copy_from_slice(bytes);
 }
 
-fn convert_opcodes_using_all_registers(
-    args: Vec<&str>,
-    base_value: u8,
-    use_every_eigth_opc: bool,
-) -> Result<Vec<u8>, &'static str> {
-    if args.len() != 1 {
-        return Err("wrong arg amount!");
-    }
-    let growth = if use_every_eigth_opc { 8 } else { 1 };
-    match args[0] {
-        "B" => return Ok(vec![base_value]),
-        "C" => return Ok(vec![base_value + (1 * growth)]),
-        "D" => return Ok(vec![base_value + (2 * growth)]),
-        "E" => return Ok(vec![base_value + (3 * growth)]),
-        "H" => return Ok(vec![base_value + (4 * growth)]),
-        "L" => return Ok(vec![base_value + (5 * growth)]),
-        "M" => return Ok(vec![base_value + (6 * growth)]),
-        "A" => return Ok(vec![base_value + (7 * growth)]),
-        _ => return Err("wrong register!"),
+/*
+ * Words that changed by exactly RELOCATION_SHIFT, since its low byte is not
+ * zero the first changed byte is always the start of a word
+ */
+fn relocations(fixed: &Object, moved: &Object, target: &Target) -> Result<Vec<Fixup>, &'static str> {
+    let same_layout = fixed.code.len() == moved.code.len()
+        && fixed.data.len() == moved.data.len()
+        && fixed.absolute.len() == moved.absolute.len()
+        && fixed.absolute.iter().zip(&moved.absolute).all(|(a, b)| a.0 == b.0 && a.1.len() == b.1.len());
+    if !same_layout {
+        return Err("Relocatable symbols must not change the size of the program");
+    }
+
+    let mut images = vec![(Segment::Code, 0, &fixed.code, &moved.code), (Segment::Data, 0, &fixed.data, &moved.data)];
+    for ((address, a), (_, b)) in fixed.absolute.iter().zip(&moved.absolute) {
+        images.push((Segment::Absolute, *address, a, b));
+    }
+
+    let mut fixups = Vec::new();
+    for (segment, start, fixed, moved) in images {
+        let mut index = 0;
+        while index < fixed.len() {
+            if fixed[index] == moved[index] {
+                index += 1;
+                continue;
+            }
+            let word = |bytes: &[u8]| u16::from_le_bytes([bytes[index], bytes[index + 1]]);
+            if index + 1 >= fixed.len() || word(moved).wrapping_sub(word(fixed)) != RELOCATION_SHIFT {
+                return Err("Relocatable symbols can only be used as 16 bit values");
+            }
+            fixups.push(Fixup {
+                segment,
+                offset: start.wrapping_add(index as u16),
+                target: target.clone(),
+            });
+            index += 2;
+        }
     }
+    Ok(fixups)
 }
 
-fn convert_lxi_args(args: Vec<&str>) -> Result<Vec<u8>, &'static str> {
-    if args.len() != 2 {
-        return Err("wrong arg amount!");
-    }
-    let imm_val = evaluate_str(args[1]);
-    match args[0] {
-        "B" => return Ok(vec![0x01, imm_val as u8, (imm_val >> 8) as u8]),
-        "D" => return Ok(vec![0x11, imm_val as u8, (imm_val >> 8) as u8]),
-        "H" => return Ok(vec![0x21, imm_val as u8, (imm_val >> 8) as u8]),
-        "SP" => return Ok(vec![0x31, imm_val as u8, (imm_val >> 8) as u8]),
-        _ => return Err("wrong register!"),
+/*
+ * Included files come from the working directory natively, in the
+ * browser there are none unless they are supplied
+ */
+fn default_resolver() -> Box<dyn FileResolver> {
+    if cfg!(target_arch = "wasm32") {
+        Box::new(MemoryFiles::new())
+    } else {
+        Box::new(FileSystem::new("."))
     }
 }
 
-fn convert_mvi_args(args: Vec<&str>) -> Result<Vec<u8>, &'static str> {
-    if args.len() != 2 {
-        return Err("wrong arg amount!");
-    }
-    let immediate_value = eval(args[1]) as u8;
-    match args[0] {
-        "B" => return Ok(vec![0x06, immediate_value]),
-        "C" => return Ok(vec![0x0e, immediate_value]),
-        "D" => return Ok(vec![0x16, immediate_value]),
-        "E" => return Ok(vec![0x1e, immediate_value]),
-        "H" => return Ok(vec![0x26, immediate_value]),
-        "L" => return Ok(vec![0x2e, immediate_value]),
-        "M" => return Ok(vec![0x36, immediate_value]),
-        "A" => return Ok(vec![0x3e, immediate_value]),
-        _ => return Err("wrong register!"),
+/*
+ * Number of bytes a source line (without label) assembles to
+ */
+pub fn instruction_size(line: &str) -> u16 {
+    let line = line.trim();
+    let (opcode, args) = match line.split_once(" ") {
+        Some((opcode, args)) => (opcode, args.trim()),
+        None => (line, ""),
+    };
+    match opcode {
+        "DB" => split_operands(args)
+            .iter()
+            .map(|arg| string_literal(arg).map_or(1, |string| string.len() as u16))
+            .sum(),
+        "DW" => 2 * split_operands(args).len() as u16,
+        "" | "ORG" | "END" | "IF" | "ELSEIF" | "ELSE" | "ENDIF" | "ENDM" => 0,
+        "INCLUDE" | "ASEG" | "CSEG" | "DSEG" | "PUBLIC" | "EXTRN" => 0,
+        "LOCAL" | "REPT" | "IRP" | "IRPC" | "EXITM" => 0,
+        _ if line.contains(" EQU ") || line.contains(" SET ") || directive(line) == "MACRO" => 0,
+        _ => opcodes::variants(opcode).next().map_or(1, |opcode| opcode.length as u16),
     }
 }
 
-fn convert_dad_args(args: Vec<&str>) -> Result<Vec<u8>, &'static str> {
-    if args.len() != 1 {
-        return Err("wrong arg amount!");
-    }
-    match args[0] {
-        "B" => return Ok(vec![0x09]),
-        "D" => return Ok(vec![0x19]),
-        "H" => return Ok(vec![0x29]),
-        "SP" => return Ok(vec![0x39]),
-        _ => return Err("wrong register!"),
+fn to_machine_code(instruction: String) -> Result<Vec<u8>, &'static str> {
+    let label_regex = Regex::new(LABEL_DECL).unwrap();
+    let instruction = label_regex.replace(&instruction, "").to_string();
+    match instruction.trim_start().split_once(" ") {
+        Some((opcode, suffix)) => {
+            let args = split_operands(suffix);
+            match opcode {
+                "MOV" => convert_mov_args(args),
+                "DB" => {
+                    let mut bytes = Vec::new();
+                    for arg in args {
+                        match string_literal(arg) {
+                            Some(string) => bytes.extend(string),
+                            None => bytes.push(evaluate_str(arg)? as u8),
+                        }
+                    }
+                    Ok(bytes)
+                }
+                "DW" => {
+                    let mut bytes = Vec::new();
+                    for arg in args {
+                        let value = evaluate_str(arg)?;
+                        bytes.extend(vec![value as u8, (value >> 8) as u8]);
+                    }
+                    Ok(bytes)
+                }
+                _ => encode(opcode, args),
+            }
+        }
+        None => encode(instruction.trim(), Vec::new()),
     }
 }
 
-fn convert_dcx_args(args: Vec<&str>) -> Result<Vec<u8>, &'static str> {
-    if args.len() != 1 {
+/*
+ * Look up the opcode by mnemonic and registers,
+ * the remaining operands are evaluated and appended
+ */
+fn encode(mnemonic: &str, args: Vec<&str>) -> Result<Vec<u8>, &'static str> {
+    let operand_count = match opcodes::variants(mnemonic).next() {
+        Some(opcode) => opcode.operands.len(),
+        None => return Err("Could not match instruction"),
+    };
+    if args.len() != operand_count {
+        if operand_count == 0 {
+            return Err("Could not match instruction");
+        }
         return Err("wrong arg amount!");
     }
-    match args[0] {
-        "B" => return Ok(vec![0x0b]),
-        "D" => return Ok(vec![0x1b]),
-        "H" => return Ok(vec![0x2b]),
-        "SP" => return Ok(vec![0x3b]),
-        _ => return Err("wrong register!"),
-    }
-}
+    let opcode = opcodes::find(mnemonic, &args).ok_or("wrong register!")?;
 
-fn convert_pop_args(args: Vec<&str>) -> Result<Vec<u8>, &'static str> {
-    if args.len() != 1 {
-        return Err("wrong arg amount!");
-    }
-    match args[0] {
-        "B" => return Ok(vec![0xc1]),
-        "D" => return Ok(vec![0xd1]),
-        "H" => return Ok(vec![0xe1]),
-        "PSW" => return Ok(vec![0xf1]),
-        _ => return Err("wrong register!"),
+    let mut bytes = vec![opcode.opcode];
+    for (operand, arg) in opcode.operands.iter().zip(args) {
+        match operand.size() {
+            1 => bytes.push(evaluate_str(arg)? as u8),
+            2 => {
+                let value = evaluate_str(arg)?;
+                bytes.extend(vec![value as u8, (value >> 8) as u8]);
+            }
+            _ => {}
+        }
     }
+    Ok(bytes)
 }
 
-fn convert_push_args(args: Vec<&str>) -> Result<Vec<u8>, &'static str> {
-    if args.len() != 1 {
-        return Err("wrong arg amount!");
-    }
-    match args[0] {
-        "B" => return Ok(vec![0xc5]),
-        "D" => return Ok(vec![0xd5]),
-        "H" => return Ok(vec![0xe5]),
-        "PSW" => return Ok(vec![0xf5]),
-        _ => return Err("wrong register!"),
-    }
+fn evaluate_str(str: &str) -> Result<u16, &'static str> {
+    eval(str).map(|value| value as u16)
 }
 
-fn convert_rst_args(args: Vec<&str>) -> Result<Vec<u8>, &'static str> {
-    if args.len() != 1 {
-        return Err("wrong arg amount!");
-    }
-    let value = args[0].parse::<u8>().unwrap();
-    if value <= 7 {
-        return Ok(vec![0xc7 + value * 8]);
+fn convert_mov_args(args: Vec<&str>) -> Result<Vec<u8>, &'static str> {
+    match args.len() {
+        0 | 1 => Err("Missing argument(s) for MOV instruction"),
+        2 => match opcodes::find("MOV", &args) {
+            Some(opcode) => Ok(vec![opcode.opcode]),
+            None if args == ["M", "M"] => Err("Invalid arguments for MOV instruction (Can't move M into M)"),
+            None if opcodes::variants("MOV").any(|opcode| opcode.operands[0].to_string() == args[0]) => {
+                Err("Invalid second argument for MOV instruction")
+            }
+            None => Err("Invalid first argument for MOV instruction"),
+        },
+        _ => Err("MOV only takes 2 arguments!"),
     }
-    Err("wrong register!")
 }
 
 #[cfg(test)]
@@ -496,12 +455,7 @@ mod tests {
 
     #[test]
     fn mov_operations() {
-        let input_codes = get_bytes_and_args_by_opcode("MOV").unwrap();
-
-        for (bytes, arg_string) in input_codes {
-            let args: Vec<&str> = arg_string.split(",").collect();
-            assert_eq!(bytes, convert_mov_args(args).unwrap());
-        }
+        assemble_all("MOV");
     }
 
     #[test]
@@ -556,157 +510,82 @@ mod tests {
 
     #[test]
     fn convert_stax() {
-        let inputs = get_bytes_and_args_by_opcode("STAX").unwrap();
-
-        for input in inputs {
-            let args: Vec<&str> = input.1.split(",").collect();
-            assert_eq!(input.0, convert_stax_args(args).unwrap());
-        }
+        assemble_all("STAX");
     }
 
     #[test]
     fn stax_errors() {
-        assert_eq!(Err("wrong register!"), convert_stax_args(vec!["L"]));
-        assert_eq!(Err("wrong arg amount!"), convert_stax_args(vec!["L", "A"]));
-        assert_eq!(Err("wrong arg amount!"), convert_stax_args(vec![]));
+        assert_eq!(Err("wrong register!"), to_machine_code("STAX L".to_string()));
+        assert_eq!(Err("wrong arg amount!"), to_machine_code("STAX L,A".to_string()));
+        assert_eq!(Err("wrong arg amount!"), to_machine_code("STAX".to_string()));
     }
 
     #[test]
     fn inx() {
-        let inputs = get_bytes_and_args_by_opcode("INX").unwrap();
-
-        for input in inputs {
-            let args: Vec<&str> = input.1.split(",").collect();
-            assert_eq!(input.0, convert_inx_args(args).unwrap());
-        }
+        assemble_all("INX");
     }
 
     #[test]
     fn inx_errors() {
-        assert_eq!(Err("wrong register!"), convert_inx_args(vec!["A"]));
-        assert_eq!(Err("wrong arg amount!"), convert_inx_args(vec!["B", "D"]));
-        assert_eq!(Err("wrong arg amount!"), convert_inx_args(vec![]));
+        assert_eq!(Err("wrong register!"), to_machine_code("INX A".to_string()));
+        assert_eq!(Err("wrong arg amount!"), to_machine_code("INX B,D".to_string()));
+        assert_eq!(Err("wrong arg amount!"), to_machine_code("INX".to_string()));
     }
 
     #[test]
     fn opcodes_using_registersteps_of_8() {
-        let inputs = get_bytes_and_args_by_opcode("INR").unwrap();
-        for input in inputs {
-            let args: Vec<&str> = input.1.split(",").collect();
-            assert_eq!(
-                input.0,
-                convert_opcodes_using_all_registers(args, 4, true).unwrap()
-            );
-        }
-
-        let inputs = get_bytes_and_args_by_opcode("DCR").unwrap();
-        for input in inputs {
-            let args: Vec<&str> = input.1.split(",").collect();
-            assert_eq!(
-                input.0,
-                convert_opcodes_using_all_registers(args, 5, true).unwrap()
-            );
-        }
+        assemble_all("INR");
+        assemble_all("DCR");
     }
 
     #[test]
     fn opcodes_using_registers() {
-        let add_value = 0x80;
-        let opcodes = vec!["ADD", "ADC", "SUB", "SBB", "ANA", "XRA", "ORA", "CMP"];
-
-        for (index, &opcode) in opcodes.iter().enumerate() {
-            let inputs = get_bytes_and_args_by_opcode(opcode).unwrap();
-            for input in inputs {
-                let args: Vec<&str> = input.1.split(",").collect();
-                assert_eq!(
-                    input.0,
-                    convert_opcodes_using_all_registers(args, add_value + 8 * index as u8, false)
-                        .unwrap()
-                );
-            }
+        for opcode in ["ADD", "ADC", "SUB", "SBB", "ANA", "XRA", "ORA", "CMP"] {
+            assemble_all(opcode);
         }
     }
 
     #[test]
     fn opcodes_using_registers_errors() {
-        assert_eq!(
-            Err("wrong arg amount!"),
-            convert_opcodes_using_all_registers(vec!["B", "D"], 1, false)
-        );
-        assert_eq!(
-            Err("wrong arg amount!"),
-            convert_opcodes_using_all_registers(vec![], 1, false)
-        );
+        assert_eq!(Err("wrong arg amount!"), to_machine_code("ADD B,D".to_string()));
+        assert_eq!(Err("wrong arg amount!"), to_machine_code("ADD".to_string()));
+        assert_eq!(Err("wrong register!"), to_machine_code("ADD SP".to_string()));
     }
 
     #[test]
     fn convert_lxi() {
-        let inputs = get_bytes_and_args_by_opcode("LXI").unwrap();
-
-        for input in inputs {
-            let args: Vec<&str> = input.1.split(",").collect();
-            assert_eq!(input.0, convert_lxi_args(args).unwrap());
-        }
+        assemble_all("LXI");
     }
 
     #[test]
     fn convert_mvi() {
-        let inputs = get_bytes_and_args_by_opcode("MVI").unwrap();
-
-        for input in inputs {
-            let args: Vec<&str> = input.1.split(",").collect();
-            assert_eq!(input.0, convert_mvi_args(args).unwrap());
-        }
+        assemble_all("MVI");
     }
 
     #[test]
     fn convert_dad() {
-        let inputs = get_bytes_and_args_by_opcode("DAD").unwrap();
-
-        for input in inputs {
-            let args: Vec<&str> = input.1.split(",").collect();
-            assert_eq!(input.0, convert_dad_args(args).unwrap());
-        }
+        assemble_all("DAD");
     }
 
     #[test]
     fn convert_dcx() {
-        let inputs = get_bytes_and_args_by_opcode("DCX").unwrap();
-
-        for input in inputs {
-            let args: Vec<&str> = input.1.split(",").collect();
-            assert_eq!(input.0, convert_dcx_args(args).unwrap());
-        }
+        assemble_all("DCX");
     }
 
     #[test]
     fn convert_pop() {
-        let inputs = get_bytes_and_args_by_opcode("POP").unwrap();
-
-        for input in inputs {
-            let args: Vec<&str> = input.1.split(",").collect();
-            assert_eq!(input.0, convert_pop_args(args).unwrap());
-        }
+        assemble_all("POP");
     }
 
     #[test]
     fn convert_push() {
-        let inputs = get_bytes_and_args_by_opcode("PUSH").unwrap();
-
-        for input in inputs {
-            let args: Vec<&str> = input.1.split(",").collect();
-            assert_eq!(input.0, convert_push_args(args).unwrap());
-        }
+        assemble_all("PUSH");
     }
 
     #[test]
     fn convert_rst() {
-        let inputs = get_bytes_and_args_by_opcode("RST").unwrap();
-
-        for input in inputs {
-            let args: Vec<&str> = input.1.split(",").collect();
-            assert_eq!(input.0, convert_rst_args(args).unwrap());
-        }
+        assemble_all("RST");
+        assert_eq!(Err("wrong register!"), to_machine_code("RST 8".to_string()));
     }
 
     #[test]
@@ -769,6 +648,13 @@ mod tests {
         assert_eq!(jumps, assembler.get_origins());
     }
 
+    #[test]
+    fn org_inside_strings() {
+        let assembler = Assembler::new("ORG 100H\nDB 'ORG ',1\nORG 200H\nNOP\nEND");
+        assert_eq!(Ok(vec![b'O', b'R', b'G', b' ', 0x01, 0x00]), assembler.assemble());
+        assert_eq!(vec![(0, 0x100), (5, 0x200)], assembler.get_origins());
+    }
+
     #[test]
     fn full_program() {
         let code = "VAR1 EQU 123\n 
@@ -797,6 +683,117 @@ mod tests {
         assert_eq!(Ok(result), Assembler::new(code).assemble());
     }
 
+    #[test]
+    fn data_definitions() {
+        assert_eq!(Ok(vec![0x01, 0xff, 0x10]), to_machine_code("DB 1, 0ffH, 10H".to_string()));
+        assert_eq!(Ok(vec![0x34, 0x12, 0x02, 0x00]), to_machine_code("DW 1234H,2".to_string()));
+        assert_eq!(3, instruction_size("DB 1, 2, 3"));
+        assert_eq!(4, instruction_size("DW 1, 2"));
+    }
+
+    #[test]
+    fn character_literals() {
+        let code = "MVI A,'X'\nCPI ';' ; semicolon\nLXI H,'AB'\nMSG: DB 'Hi, $',0\nDW 'AB'\nDB ''''\nJMP $\nEND";
+        let result = vec![
+            0x3e, 0x58, 0xfe, 0x3b, 0x21, 0x42, 0x41, 0x48, 0x69, 0x2c, 0x20, 0x24, 0x00, 0x42, 0x41, 0x27, 0xc3,
+            0x10, 0x00,
+        ];
+
+        assert_eq!(Ok(result), Assembler::new(code).assemble());
+        assert_eq!(6, instruction_size("DB 'Hi, $',0"));
+        assert_eq!("Unterminated character constant", Assembler::new("MVI A,'X\nEND").assemble().unwrap_err());
+        assert_eq!("Character constant too long", Assembler::new("LXI H,'ABC'\nEND").assemble().unwrap_err());
+    }
+
+    #[test]
+    fn labels_are_byte_addresses() {
+        let code = "ORG 100H\nSTART: MVI A,1\nLOOP: DCR A\n JNZ LOOP\n JMP START\nEND";
+        let result = vec![0x3e, 0x01, 0x3d, 0xc2, 0x02, 0x01, 0xc3, 0x00, 0x01];
+
+        assert_eq!(Ok(result), Assembler::new(code).assemble());
+    }
+
+    #[test]
+    fn symbol_export() {
+        let code = "PORT EQU 10H\nORG 100H\nSTART: IN PORT\nLOOP: JMP LOOP\nEND";
+        let symbols = Assembler::new(code).get_symbols().unwrap();
+
+        assert_eq!(symbols.name(0x100), Some("START"));
+        assert_eq!(symbols.name(0x102), Some("LOOP"));
+        assert_eq!(symbols.name(0x10), Some("PORT"));
+        assert_eq!("0010 PORT\n0100 START\n0102 LOOP\n", symbols.to_string());
+    }
+
+    #[test]
+    fn includes() {
+        let mut files = MemoryFiles::new();
+        files.insert("defs.asm", "PORT EQU 10H\nSEND MACRO\nOUT PORT\nENDM");
+        let code = "INCLUDE \"defs.asm\"\nMVI A,1\nSEND\nEND";
+
+        let bytes = Assembler::new(code).with_resolver(files).assemble();
+        assert_eq!(Ok(vec![0x3e, 0x01, 0xd3, 0x10]), bytes);
+
+        let mut files = MemoryFiles::new();
+        files.insert("broken.asm", "NOP\nIF 1");
+        let bytes = Assembler::new("INCLUDE \"broken.asm\"\nEND").with_resolver(files).assemble();
+        assert_eq!("Every IF must be closed (broken.asm, line 2)", bytes.unwrap_err());
+    }
+
+    #[test]
+    fn dialects() {
+        let intel = "ORG 100H\nSTART: LXI H,BUF\nMVI B,3\nLOOP: MOV M,B\nINX H\nDCR B\nJNZ LOOP\nLDA BUF\nCPI 3\n\
+                     CZ DONE\nPUSH PSW\nXCHG\nRST 7\nHLT\nDONE: RET\nBUF: DB 0,0,0\nEND";
+        let zilog = "ORG 100H\nSTART: LD HL,BUF\nLD B,3\nLOOP: LD (HL),B\nINC HL\nDEC B\nJP NZ,LOOP\nLD A,(BUF)\n\
+                     CP 3\nCALL Z,DONE\nPUSH AF\nEX DE,HL\nRST 38H\nHALT\nDONE: RET\nBUF: DB 0,0,0\nEND";
+        let bytes = Assembler::new(intel).assemble().expect("Fuck");
+        assert_eq!(Ok(bytes), Assembler::new(zilog).with_dialect(Dialect::Zilog).assemble());
+
+        let code = "MVI A,0\nNOP\nDJNZ $\nEND";
+        let result = Assembler::new(code).with_dialect(Dialect::Zilog).assemble();
+        assert_eq!("Z80 instruction is not available on the 8080 (line 3)", result.unwrap_err());
+
+        let code = "RIM\nORI 8\nSIM\nEND";
+        let bytes = Assembler::new(code).with_dialect(Dialect::Intel8085).assemble();
+        assert_eq!(Ok(vec![0x20, 0xf6, 0x08, 0x30]), bytes);
+        assert_eq!("Could not match instruction", Assembler::new(code).assemble().unwrap_err());
+    }
+
+    #[test]
+    fn object_output() {
+        let code = "PUBLIC START,COUNT\nEXTRN PUTC\nCOUNT EQU 3\nSTART: LXI H,TEXT\nMVI B,COUNT\nCALL PUTC+1\n\
+                    DSEG\nTEXT: DB 'ABC'\nPTR: DW TEXT\nASEG\nORG 38H\nJMP START\nEND";
+        let object = Assembler::new(code).assemble_object().expect("Fuck");
+
+        assert_eq!(vec![0x21, 0x00, 0x00, 0x06, 0x03, 0xcd, 0x01, 0x00], object.code);
+        assert_eq!(vec![0x41, 0x42, 0x43, 0x00, 0x00], object.data);
+        assert_eq!(vec![(0x38, vec![0xc3, 0x00, 0x00])], object.absolute);
+        assert_eq!(vec!["PUTC".to_string()], object.externals);
+        let publics = vec![
+            Public { name: "START".to_string(), segment: Segment::Code, offset: 0 },
+            Public { name: "COUNT".to_string(), segment: Segment::Absolute, offset: 3 },
+        ];
+        assert_eq!(publics, object.publics);
+        let fixups = vec![
+            Fixup { segment: Segment::Absolute, offset: 0x39, target: Target::Code },
+            Fixup { segment: Segment::Code, offset: 1, target: Target::Data },
+            Fixup { segment: Segment::Data, offset: 3, target: Target::Data },
+            Fixup { segment: Segment::Code, offset: 6, target: Target::External("PUTC".to_string()) },
+        ];
+        assert_eq!(fixups, object.fixups);
+
+        let object = Assembler::new("CSEG\nLAB: MVI A,LOW LAB\nEND").assemble_object();
+        assert_eq!("Relocatable symbols can only be used as 16 bit values", object.unwrap_err());
+
+        let object = Assembler::new("PUBLIC NOPE\nNOP\nEND").assemble_object();
+        assert_eq!("PUBLIC symbol NOPE is not defined", object.unwrap_err());
+    }
+
+    fn assemble_all(opcode: &str) {
+        for (bytes, args) in get_bytes_and_args_by_opcode(opcode).unwrap() {
+            assert_eq!(Ok(bytes), to_machine_code(format!("{} {}", opcode, args)));
+        }
+    }
+
     fn get_bytes_and_args_by_opcode(opcode: &str) -> io::Result<Vec<(Vec<u8>, String)>> {
         let f = File::open(OPCODE_TEST_DATA)?;
         let mut lines = io::BufReader::new(f).lines();
@@ -0,0 +1,328 @@
+use super::parser::{eval, split_operands};
+use crate::core::opcodes;
+
+/*
+ * Mnemonics a program is written in, every dialect is translated to Intel 8080
+ * mnemonics so instructions they share assemble to the same bytes
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Dialect {
+    #[default]
+    Intel8080,
+    Zilog,
+    Intel8085,
+}
+
+/*
+ * Z80 instructions without an 8080 counterpart
+ */
+const Z80_ONLY: [&str; 33] = [
+    "JR", "DJNZ", "EXX", "LDI", "LDIR", "LDD", "LDDR", "CPI", "CPIR", "CPD", "CPDR", "NEG", "IM", "RETI", "RETN",
+    "RL", "RR", "SLA", "SRA", "SRL", "RLD", "RRD", "BIT", "SET", "RES", "INI", "INIR", "IND", "INDR", "OUTI", "OTIR",
+    "OUTD", "OTDR",
+];
+
+/*
+ * Z80 mnemonics the translation knows, other words are left alone
+ */
+const ZILOG: [&str; 30] = [
+    "LD", "PUSH", "POP", "EX", "ADD", "ADC", "SUB", "SBC", "AND", "XOR", "OR", "CP", "INC", "DEC", "JP", "CALL",
+    "RET", "RST", "IN", "OUT", "RLCA", "RRCA", "RLA", "RRA", "CPL", "SCF", "CCF", "HALT", "DAA", "NOP",
+];
+
+impl Dialect {
+    pub fn from_name(name: &str) -> Result<Self, &'static str> {
+        match name.to_ascii_lowercase().as_str() {
+            "8080" | "intel" => Ok(Self::Intel8080),
+            "z80" | "zilog" => Ok(Self::Zilog),
+            "8085" => Ok(Self::Intel8085),
+            _ => Err("Unknown dialect, expected 8080, z80 or 8085"),
+        }
+    }
+
+    /*
+     * Intel 8080 version of an instruction without label, directives and
+     * macro calls are returned unchanged
+     */
+    pub fn translate(&self, line: &str) -> Result<String, &'static str> {
+        let (mnemonic, operands) = match line.split_once(' ') {
+            Some((mnemonic, operands)) => (mnemonic.to_ascii_uppercase(), operands.trim()),
+            None => (line.to_ascii_uppercase(), ""),
+        };
+        match self {
+            Self::Intel8080 => Ok(line.to_string()),
+            Self::Intel8085 => Ok(match opcodes::find_8085(&mnemonic).filter(|_| operands.is_empty()) {
+                Some(opcode) => format!("DB {:03X}H", opcode.opcode),
+                None => line.to_string(),
+            }),
+            Self::Zilog => {
+                let args = if operands.is_empty() { Vec::new() } else { split_operands(operands) };
+                if Z80_ONLY.contains(&mnemonic.as_str()) || args.iter().any(|arg| is_z80_register(arg)) {
+                    return Err("Z80 instruction is not available on the 8080");
+                }
+                if !ZILOG.contains(&mnemonic.as_str()) {
+                    return Ok(line.to_string());
+                }
+                zilog(&mnemonic, &args).ok_or("Invalid operands for Z80 instruction")
+            }
+        }
+    }
+}
+
+fn zilog(mnemonic: &str, args: &[&str]) -> Option<String> {
+    let intel = match (mnemonic, args) {
+        ("LD", [destination, source]) => return load(destination, source),
+        ("PUSH" | "POP", [pair]) => format!("{} {}", mnemonic, stack_pair(pair)?),
+        ("EX", [a, b]) => match (a.to_ascii_uppercase().as_str(), b.to_ascii_uppercase().as_str()) {
+            ("DE", "HL") => "XCHG".to_string(),
+            ("(SP)", "HL") => "XTHL".to_string(),
+            _ => return None,
+        },
+        ("ADD", [hl, pair]) if hl.eq_ignore_ascii_case("HL") => format!("DAD {}", register_pair(pair)?),
+        ("ADD" | "ADC" | "SUB" | "SBC" | "AND" | "XOR" | "OR" | "CP", [a, source]) if a.eq_ignore_ascii_case("A") => {
+            return arithmetic(mnemonic, source)
+        }
+        ("ADD" | "ADC" | "SUB" | "SBC" | "AND" | "XOR" | "OR" | "CP", [source]) => return arithmetic(mnemonic, source),
+        ("INC" | "DEC", [target]) => {
+            let increment = mnemonic == "INC";
+            match (register(target), register_pair(target)) {
+                (Some(register), _) => format!("{} {}", if increment { "INR" } else { "DCR" }, register),
+                (_, Some(pair)) => format!("{} {}", if increment { "INX" } else { "DCX" }, pair),
+                _ => return None,
+            }
+        }
+        ("JP", [target]) if register(target) == Some("M") => "PCHL".to_string(),
+        ("JP", [target]) => format!("JMP {}", value(target)?),
+        ("JP", [condition_code, target]) => format!("J{} {}", condition(condition_code)?, value(target)?),
+        ("CALL", [target]) => format!("CALL {}", value(target)?),
+        ("CALL", [condition_code, target]) => format!("C{} {}", condition(condition_code)?, value(target)?),
+        ("RET", []) => "RET".to_string(),
+        ("RET", [condition_code]) => format!("R{}", condition(condition_code)?),
+        ("RST", [address]) => match eval(address) {
+            Ok(address) if address % 8 == 0 && (0..=0x38).contains(&address) => format!("RST {}", address / 8),
+            _ => return None,
+        },
+        ("IN", [a, port]) if a.eq_ignore_ascii_case("A") => format!("IN {}", memory(port)?),
+        ("OUT", [port, a]) if a.eq_ignore_ascii_case("A") => format!("OUT {}", memory(port)?),
+        (_, []) => match mnemonic {
+            "RLCA" => "RLC",
+            "RRCA" => "RRC",
+            "RLA" => "RAL",
+            "RRA" => "RAR",
+            "CPL" => "CMA",
+            "SCF" => "STC",
+            "CCF" => "CMC",
+            "HALT" => "HLT",
+            "DAA" | "NOP" => mnemonic,
+            _ => return None,
+        }
+        .to_string(),
+        _ => return None,
+    };
+    Some(intel)
+}
+
+/*
+ * All forms of LD that exist on the 8080
+ */
+fn load(destination: &str, source: &str) -> Option<String> {
+    let is_a = |operand: &str| operand.eq_ignore_ascii_case("A");
+    let intel = match (register(destination), register(source)) {
+        (Some("M"), Some("M")) => return None,
+        (Some(destination), Some(source)) => format!("MOV {},{}", destination, source),
+        (Some(destination), None) if memory(source).is_none() && register_pair(source).is_none() => {
+            format!("MVI {},{}", destination, source)
+        }
+        _ if is_a(destination) => match indirect_pair(source) {
+            Some(pair) => format!("LDAX {}", pair),
+            None => format!("LDA {}", memory(source)?),
+        },
+        _ if is_a(source) => match indirect_pair(destination) {
+            Some(pair) => format!("STAX {}", pair),
+            None => format!("STA {}", memory(destination)?),
+        },
+        _ if source.eq_ignore_ascii_case("HL") && memory(destination).is_some() => {
+            format!("SHLD {}", memory(destination)?)
+        }
+        _ => match (register_pair(destination)?, memory(source)) {
+            ("SP", None) if source.eq_ignore_ascii_case("HL") => "SPHL".to_string(),
+            ("H", Some(address)) => format!("LHLD {}", address),
+            (_, Some(_)) => return None,
+            (pair, None) => format!("LXI {},{}", pair, value(source)?),
+        },
+    };
+    Some(intel)
+}
+
+/*
+ * 8 bit arithmetic and logic with a register or an immediate value
+ */
+fn arithmetic(mnemonic: &str, source: &str) -> Option<String> {
+    let (with_register, with_immediate) = match mnemonic {
+        "ADD" => ("ADD", "ADI"),
+        "ADC" => ("ADC", "ACI"),
+        "SUB" => ("SUB", "SUI"),
+        "SBC" => ("SBB", "SBI"),
+        "AND" => ("ANA", "ANI"),
+        "XOR" => ("XRA", "XRI"),
+        "OR" => ("ORA", "ORI"),
+        _ => ("CMP", "CPI"),
+    };
+    match register(source) {
+        Some(register) => Some(format!("{} {}", with_register, register)),
+        None => Some(format!("{} {}", with_immediate, value(source)?)),
+    }
+}
+
+/*
+ * 8 bit register, (HL) is the memory pseudo register M
+ */
+fn register(operand: &str) -> Option<&'static str> {
+    let operand = operand.to_ascii_uppercase();
+    if memory(&operand).is_some_and(|inner| inner == "HL") {
+        return Some("M");
+    }
+    ["A", "B", "C", "D", "E", "H", "L"].iter().copied().find(|name| *name == operand)
+}
+
+fn register_pair(operand: &str) -> Option<&'static str> {
+    match operand.to_ascii_uppercase().as_str() {
+        "BC" => Some("B"),
+        "DE" => Some("D"),
+        "HL" => Some("H"),
+        "SP" => Some("SP"),
+        _ => None,
+    }
+}
+
+fn stack_pair(operand: &str) -> Option<&'static str> {
+    match operand.to_ascii_uppercase().as_str() {
+        "AF" => Some("PSW"),
+        "SP" => None,
+        _ => register_pair(operand),
+    }
+}
+
+/*
+ * (BC) and (DE), used by LDAX and STAX
+ */
+fn indirect_pair(operand: &str) -> Option<&'static str> {
+    memory(operand).and_then(register_pair).filter(|pair| *pair == "B" || *pair == "D")
+}
+
+fn condition(operand: &str) -> Option<&'static str> {
+    let operand = operand.to_ascii_uppercase();
+    ["NZ", "Z", "NC", "C", "PO", "PE", "P", "M"].iter().copied().find(|name| *name == operand)
+}
+
+/*
+ * Inside of an operand enclosed in parentheses, (1)+(2) is not one
+ */
+fn memory(operand: &str) -> Option<&str> {
+    let inner = operand.trim().strip_prefix('(')?.strip_suffix(')')?;
+    let mut depth = 0;
+    for c in inner.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return None,
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+    Some(inner.trim())
+}
+
+/*
+ * Immediate value or address, registers are not one
+ */
+fn value(operand: &str) -> Option<&str> {
+    match register(operand).is_some() || register_pair(operand).is_some() || memory(operand).is_some() {
+        true => None,
+        false => Some(operand),
+    }
+}
+
+fn is_z80_register(operand: &str) -> bool {
+    let operand = operand.to_ascii_uppercase();
+    let operand = memory(&operand).unwrap_or(&operand).to_string();
+    let indexed = operand.starts_with("IX+") || operand.starts_with("IY+");
+    indexed || ["IX", "IY", "I", "R", "AF'"].contains(&operand.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zilog(line: &str) -> Result<String, &'static str> {
+        Dialect::Zilog.translate(line)
+    }
+
+    #[test]
+    fn zilog_loads() {
+        let pairs = [
+            ("LD A,(HL)", "MOV A,M"),
+            ("ld (hl),b", "MOV M,B"),
+            ("LD E,10H", "MVI E,10H"),
+            ("LD (HL),'X'", "MVI M,'X'"),
+            ("LD A,(BC)", "LDAX B"),
+            ("LD (DE),A", "STAX D"),
+            ("LD A,(COUNT+1)", "LDA COUNT+1"),
+            ("LD (BUF),A", "STA BUF"),
+            ("LD A,(1)+(2)", "MVI A,(1)+(2)"),
+            ("LD HL,(PTR)", "LHLD PTR"),
+            ("LD (PTR),HL", "SHLD PTR"),
+            ("LD SP,HL", "SPHL"),
+            ("LD DE,TABLE", "LXI D,TABLE"),
+        ];
+        for (z80, intel) in pairs {
+            assert_eq!(Ok(intel.to_string()), zilog(z80), "{}", z80);
+        }
+        assert_eq!(Err("Invalid operands for Z80 instruction"), zilog("LD (HL),(HL)"));
+        assert_eq!(Err("Invalid operands for Z80 instruction"), zilog("LD BC,(PTR)"));
+    }
+
+    #[test]
+    fn zilog_instructions() {
+        let pairs = [
+            ("JP NZ,LOOP", "JNZ LOOP"),
+            ("JP (HL)", "PCHL"),
+            ("JP START", "JMP START"),
+            ("CALL C,PRINT", "CC PRINT"),
+            ("RET PE", "RPE"),
+            ("ADD A,B", "ADD B"),
+            ("ADD HL,DE", "DAD D"),
+            ("SBC A,5", "SBI 5"),
+            ("CP (HL)", "CMP M"),
+            ("AND 0FH", "ANI 0FH"),
+            ("INC BC", "INX B"),
+            ("DEC (HL)", "DCR M"),
+            ("PUSH AF", "PUSH PSW"),
+            ("EX DE,HL", "XCHG"),
+            ("EX (SP),HL", "XTHL"),
+            ("RST 38H", "RST 7"),
+            ("IN A,(PORT)", "IN PORT"),
+            ("OUT (1),A", "OUT 1"),
+            ("HALT", "HLT"),
+            ("CPL", "CMA"),
+            ("DB 1,2", "DB 1,2"),
+        ];
+        for (z80, intel) in pairs {
+            assert_eq!(Ok(intel.to_string()), zilog(z80), "{}", z80);
+        }
+        assert_eq!(Err("Z80 instruction is not available on the 8080"), zilog("DJNZ LOOP"));
+        assert_eq!(Err("Z80 instruction is not available on the 8080"), zilog("LD A,(IX+2)"));
+        assert_eq!(Err("Invalid operands for Z80 instruction"), zilog("RST 5"));
+        assert_eq!(Err("Invalid operands for Z80 instruction"), zilog("PUSH SP"));
+    }
+
+    #[test]
+    fn other_dialects() {
+        assert_eq!(Ok("DB 020H".to_string()), Dialect::Intel8085.translate("RIM"));
+        assert_eq!(Ok("DB 030H".to_string()), Dialect::Intel8085.translate("sim"));
+        assert_eq!(Ok("LDHI 5".to_string()), Dialect::Intel8085.translate("LDHI 5"));
+        assert_eq!(Ok("RIM".to_string()), Dialect::Intel8080.translate("RIM"));
+        assert_eq!(Ok("JP LOOP".to_string()), Dialect::Intel8080.translate("JP LOOP"));
+        assert_eq!(Ok(Dialect::Zilog), Dialect::from_name("Z80"));
+        assert!(Dialect::from_name("6502").is_err());
+    }
+}
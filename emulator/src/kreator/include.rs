@@ -0,0 +1,227 @@
+use super::parser::strip_comment;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+/*
+ * Source of files named by INCLUDE
+ */
+pub trait FileResolver {
+    fn read(&self, name: &str) -> Result<String, &'static str>;
+}
+
+/*
+ * Files on the host, names are relative to root
+ */
+pub struct FileSystem {
+    root: PathBuf,
+}
+
+impl FileSystem {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+impl FileResolver for FileSystem {
+    fn read(&self, name: &str) -> Result<String, &'static str> {
+        fs::read_to_string(self.root.join(name)).map_err(|_| "File not found")
+    }
+}
+
+/*
+ * Files kept in memory, e.g. handed over from JavaScript
+ */
+#[derive(Default)]
+pub struct MemoryFiles {
+    files: HashMap<String, String>,
+}
+
+impl MemoryFiles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, name: &str, contents: &str) {
+        self.files.insert(name.to_string(), contents.to_string());
+    }
+}
+
+impl FileResolver for MemoryFiles {
+    fn read(&self, name: &str) -> Result<String, &'static str> {
+        self.files.get(name).cloned().ok_or("File not found")
+    }
+}
+
+/*
+ * Where a line comes from, file is None for the main program and
+ * lines produced by a macro know the call that expanded them
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Location {
+    pub file: Option<String>,
+    pub line: usize,
+    pub expansion: Option<Box<Expansion>>,
+}
+
+/*
+ * Macro or repetition block that produced a line
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expansion {
+    pub name: String,
+    pub call: Location,
+}
+
+impl Location {
+    pub fn new(file: Option<&str>, line: usize) -> Self {
+        Self {
+            file: file.map(str::to_string),
+            line,
+            expansion: None,
+        }
+    }
+
+    /*
+     * Same place as written, reached through the expansion of name at call
+     */
+    pub fn expanded(&self, name: &str, call: &Location) -> Self {
+        Self {
+            expansion: Some(Box::new(Expansion {
+                name: name.to_string(),
+                call: call.clone(),
+            })),
+            ..self.clone()
+        }
+    }
+
+    /*
+     * Message followed by the location, e.g. "Illegal macro name supplied! (lib.asm, line 3)"
+     */
+    pub fn error(&self, message: &str) -> String {
+        format!("{} ({})", message, self)
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}, line {}", file, self.line)?,
+            None => write!(f, "line {}", self.line)?,
+        }
+        match &self.expansion {
+            Some(expansion) => write!(f, ", in {} called at {}", expansion.name, expansion.call),
+            None => Ok(()),
+        }
+    }
+}
+
+/*
+ * Replace INCLUDE "file" lines by the contents of the file, returns
+ * the location of every resulting line
+ */
+pub fn expand_includes(
+    code: &[String],
+    resolver: &dyn FileResolver,
+) -> Result<(Vec<String>, Vec<Location>), String> {
+    let mut expanded = Vec::new();
+    let mut locations = Vec::new();
+    include(code, None, resolver, &mut Vec::new(), &mut expanded, &mut locations)?;
+    Ok((expanded, locations))
+}
+
+fn include(
+    code: &[String],
+    file: Option<&str>,
+    resolver: &dyn FileResolver,
+    open_files: &mut Vec<String>,
+    expanded: &mut Vec<String>,
+    locations: &mut Vec<Location>,
+) -> Result<(), String> {
+    for (index, line) in code.iter().enumerate() {
+        let location = Location::new(file, index + 1);
+        let name = match included_file(line) {
+            Some(name) => name.map_err(|message| location.error(message))?,
+            None => {
+                expanded.push(line.clone());
+                locations.push(location);
+                continue;
+            }
+        };
+        if open_files.iter().any(|open| open == name) {
+            return Err(location.error(&format!("\"{}\" includes itself", name)));
+        }
+        let contents = resolver
+            .read(name)
+            .map_err(|message| location.error(&format!("{}: \"{}\"", message, name)))?;
+        let lines: Vec<String> = contents.lines().map(|line| strip_comment(line).trim_end().to_string()).collect();
+
+        open_files.push(name.to_string());
+        include(&lines, Some(name), resolver, open_files, expanded, locations)?;
+        open_files.pop();
+    }
+    Ok(())
+}
+
+/*
+ * File name of an INCLUDE line, None for other lines
+ */
+fn included_file(line: &str) -> Option<Result<&str, &'static str>> {
+    let line = line.trim();
+    let (keyword, operand) = line.split_once(' ').unwrap_or((line, ""));
+    if !keyword.eq_ignore_ascii_case("INCLUDE") {
+        return None;
+    }
+    let operand = operand.trim();
+    Some(
+        operand
+            .strip_prefix('"')
+            .and_then(|name| name.strip_suffix('"'))
+            .filter(|name| !name.is_empty())
+            .ok_or("INCLUDE expects a quoted file name"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files() -> MemoryFiles {
+        let mut files = MemoryFiles::new();
+        files.insert("io.asm", "OUT 1 ; write\nINCLUDE \"lib/util.asm\"");
+        files.insert("lib/util.asm", "NOP");
+        files.insert("loop.asm", "INCLUDE \"loop2.asm\"");
+        files.insert("loop2.asm", "NOP\nINCLUDE \"loop.asm\"");
+        files
+    }
+
+    fn lines(code: &str) -> Vec<String> {
+        code.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn nested_includes() {
+        let (code, locations) = expand_includes(&lines("MVI A,1\nINCLUDE \"io.asm\"\nEND"), &files()).expect("Fuck");
+        assert_eq!(code, lines("MVI A,1\nOUT 1\nNOP\nEND"));
+
+        let location = Location::new;
+        assert_eq!(
+            locations,
+            vec![location(None, 1), location(Some("io.asm"), 1), location(Some("lib/util.asm"), 1), location(None, 3)]
+        );
+        assert_eq!("lib/util.asm, line 1", locations[2].to_string());
+    }
+
+    #[test]
+    fn include_errors() {
+        let result = expand_includes(&lines("NOP\ninclude \"loop.asm\""), &files());
+        assert_eq!("\"loop.asm\" includes itself (loop2.asm, line 2)", result.unwrap_err());
+
+        let result = expand_includes(&lines("INCLUDE \"missing.asm\""), &files());
+        assert_eq!("File not found: \"missing.asm\" (line 1)", result.unwrap_err());
+
+        let result = expand_includes(&lines("NOP\nINCLUDE io.asm"), &files());
+        assert_eq!("INCLUDE expects a quoted file name (line 2)", result.unwrap_err());
+    }
+}
@@ -0,0 +1,284 @@
+use super::object::{Object, Segment, Target};
+use crate::core::emulator::Emulator;
+use crate::terminator::symbols::SymbolTable;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+/*
+ * Places CSEG of all modules one after another starting at the code address,
+ * DSEG follows unless it has an address of its own
+ */
+#[derive(Default)]
+pub struct Linker {
+    modules: Vec<(String, Object)>,
+    code: u16,
+    data: Option<u16>,
+}
+
+/*
+ * Where a segment of a module ended up
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Placement {
+    pub module: String,
+    pub segment: Segment,
+    pub start: u16,
+    pub length: usize,
+}
+
+/*
+ * Linked program, origins are (offset in bytes, load address) pairs
+ * like Assembler::get_origins returns them
+ */
+pub struct Program {
+    pub bytes: Vec<u8>,
+    pub origins: Vec<(u16, u16)>,
+    pub symbols: SymbolTable,
+    pub placements: Vec<Placement>,
+}
+
+impl Linker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_code_address(mut self, address: u16) -> Self {
+        self.code = address;
+        self
+    }
+
+    pub fn with_data_address(mut self, address: u16) -> Self {
+        self.data = Some(address);
+        self
+    }
+
+    pub fn add(&mut self, name: &str, object: Object) {
+        self.modules.push((name.to_string(), object));
+    }
+
+    pub fn link(&self) -> Result<Program, String> {
+        let mut placements = Vec::new();
+        let mut code_starts = Vec::new();
+        let mut data_starts = Vec::new();
+
+        let mut address = self.code as usize;
+        for (name, object) in &self.modules {
+            code_starts.push(address as u16);
+            placements.push(placement(name, Segment::Code, address, object.code.len()));
+            address += object.code.len();
+        }
+        if address > 0x10000 {
+            return Err(String::from("Program does not fit into memory"));
+        }
+        let mut address = self.data.map_or(address, usize::from);
+        for (name, object) in &self.modules {
+            data_starts.push(address as u16);
+            placements.push(placement(name, Segment::Data, address, object.data.len()));
+            address += object.data.len();
+        }
+        if address > 0x10000 {
+            return Err(String::from("Program does not fit into memory"));
+        }
+
+        // public symbols of all modules
+        let mut globals: HashMap<&str, u16> = HashMap::new();
+        for (index, (_, object)) in self.modules.iter().enumerate() {
+            for public in &object.publics {
+                let address = match public.segment {
+                    Segment::Absolute => public.offset,
+                    Segment::Code => code_starts[index].wrapping_add(public.offset),
+                    Segment::Data => data_starts[index].wrapping_add(public.offset),
+                };
+                if globals.insert(&public.name, address).is_some() {
+                    return Err(format!("Symbol {} is PUBLIC in more than one module", public.name));
+                }
+            }
+        }
+
+        // relocate a copy of every segment
+        let mut chunks: Vec<(u16, Vec<u8>)> = Vec::new();
+        for (index, (name, object)) in self.modules.iter().enumerate() {
+            let mut code = object.code.clone();
+            let mut data = object.data.clone();
+            let mut absolute = object.absolute.clone();
+            for fixup in &object.fixups {
+                let value = match &fixup.target {
+                    Target::Code => code_starts[index],
+                    Target::Data => data_starts[index],
+                    Target::External(symbol) => *globals.get(symbol.as_str()).ok_or_else(|| {
+                        format!("Unresolved external symbol {} in {}", symbol, name)
+                    })?,
+                };
+                let (bytes, offset) = match fixup.segment {
+                    Segment::Code => (&mut code, fixup.offset as usize),
+                    Segment::Data => (&mut data, fixup.offset as usize),
+                    Segment::Absolute => {
+                        let (start, bytes) = absolute
+                            .iter_mut()
+                            .find(|(start, bytes)| fixup.offset >= *start && fixup.offset - start < bytes.len() as u16)
+                            .ok_or("Invalid fixup")?;
+                        let offset = (fixup.offset - *start) as usize;
+                        (bytes, offset)
+                    }
+                };
+                if offset + 1 >= bytes.len() {
+                    return Err(String::from("Invalid fixup"));
+                }
+                let word = u16::from_le_bytes([bytes[offset], bytes[offset + 1]]).wrapping_add(value);
+                bytes[offset..offset + 2].copy_from_slice(&word.to_le_bytes());
+            }
+            for (start, bytes) in &absolute {
+                placements.push(placement(name, Segment::Absolute, *start as usize, bytes.len()));
+            }
+            chunks.push((code_starts[index], code));
+            chunks.push((data_starts[index], data));
+            chunks.extend(absolute);
+        }
+        chunks.retain(|(_, bytes)| !bytes.is_empty());
+        chunks.sort_by_key(|(start, _)| *start);
+
+        let mut program = Program {
+            bytes: Vec::new(),
+            origins: Vec::new(),
+            symbols: SymbolTable::new(),
+            placements: placements.into_iter().filter(|placement| placement.length > 0).collect(),
+        };
+        let mut end = 0;
+        for (start, bytes) in chunks {
+            if (start as usize) < end {
+                return Err(String::from("Segments overlap"));
+            }
+            end = start as usize + bytes.len();
+            let offset = u16::try_from(program.bytes.len()).map_err(|_| "Program does not fit into memory")?;
+            program.origins.push((offset, start));
+            program.bytes.extend(bytes);
+        }
+        for (name, address) in globals {
+            program.symbols.insert(address, name);
+        }
+        Ok(program)
+    }
+}
+
+fn placement(module: &str, segment: Segment, start: usize, length: usize) -> Placement {
+    Placement {
+        module: module.to_string(),
+        segment,
+        start: start as u16,
+        length,
+    }
+}
+
+impl Program {
+    /*
+     * Segment list followed by the public symbols in the symbol file format
+     */
+    pub fn map(&self) -> String {
+        let mut map = String::from("START END  SEGMENT MODULE\n");
+        let mut placements = self.placements.clone();
+        placements.sort_by_key(|placement| placement.start);
+        for placement in placements {
+            let end = placement.start as usize + placement.length - 1;
+            let segment = placement.segment.directive();
+            writeln!(map, "{:04X}  {:04X} {}    {}", placement.start, end, segment, placement.module).unwrap();
+        }
+        writeln!(map).unwrap();
+        map.push_str(&self.symbols.to_string());
+        map
+    }
+
+    pub fn load(&self, emulator: &mut Emulator) {
+        for (index, (offset, address)) in self.origins.iter().enumerate() {
+            let end = self.origins.get(index + 1).map_or(self.bytes.len(), |(next, _)| *next as usize);
+            emulator.load_ram(self.bytes[*offset as usize..end].to_vec(), *address);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kreator::assembler::Assembler;
+
+    fn object(code: &str) -> Object {
+        Assembler::new(code).assemble_object().expect("Fuck")
+    }
+
+    #[test]
+    fn links_modules() {
+        let main = object("EXTRN PRINT\nCSEG\nSTART: LXI H,MSG\nCALL PRINT\nHLT\nDSEG\nMSG: DB 'HI',0\nEND");
+        let print = object("PUBLIC PRINT\nCSEG\nPRINT: MOV A,M\nORA A\nRZ\nINX H\nJMP PRINT\nEND");
+
+        let mut linker = Linker::new().with_code_address(0x100);
+        linker.add("main", main);
+        linker.add("print", print);
+        let program = linker.link().expect("Fuck");
+
+        let code = vec![0x21, 0x0e, 0x01, 0xcd, 0x07, 0x01, 0x76];
+        let print = vec![0x7e, 0xb7, 0xc8, 0x23, 0xc3, 0x07, 0x01];
+        let data = vec![0x48, 0x49, 0x00];
+        assert_eq!([code, print, data].concat(), program.bytes);
+        assert_eq!(vec![(0, 0x100), (7, 0x107), (14, 0x10e)], program.origins);
+        assert_eq!(Some("PRINT"), program.symbols.name(0x107));
+
+        let map = "START END  SEGMENT MODULE\n\
+                   0100  0106 CSEG    main\n\
+                   0107  010D CSEG    print\n\
+                   010E  0110 DSEG    main\n\
+                   \n\
+                   0107 PRINT\n";
+        assert_eq!(map, program.map());
+    }
+
+    #[test]
+    fn absolute_segments() {
+        let mut linker = Linker::new().with_code_address(0x200).with_data_address(0x1000);
+        linker.add("vectors", object("EXTRN MAIN\nASEG\nORG 0\nJMP MAIN\nEND"));
+        linker.add("main", object("PUBLIC MAIN\nCSEG\nNOP\nMAIN: LDA VALUE\nHLT\nDSEG\nVALUE: DB 7\nEND"));
+        let program = linker.link().expect("Fuck");
+
+        assert_eq!(vec![(0, 0), (3, 0x200), (8, 0x1000)], program.origins);
+        assert_eq!(vec![0xc3, 0x01, 0x02, 0x00, 0x3a, 0x00, 0x10, 0x76, 0x07], program.bytes);
+
+        let mut emulator = Emulator::new();
+        program.load(&mut emulator);
+        while emulator.is_running() {
+            emulator.step().expect("Fuck");
+        }
+        assert_eq!(7, emulator.get_registers()['a']);
+    }
+
+    #[test]
+    fn link_errors() {
+        let mut linker = Linker::new();
+        linker.add("main", object("EXTRN GONE\nCALL GONE\nEND"));
+        assert_eq!(Some("Unresolved external symbol GONE in main"), linker.link().err().as_deref());
+
+        let mut linker = Linker::new();
+        linker.add("one", object("PUBLIC X\nX: NOP\nEND"));
+        linker.add("two", object("PUBLIC X\nX: NOP\nEND"));
+        assert_eq!(Some("Symbol X is PUBLIC in more than one module"), linker.link().err().as_deref());
+
+        let mut linker = Linker::new().with_code_address(0x10);
+        linker.add("main", object("NOP\nASEG\nORG 10H\nNOP\nEND"));
+        assert_eq!(Some("Segments overlap"), linker.link().err().as_deref());
+
+        // Code running past FFFFH is rejected even when DSEG has its own address
+        let mut linker = Linker::new().with_code_address(0xfff0).with_data_address(0x100);
+        linker.add("main", Object { code: vec![0; 0x20], data: vec![1], ..Object::default() });
+        assert_eq!(Some("Program does not fit into memory"), linker.link().err().as_deref());
+    }
+
+    #[test]
+    fn full_memory() {
+        let mut linker = Linker::new();
+        linker.add("main", Object { code: vec![0; 0x10000], ..Object::default() });
+        let program = linker.link().expect("Fuck");
+        assert_eq!(vec![(0, 0)], program.origins);
+        assert_eq!(0x10000, program.bytes.len());
+
+        linker.add("more", Object { absolute: vec![(0xffff, vec![1])], ..Object::default() });
+        assert_eq!(Some("Segments overlap"), linker.link().err().as_deref());
+    }
+}
@@ -0,0 +1,311 @@
+use super::assembler::get_reserved_names;
+use super::include::Location;
+use super::parser::substitute_parameters;
+use std::collections::HashMap;
+
+/*
+ * Deepest nesting of macro calls and repetition blocks, stops runaway recursion
+ */
+pub const MAX_NESTING: usize = 64;
+
+/*
+ * Source line along with where it was written
+ */
+pub type Line = (String, Location);
+
+/*
+ * Body of a macro or repetition block, LOCAL names get a fresh name on every expansion
+ */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Block {
+    pub parameters: Vec<String>,
+    pub locals: Vec<String>,
+    pub body: Vec<Line>,
+}
+
+impl Block {
+    /*
+     * LOCAL lines at the start of the body declare the local names
+     */
+    pub fn new(parameters: Vec<String>, mut body: Vec<Line>) -> Result<Self, String> {
+        let mut locals = Vec::new();
+        while body.first().is_some_and(|(line, _)| directive(line) == "LOCAL") {
+            let (line, location) = body.remove(0);
+            let names = line.trim().split_once(char::is_whitespace).map_or("", |(_, names)| names);
+            for name in names.split(',').map(|name| name.trim().to_uppercase()) {
+                if !is_name(&name) {
+                    return Err(location.error("Illegal LOCAL name"));
+                }
+                locals.push(name);
+            }
+        }
+        Ok(Self { parameters, locals, body })
+    }
+
+    /*
+     * Body with parameters replaced by arguments and local names made unique,
+     * missing arguments are empty
+     */
+    pub fn expand(
+        &self,
+        arguments: &[String],
+        name: &str,
+        call: &Location,
+        local_count: &mut usize,
+    ) -> Result<Vec<Line>, String> {
+        if arguments.len() > self.parameters.len() {
+            return Err(call.error(&format!("Too many arguments for {}", name)));
+        }
+        let mut bindings = HashMap::new();
+        for (index, parameter) in self.parameters.iter().enumerate() {
+            bindings.insert(parameter.clone(), arguments.get(index).cloned().unwrap_or_default());
+        }
+        for local in &self.locals {
+            let generated = local_name(*local_count).ok_or_else(|| call.error("Too many LOCAL names"))?;
+            bindings.insert(local.clone(), generated);
+            *local_count += 1;
+        }
+        Ok(self
+            .body
+            .iter()
+            .map(|(line, location)| (substitute_parameters(line, &bindings), location.expanded(name, call)))
+            .collect())
+    }
+}
+
+/*
+ * Take the MACRO ... ENDM definitions out of the code, names are upper case
+ */
+pub fn definitions(code: Vec<Line>) -> Result<(Vec<Line>, HashMap<String, Block>), String> {
+    let reserved = get_reserved_names();
+    let mut remaining = Vec::new();
+    let mut macros = HashMap::new();
+    let mut index = 0;
+
+    while index < code.len() {
+        let (line, location) = &code[index];
+        index += 1;
+        match directive(line).as_str() {
+            "MACRO" => {
+                let line = line.trim();
+                let (name, parameters) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+                if name.eq_ignore_ascii_case("MACRO") {
+                    return Err(location.error("Cannot define macro without name"));
+                }
+                let name = name.to_uppercase();
+                if !is_name(&name) || reserved.contains(&name.as_str()) {
+                    return Err(location.error("Illegal macro name supplied!"));
+                }
+                if macros.contains_key(&name) {
+                    return Err(location.error("Macro must not be defined twice"));
+                }
+                let parameters = parameters.trim_start()[5..].trim();
+                let parameters: Vec<String> = match parameters.is_empty() {
+                    true => Vec::new(),
+                    false => parameters.split(',').map(|parameter| parameter.trim().to_uppercase()).collect(),
+                };
+                if let Some(parameter) = parameters.iter().find(|parameter| !is_name(parameter)) {
+                    return Err(location.error(&format!("Illegal macro parameter {}", parameter)));
+                }
+                let (body, next) = block(&code, index, "MACRO", location)?;
+                macros.insert(name, Block::new(parameters, body)?);
+                index = next;
+            }
+            "REPT" | "IRP" | "IRPC" => {
+                let (_, next) = block(&code, index, &directive(line), location)?;
+                remaining.extend_from_slice(&code[index - 1..next]);
+                index = next;
+            }
+            "ENDM" => return Err(location.error("Every ENDM must have a corresponding MACRO")),
+            _ => remaining.push((line.clone(), location.clone())),
+        }
+    }
+    Ok((remaining, macros))
+}
+
+/*
+ * Lines from start up to the ENDM closing the block opened by the line before,
+ * returns them along with the index after ENDM
+ */
+pub fn block(lines: &[Line], start: usize, name: &str, opened: &Location) -> Result<(Vec<Line>, usize), String> {
+    let mut depth = 0;
+    for (index, (line, location)) in lines.iter().enumerate().skip(start) {
+        match directive(line).as_str() {
+            "MACRO" => return Err(location.error("Cannot define macro within macro")),
+            "REPT" | "IRP" | "IRPC" => depth += 1,
+            "ENDM" if !line.trim().eq_ignore_ascii_case("ENDM") => return Err(location.error("ENDM must stand alone")),
+            "ENDM" if depth == 0 => return Ok((lines[start..index].to_vec(), index + 1)),
+            "ENDM" => depth -= 1,
+            _ => {}
+        }
+    }
+    Err(opened.error(&format!("Every {} has to be followed by an ENDM", name)))
+}
+
+/*
+ * Parameter and arguments of every iteration of an IRP or IRPC block,
+ * IRP takes a list like <1, 2, 3> and IRPC the characters of its text
+ */
+pub fn iterations(directive: &str, operands: &str) -> Result<(Vec<String>, Vec<Vec<String>>), &'static str> {
+    let (parameter, list) = operands.split_once(',').ok_or("IRP and IRPC expect a parameter and a list")?;
+    let parameter = parameter.trim().to_uppercase();
+    if !is_name(&parameter) {
+        return Err("Illegal macro parameter");
+    }
+    let list = unbracket(list);
+    let arguments: Vec<String> = match directive {
+        "IRP" => split_arguments(&list),
+        _ => list.chars().map(String::from).collect(),
+    };
+    Ok((vec![parameter], arguments.into_iter().map(|argument| vec![argument]).collect()))
+}
+
+/*
+ * Upper case directive of a line, a leading label is skipped and
+ * "NAME MACRO parameters" is a MACRO line
+ */
+pub fn directive(line: &str) -> String {
+    let mut words = line.split_whitespace();
+    let first = words.next().unwrap_or("");
+    let second = words.next().unwrap_or("");
+    if second.eq_ignore_ascii_case("MACRO") && !first.contains(':') {
+        return "MACRO".to_string();
+    }
+    match first.split_once(':') {
+        Some((_, "")) => second.to_ascii_uppercase(),
+        Some((_, rest)) => rest.to_ascii_uppercase(),
+        None => first.to_ascii_uppercase(),
+    }
+}
+
+/*
+ * Split arguments at commas outside of quotes and angle brackets,
+ * brackets around an argument are removed so <1, 2> passes "1, 2"
+ */
+pub fn split_arguments(text: &str) -> Vec<String> {
+    if text.trim().is_empty() {
+        return Vec::new();
+    }
+    let mut arguments = Vec::new();
+    let mut argument = String::new();
+    let mut quoted = false;
+    let mut brackets = 0;
+    for c in text.chars() {
+        match c {
+            '\'' => quoted = !quoted,
+            '<' if !quoted => brackets += 1,
+            '>' if !quoted && brackets > 0 => brackets -= 1,
+            ',' if !quoted && brackets == 0 => {
+                arguments.push(unbracket(&argument));
+                argument.clear();
+                continue;
+            }
+            _ => {}
+        }
+        argument.push(c);
+    }
+    arguments.push(unbracket(&argument));
+    arguments
+}
+
+fn unbracket(argument: &str) -> String {
+    let argument = argument.trim();
+    match argument.strip_prefix('<').and_then(|inner| inner.strip_suffix('>')) {
+        Some(inner) => inner.trim().to_string(),
+        None => argument.to_string(),
+    }
+}
+
+fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '@' || c == '?')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '@' || c == '?' || c == '_')
+}
+
+/*
+ * Generated names are ??000 to ??ZZZ which fits into five characters
+ */
+fn local_name(index: usize) -> Option<String> {
+    const DIGITS: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+    if index >= 36 * 36 * 36 {
+        return None;
+    }
+    let digit = |value: usize| DIGITS[value % 36] as char;
+    Some(format!("??{}{}{}", digit(index / 1296), digit(index / 36), digit(index)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(code: &[&str]) -> Vec<Line> {
+        code.iter().enumerate().map(|(index, line)| (line.to_string(), Location::new(None, index + 1))).collect()
+    }
+
+    fn text(lines: &[Line]) -> Vec<&str> {
+        lines.iter().map(|(line, _)| line.as_str()).collect()
+    }
+
+    #[test]
+    fn macro_definitions() {
+        let (code, macros) = definitions(lines(&["SHRT MACRO", "RRC", "ANI 7FH", "ENDM", "shrt"])).expect("Fuck");
+        assert_eq!(vec!["shrt"], text(&code));
+        assert_eq!(vec!["RRC", "ANI 7FH"], text(&macros["SHRT"].body));
+
+        let code = lines(&["MAC1 MACRO P1, p2, COMMENT", "LOCAL L1, L2", "XRA P2", "ENDM", "REPT 2", "NOP", "ENDM"]);
+        let (code, macros) = definitions(code).expect("Fuck");
+        assert_eq!(vec!["P1", "P2", "COMMENT"], macros["MAC1"].parameters);
+        assert_eq!(vec!["L1", "L2"], macros["MAC1"].locals);
+        assert_eq!(vec!["REPT 2", "NOP", "ENDM"], text(&code));
+
+        let error = |code: &[&str]| definitions(lines(code)).err().unwrap_or_default();
+        assert_eq!("Every MACRO has to be followed by an ENDM (line 1)", error(&["THE MACRO"]));
+        assert_eq!("Every ENDM must have a corresponding MACRO (line 2)", error(&["NOP", "ENDM"]));
+        assert_eq!("Cannot define macro without name (line 1)", error(&["MACRO", "ENDM", "END"]));
+        assert_eq!("Cannot define macro within macro (line 2)", error(&["ABC MACRO", "DEF MACRO", "ENDM"]));
+        assert_eq!("Illegal macro name supplied! (line 1)", error(&["A MACRO", "ENDM"]));
+        assert_eq!("ENDM must stand alone (line 2)", error(&["ABC MACRO", "ENDM ABC"]));
+        assert_eq!("Every IRP has to be followed by an ENDM (line 1)", error(&["IRP X,<1>", "DB X"]));
+    }
+
+    #[test]
+    fn identifiers_containing_macro() {
+        let (code, macros) = definitions(lines(&["MACROS EQU 1", "ENDMAC: DB MACROS"])).expect("Fuck");
+        assert_eq!(2, code.len());
+        assert!(macros.is_empty());
+    }
+
+    #[test]
+    fn expansion() {
+        let (_, macros) = definitions(lines(&["M MACRO X", "LOCAL L", "L: DCR X", "JNZ L", "ENDM"])).expect("Fuck");
+        let call = Location::new(Some("main.asm"), 9);
+        let mut local_count = 0;
+        let first = macros["M"].expand(&["B".to_string()], "M", &call, &mut local_count).expect("Fuck");
+        let second = macros["M"].expand(&[], "M", &call, &mut local_count).expect("Fuck");
+
+        assert_eq!(vec!["??000: DCR B", "JNZ ??000"], text(&first));
+        assert_eq!(vec!["??001: DCR ", "JNZ ??001"], text(&second));
+        assert_eq!("line 3, in M called at main.asm, line 9", first[0].1.to_string());
+
+        let arguments = vec!["A".to_string(), "B".to_string()];
+        let result = macros["M"].expand(&arguments, "M", &call, &mut 0);
+        assert_eq!("Too many arguments for M (main.asm, line 9)", result.unwrap_err());
+        assert_eq!(Some("??00Z".to_string()), local_name(35));
+        assert_eq!(None, local_name(36 * 36 * 36));
+    }
+
+    #[test]
+    fn arguments() {
+        assert_eq!(vec!["A", "1, 2", "','", ""], split_arguments("A, <1, 2>, ',',"));
+        assert!(split_arguments(" ").is_empty());
+
+        let (parameter, iterations) = iterations("IRP", "X, <1, 'A,B'>").expect("Fuck");
+        assert_eq!(vec!["X"], parameter);
+        assert_eq!(vec![vec!["1".to_string()], vec!["'A,B'".to_string()]], iterations);
+        assert_eq!(3, super::iterations("IRPC", "C,ABC").expect("Fuck").1.len());
+
+        assert_eq!("MACRO", directive("send macro a"));
+        assert_eq!("REPT", directive("LAB: rept 3"));
+        assert_eq!("IRP", directive("LAB:IRP X,<1>"));
+    }
+}
@@ -1,3 +1,8 @@
 pub mod assembler;
+pub mod dialect;
+pub mod include;
+pub mod linker;
+pub mod macros;
+pub mod object;
 pub mod parser;
 pub mod preprocessor;
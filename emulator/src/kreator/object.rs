@@ -0,0 +1,199 @@
+use core::fmt;
+
+/*
+ * ASEG is placed where it is written, CSEG and DSEG are moved by the linker
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Segment {
+    Absolute,
+    Code,
+    Data,
+}
+
+impl Segment {
+    pub fn from_directive(directive: &str) -> Option<Self> {
+        match directive.trim().to_ascii_uppercase().as_str() {
+            "ASEG" => Some(Self::Absolute),
+            "CSEG" => Some(Self::Code),
+            "DSEG" => Some(Self::Data),
+            _ => None,
+        }
+    }
+
+    pub fn directive(&self) -> &'static str {
+        match self {
+            Self::Absolute => "ASEG",
+            Self::Code => "CSEG",
+            Self::Data => "DSEG",
+        }
+    }
+}
+
+/*
+ * What the linker adds to a relocated word
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub enum Target {
+    Code,
+    Data,
+    External(String),
+}
+
+/*
+ * 16 bit word at offset in segment, for ASEG offset is the address
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fixup {
+    pub segment: Segment,
+    pub offset: u16,
+    pub target: Target,
+}
+
+/*
+ * Public symbols keep their segment and offset within it
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Public {
+    pub name: String,
+    pub segment: Segment,
+    pub offset: u16,
+}
+
+/*
+ * Relocatable object produced by Assembler::assemble_object
+ */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Object {
+    pub code: Vec<u8>,
+    pub data: Vec<u8>,
+    pub absolute: Vec<(u16, Vec<u8>)>,
+    pub publics: Vec<Public>,
+    pub externals: Vec<String>,
+    pub fixups: Vec<Fixup>,
+}
+
+/*
+ * Object file format, one record per line:
+ *   CSEG 3E01CD0000
+ *   ASEG 0100 C9
+ *   PUBLIC START CSEG 0000
+ *   EXTRN PRINT
+ *   FIXUP CSEG 0003 PRINT
+ */
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.code.is_empty() {
+            writeln!(f, "CSEG {}", hex(&self.code))?;
+        }
+        if !self.data.is_empty() {
+            writeln!(f, "DSEG {}", hex(&self.data))?;
+        }
+        for (address, bytes) in &self.absolute {
+            writeln!(f, "ASEG {:04X} {}", address, hex(bytes))?;
+        }
+        for public in &self.publics {
+            writeln!(f, "PUBLIC {} {} {:04X}", public.name, public.segment.directive(), public.offset)?;
+        }
+        for name in &self.externals {
+            writeln!(f, "EXTRN {}", name)?;
+        }
+        for fixup in &self.fixups {
+            let target = match &fixup.target {
+                Target::Code => "CSEG",
+                Target::Data => "DSEG",
+                Target::External(name) => name,
+            };
+            writeln!(f, "FIXUP {} {:04X} {}", fixup.segment.directive(), fixup.offset, target)?;
+        }
+        Ok(())
+    }
+}
+
+impl Object {
+    pub fn parse(text: &str) -> Result<Self, &'static str> {
+        let mut object = Object::default();
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                ["CSEG", bytes] => object.code = parse_hex(bytes)?,
+                ["DSEG", bytes] => object.data = parse_hex(bytes)?,
+                ["ASEG", address, bytes] => object.absolute.push((parse_word(address)?, parse_hex(bytes)?)),
+                ["PUBLIC", name, segment, offset] => object.publics.push(Public {
+                    name: name.to_string(),
+                    segment: Segment::from_directive(segment).ok_or("Invalid object file")?,
+                    offset: parse_word(offset)?,
+                }),
+                ["EXTRN", name] => object.externals.push(name.to_string()),
+                ["FIXUP", segment, offset, target] => object.fixups.push(Fixup {
+                    segment: Segment::from_directive(segment).ok_or("Invalid object file")?,
+                    offset: parse_word(offset)?,
+                    target: match *target {
+                        "CSEG" => Target::Code,
+                        "DSEG" => Target::Data,
+                        name => Target::External(name.to_string()),
+                    },
+                }),
+                _ => return Err("Invalid object file"),
+            }
+        }
+        Ok(object)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+fn parse_hex(text: &str) -> Result<Vec<u8>, &'static str> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return Err("Invalid object file");
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&text[index..index + 2], 16).map_err(|_| "Invalid object file"))
+        .collect()
+}
+
+fn parse_word(text: &str) -> Result<u16, &'static str> {
+    u16::from_str_radix(text, 16).map_err(|_| "Invalid object file")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn object_file_format() {
+        let object = Object {
+            code: vec![0x21, 0x00, 0x00, 0xcd, 0x02, 0x00],
+            data: vec![0x00, 0x00],
+            absolute: vec![(0x38, vec![0xc3, 0x00, 0x00])],
+            publics: vec![Public {
+                name: "START".to_string(),
+                segment: Segment::Code,
+                offset: 0,
+            }],
+            externals: vec!["PRINT".to_string()],
+            fixups: vec![
+                Fixup {
+                    segment: Segment::Code,
+                    offset: 1,
+                    target: Target::Data,
+                },
+                Fixup {
+                    segment: Segment::Absolute,
+                    offset: 0x39,
+                    target: Target::External("PRINT".to_string()),
+                },
+            ],
+        };
+        let text = "CSEG 210000CD0200\nDSEG 0000\nASEG 0038 C30000\nPUBLIC START CSEG 0000\nEXTRN PRINT\n\
+                    FIXUP CSEG 0001 DSEG\nFIXUP ASEG 0039 PRINT\n";
+
+        assert_eq!(text, object.to_string());
+        assert_eq!(Ok(object), Object::parse(text));
+        assert_eq!(Err("Invalid object file"), Object::parse("CSEG 123"));
+        assert_eq!(Err("Invalid object file"), Object::parse("LINK ME"));
+        assert_eq!(Err("Invalid object file"), Object::parse("CSEG 1é1"));
+    }
+}
@@ -1,10 +1,22 @@
+use std::collections::HashMap;
 use std::{iter::Peekable, str::Chars};
+
+/*
+ * Values are 16 bit, negative numbers down to -32768 are allowed
+ * so that -1 and 0FFFFH mean the same thing
+ */
+const MIN_VALUE: i32 = -0x8000;
+const MAX_VALUE: i32 = 0xffff;
+const TRUE: i32 = 0xffff;
+const REGISTERS: [&str; 10] = ["A", "B", "C", "D", "E", "H", "L", "M", "SP", "PSW"];
+
 #[derive(Debug, PartialEq)]
 enum Token {
     Number(i32),
+    Symbol(String),
     Operator(Op),
+    Unary(UnaryOp),
     Parenthesis(char),
-    Unary,
 }
 
 #[derive(Debug, PartialEq)]
@@ -14,49 +26,333 @@ enum Op {
     Mul,
     Div,
     Mod,
+    Shl,
+    Shr,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
     And,
     Or,
     Xor,
-    Shr,
-    Shl,
+}
+
+#[derive(Debug, PartialEq)]
+enum UnaryOp {
+    Plus,
+    Minus,
+    Not,
+    High,
+    Low,
 }
 
 impl Op {
+    /*
+     * Precedence as in the Intel 8080/8085 assembly language manual
+     */
     fn precedence(&self) -> i32 {
         match self {
-            Self::Or | Self::Xor => -2,
-            Self::And => -1,
-            Self::Add | Self::Sub => 0,
-            Self::Mul | Self::Div | Self::Mod | Self::Shl | Self::Shr => 1,
+            Self::Or | Self::Xor => 0,
+            Self::And => 1,
+            Self::Eq | Self::Ne | Self::Lt | Self::Le | Self::Gt | Self::Ge => 3,
+            Self::Add | Self::Sub => 4,
+            Self::Mul | Self::Div | Self::Mod | Self::Shl | Self::Shr => 5,
         }
     }
 
-    fn apply(&self, arg1: i32, arg2: i32) -> i32 {
-        match self {
+    fn apply(&self, arg1: i32, arg2: i32) -> Result<i32, &'static str> {
+        let result = match self {
             Self::Add => arg1 + arg2,
             Self::Sub => arg1 - arg2,
-            Self::Mul => arg1 * arg2,
+            Self::Mul => arg1.checked_mul(arg2).ok_or("Expression overflow")?,
+            Self::Div | Self::Mod if arg2 == 0 => return Err("Division by zero"),
             Self::Div => arg1 / arg2,
             Self::Mod => arg1 % arg2,
-            Self::And => arg1 & arg2,
-            Self::Or => arg1 | arg2,
-            Self::Xor => arg1 ^ arg2,
-            Self::Shr => arg1 >> arg2,
-            Self::Shl => arg1 << arg2,
+            Self::Shl => (arg1 & MAX_VALUE).checked_shl(arg2 as u32).unwrap_or(0),
+            Self::Shr => (arg1 & MAX_VALUE).checked_shr(arg2 as u32).unwrap_or(0),
+            Self::Eq => compare(arg1 & MAX_VALUE == arg2 & MAX_VALUE),
+            Self::Ne => compare(arg1 & MAX_VALUE != arg2 & MAX_VALUE),
+            Self::Lt => compare(arg1 & MAX_VALUE < arg2 & MAX_VALUE),
+            Self::Le => compare(arg1 & MAX_VALUE <= arg2 & MAX_VALUE),
+            Self::Gt => compare(arg1 & MAX_VALUE > arg2 & MAX_VALUE),
+            Self::Ge => compare(arg1 & MAX_VALUE >= arg2 & MAX_VALUE),
+            Self::And => (arg1 & arg2) & MAX_VALUE,
+            Self::Or => (arg1 | arg2) & MAX_VALUE,
+            Self::Xor => (arg1 ^ arg2) & MAX_VALUE,
+        };
+        check_range(result)
+    }
+}
+
+impl UnaryOp {
+    fn precedence(&self) -> i32 {
+        match self {
+            Self::Not => 2,
+            Self::Plus | Self::Minus | Self::High | Self::Low => 6,
         }
     }
+
+    fn apply(&self, arg: i32) -> Result<i32, &'static str> {
+        check_range(match self {
+            Self::Plus => arg,
+            Self::Minus => -arg,
+            Self::Not => !arg & MAX_VALUE,
+            Self::High => (arg >> 8) & 0xff,
+            Self::Low => arg & 0xff,
+        })
+    }
+}
+
+fn compare(condition: bool) -> i32 {
+    if condition {
+        TRUE
+    } else {
+        0
+    }
+}
+
+fn check_range(value: i32) -> Result<i32, &'static str> {
+    if (MIN_VALUE..=MAX_VALUE).contains(&value) {
+        Ok(value)
+    } else {
+        Err("Expression overflow")
+    }
+}
+
+/*
+ * Where the values of names and memory references ([address]) come from
+ */
+pub trait Environment {
+    fn value(&self, name: &str) -> Option<u16>;
+
+    fn memory(&self, _address: u16) -> Result<u8, &'static str> {
+        Err("Memory references are not allowed here")
+    }
+}
+
+impl Environment for HashMap<String, u16> {
+    fn value(&self, name: &str) -> Option<u16> {
+        self.get(name).copied()
+    }
 }
 
-pub fn eval(expression: &str) -> i32 {
-    to_expression_tree(tokenize(expression.to_string())).evaluate()
+/*
+ * Parsed expression for evaluating it more than once
+ */
+#[derive(Debug)]
+pub struct Expression {
+    tree: BinaryExpressionTree,
+}
+
+impl Expression {
+    pub fn parse(expression: &str) -> Result<Self, &'static str> {
+        Ok(Expression { tree: to_expression_tree(tokenize(expression)?)? })
+    }
+
+    pub fn evaluate(&self, environment: &dyn Environment) -> Result<i32, &'static str> {
+        self.tree.evaluate(environment)
+    }
+}
+
+/*
+ * Evaluate an expression without symbols
+ */
+pub fn eval(expression: &str) -> Result<i32, &'static str> {
+    eval_with_symbols(expression, &HashMap::new())
+}
+
+/*
+ * Evaluate an expression, names are looked up in symbols
+ */
+pub fn eval_with_symbols(expression: &str, symbols: &HashMap<String, u16>) -> Result<i32, &'static str> {
+    Expression::parse(expression)?.evaluate(symbols)
+}
+
+/*
+ * Split a line into unquoted and quoted parts, quoted parts keep
+ * their quotes, a doubled quote inside a string is an escaped quote
+ */
+fn split_quotes(line: &str) -> Vec<(bool, &str)> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut chars = line.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        if c != '\'' {
+            continue;
+        }
+        if !quoted {
+            parts.push((false, &line[start..index]));
+            start = index;
+            quoted = true;
+        } else if chars.next_if(|&(_, next)| next == '\'').is_none() {
+            parts.push((true, &line[start..=index]));
+            start = index + 1;
+            quoted = false;
+        }
+    }
+    parts.push((quoted, &line[start..]));
+    parts
+}
+
+/*
+ * String::replace that leaves character and string constants alone
+ */
+pub fn replace_unquoted(line: &str, from: &str, to: &str) -> String {
+    split_quotes(line)
+        .into_iter()
+        .map(|(quoted, part)| if quoted { part.to_string() } else { part.replace(from, to) })
+        .collect()
+}
+
+/*
+ * Replace names by their values, numbers, registers, operators and
+ * quoted constants are left alone
+ */
+pub fn substitute_symbols(operands: &str, symbols: &HashMap<String, u16>) -> String {
+    let mut result = String::new();
+    for (quoted, part) in split_quotes(operands) {
+        if quoted {
+            result.push_str(part);
+            continue;
+        }
+        let mut chars = part.chars().peekable();
+        while let Some(c) = chars.next() {
+            if !is_identifier_char(c) {
+                result.push(c);
+                continue;
+            }
+            let mut word = String::from(c);
+            while let Some(next) = chars.next_if(|&x| is_identifier_char(x)) {
+                word.push(next);
+            }
+            let name = word.to_uppercase();
+            let is_name = !c.is_ascii_digit() && !REGISTERS.contains(&name.as_str()) && keyword(&name).is_none();
+            match symbols.get(&name) {
+                Some(value) if is_name => result.push_str(&value.to_string()),
+                _ => result.push_str(&word),
+            }
+        }
+    }
+    result
+}
+
+/*
+ * Replace macro parameters by their arguments anywhere in the line, & glues
+ * a parameter to the text around it and is dropped
+ */
+pub fn substitute_parameters(line: &str, parameters: &HashMap<String, String>) -> String {
+    let mut result = String::new();
+    for (quoted, part) in split_quotes(line) {
+        if quoted {
+            result.push_str(part);
+            continue;
+        }
+        let mut chars = part.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c == '&' {
+                continue;
+            }
+            if !is_identifier_char(c) {
+                result.push(c);
+                continue;
+            }
+            let mut word = String::from(c);
+            while let Some(next) = chars.next_if(|&x| is_identifier_char(x)) {
+                word.push(next);
+            }
+            match parameters.get(&word.to_uppercase()) {
+                Some(argument) if !c.is_ascii_digit() => result.push_str(argument),
+                _ => result.push_str(&word),
+            }
+        }
+    }
+    result
+}
+
+/*
+ * Remove a comment, semicolons inside quotes do not start one
+ */
+pub fn strip_comment(line: &str) -> &str {
+    let mut offset = 0;
+    for (quoted, part) in split_quotes(line) {
+        if !quoted {
+            if let Some(index) = part.find(';') {
+                return &line[..offset + index];
+            }
+        }
+        offset += part.len();
+    }
+    line
+}
+
+/*
+ * Split instruction operands at commas outside of quotes
+ */
+pub fn split_operands(operands: &str) -> Vec<&str> {
+    let mut result = Vec::new();
+    let mut start = 0;
+    let mut offset = 0;
+    for (quoted, part) in split_quotes(operands) {
+        if !quoted {
+            for (index, _) in part.match_indices(',') {
+                result.push(operands[start..offset + index].trim());
+                start = offset + index + 1;
+            }
+        }
+        offset += part.len();
+    }
+    result.push(operands[start..].trim());
+    result
+}
+
+/*
+ * Characters of an operand that is a single quoted string
+ */
+pub fn string_literal(operand: &str) -> Option<Vec<u8>> {
+    match split_quotes(operand.trim()).as_slice() {
+        [(false, ""), (true, string), (false, "")] if string.len() >= 2 => {
+            Some(string[1..string.len() - 1].replace("''", "'").bytes().collect())
+        }
+        _ => None,
+    }
+}
+
+/*
+ * Value of a character constant after its opening quote, two
+ * characters form a 16 bit value with the first one in the high byte
+ */
+fn char_constant(chars: &mut Peekable<impl Iterator<Item = char>>) -> Result<i32, &'static str> {
+    let mut value = 0;
+    let mut length = 0;
+    while let Some(c) = chars.next() {
+        if c == '\'' && chars.next_if(|&x| x == '\'').is_none() {
+            return match length {
+                0..=2 => Ok(value),
+                _ => Err("Character constant too long"),
+            };
+        }
+        value = ((value << 8) | (c as i32 & 0xff)) & 0xffff;
+        length += 1;
+    }
+    Err("Unterminated character constant")
 }
 
 #[derive(Debug)]
 enum Item {
     Number(i32),
+    Symbol(String),
     Operator(Op),
+    Unary(UnaryOp),
+    // Byte at the address in the left subtree
+    Memory,
 }
 
+/*
+ * Unary operators only use the left subtree
+ */
 #[derive(Debug)]
 struct BinaryExpressionTree {
     root: Item,
@@ -81,273 +377,240 @@ impl BinaryExpressionTree {
         }
     }
 
-    pub fn evaluate(&self) -> i32 {
-        if let Item::Number(c) = &self.root {
-            *c
-        } else if let Item::Operator(op) = &self.root {
-            op.apply(
-                self.left.as_ref().unwrap().evaluate(),
-                self.right.as_ref().unwrap().evaluate(),
-            )
-        } else {
-            0
+    pub fn unary(val: Item, operand: Self) -> Self {
+        Self {
+            root: val,
+            left: Some(Box::new(operand)),
+            right: None,
         }
     }
-}
 
-fn tokenize(expr: String) -> Vec<Token> {
-    let mut tokens = Vec::new();
-    let mut chars = expr.chars().peekable();
-    'outer: while let Some(c) = chars.next() {
-        match c {
-            '+' => tokens.push(Token::Operator(Op::Add)),
-            '-' => {
-                if tokens.len() == 0 {
-                    tokens.push(Token::Unary);
-                    continue;
-                }
-                match &tokens[tokens.len() - 1] {
-                    Token::Number(_) => tokens.push(Token::Operator(Op::Sub)),
-                    _ => tokens.push(Token::Unary),
-                };
-            }
-            '*' => tokens.push(Token::Operator(Op::Mul)),
-            '/' => tokens.push(Token::Operator(Op::Div)),
-            'X' if consume(&mut chars, "OR") => {
-                tokens.push(Token::Operator(Op::Xor));
+    pub fn evaluate(&self, environment: &dyn Environment) -> Result<i32, &'static str> {
+        match &self.root {
+            Item::Number(c) => Ok(*c),
+            Item::Symbol(name) => environment.value(name).map(|value| value as i32).ok_or("Undefined symbol"),
+            Item::Operator(op) => op.apply(
+                self.left.as_ref().unwrap().evaluate(environment)?,
+                self.right.as_ref().unwrap().evaluate(environment)?,
+            ),
+            Item::Unary(op) => op.apply(self.left.as_ref().unwrap().evaluate(environment)?),
+            Item::Memory => {
+                let address = self.left.as_ref().unwrap().evaluate(environment)?;
+                environment.memory(address as u16).map(i32::from)
             }
-            'O' if consume(&mut chars, "R") => {
-                tokens.push(Token::Operator(Op::Xor));
-            }
-            'A' if consume(&mut chars, "ND") => tokens.push(Token::Operator(Op::And)),
-            'S' if consume(&mut chars, "H") => {
-                if let Some('L') = chars.next() {
-                    tokens.push(Token::Operator(Op::Shl));
-                } else if let Some('R') = chars.next() {
-                    tokens.push(Token::Operator(Op::Shr));
-                }
-            }
-            '(' | ')' => tokens.push(Token::Parenthesis(c)),
-            '0'..='9' => {
-                let mut num_str = String::from(c);
-                while let Some(d) = chars.peek() {
-                    match d {
-                        '0'..='9' | 'a'..='f' => {
-                            num_str.push(*d);
-                            chars.next();
-                        }
-                        'H' => {
-                            tokens.push(Token::Number(i32::from_str_radix(&num_str, 16).unwrap()));
-                            chars.next();
-                            continue 'outer;
-                        }
-                        'B' => {
-                            tokens.push(Token::Number(i32::from_str_radix(&num_str, 2).unwrap()));
-                            chars.next();
-                            continue 'outer;
-                        }
-                        'O' => {
-                            tokens.push(Token::Number(i32::from_str_radix(&num_str, 8).unwrap()));
-                            chars.next();
-                            continue 'outer;
-                        }
-                        _ => {
-                            tokens.push(Token::Number(num_str.parse::<i32>().unwrap()));
-                            continue 'outer;
-                        }
-                    }
-                }
-                tokens.push(Token::Number(num_str.parse::<i32>().unwrap()));
-            }
-            _ => (),
         }
     }
-    tokens
 }
 
-fn consume(chars: &mut Peekable<impl Iterator<Item = char>>, expected: &str) -> bool {
-    for c in expected.chars() {
-        if chars.next_if(|&x| x == c).is_some() {
-            chars.next();
-        } else {
-            return false;
-        }
-    }
-    true
+fn tokenize(expression: &str) -> Result<Vec<Token>, &'static str> {
+    Tokenizer::new(expression).collect()
 }
 
 struct Tokenizer<'a> {
     chars: Peekable<Chars<'a>>,
+    after_operand: bool,
 }
 
 impl<'a> Tokenizer<'a> {
     fn new(input_str: &'a str) -> Self {
         Self {
             chars: input_str.chars().peekable(),
+            after_operand: false,
         }
     }
 
-    fn consume(&mut self, expected: &str) -> bool {
-        for c in expected.chars() {
-            if self.chars.next_if(|&x| x == c).is_none() {
-                return false;
-            }
+    fn take_word(&mut self, first: char) -> String {
+        let mut word = String::from(first);
+        while let Some(c) = self.chars.next_if(|&x| is_identifier_char(x)) {
+            word.push(c);
+        }
+        word
+    }
+
+    /*
+     * Numbers start with a digit, the suffix H, O/Q, B or D selects
+     * the base, decimal is the default
+     */
+    fn number(&mut self, first: char) -> Result<Token, &'static str> {
+        let word = self.take_word(first).to_uppercase();
+        let (digits, radix) = match word.chars().last().unwrap() {
+            'H' => (&word[..word.len() - 1], 16),
+            'O' | 'Q' => (&word[..word.len() - 1], 8),
+            'B' => (&word[..word.len() - 1], 2),
+            'D' => (&word[..word.len() - 1], 10),
+            _ => (&word[..], 10),
+        };
+        let value = u32::from_str_radix(digits, radix).map_err(|_| "Invalid number")?;
+        if value > MAX_VALUE as u32 {
+            return Err("Expression overflow");
         }
-        true
+        Ok(Token::Number(value as i32))
+    }
+
+    /*
+     * Names are case insensitive, symbol tables are keyed in upper case
+     */
+    fn word(&mut self, first: char) -> Token {
+        let word = self.take_word(first).to_uppercase();
+        keyword(&word).unwrap_or(Token::Symbol(word))
+    }
+
+    /*
+     * ==, !=, <>, <, <=, > and >= as alternatives to EQ, NE, ...
+     */
+    fn comparison(&mut self, first: char) -> Result<Token, &'static str> {
+        let second = self.chars.next_if(|&x| x == '=' || (first == '<' && x == '>'));
+        let op = match (first, second) {
+            ('=', Some('=')) => Op::Eq,
+            ('!', Some('=')) | ('<', Some('>')) => Op::Ne,
+            ('<', Some('=')) => Op::Le,
+            ('<', None) => Op::Lt,
+            ('>', Some('=')) => Op::Ge,
+            ('>', None) => Op::Gt,
+            _ => return Err("Invalid character in expression"),
+        };
+        Ok(Token::Operator(op))
     }
 }
 
+fn keyword(word: &str) -> Option<Token> {
+    Some(match word {
+        "MOD" => Token::Operator(Op::Mod),
+        "SHL" => Token::Operator(Op::Shl),
+        "SHR" => Token::Operator(Op::Shr),
+        "EQ" => Token::Operator(Op::Eq),
+        "NE" => Token::Operator(Op::Ne),
+        "LT" => Token::Operator(Op::Lt),
+        "LE" => Token::Operator(Op::Le),
+        "GT" => Token::Operator(Op::Gt),
+        "GE" => Token::Operator(Op::Ge),
+        "AND" => Token::Operator(Op::And),
+        "OR" => Token::Operator(Op::Or),
+        "XOR" => Token::Operator(Op::Xor),
+        "NOT" => Token::Unary(UnaryOp::Not),
+        "HIGH" => Token::Unary(UnaryOp::High),
+        "LOW" => Token::Unary(UnaryOp::Low),
+        _ => return None,
+        })
+}
+
 impl<'a> Iterator for Tokenizer<'a> {
-    type Item = Token;
+    type Item = Result<Token, &'static str>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some(c) = self.chars.next() {
-            match c {
-                '+' => Some(Token::Operator(Op::Add)),
-                '-' => Some(Token::Operator(Op::Sub)),
-                '*' => Some(Token::Operator(Op::Mul)),
-                '/' => Some(Token::Operator(Op::Div)),
-                'X' if self.consume("OR") => Some(Token::Operator(Op::Xor)),
-                'A' if self.consume("ND") => Some(Token::Operator(Op::And)),
-                'O' if self.consume("R") => Some(Token::Operator(Op::Or)),
-                'S' if self.consume("H") => {
-                    if self.consume("L") {
-                        Some(Token::Operator(Op::Shl))
-                    } else if self.consume("R") {
-                        Some(Token::Operator(Op::Shr))
-                    } else {
-                        panic!();
-                    }
-                }
-                '0'..='9' | 'a'..='f' | 'A'..='F' => {
-                    let mut num_str = String::from(c);
-                    while let Some(digit) = self.chars.next_if(|&x| x.is_ascii_hexdigit()) {
-                        num_str.push(digit);
-                    }
-                    if let Some(post) = self.chars.peek() {
-                        return match post {
-                            'H' => Some(Token::Number(i32::from_str_radix(&num_str, 16).unwrap())),
-                            'O' | 'Q' => Some(Token::Number(i32::from_str_radix(&num_str, 8).unwrap())),
-                            _ => Some(Token::Number(i32::from_str_radix(&num_str, 10).unwrap())),
-                        }
-                    }
-                    if let Some(post) = self.chars.next_if(|&x| x == 'H') {
-                        Some(Token::Number(i32::from_str_radix(&num_str, 16).unwrap()))
-                    } else if let Some(post) = self.chars.next_if(|&x| x == 'O' || x == 'Q') {
-                        Some(Token::Number(i32::from_str_radix(&num_str, 8).unwrap()))
-                    } else {
-                        match num_str.chars().last().unwrap() {
-                            'B' => Some(Token::Number(i32::from_str_radix(&num_str[..num_str.len()-1], 2).unwrap())),
-                            'D' => Some(Token::Number(i32::from_str_radix(&num_str[..num_str.len()-1], 10).unwrap())),
-                            _ => Some(Token::Number(i32::from_str_radix(&num_str, 10).unwrap())),
-                        }
-                    }
-                }
-                _ => panic!(),
-            }
-        } else {
-            None
-        }
+        while self.chars.next_if(|x| x.is_whitespace()).is_some() {}
+        let c = self.chars.next()?;
+        let token = match c {
+            '+' if !self.after_operand => Ok(Token::Unary(UnaryOp::Plus)),
+            '-' if !self.after_operand => Ok(Token::Unary(UnaryOp::Minus)),
+            '+' => Ok(Token::Operator(Op::Add)),
+            '-' => Ok(Token::Operator(Op::Sub)),
+            '*' => Ok(Token::Operator(Op::Mul)),
+            '/' => Ok(Token::Operator(Op::Div)),
+            '(' | ')' | '[' | ']' => Ok(Token::Parenthesis(c)),
+            '=' | '!' | '<' | '>' => self.comparison(c),
+            '\'' => char_constant(&mut self.chars).map(Token::Number),
+            '0'..='9' => self.number(c),
+            _ if is_identifier_char(c) => Ok(self.word(c)),
+            _ => Err("Invalid character in expression"),
+        };
+        self.after_operand = matches!(
+            token,
+            Ok(Token::Number(_)) | Ok(Token::Symbol(_)) | Ok(Token::Parenthesis(')')) | Ok(Token::Parenthesis(']'))
+        );
+        Some(token)
     }
 }
 
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '@' || c == '?' || c == '_'
+}
+
 /*
 * Convert Token vector to binary expression tree using the shunning yard algorithm
 * RANGIERBAHNHOF
  */
-fn to_expression_tree(tokens: Vec<Token>) -> BinaryExpressionTree {
+fn to_expression_tree(tokens: Vec<Token>) -> Result<BinaryExpressionTree, &'static str> {
     let mut stack: Vec<Token> = Vec::new();
     let mut trees: Vec<BinaryExpressionTree> = Vec::new();
     for t in tokens {
         match t {
             Token::Number(v) => trees.push(BinaryExpressionTree::new(Item::Number(v))),
-            Token::Unary => {
-                stack.push(t);
-            }
+            Token::Symbol(name) => trees.push(BinaryExpressionTree::new(Item::Symbol(name))),
+            Token::Unary(_) => stack.push(t),
             Token::Operator(ref c) => {
-                // If precedence of t is lower than the top of the stack
                 // Pop stack until t has higher precedence than top
-                while stack.len() > 0 {
-                    if let Token::Parenthesis(_) = stack[stack.len() - 1] {
+                while let Some(top) = stack.last() {
+                    let precedence = match top {
+                        Token::Operator(op) => op.precedence(),
+                        Token::Unary(op) => op.precedence(),
+                        _ => break,
+                    };
+                    if precedence < c.precedence() {
                         break;
                     }
-                    if let Token::Unary = stack[stack.len() - 1] {
-                        let t1 = trees.pop().unwrap();
-                        trees.push(BinaryExpressionTree::from(
-                            Item::Operator(Op::Sub),
-                            BinaryExpressionTree::new(Item::Number(0)),
-                            t1,
-                        ));
-                        stack.pop();
-                    } else if let Token::Operator(ref op) = stack[stack.len() - 1] {
-                        if op.precedence() >= c.precedence() {
-                            let t2 = trees.pop().unwrap();
-                            let t1 = trees.pop().unwrap();
-                            if let Token::Operator(top) = stack.pop().unwrap() {
-                                trees.push(BinaryExpressionTree::from(Item::Operator(top), t1, t2));
-                            }
-                        } else {
-                            break;
-                        }
-                    }
+                    reduce(&mut stack, &mut trees)?;
                 }
                 stack.push(t);
             }
-            Token::Parenthesis(c) => match c {
-                '(' => stack.push(t),
-                ')' => {
-                    while stack.len() > 0 {
-                        if let Token::Parenthesis(_) = stack[stack.len() - 1] {
-                            stack.pop();
-                            break;
+            Token::Parenthesis('(') | Token::Parenthesis('[') => stack.push(t),
+            Token::Parenthesis(close) => loop {
+                match stack.last() {
+                    None => return Err("Unbalanced parentheses"),
+                    Some(Token::Parenthesis(open)) => {
+                        if (*open == '[') != (close == ']') {
+                            return Err("Unbalanced parentheses");
                         }
-                        if let Token::Unary = stack[stack.len() - 1] {
-                            let t1 = trees.pop().unwrap();
-                            trees.push(BinaryExpressionTree::from(
-                                Item::Operator(Op::Sub),
-                                BinaryExpressionTree::new(Item::Number(0)),
-                                t1,
-                            ));
-                            stack.pop();
-                        } else {
-                            let t2 = trees.pop().unwrap();
-                            let t1 = trees.pop().unwrap();
-                            if let Token::Operator(op) = stack.pop().unwrap() {
-                                trees.push(BinaryExpressionTree::from(Item::Operator(op), t1, t2));
-                            }
+                        stack.pop();
+                        if close == ']' {
+                            let address = trees.pop().ok_or("Missing operand")?;
+                            trees.push(BinaryExpressionTree::unary(Item::Memory, address));
                         }
+                        break;
                     }
+                    Some(_) => reduce(&mut stack, &mut trees)?,
                 }
-                _ => (),
             },
         }
     }
     // No more Tokens in input -> process the remaining operators on the stack
-    while stack.len() > 0 {
-        if let Token::Parenthesis(_) = stack[stack.len() - 1] {
-            panic!("Parenthesis in stack after traversing all tokens");
+    while let Some(top) = stack.last() {
+        if let Token::Parenthesis(_) = top {
+            return Err("Unbalanced parentheses");
         }
-        if let Token::Unary = stack[stack.len() - 1] {
-            let t1 = trees.pop().unwrap();
-            trees.push(BinaryExpressionTree::from(
-                Item::Operator(Op::Sub),
-                BinaryExpressionTree::new(Item::Number(0)),
-                t1,
-            ));
-            stack.pop();
-        } else if let Token::Operator(op) = stack.pop().unwrap() {
-            let t2 = trees.pop().unwrap();
-            let t1 = trees.pop().unwrap();
+        reduce(&mut stack, &mut trees)?;
+    }
+    let tree = trees.pop().ok_or("Missing operand")?;
+    if !trees.is_empty() {
+        return Err("Missing operator");
+    }
+    Ok(tree)
+}
+
+/*
+ * Replace the operator on top of the stack and its operands by a tree
+ */
+fn reduce(stack: &mut Vec<Token>, trees: &mut Vec<BinaryExpressionTree>) -> Result<(), &'static str> {
+    match stack.pop() {
+        Some(Token::Unary(op)) => {
+            let operand = trees.pop().ok_or("Missing operand")?;
+            trees.push(BinaryExpressionTree::unary(Item::Unary(op), operand));
+        }
+        Some(Token::Operator(op)) => {
+            let t2 = trees.pop().ok_or("Missing operand")?;
+            let t1 = trees.pop().ok_or("Missing operand")?;
             trees.push(BinaryExpressionTree::from(Item::Operator(op), t1, t2));
         }
+        _ => (),
     }
-    trees.pop().unwrap()
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
     fn test_eval() {
         // Just a bunch of expressions I hope it covers enough cases
         let expressions = vec![
@@ -361,10 +624,6 @@ mod tests {
             ("3 + 4 * (4 + 2)", 27),
             ("(3) * (4 + 2)", 18),
             ("(((3)))", 3),
-            //("3^2*3", 27),
-            //("3^(2*3)", (3 as i32).pow(6)),
-            //("3^2+3", 12),
-            //("3*3^2+3", 30),
             ("-3", -3),
             ("3 + -4", -1),
             ("3*-(4+2)", -18),
@@ -373,17 +632,172 @@ mod tests {
             ("200 OR 1", 201),
         ];
         for (expr, res) in expressions {
-            assert_eq!(
-                to_expression_tree(tokenize(String::from(expr))).evaluate(),
-                res
-            );
+            assert_eq!(eval(expr), Ok(res), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn operators() {
+        let expressions = vec![
+            ("+5", 5),
+            ("17 MOD 5", 2),
+            ("1 SHL 4", 16),
+            ("80H SHR 7", 1),
+            ("NOT 0", 0xffff),
+            ("NOT 0FFFFH", 0),
+            ("HIGH 1234H", 0x12),
+            ("LOW 1234H", 0x34),
+            ("HIGH -1", 0xff),
+            ("3 EQ 3", 0xffff),
+            ("3 NE 3", 0),
+            ("2 LT 3", 0xffff),
+            ("3 LE 3", 0xffff),
+            ("2 GT 3", 0),
+            ("-1 GE 0", 0xffff),
+            ("0ffH", 255),
+            ("0FFh", 255),
+            ("17q", 15),
+            ("101b", 5),
+            ("12d", 12),
+        ];
+        for (expr, res) in expressions {
+            assert_eq!(eval(expr), Ok(res), "{}", expr);
         }
     }
 
+    #[test]
+    fn precedence() {
+        let expressions = vec![
+            ("1 OR 2 AND 3", 3),
+            ("NOT 1 EQ 2", 0xffff),
+            ("NOT 0 AND 5", 5),
+            ("1 + 2 EQ 3", 0xffff),
+            ("2 + 3 * 4", 14),
+            ("1 + 1 SHL 2", 5),
+            ("HIGH 1234H + 1", 0x13),
+            ("-2 * 3", -6),
+            ("10 - 4 - 3", 3),
+            ("12 / 2 / 3", 2),
+        ];
+        for (expr, res) in expressions {
+            assert_eq!(eval(expr), Ok(res), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(eval("1 / 0"), Err("Division by zero"));
+        assert_eq!(eval("1 MOD 0"), Err("Division by zero"));
+        assert_eq!(eval("0FFFFH * 2"), Err("Expression overflow"));
+        assert_eq!(eval("10000H"), Err("Expression overflow"));
+        assert_eq!(eval("12G"), Err("Invalid number"));
+        assert_eq!(eval("1 # 2"), Err("Invalid character in expression"));
+        assert_eq!(eval("(1 + 2"), Err("Unbalanced parentheses"));
+        assert_eq!(eval("1 + 2)"), Err("Unbalanced parentheses"));
+        assert_eq!(eval("1 +"), Err("Missing operand"));
+        assert_eq!(eval(""), Err("Missing operand"));
+        assert_eq!(eval("1 2"), Err("Missing operator"));
+        assert_eq!(eval("COUNT"), Err("Undefined symbol"));
+        assert_eq!(eval("(1 + 2]"), Err("Unbalanced parentheses"));
+        assert_eq!(eval("[1]"), Err("Memory references are not allowed here"));
+        assert_eq!(eval("1 = 2"), Err("Invalid character in expression"));
+        assert_eq!(eval("'X"), Err("Unterminated character constant"));
+        assert_eq!(eval("'It''s"), Err("Unterminated character constant"));
+        assert_eq!(eval("'ABC'"), Err("Character constant too long"));
+    }
+
+    struct Memory;
+
+    impl Environment for Memory {
+        fn value(&self, name: &str) -> Option<u16> {
+            (name == "HL").then_some(0x10)
+        }
+
+        fn memory(&self, address: u16) -> Result<u8, &'static str> {
+            Ok(address as u8 + 1)
+        }
+    }
+
+    #[test]
+    fn comparisons_and_memory() {
+        let expressions = vec![
+            ("3 == 3", 0xffff),
+            ("3 != 3", 0),
+            ("3 <> 4", 0xffff),
+            ("2 < 3", 0xffff),
+            ("3 <= 2", 0),
+            ("2 > 3", 0),
+            ("3 >= 3", 0xffff),
+            ("[HL]", 0x11),
+            ("[HL + 1] * 2", 0x24),
+            ("[[HL]] > 10H AND HL == 10H", 0xffff),
+        ];
+        for (expr, res) in expressions {
+            let expression = Expression::parse(expr).expect("Fuck");
+            assert_eq!(expression.evaluate(&Memory), Ok(res), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn symbols() {
+        let mut symbols = HashMap::new();
+        symbols.insert(String::from("COUNT"), 10);
+        symbols.insert(String::from("BUF@1"), 0x2000);
+
+        assert_eq!(eval_with_symbols("COUNT * 2", &symbols), Ok(20));
+        assert_eq!(eval_with_symbols("HIGH BUF@1 + COUNT", &symbols), Ok(0x2a));
+        assert_eq!(eval_with_symbols("COUNT2", &symbols), Err("Undefined symbol"));
+        assert_eq!(eval_with_symbols("count + Count", &symbols), Ok(20));
+    }
+
+    #[test]
+    fn symbol_substitution() {
+        let mut symbols = HashMap::new();
+        symbols.insert(String::from("A1"), 5);
+        symbols.insert(String::from("L"), 7);
+        symbols.insert(String::from("LOOP"), 0x100);
+
+        assert_eq!(substitute_symbols("A1+LA1, a1", &symbols), "5+LA1, 5");
+        assert_eq!(substitute_symbols("A, L", &symbols), "A, L");
+        assert_eq!(substitute_symbols("loop AND 0A1H", &symbols), "256 AND 0A1H");
+        assert_eq!(substitute_symbols("'LOOP', LOOP", &symbols), "'LOOP', 256");
+    }
+
+    #[test]
+    fn parameter_substitution() {
+        let mut parameters = HashMap::new();
+        parameters.insert(String::from("REG"), String::from("B"));
+        parameters.insert(String::from("N"), String::from("7"));
+
+        assert_eq!(substitute_parameters("MOV reg, REGS", &parameters), "MOV B, REGS");
+        assert_eq!(substitute_parameters("MVI A,N+1 ; N", &parameters), "MVI A,7+1 ; 7");
+        assert_eq!(substitute_parameters("LAB&N: DB 'N', 1N", &parameters), "LAB7: DB 'N', 1N");
+    }
+
+    #[test]
+    fn char_constants() {
+        assert_eq!(eval("'A'"), Ok(0x41));
+        assert_eq!(eval("'a' + 1"), Ok(0x62));
+        assert_eq!(eval("''''"), Ok(0x27));
+        assert_eq!(eval("'AB'"), Ok(0x4142));
+        assert_eq!(eval("' '"), Ok(0x20));
+        assert_eq!(Tokenizer::new("'x'").next(), Some(Ok(Token::Number(0x78))));
+    }
+
+    #[test]
+    fn quoted_operands() {
+        assert_eq!(split_operands("A, ','"), vec!["A", "','"]);
+        assert_eq!(split_operands("'It''s, ok', 0"), vec!["'It''s, ok'", "0"]);
+        assert_eq!(strip_comment("CPI ';' ; semicolon"), "CPI ';' ");
+        assert_eq!(replace_unquoted("DB '$', $", "$", "7"), "DB '$', 7");
+        assert_eq!(string_literal("'It''s'"), Some(b"It's".to_vec()));
+        assert_eq!(string_literal("'A'+1"), None);
+    }
+
     #[test]
     fn tokenizer() {
         for x in 0..1000 {
-            let hex: &str = &format!("{:x}H", x);
+            let hex: &str = &format!("0{:x}H", x);
             let oct: &str = &format!("{:o}O", x);
             let bin: &str = &format!("{:b}B", x);
             let dec: &str = &format!("{}D", x);
@@ -393,11 +807,11 @@ mod tests {
             let mut t3 = Tokenizer::new(bin);
             let mut t4 = Tokenizer::new(dec);
             let mut t5 = Tokenizer::new(dec2);
-            assert_eq!(t1.next(), Some(Token::Number(x)));
-            assert_eq!(t2.next(), Some(Token::Number(x)));
-            assert_eq!(t3.next(), Some(Token::Number(x)));
-            assert_eq!(t4.next(), Some(Token::Number(x)));
-            assert_eq!(t5.next(), Some(Token::Number(x)));
+            assert_eq!(t1.next(), Some(Ok(Token::Number(x))));
+            assert_eq!(t2.next(), Some(Ok(Token::Number(x))));
+            assert_eq!(t3.next(), Some(Ok(Token::Number(x))));
+            assert_eq!(t4.next(), Some(Ok(Token::Number(x))));
+            assert_eq!(t5.next(), Some(Ok(Token::Number(x))));
         }
     }
 }
@@ -1,390 +1,438 @@
-use super::assembler::{get_reserved_names, LABEL_DECL};
-use super::parser::eval;
+use super::assembler::{get_reserved_names, instruction_size, LABEL_DECL};
+use super::dialect::Dialect;
+use super::include::{expand_includes, FileResolver, Location};
+use super::macros::{block, definitions, directive, iterations, split_arguments, Block, Line, MAX_NESTING};
+use super::object::Segment;
+use super::parser::{eval_with_symbols, replace_unquoted, split_operands, substitute_symbols};
 use std::collections::HashMap;
 use regex::Regex;
 
-const MACRO_START: &str = "Custom Mac";
-const MACRO_END: &str = "Custom End";
-
-pub fn get_preprocessed_code(code: &Vec<String>) -> Result<Vec<String>, &'static str> {
-    let decl_regex = Regex::new(LABEL_DECL).unwrap();
-    if !has_correct_end(code) {
-        return Err("A program must only contain one END statement and it has to be the last");
-    }
-
-    let mut equate_assignments: HashMap<String, u16> = HashMap::new();
-    let mut set_assignments: HashMap<String, u16> = HashMap::new();
-    let mut in_conditional = false;
-    let mut condition = false;
-    let mut preprocessed_code: Vec<String> = Vec::new();
-    let mut pc = 0;
-
-    let labels = get_labels(code)?;
-    let code = replace_macros(code)?;
-
-    for line in code {
-        let mut owned_line = line.trim().to_string();
-
-        // replace program counter references
-        owned_line = owned_line.replace("$", &pc.to_string());
-
-        // remove declaration of labels
-        while let Some(_) = decl_regex.find(&owned_line) {
-            owned_line = decl_regex.replace(&owned_line, "").to_string();
-        }
+pub fn get_preprocessed_code(code: &[String], resolver: &dyn FileResolver) -> Result<Vec<String>, String> {
+    preprocess_module(code, resolver, &Layout::default()).map(|module| module.code)
+}
 
-        // replace labels with according values
-        for (key, value) in &labels {
-            owned_line = owned_line.replace(key, &value.to_string());
-        }
+/*
+ * Labels and EQU constants of a program
+ */
+pub fn get_symbols(code: &[String], resolver: &dyn FileResolver) -> Result<HashMap<String, u16>, String> {
+    preprocess_module(code, resolver, &Layout::default()).map(|module| module.symbols)
+}
 
-        // determine if a variable is being declared by EQU
-        if owned_line.contains("EQU") {
-            let (name, expression) = owned_line.split_once(" EQU ").unwrap();
-            if equate_assignments.contains_key(name) {
-                return Err("Can't assign a variable more than once using EQU!");
-            }
-            equate_assignments.insert(name.to_string(), eval_str(expression.to_string()));
-            continue;
-        }
+/*
+ * Where the relocatable segments start, what external symbols stand for and
+ * which mnemonics the code uses, without a data address DSEG follows CSEG
+ */
+#[derive(Clone, Default)]
+pub struct Layout {
+    pub code: u16,
+    pub data: Option<u16>,
+    pub externals: HashMap<String, u16>,
+    pub dialect: Dialect,
+}
 
-        // determine if a variable is being declared by SET
-        if owned_line.contains("SET") {
-            let (name, expression) = owned_line.split_once(" SET ").unwrap();
-            set_assignments.insert(name.to_string(), eval_str(expression.to_string()));
-            continue;
-        }
+/*
+ * Preprocessed code, segment switches show up as the directive followed
+ * by ORG with the absolute address
+ */
+pub struct Module {
+    pub code: Vec<String>,
+    pub symbols: HashMap<String, u16>,
+    pub segments: HashMap<String, Segment>,
+    pub publics: Vec<String>,
+    pub externals: Vec<String>,
+}
 
-        // replace values of variables declared by EQU
-        for (key, value) in &equate_assignments {
-            owned_line = owned_line.replace(&format!(" {}", key), &format!(" {}", value));
-        }
+/*
+ * Result of one pass over the macro expanded code
+ */
+struct Pass {
+    code: Vec<String>,
+    labels: HashMap<String, u16>,
+    segments: HashMap<String, Segment>,
+    equates: HashMap<String, u16>,
+    publics: Vec<String>,
+    externals: Vec<String>,
+    code_size: u16,
+    uses_data: bool,
+}
 
-        // replace values of variables declared by SET
-        for (key, value) in &set_assignments {
-            owned_line = owned_line.replace(&format!(" {}", key), &format!(" {}", value));
-        }
+/*
+ * One open IF block, location is where it was opened
+ */
+struct Conditional {
+    location: Location,
+    enclosing_active: bool,
+    taken: bool,
+    active: bool,
+    has_else: bool,
+}
 
-        // check if conditional is exited (before check for entering since "IF" is contained in "ENDIF")
-        if owned_line.contains("ENDIF") {
-            if !in_conditional {
-                return Err("Every ENDIF must have a corresponding IF");
-            }
-            condition = false;
-            in_conditional = false;
-            continue;
-        }
-        // check if conditional is being entered
-        else if owned_line.contains("IF") {
-            in_conditional = true;
-            let condition_str = owned_line.split_once(" ").unwrap().1.to_string();
-            condition = eval_str(condition_str) != 0;
-            continue;
-        }
+/*
+ * Lines being read, the program itself or the expansion of a macro or
+ * repetition block, EXITM drops the conditionals opened inside of it
+ */
+struct Frame {
+    lines: Vec<Line>,
+    next: usize,
+    conditionals: usize,
+}
 
-        // check if conditional holds true
-        if in_conditional {
-            if !condition {
-                continue;
-            }
-        }
+pub fn preprocess_module(
+    code: &[String],
+    resolver: &dyn FileResolver,
+    layout: &Layout,
+) -> Result<Module, String> {
+    let (source, sources) = expand_includes(code, resolver)?;
+    if !has_correct_end(&source) {
+        return Err(String::from("A program must only contain one END statement and it has to be the last"));
+    }
 
-        pc += 1;
-        if !owned_line.is_empty() {
-            preprocessed_code.push(owned_line.trim().to_string());
-        } else {
-            pc -= 1;
+    // macros are expanded while passing over the code so conditionals and EXITM inside of them work
+    let (code, macros) = definitions(source.into_iter().zip(sources).collect())?;
+
+    // labels inside of false conditionals don't take up space, so the first pass
+    // starts from an estimate and the second one uses the addresses it found
+    let estimate = get_labels(&code.iter().map(|(line, _)| line.clone()).collect())?;
+    let mut layout = layout.clone();
+    let mut first = pass(&code, &macros, &estimate, &layout)?;
+    if layout.data.is_none() {
+        layout.data = Some(layout.code.wrapping_add(first.code_size));
+        if first.uses_data {
+            first = pass(&code, &macros, &estimate, &layout)?;
         }
     }
-
-    if in_conditional {
-        return Err("Every IF must be closed");
+    let mut second = pass(&code, &macros, &first.labels, &layout)?;
+    if first.labels != second.labels {
+        return Err(String::from("Phase error: conditional assembly depends on a later label"));
     }
 
     // remove "END" from code
-    preprocessed_code.remove(preprocessed_code.len() - 1);
-    Ok(preprocessed_code)
+    second.code.remove(second.code.len() - 1);
+
+    let mut symbols = second.labels;
+    symbols.extend(second.equates);
+    Ok(Module {
+        code: second.code,
+        symbols,
+        segments: second.segments,
+        publics: second.publics,
+        externals: second.externals,
+    })
 }
 
-fn eval_str(str: String) -> u16 {
-    eval(&str) as u16
-}
-
-fn replace_macros(code: &Vec<String>) -> Result<Vec<String>, &'static str> {
-    let (macro_instructions, macro_params) = get_macros(code)?;
-    let mut macroless_code: Vec<String> = Vec::new();
-    let mut in_macro_declaration = false;
+fn pass(
+    code: &[Line],
+    macros: &HashMap<String, Block>,
+    labels: &HashMap<String, u16>,
+    layout: &Layout,
+) -> Result<Pass, String> {
+    let decl_regex = Regex::new(LABEL_DECL).unwrap();
+    let mut symbols: HashMap<String, u16> = labels.clone();
+    let mut equate_assignments: HashMap<String, u16> = HashMap::new();
+    let mut conditionals: Vec<Conditional> = Vec::new();
+    let mut declared_labels: HashMap<String, u16> = HashMap::new();
+    let mut label_segments: HashMap<String, Segment> = HashMap::new();
+    let mut pending_labels: Vec<String> = Vec::new();
+    let mut publics: Vec<String> = Vec::new();
+    let mut externals: Vec<String> = Vec::new();
+    let mut preprocessed_code: Vec<String> = Vec::new();
+    let mut frames = vec![Frame { lines: code.to_vec(), next: 0, conditionals: 0 }];
+    let mut local_count = 0;
+
+    // every segment has its own location counter, offsets are relative to the segment base
+    let mut segment = Segment::Code;
+    let mut offsets: HashMap<Segment, u16> = HashMap::new();
+    let mut code_size: u16 = 0;
+    let mut uses_data = false;
+    let base = |segment: Segment| match segment {
+        Segment::Absolute => 0,
+        Segment::Code => layout.code,
+        Segment::Data => layout.data.unwrap_or(0),
+    };
+
+    while let Some(frame) = frames.last_mut() {
+        let (line, location) = match frame.lines.get(frame.next) {
+            Some(line) => line.clone(),
+            None => {
+                frames.pop();
+                continue;
+            }
+        };
+        frame.next += 1;
+        let evaluate = |expression: &str, symbols: &HashMap<String, u16>| {
+            eval_str(expression, symbols).map_err(|message| location.error(message))
+        };
+        let mut owned_line = line.trim().to_string();
+        let active = conditionals.last().is_none_or(|conditional| conditional.active);
+        let offset = offsets.get(&segment).copied().unwrap_or(0);
+        let pc = base(segment).wrapping_add(offset);
 
-    'outer: for line in code {
-        let owned_line = line.trim().to_string();
+        // replace program counter references
+        owned_line = replace_unquoted(&owned_line, "$", &pc.to_string());
 
-        // check if macro is being declared
-        if line.contains("MACRO") {
-            in_macro_declaration = true;
-            continue;
+        // remove declaration of labels, they point to the next line that is assembled
+        while let Some(declaration) = decl_regex.find(&owned_line) {
+            if active {
+                pending_labels.push(declaration.as_str().trim().trim_end_matches(':').to_uppercase());
+            }
+            owned_line = owned_line[declaration.end()..].trim().to_string();
         }
 
-        // check lines for ENDM statement
-        if line.contains("ENDM") {
-            in_macro_declaration = false;
-            continue;
+        // handle IF, ELSEIF, ELSE and ENDIF, conditions are only evaluated in active code
+        let (keyword, operands) = match owned_line.split_once(' ') {
+            Some((keyword, operands)) => (keyword.to_ascii_uppercase(), operands.trim()),
+            None => (owned_line.to_ascii_uppercase(), ""),
+        };
+        match keyword.as_str() {
+            "IF" => {
+                let taken = active && evaluate(operands, &symbols)? != 0;
+                conditionals.push(Conditional {
+                    location: location.clone(),
+                    enclosing_active: active,
+                    taken,
+                    active: taken,
+                    has_else: false,
+                });
+                continue;
+            }
+            "ELSEIF" => {
+                let conditional = conditionals
+                    .last_mut()
+                    .ok_or_else(|| location.error("Every ELSEIF must have a corresponding IF"))?;
+                if conditional.has_else {
+                    return Err(location.error("ELSEIF must not follow ELSE"));
+                }
+                conditional.active =
+                    conditional.enclosing_active && !conditional.taken && evaluate(operands, &symbols)? != 0;
+                conditional.taken |= conditional.active;
+                continue;
+            }
+            "ELSE" => {
+                let conditional = conditionals
+                    .last_mut()
+                    .ok_or_else(|| location.error("Every ELSE must have a corresponding IF"))?;
+                if conditional.has_else {
+                    return Err(location.error("IF must not have more than one ELSE"));
+                }
+                conditional.active = conditional.enclosing_active && !conditional.taken;
+                conditional.taken = true;
+                conditional.has_else = true;
+                continue;
+            }
+            "ENDIF" => {
+                if conditionals.pop().is_none() {
+                    return Err(location.error("Every ENDIF must have a corresponding IF"));
+                }
+                continue;
+            }
+            _ => {}
         }
 
-        // remove lines of macro declaration
-        if in_macro_declaration {
+        if !active || owned_line.is_empty() {
             continue;
         }
 
-        for (macro_name, instructions) in &macro_instructions {
-            if owned_line.contains(macro_name) {
-                macroless_code.push(MACRO_START.to_string());
-                let input_string = owned_line.split_once(macro_name).unwrap().1.trim();
-                let mut inputs: Vec<&str> = Vec::new();
-
-                for input in input_string.split(",") {
-                    inputs.push(input.trim());
+        // repetition blocks and macro calls continue with their expansion
+        let mut expansion = None;
+        match keyword.as_str() {
+            "REPT" | "IRP" | "IRPC" => {
+                let frame = frames.last_mut().unwrap();
+                let (body, next) = block(&frame.lines, frame.next, &keyword, &location)?;
+                frame.next = next;
+                let (parameters, arguments) = match keyword.as_str() {
+                    "REPT" => (Vec::new(), vec![Vec::new(); evaluate(operands, &symbols)? as usize]),
+                    _ => iterations(&keyword, operands).map_err(|message| location.error(message))?,
+                };
+                let repetition = Block::new(parameters, body)?;
+                let mut lines = Vec::new();
+                for arguments in &arguments {
+                    lines.extend(repetition.expand(arguments, &keyword, &location, &mut local_count)?);
                 }
-                let mut input_map: HashMap<String, String> = HashMap::new();
-
-                for (index, parameter) in macro_params.get(macro_name).unwrap().iter().enumerate() {
-                    let value = if index >= inputs.len() {
-                        String::new()
-                    } else {
-                        inputs[index].to_string()
-                    };
-                    input_map.insert(parameter.to_string(), value);
+                expansion = Some(lines);
+            }
+            "EXITM" => {
+                if frames.len() == 1 {
+                    return Err(location.error("EXITM must be inside of a macro"));
                 }
-
-                for instruction in instructions {
-                    let replacement_protection = "@";
-                    let mut line = instruction.to_string();
-
-                    for (variable, value) in &input_map {
-                        let var_regex = Regex::new(&format!(r"[ ,]{}[ ,+\-*/,].", variable)).unwrap();
-                        let end_regex = Regex::new(&format!("[ ,]{} ?$", variable)).unwrap();
-
-                        while let Some(reg_match) = var_regex.find(&line.clone()) {
-                            let first_match_symbol = line.get(reg_match.start()..reg_match.start() + 1).unwrap();
-                            let last_match_symbol =  line.get(reg_match.end()..).unwrap();
-                            let start = match first_match_symbol {
-                                " " | "," => reg_match.start() + 1,
-                                _ => reg_match.start()
-                            };
-                            let end = match last_match_symbol {
-                                " " | "," | "+" | "-" | "*" | "/" => reg_match.end() - 2,
-                                _ => reg_match.end() -1
-                            };
-                            line.replace_range(start..end - 1, &format!("{}{}", &value, replacement_protection));
-                        }
-                        if let Some(reg_match) = end_regex.find(&line.clone()) {
-                            let first_symbol = line.get(reg_match.start()..reg_match.start() + 1).unwrap();
-                            let start = match first_symbol {
-                                " " | "," => reg_match.start() + 1,
-                                _ => reg_match.start()
-                            };
-                            line.replace_range(start..reg_match.end(), &format!("{}{}", &value, replacement_protection));
-                        }
-                    }
-                    line = line.replace(replacement_protection, "");
-                    macroless_code.push(line.trim().to_string());
+                let frame = frames.pop().unwrap();
+                conditionals.truncate(frame.conditionals);
+                continue;
+            }
+            "LOCAL" => return Err(location.error("LOCAL must directly follow MACRO, REPT, IRP or IRPC")),
+            _ => {
+                if let Some(definition) = macros.get(&keyword).filter(|_| assignment(&owned_line).is_none()) {
+                    let arguments = split_arguments(operands);
+                    expansion = Some(definition.expand(&arguments, &keyword, &location, &mut local_count)?);
                 }
-                macroless_code.push(MACRO_END.to_string());
-                continue 'outer;
             }
         }
-
-        macroless_code.push(owned_line.trim().to_string());
-    }
-    macroless_code = handle_macro_locals(&macroless_code).unwrap();
-    Ok(macroless_code)
-}
-
-fn handle_macro_locals(code: &Vec<String>) -> Result<Vec<String>, &'static str> {
-    let loc_label_regex = Regex::new(LABEL_DECL).unwrap();
-    let glob_label_regex = Regex::new(&format!("{}:", LABEL_DECL)).unwrap();
-    let var_name_regex = Regex::new(r"^( *[a-zA-Z@?][a-zA-Z@?0-9]{0,4} )").unwrap();
-
-    let mut generated_label_count: u32 = 0;
-    let mut handled_code: Vec<String> = Vec::new();
-    let mut label_names: Vec<String> = Vec::new();
-    let mut label_map: HashMap<String, String> = HashMap::new();
-    let mut equ_names: Vec<String> = Vec::new();
-    let mut equ_map: HashMap<String, String> = HashMap::new();
-    let mut found_set_names: Vec<String> = Vec::new();
-    let mut set_map: HashMap<String, String> = HashMap::new();
-    let mut all_existing_names: Vec<String> = Vec::new();
-    let mut in_macro = false;
-
-    // search entire code for labels and equ assignments outside of macros
-    for line in code {
-        if line.eq(MACRO_START) {
-            in_macro = true;
+        if let Some(lines) = expansion {
+            if frames.len() > MAX_NESTING {
+                return Err(location.error("Macro expansion nested too deeply"));
+            }
+            frames.push(Frame { lines, next: 0, conditionals: conditionals.len() });
             continue;
         }
-        if line.eq(MACRO_END) {
-            in_macro = false;
-            continue;
+
+        // segment switches and ORG only move location counters
+        let switch = Segment::from_directive(&keyword);
+        let is_origin = keyword == "ORG" || switch.is_some();
+        if let Some(next) = switch {
+            segment = next;
+            uses_data |= segment == Segment::Data;
+        } else if is_origin {
+            offsets.insert(segment, evaluate(operands, &symbols)?);
         }
-        if !in_macro && loc_label_regex.is_match(line) {
-            let (label, _) = line.split_once(":").unwrap();
-            if !label_names.contains(&label.to_string()) {
-                label_names.push(label.to_string());
+        let offset = offsets.get(&segment).copied().unwrap_or(0);
+        let pc = base(segment).wrapping_add(offset);
+        for label in pending_labels.drain(..) {
+            if declared_labels.insert(label.clone(), pc).is_some() {
+                return Err(location.error("label must not be assigned twice"));
             }
+            label_segments.insert(label, segment);
         }
-        if !in_macro && line.contains(" EQU ") {
-            let (var, _) = line.split_once(" EQU ").unwrap();
-            if !var_name_regex.is_match(line) {
-                return Err("Illegal variable name!");
+        if is_origin {
+            if let Some(next) = switch {
+                preprocessed_code.push(next.directive().to_string());
             }
-            if !equ_names.contains(&var.to_string()) {
-                equ_names.push(var.to_string());
-            }
-        }
-    }
-
-    all_existing_names.append(&mut equ_names.clone());
-    all_existing_names.append(&mut label_names.clone());
-
-    for line in code {
-        let mut owned_line = line.clone();
-
-        // check if current block of code is (not) a macro
-        if owned_line.eq(MACRO_START) {
-            in_macro = true;
-            continue;
-        } 
-        if owned_line.eq(MACRO_END) {
-            in_macro = false;
-            label_map.clear();
-            equ_map.clear();
-            set_map.clear();
+            preprocessed_code.push(format!("ORG {}", pc));
             continue;
         }
 
-        // find set assignments outside of macros
-        if owned_line.contains(" SET ") && !in_macro {
-            let (name, _) = owned_line.split_once(" SET ").unwrap();
-            if !var_name_regex.is_match(line) {
-                return Err("Illegal variable name!");
-            }
-            if !found_set_names.contains(&name.to_string()) {
-                found_set_names.push(name.to_string());
-                all_existing_names.push(name.to_string());
+        // names exported to or imported from other modules
+        if keyword == "PUBLIC" || keyword == "EXTRN" {
+            for name in split_operands(operands) {
+                let name = name.trim().to_uppercase();
+                if keyword == "EXTRN" {
+                    symbols.insert(name.clone(), layout.externals.get(&name).copied().unwrap_or(0));
+                    externals.push(name);
+                } else {
+                    publics.push(name);
+                }
             }
+            continue;
         }
 
-        // change line of macro according to principles of locality
-        if in_macro {
-            
-            // map local labels in macros
-            if loc_label_regex.is_match(&owned_line) && !glob_label_regex.is_match(&owned_line) {
-                let (label, _) = owned_line.split_once(":").unwrap();
-                let gen_name = generate_label_name(&all_existing_names, &mut generated_label_count).unwrap();
-                generated_label_count += 1;
-                all_existing_names.push(gen_name.clone());
-                label_map.insert(label.to_string(), gen_name);
+        // variables declared by EQU are constant, SET may assign them again
+        if let Some((name, directive, expression)) = assignment(&owned_line) {
+            let name = name.to_uppercase();
+            if labels.contains_key(&name) {
+                return Err(location.error("Can't assign a variable with the name of a label!"));
             }
-
-            // convert globally declared label to normal label
-            if glob_label_regex.is_match(&owned_line) {
-                owned_line = owned_line.replace("::", ":");
+            if directive == "EQU" && equate_assignments.contains_key(&name) {
+                return Err(location.error("Can't assign a variable more than once using EQU!"));
             }
-
-            // map local equ assignments
-            if line.contains(" EQU ") {
-                let (name, _) = owned_line.split_once(" EQU ").unwrap();
-                let gen_name = generate_label_name(&all_existing_names, &mut generated_label_count).unwrap();
-                generated_label_count += 1;
-                all_existing_names.push(gen_name.clone());
-                equ_map.insert(name.to_string(), gen_name);
+            let value = evaluate(expression, &symbols)?;
+            if directive == "EQU" {
+                equate_assignments.insert(name.clone(), value);
             }
+            symbols.insert(name, value);
+            continue;
+        }
 
-            // replace local label calls and equ assigned variables
-            for (old_label, generated_label) in &label_map {
-                owned_line = owned_line.replace(old_label, generated_label);
-            }
-            for (old_name, new_name) in &equ_map {
-                owned_line = owned_line.replace(old_name, new_name);
-            }
-            
-            // check if set assignment variable existed outside of macro
-            if owned_line.contains(" SET ") {
-                let (name, _) = owned_line.split_once(" SET ").unwrap();
-                if !found_set_names.contains(&name.to_string()) {
-                    let gen_name = generate_label_name(&all_existing_names, &mut generated_label_count).unwrap();
-                    generated_label_count += 1;
-                    all_existing_names.push(gen_name.clone());
-                    set_map.insert(name.to_string(), gen_name);
-                }
-            }
-            for (old_name, new_name) in &set_map {
-                owned_line = owned_line.replace(old_name, new_name);
-            }
+        // other dialects become Intel mnemonics, then names are replaced in the operand field only
+        let translated = layout.dialect.translate(&owned_line).map_err(|message| location.error(message))?;
+        let owned_line = match translated.split_once(' ') {
+            Some((mnemonic, operands)) => format!("{} {}", mnemonic, substitute_symbols(operands.trim(), &symbols)),
+            None => translated,
+        };
+
+        let offset = offset.wrapping_add(instruction_size(&owned_line));
+        offsets.insert(segment, offset);
+        if segment == Segment::Code {
+            code_size = code_size.max(offset);
         }
-        handled_code.push(owned_line.to_string());
+        preprocessed_code.push(owned_line);
     }
-    Ok(handled_code)
-}
 
-fn generate_label_name(taken_names: &Vec<String>, generated_label_count: &mut u32) -> Result<String, &'static str> {
-    loop {
-        let label_char = char::from_u32((*generated_label_count / 10000) as u32 + 'A' as u32).unwrap();
-        let label_num = (*generated_label_count % 10000) as u32;
+    if let Some(conditional) = conditionals.last() {
+        return Err(conditional.location.error("Every IF must be closed"));
+    }
 
-        if label_char.eq(&'[') {
-            return Err("Exceeded maximum amount of local labels!")
-        }
-        let new_label = format!("{}{}", label_char, label_num);
-        if !taken_names.contains(&new_label) {
-            return Ok(new_label)
-        }
-        *generated_label_count += 1;
+    Ok(Pass {
+        code: preprocessed_code,
+        labels: declared_labels,
+        segments: label_segments,
+        equates: equate_assignments,
+        publics,
+        externals,
+        code_size,
+        uses_data,
+    })
+}
+
+/*
+ * Split "NAME EQU expression" and "NAME SET expression", the directive is returned in upper case
+ */
+fn assignment(line: &str) -> Option<(&str, String, &str)> {
+    let (name, rest) = line.split_once(' ')?;
+    let (directive, expression) = rest.trim_start().split_once(' ')?;
+    let directive = directive.to_ascii_uppercase();
+    if directive == "EQU" || directive == "SET" {
+        Some((name, directive, expression.trim()))
+    } else {
+        None
     }
 }
 
+fn eval_str(str: &str, symbols: &HashMap<String, u16>) -> Result<u16, &'static str> {
+    eval_with_symbols(str, symbols).map(|value| value as u16)
+}
+
 fn get_labels(code: &Vec<String>) -> Result<HashMap<String, u16>, &'static str> {
     let label_regex = Regex::new(LABEL_DECL).unwrap();
-    let reserved_names = vec![
-        "STC", "CMC", "INR", "DCR", "CMA", "DAA", "NOP", "MOV", "STAX", "LDAX", "ADD", "ADC",
-        "SUB", "SBB", "ANA", "XRA", "ORA", "CMP", "RLC", "RRC", "RAL", "RAR", "PUSH", "POP", "DAD",
-        "INX", "DCX", "XCHG", "XTHL", "SPHL", "LXI", "MVI", "ADI", "ACI", "SUI", "SBI", "ANI",
-        "XRI", "ORI", "CPI", "STA", "LDA", "SHLD", "LHLD", "PCHL", "JMP", "JC", "JNC", "JZ", "JNZ",
-        "JP", "JM", "JPE", "JPO", "CALL", "CC", "CNC", "CZ", "CNZ", "CP", "CM", "CPE", "CPO",
-        "RET", "RC", "RNC", "RZ", "RNZ", "RM", "RP", "RPE", "RPO", "RST", "EI", "DI", "IN", "OUT",
-        "HLT", "ORG", "EQU", "SET", "END", "IF", "ENDIF", "MACRO", "ENDM", "B", "C", "D", "H", "L",
-        "A", "SP", "PSW"
-    ];
+    let reserved_names = get_reserved_names();
     let mut temp_labels = Vec::new();
     let mut labels = HashMap::new();
-    let mut mem_address = 0;
+    let mut mem_address: u16 = 0;
+    let mut repetitions = 0;
 
+    // labels inside of repetition blocks are only known after expanding them
     for line in code {
+        let directive = directive(line);
+        let nested = repetitions > 0 || directive == "ENDM";
+        match directive.as_str() {
+            "REPT" | "IRP" | "IRPC" => repetitions += 1,
+            "ENDM" => repetitions -= 1,
+            _ => {}
+        }
+        if nested {
+            continue;
+        }
         if label_regex.is_match(&line) {
-            let split = line.split(":").collect::<Vec<&str>>();
-            let label = split[0].trim_start();
-            if reserved_names.contains(&label) {
+            let split = line.splitn(2, ":").collect::<Vec<&str>>();
+            let label = split[0].trim_start().to_uppercase();
+            if reserved_names.contains(&label.as_str()) {
                 return Err("illegal label name");
             }
-            temp_labels.push(label.to_string());
-            if !split[1].trim().is_empty() {
+            temp_labels.push(label);
+            let instruction = split[1].trim();
+            if !instruction.is_empty() {
+                if let Some(origin) = instruction.strip_prefix("ORG ") {
+                    mem_address = eval_str(origin, &HashMap::new()).unwrap_or(mem_address);
+                }
                 while let Some(new_label) = temp_labels.pop() {
-                    if labels.contains_key(&new_label) {
+                    if labels.insert(new_label, mem_address).is_some() {
                         return Err("label must not be assigned twice");
-                    } else {
-                        labels.insert(String::from(new_label), mem_address as u16);
                     }
                 }
-                mem_address += 1;
+                mem_address = mem_address.wrapping_add(instruction_size(instruction));
             }
         } else {
+            let instruction = line.trim();
+            // the passes report ORG operands that cannot be evaluated with their location
+            if let Some(origin) = instruction.strip_prefix("ORG ") {
+                mem_address = eval_str(origin, &HashMap::new()).unwrap_or(mem_address);
+            }
+            if instruction.is_empty() {
+                continue;
+            }
             while let Some(new_label) = temp_labels.pop() {
-                if labels.contains_key(&new_label) {
+                if labels.insert(new_label, mem_address).is_some() {
                     return Err("label must not be assigned twice!");
-                } else {
-                    labels.insert(String::from(new_label), mem_address as u16);
                 }
             }
-            mem_address += 1;
+            mem_address = mem_address.wrapping_add(instruction_size(instruction));
         }
     }
     if !temp_labels.is_empty() {
@@ -393,65 +441,6 @@ fn get_labels(code: &Vec<String>) -> Result<HashMap<String, u16>, &'static str>
     Ok(labels)
 }
 
-fn get_macros(code: &Vec<String>) -> Result<(HashMap<String, Vec<String>>, HashMap<String, Vec<String>>), &'static str> {
-    let name_regex = Regex::new(r"^( *[a-zA-Z@?][a-zA-Z@?0-9]{0,4})").unwrap();
-
-    let mut macros: HashMap<String, Vec<String>> = HashMap::new();
-    let mut parameters: HashMap<String, Vec<String>> = HashMap::new();
-    let mut in_macro = false;
-    let mut macro_name = String::new();
-    let mut current_macro: Vec<String> = Vec::new();
-    let mut current_parameters: Vec<String> = Vec::new();
-
-    for line in code {
-        let line = line.trim();
-        if line.contains("MACRO") {
-            if in_macro {
-                return Err("Cannot define macro within macro");
-            }
-            in_macro = true;
-            let split: Vec<&str> = line.split("MACRO").collect();
-            macro_name = split[0].trim().to_string();
-            if macro_name.is_empty() {
-                return Err("Cannot define macro without name");
-            }
-            if !name_regex.is_match(&macro_name)
-                || get_reserved_names().iter().any(|&name| name == &macro_name)
-            {
-                return Err("Illegal macro name supplied!");
-            }
-            for parameter in split[1].split(",") {
-                if !parameter.is_empty() {
-                    current_parameters.push(parameter.trim().to_string());
-                }
-            }
-            continue;
-        }
-        if line.contains("ENDM") {
-            if line != "ENDM" {
-                return Err("ENDM must stand alone");
-            }
-            if in_macro {
-                macros.insert(macro_name.to_string(), current_macro.to_owned());
-                parameters.insert(macro_name.to_string(), current_parameters.to_owned());
-                current_macro.clear();
-                current_parameters.clear();
-                macro_name.clear();
-                in_macro = false;
-            } else {
-                return Err("Every ENDM must have a corresponding MACRO");
-            }
-        }
-        if in_macro {
-            current_macro.push(line.to_string());
-        }
-    }
-    if in_macro {
-        return Err("Every MACRO has to be followed by an ENDM");
-    }
-    Ok((macros, parameters))
-}
-
 fn has_correct_end(code: &Vec<String>) -> bool {
     let mut has_end = false;
 
@@ -459,7 +448,7 @@ fn has_correct_end(code: &Vec<String>) -> bool {
         if line.is_empty() {
             continue;
         }
-        if line.trim().contains("END") && !line.contains("ENDIF") && !line.contains("ENDM") {
+        if line.split_whitespace().next().is_some_and(|word| word.eq_ignore_ascii_case("END")) {
             if has_end {
                 return false;
             }
@@ -473,23 +462,25 @@ fn has_correct_end(code: &Vec<String>) -> bool {
     return has_end;
 }
 
+#[cfg(test)]
 mod tests {
     use super::*;
+    use crate::kreator::include::MemoryFiles;
 
     #[test]
     fn preprocessing_pc() {
         let code = vec!["MOV A,B", "JMP $", "END"];
-        let ppc = get_preprocessed_code(&convert_input(code));
+        let ppc = get_preprocessed_code(&convert_input(code), &MemoryFiles::new());
         assert_eq!(Ok(convert_input(vec!["MOV A,B", "JMP 1"])), ppc);
 
-        let preprocessed_code = get_preprocessed_code(&convert_input(vec!["MOV $, $", "END"]));
+        let preprocessed_code = get_preprocessed_code(&convert_input(vec!["MOV $, $", "END"]), &MemoryFiles::new());
         assert_eq!(Ok(vec!["MOV 0, 0".to_string()]), preprocessed_code);
     }
 
     #[test]
     fn remove_label_declarations() {
         let code = vec!["label:", "MOV A,B", "@LAB:", "test:", "MOV A,B", "END"];
-        let ppc = get_preprocessed_code(&convert_input(code));
+        let ppc = get_preprocessed_code(&convert_input(code), &MemoryFiles::new());
 
         assert_eq!(Ok(convert_input(vec!["MOV A,B", "MOV A,B"])), ppc);
     }
@@ -505,24 +496,76 @@ mod tests {
 
     #[test]
     fn label_replacement() {
-        let ppc = get_preprocessed_code(&convert_input(vec!["lab: lab", "END"]));
-        assert_eq!(Ok(vec!["0".to_string()]), ppc);
+        let ppc = get_preprocessed_code(&convert_input(vec!["lab: DW lab", "END"]), &MemoryFiles::new());
+        assert_eq!(Ok(vec!["DW 0".to_string()]), ppc);
 
         let ppc =
-            get_preprocessed_code(&convert_input(vec!["MOV A, lab", "lab: RRC", "END"]));
+            get_preprocessed_code(&convert_input(vec!["MOV A, lab", "lab: RRC", "END"]), &MemoryFiles::new());
         assert_eq!(Ok(convert_input(vec!["MOV A, 1", "RRC"])), ppc);
     }
 
+    #[test]
+    fn token_substitution() {
+        let code = vec!["A1: NOP", "LA1: JMP A1", "JMP LA1", "END"];
+        let ppc = get_preprocessed_code(&convert_input(code), &MemoryFiles::new());
+        assert_eq!(Ok(convert_input(vec!["NOP", "JMP 0", "JMP 1"])), ppc);
+
+        let code = vec!["E EQU 5", "LOOP: MOV A,E", "MVI E,e", "jmp loop", "END"];
+        let ppc = get_preprocessed_code(&convert_input(code), &MemoryFiles::new());
+        assert_eq!(Ok(convert_input(vec!["MOV A,E", "MVI E,e", "jmp 0"])), ppc);
+
+        let code = vec!["Count equ 3", "NOP", "DB '$ COUNT', count, $", "END"];
+        let ppc = get_preprocessed_code(&convert_input(code), &MemoryFiles::new());
+        assert_eq!(Ok(convert_input(vec!["NOP", "DB '$ COUNT', 3, 1"])), ppc);
+
+        let code = vec!["START: NOP", "start SET 1", "END"];
+        let ppc = get_preprocessed_code(&convert_input(code), &MemoryFiles::new());
+        assert_eq!("Can't assign a variable with the name of a label! (line 2)", ppc.unwrap_err());
+    }
+
+    #[test]
+    fn segments() {
+        let code = vec![
+            "PUBLIC START",
+            "EXTRN OUTC",
+            "DSEG",
+            "BUF: DB 0",
+            "CSEG",
+            "START: LDA BUF",
+            "CALL OUTC",
+            "JMP $",
+            "END",
+        ];
+        let ppc = get_preprocessed_code(&convert_input(code), &MemoryFiles::new());
+        let expected = vec!["DSEG", "ORG 9", "DB 0", "CSEG", "ORG 0", "LDA 9", "CALL 0", "JMP 6"];
+        assert_eq!(Ok(convert_input(expected)), ppc);
+
+        let code = convert_input(vec!["ORG 10H", "NOP", "ASEG", "ORG 10H", "DSEG", "X: DB 1", "CSEG", "Y: NOP", "END"]);
+        let layout = Layout { code: 0x100, data: Some(0x200), ..Layout::default() };
+        let module = preprocess_module(&code, &MemoryFiles::new(), &layout).expect("Fuck");
+        assert_eq!(Some(&0x200), module.symbols.get("X"));
+        assert_eq!(Some(&0x111), module.symbols.get("Y"));
+        assert_eq!(Some(&Segment::Data), module.segments.get("X"));
+        let expected = vec!["ORG 272", "NOP", "ASEG", "ORG 0", "ORG 16", "DSEG", "ORG 512", "DB 1", "CSEG", "ORG 273", "NOP"];
+        assert_eq!(convert_input(expected), module.code);
+    }
+
     #[test]
     fn equate() {
-        let ppc = get_preprocessed_code(&convert_input(vec!["PTO EQU 8", "OUT PTO", "END"]));
+        let ppc = get_preprocessed_code(&convert_input(vec!["PTO EQU 8", "OUT PTO", "END"]), &MemoryFiles::new());
         assert_eq!(Ok(vec!["OUT 8".to_string()]), ppc);
 
-        let ppc = get_preprocessed_code(&convert_input(vec!["test EQU 10H + 20", "JMP test", "END"]));
+        let ppc = get_preprocessed_code(&convert_input(vec!["test EQU 10H + 20", "JMP test", "END"]), &MemoryFiles::new());
         assert_eq!(Ok(vec!["JMP 36".to_string()]), ppc);
 
-        let ppc = get_preprocessed_code(&convert_input(vec!["test EQU 5", "test EQU 6", "END"]));
-        assert_eq!(Err("Can't assign a variable more than once using EQU!"), ppc);
+        let ppc = get_preprocessed_code(&convert_input(vec!["test EQU 5", "test EQU 6", "END"]), &MemoryFiles::new());
+        assert_eq!("Can't assign a variable more than once using EQU! (line 2)", ppc.unwrap_err());
+
+        let ppc = get_preprocessed_code(&convert_input(vec!["BASE EQU 10H", "NEXT EQU BASE+1", "ADI NEXT", "END"]), &MemoryFiles::new());
+        assert_eq!(Ok(vec!["ADI 17".to_string()]), ppc);
+
+        let ppc = get_preprocessed_code(&convert_input(vec!["test EQU 1/0", "END"]), &MemoryFiles::new());
+        assert_eq!("Division by zero (line 1)", ppc.unwrap_err());
     }
 
     #[test]
@@ -534,7 +577,7 @@ mod tests {
             "ADI IMMED",
             "END",
         ];
-        let ppc = get_preprocessed_code(&convert_input(code));
+        let ppc = get_preprocessed_code(&convert_input(code), &MemoryFiles::new());
         assert_eq!(Ok(convert_input(vec!["ADI 5", "ADI 10"])), ppc);
     }
 
@@ -552,98 +595,260 @@ mod tests {
             "XRA C",
             "END",
         ];
-        let ppc = get_preprocessed_code(&convert_input(code));
+        let ppc = get_preprocessed_code(&convert_input(code), &MemoryFiles::new());
         assert_eq!(Ok(convert_input(vec!["MOV A,C", "XRA C"])), ppc);
 
-        let ppc = get_preprocessed_code(&convert_input(vec!["IF 1", "END"]));
-        assert_eq!(Err("Every IF must be closed"), ppc);
+        let ppc = get_preprocessed_code(&convert_input(vec!["IF 1", "END"]), &MemoryFiles::new());
+        assert_eq!("Every IF must be closed (line 1)", ppc.unwrap_err());
 
-        let ppc = get_preprocessed_code(&convert_input(vec!["ENDIF", "END"]));
-        assert_eq!(Err("Every ENDIF must have a corresponding IF"), ppc);
+        let ppc = get_preprocessed_code(&convert_input(vec!["ENDIF", "END"]), &MemoryFiles::new());
+        assert_eq!("Every ENDIF must have a corresponding IF (line 1)", ppc.unwrap_err());
     }
 
     #[test]
-    fn macro_replacement() {
-        let code = &convert_input(vec!["SHRT MACRO", "RRC", "ANI 7FH", "ENDM", "SHRT", "END"]);
-        let ppc = replace_macros(code);
-        assert_eq!(Ok(convert_input(vec!["RRC", "ANI 7FH", "END"])), ppc);
-
-        let code = &convert_input(vec!["SHRT MACRO", "RRC", "ANI 7FH", "ENDM", "END"]);
-        let ppc = replace_macros(code);
-        assert_eq!(Ok(convert_input(vec!["END"])), ppc);
-
-        let code = &convert_input(vec![
-            "MAC1 MACRO P1, P2,COMMENT",
-            "XRA P2",
-            "DCR P1 COMMENT",
-            "ENDM",
-            "MAC1 C, D",
+    fn nested_conditionals() {
+        let code = vec![
+            "DEBUG EQU 1",
+            "LEVEL SET 2",
+            "IF DEBUG",
+            "IF LEVEL GT 1",
+            "MVI A,2",
+            "ELSE",
+            "MVI A,1",
+            "ENDIF",
+            "ELSE",
+            "IF 1",
+            "MVI A,0",
+            "ENDIF",
+            "ENDIF",
             "END",
-        ]);
-        let ppc = replace_macros(code);
-        assert_eq!(Ok(convert_input(vec!["XRA D", "DCR C", "END"])), ppc);
+        ];
+        let ppc = get_preprocessed_code(&convert_input(code), &MemoryFiles::new());
+        assert_eq!(Ok(convert_input(vec!["MVI A,2"])), ppc);
+
+        let code = vec!["IF 0", "UNDEF EQU 1/0", "IF 1/0", "NOP", "ENDIF", "ENDIF", "RRC", "END"];
+        let ppc = get_preprocessed_code(&convert_input(code), &MemoryFiles::new());
+        assert_eq!(Ok(convert_input(vec!["RRC"])), ppc);
+    }
+
+    #[test]
+    fn elseif() {
+        for (mode, expected) in [("0", "MVI A,0"), ("1", "MVI A,1"), ("2", "MVI A,2")] {
+            let equate = format!("MODE EQU {}", mode);
+            let code = vec![
+                equate.as_str(),
+                "IF MODE EQ 0",
+                "MVI A,0",
+                "ELSEIF MODE EQ 1",
+                "MVI A,1",
+                "ELSE",
+                "MVI A,2",
+                "ENDIF",
+                "END",
+            ];
+            assert_eq!(Ok(vec![expected.to_string()]), get_preprocessed_code(&convert_input(code), &MemoryFiles::new()));
+        }
+    }
+
+    #[test]
+    fn conditional_labels() {
+        let code = vec!["IF 0", "SKIP: DB 1,2,3", "ENDIF", "START: NOP", "IF START EQ 0", "JMP START", "ENDIF", "END"];
+        let ppc = get_preprocessed_code(&convert_input(code.clone()), &MemoryFiles::new());
+        assert_eq!(Ok(convert_input(vec!["NOP", "JMP 0"])), ppc);
+
+        let symbols = get_symbols(&convert_input(code), &MemoryFiles::new()).expect("Fuck");
+        assert_eq!(Some(&0), symbols.get("START"));
+        assert_eq!(None, symbols.get("SKIP"));
+    }
+
+    #[test]
+    fn conditional_errors() {
+        let code = vec!["IF 1", "IF 0", "ENDIF", "NOP", "END"];
+        let ppc = get_preprocessed_code(&convert_input(code), &MemoryFiles::new());
+        assert_eq!("Every IF must be closed (line 1)", ppc.unwrap_err());
+
+        let ppc = get_preprocessed_code(&convert_input(vec!["NOP", "ELSE", "END"]), &MemoryFiles::new());
+        assert_eq!("Every ELSE must have a corresponding IF (line 2)", ppc.unwrap_err());
+
+        let ppc = get_preprocessed_code(&convert_input(vec!["ELSEIF 1", "END"]), &MemoryFiles::new());
+        assert_eq!("Every ELSEIF must have a corresponding IF (line 1)", ppc.unwrap_err());
+
+        let ppc = get_preprocessed_code(&convert_input(vec!["IF 1", "ELSE", "ELSE", "ENDIF", "END"]), &MemoryFiles::new());
+        assert_eq!("IF must not have more than one ELSE (line 3)", ppc.unwrap_err());
+
+        let ppc = get_preprocessed_code(&convert_input(vec!["IF 1", "ELSE", "ELSEIF 1", "ENDIF", "END"]), &MemoryFiles::new());
+        assert_eq!("ELSEIF must not follow ELSE (line 3)", ppc.unwrap_err());
+
+        let code = vec!["MAC MACRO", "NOP", "ENDM", "MAC", "IF 1", "MAC", "END"];
+        let ppc = get_preprocessed_code(&convert_input(code), &MemoryFiles::new());
+        assert_eq!("Every IF must be closed (line 5)", ppc.unwrap_err());
+    }
+
+    #[test]
+    fn macro_replacement() {
+        let code = convert_input(vec!["SHRT MACRO", "RRC", "ANI 7FH", "ENDM", "SHRT", "END"]);
+        let ppc = get_preprocessed_code(&code, &MemoryFiles::new());
+        assert_eq!(Ok(convert_input(vec!["RRC", "ANI 7FH"])), ppc);
+
+        let code = convert_input(vec!["SHRT MACRO", "RRC", "ANI 7FH", "ENDM", "END"]);
+        assert_eq!(Ok(Vec::new()), get_preprocessed_code(&code, &MemoryFiles::new()));
 
-        let code = &convert_input(vec!["MA MACRO Foo, FooBar", "MOV Foo, FooBar", "ENDM", "MA A, B"]);
-        let ppc = replace_macros(code);
-        assert_eq!(Ok(convert_input(vec!["MOV A, B"])), ppc);
+        let code = vec!["MAC1 MACRO P1, P2,COMMENT", "XRA P2", "DCR P1 COMMENT", "ENDM", "MAC1 C, D", "END"];
+        let code = convert_input(code);
+        let ppc = get_preprocessed_code(&code, &MemoryFiles::new());
+        assert_eq!(Ok(convert_input(vec!["XRA D", "DCR C"])), ppc);
 
-        let code = &convert_input(vec!["MAC MACRO p1, p2", "ADI p1", "ADI p2", "ENDM", "MAC p2, 5"]);
-        let ppc = replace_macros(code);
+        let code = convert_input(vec!["MA MACRO Foo, FooBar", "MOV Foo, FooBar", "ENDM", "MA A, B", "END"]);
+        assert_eq!(Ok(convert_input(vec!["MOV A, B"])), get_preprocessed_code(&code, &MemoryFiles::new()));
+
+        let code = convert_input(vec!["MAC MACRO p1, p2", "ADI p1", "ADI p2", "ENDM", "MAC p2, 5", "END"]);
+        let ppc = get_preprocessed_code(&code, &MemoryFiles::new());
         assert_eq!(Ok(convert_input(vec!["ADI p2", "ADI 5"])), ppc);
+
+        let code = convert_input(vec!["MACROS EQU 3", "CLR MACRO R", "MVI R,MACROS", "ENDM", "CLR B", "END"]);
+        assert_eq!(Ok(convert_input(vec!["MVI B,3"])), get_preprocessed_code(&code, &MemoryFiles::new()));
     }
 
     #[test]
-    fn labels_in_macros() {
-        let code = convert_input(vec![MACRO_START, "LOOP:", "MOV A,B", "JMP LOOP", MACRO_END, MACRO_START, "LOOP:", "MOV A,B", "JMP LOOP", MACRO_END]);
-        let ppc = handle_macro_locals(&code).unwrap();
-        assert_eq!(ppc[0], "A0:");
-        assert_eq!(ppc[2], "JMP A0");
-        assert_eq!(ppc[3], "A1:");
+    fn nested_macros() {
+        let code = convert_input(vec![
+            "OUTC MACRO C",
+            "MVI A,C",
+            "OUT 1",
+            "ENDM",
+            "CRLF MACRO",
+            "OUTC 13",
+            "OUTC 10",
+            "ENDM",
+            "CRLF",
+            "END",
+        ]);
+        let ppc = get_preprocessed_code(&code, &MemoryFiles::new());
+        assert_eq!(Ok(convert_input(vec!["MVI A,13", "OUT 1", "MVI A,10", "OUT 1"])), ppc);
 
-        let code = convert_input(vec!["@LAB:", "MOV A,B", MACRO_START, "@LAB: JMP @LAB", MACRO_END]);
-        let ppc = handle_macro_locals(&code).unwrap();
-        assert_eq!(ppc[0], "@LAB:");
-        assert_eq!(ppc[2], "A0: JMP A0");
+        let code = convert_input(vec!["SHIFT MACRO N", "IF N", "RLC", "SHIFT N-1", "ENDIF", "ENDM", "SHIFT 3", "END"]);
+        let ppc = get_preprocessed_code(&code, &MemoryFiles::new());
+        assert_eq!(Ok(convert_input(vec!["RLC", "RLC", "RLC"])), ppc);
 
-        let code = convert_input(vec!["GLOB: MOV A,B", MACRO_START, "GLOB2::", "NOP", "JMP GLOB2", MACRO_END]);
-        let ppc = handle_macro_locals(&code).unwrap();
-        assert_eq!(ppc[1], "GLOB2:");
-        assert_eq!(ppc[3], "JMP GLOB2");
+        let code = convert_input(vec!["LOOP MACRO", "NOP", "LOOP", "ENDM", "LOOP", "END"]);
+        let ppc = get_preprocessed_code(&code, &MemoryFiles::new());
+        let message = "Macro expansion nested too deeply (line 3, in LOOP called at ".to_string();
+        assert!(ppc.unwrap_err().starts_with(&message));
+    }
 
-        let code = convert_input(vec!["A0: MOV A,B", MACRO_START, "LAB:", "MOV A,B", MACRO_END]);
-        let ppc = handle_macro_locals(&code).unwrap();
-        assert_eq!(ppc[1], "A1:");
+    #[test]
+    fn local_names() {
+        let code = convert_input(vec![
+            "WAIT MACRO N",
+            "LOCAL AGAIN",
+            "MVI B,N",
+            "AGAIN: DCR B",
+            "JNZ AGAIN",
+            "ENDM",
+            "WAIT 2",
+            "AGAIN: WAIT 3",
+            "JMP AGAIN",
+            "END",
+        ]);
+        let ppc = get_preprocessed_code(&code, &MemoryFiles::new());
+        let expected = vec!["MVI B,2", "DCR B", "JNZ 2", "MVI B,3", "DCR B", "JNZ 8", "JMP 6"];
+        assert_eq!(Ok(convert_input(expected)), ppc);
+
+        let code = convert_input(vec!["WAIT MACRO", "AGAIN: NOP", "ENDM", "WAIT", "WAIT", "END"]);
+        let ppc = get_preprocessed_code(&code, &MemoryFiles::new());
+        assert_eq!("label must not be assigned twice (line 2, in WAIT called at line 5)", ppc.unwrap_err());
+
+        let code = convert_input(vec!["WAIT MACRO", "NOP", "LOCAL AGAIN", "ENDM", "WAIT", "END"]);
+        let ppc = get_preprocessed_code(&code, &MemoryFiles::new());
+        let message = "LOCAL must directly follow MACRO, REPT, IRP or IRPC (line 3, in WAIT called at line 5)";
+        assert_eq!(message, ppc.unwrap_err());
+    }
 
-        let code = convert_input(vec!["A2: JMP A1", MACRO_START, "LAB:", "JMP LAB", MACRO_END, "A0:", "MOV A,B"]);
-        let ppc = handle_macro_locals(&code).unwrap();
-        assert_eq!(ppc[1], "A1:");
+    #[test]
+    fn repetition_blocks() {
+        let code = convert_input(vec!["COUNT EQU 2", "REPT COUNT+1", "RLC", "ENDM", "REPT 0", "NOP", "ENDM", "END"]);
+        let ppc = get_preprocessed_code(&code, &MemoryFiles::new());
+        assert_eq!(Ok(convert_input(vec!["RLC", "RLC", "RLC"])), ppc);
+
+        let code = vec!["IRP REG,<B, D, H>", "PUSH REG", "ENDM", "IRPC C,12", "DB 'C',C&0H", "ENDM", "END"];
+        let code = convert_input(code);
+        let ppc = get_preprocessed_code(&code, &MemoryFiles::new());
+        let expected = vec!["PUSH B", "PUSH D", "PUSH H", "DB 'C',10H", "DB 'C',20H"];
+        assert_eq!(Ok(convert_input(expected)), ppc);
+
+        let code = convert_input(vec!["REPT 2", "LOCAL L", "L: JMP L", "ENDM", "END"]);
+        let ppc = get_preprocessed_code(&code, &MemoryFiles::new());
+        assert_eq!(Ok(convert_input(vec!["JMP 0", "JMP 3"])), ppc);
+
+        let code = convert_input(vec!["FILL MACRO N, V", "REPT N", "DB V", "ENDM", "ENDM", "FILL 2, 0FFH", "END"]);
+        let ppc = get_preprocessed_code(&code, &MemoryFiles::new());
+        assert_eq!(Ok(convert_input(vec!["DB 0FFH", "DB 0FFH"])), ppc);
+
+        let ppc = get_preprocessed_code(&convert_input(vec!["REPT 2", "NOP", "END"]), &MemoryFiles::new());
+        assert_eq!("Every REPT has to be followed by an ENDM (line 1)", ppc.unwrap_err());
     }
 
     #[test]
-    fn variables_in_macros() {
-        let code = convert_input(vec!["VAL EQU 6", MACRO_START, "VAL EQU 8", "DB VAL", MACRO_END, "JMP VAL"]);
-        let ppc = handle_macro_locals(&code).unwrap();
-        assert!(ppc[0].contains("VAL"));
-        assert_eq!(ppc[1], "A0 EQU 8");
-        assert_eq!(ppc[2], "DB A0");
-        assert!(ppc[3].contains("VAL"));
+    fn exit_macro() {
+        let code = convert_input(vec![
+            "SAVE MACRO R, SKIP",
+            "IF SKIP",
+            "EXITM",
+            "ENDIF",
+            "PUSH R",
+            "ENDM",
+            "SAVE B, 0",
+            "SAVE D, 1",
+            "REPT 5",
+            "NOP",
+            "EXITM",
+            "ENDM",
+            "END",
+        ]);
+        let ppc = get_preprocessed_code(&code, &MemoryFiles::new());
+        assert_eq!(Ok(convert_input(vec!["PUSH B", "NOP"])), ppc);
+
+        let ppc = get_preprocessed_code(&convert_input(vec!["EXITM", "END"]), &MemoryFiles::new());
+        assert_eq!("EXITM must be inside of a macro (line 1)", ppc.unwrap_err());
+    }
 
-        let code = convert_input(vec!["VAL SET 5", MACRO_START, "VAL SET 8", MACRO_END]);
-        let ppc = handle_macro_locals(&code).unwrap();
-        assert!(ppc[1].eq("VAL SET 8"));
+    #[test]
+    fn expansion_provenance() {
+        let mut files = MemoryFiles::new();
+        files.insert("lib.asm", "OUTC MACRO C\nMVI A,C\nENDIF\nENDM");
+        let code = convert_input(vec!["INCLUDE \"lib.asm\"", "IRP X,<1>", "OUTC X", "ENDM", "END"]);
+        let ppc = get_preprocessed_code(&code, &files);
+        let location = "lib.asm, line 3, in OUTC called at line 3, in IRP called at line 2";
+        assert_eq!(format!("Every ENDIF must have a corresponding IF ({})", location), ppc.unwrap_err());
+    }
 
-        let code = convert_input(vec!["TEST SET 5", MACRO_START, "VAL SET 8", MACRO_END]);
-        let ppc = handle_macro_locals(&code).unwrap();
-        assert_eq!(ppc[1], "A0 SET 8");
+    #[test]
+    fn expression_errors_in_included_files() {
+        let mut files = MemoryFiles::new();
+        files.insert("lib.asm", "NOP\nIF UNDEF\nENDIF");
+        let code = convert_input(vec!["INCLUDE \"lib.asm\"", "END"]);
+        let ppc = get_preprocessed_code(&code, &files);
+        assert_eq!("Undefined symbol (lib.asm, line 2)", ppc.unwrap_err());
+
+        files.insert("lib.asm", "REPT 1/0\nNOP\nENDM");
+        let ppc = get_preprocessed_code(&code, &files);
+        assert_eq!("Division by zero (lib.asm, line 1)", ppc.unwrap_err());
+
+        let code = convert_input(vec!["IF 0", "ELSEIF 1/0", "ENDIF", "END"]);
+        let ppc = get_preprocessed_code(&code, &MemoryFiles::new());
+        assert_eq!("Division by zero (line 2)", ppc.unwrap_err());
+
+        let ppc = get_preprocessed_code(&convert_input(vec!["NOP", "ORG UNDEF", "END"]), &MemoryFiles::new());
+        assert_eq!("Undefined symbol (line 2)", ppc.unwrap_err());
     }
 
     #[test]
     fn valid_labels() {
         let code = convert_input(vec!["label:", "MOV A,B", " @LAB:", "test:", "MOV A,B"]);
         let mut labels = HashMap::new();
-        labels.insert(String::from("test"), 1);
+        labels.insert(String::from("TEST"), 1);
         labels.insert(String::from("@LAB"), 1);
-        labels.insert(String::from("label"), 0);
+        labels.insert(String::from("LABEL"), 0);
 
         assert_eq!(Ok(labels), get_labels(&code));
     }
@@ -664,40 +869,11 @@ mod tests {
     fn illegal_label() {
         let labels = get_labels(&vec!["IF: RRC".to_string()]);
         assert_eq!(Err("illegal label name"), labels);
-    }
-
-    #[test]
-    fn macro_definitions() {
-        let code = convert_input(vec!["SHRT MACRO", "RRC", "ANI 7FH", "ENDM", "SHRT"]);
-        let mut instructions = HashMap::new();
-        instructions.insert("SHRT".to_string(), convert_input(vec!["RRC", "ANI 7FH"]));
-        assert_eq!(instructions, get_macros(&code).unwrap().0);
-
-        let code = convert_input(vec![
-            "MAC1 MACRO P1, P2, COMMENT",
-            "XRA P2",
-            "DCR P1 COMMENT",
-            "ENDM",
-            "MAC1 C, D",
-        ]);
-        let mut params = HashMap::new();
-        params.insert("MAC1".to_string(), convert_input(vec!["P1", "P2", "COMMENT"]));
-        assert_eq!(params, get_macros(&code).unwrap().1);
-
-        let code = convert_input(vec!["THE MACRO"]);
-        assert_eq!(Err("Every MACRO has to be followed by an ENDM"), get_macros(&code));
-
-        let code = convert_input(vec!["ENDM"]);
-        assert_eq!(Err("Every ENDM must have a corresponding MACRO"), get_macros(&code));
-
-        let code = convert_input(vec!["MACRO", "ENDM", "END"]);
-        assert_eq!(Err("Cannot define macro without name"), get_macros(&code));
-
-        let code = convert_input(vec!["ABC MACRO", "A MACRO", "ENDM"]);
-        assert_eq!(Err("Cannot define macro within macro"), get_macros(&code));
-
-        let code = convert_input(vec!["A MACRO", "ENDM"]);
-        assert_eq!(Err("Illegal macro name supplied!"), get_macros(&code));
+        for name in ["extrn", "CSEG", "DSEG", "ASEG"] {
+            assert_eq!(Err("illegal label name"), get_labels(&convert_input(vec![&format!("{}: NOP", name)])));
+        }
+        let ppc = get_preprocessed_code(&convert_input(vec!["CSEG: NOP", "JMP CSEG", "END"]), &MemoryFiles::new());
+        assert_eq!("illegal label name", ppc.unwrap_err());
     }
 
     #[test]
@@ -742,7 +918,7 @@ mod tests {
 
         let result = convert_input(vec!["JMP 0 +6", "ADD C", "POP B", "RZ", "EI"]);
 
-        assert_eq!(Ok(result), get_preprocessed_code(&code));
+        assert_eq!(Ok(result), get_preprocessed_code(&code, &MemoryFiles::new()));
     }
 
     fn convert_input(lines: Vec<&str>) -> Vec<String> {
@@ -1,7 +1,8 @@
-mod core;
-mod terminator;
-mod kreator;
+pub mod core;
+pub mod terminator;
+pub mod kreator;
 mod utils;
+pub mod wasm;
 
 use wasm_bindgen::prelude::*;
 
@@ -0,0 +1,84 @@
+use std::fmt::Write;
+
+use super::symbols::SymbolTable;
+use crate::core::emulator::{CallKind, Emulator};
+
+fn describe(symbols: &SymbolTable, address: u16) -> String {
+    match symbols.label(address) {
+        Some(label) => format!("{:04X} ({})", address, label),
+        None => format!("{:04X}", address),
+    }
+}
+
+/*
+ * The shadow call stack innermost first, starting with the PC:
+ *
+ *   #0 0012 (PRINT+2)
+ *   #1 0006 (MAIN+6)  CALL 0010 (PRINT) at 0003 (MAIN+3), SP=00FE
+ */
+pub fn backtrace(emulator: &Emulator, symbols: &SymbolTable) -> String {
+    let mut lines = vec![format!("#0 {}", describe(symbols, emulator.get_pc()))];
+    for (depth, frame) in emulator.get_call_stack().iter().rev().enumerate() {
+        let kind = match frame.kind {
+            CallKind::Call => "CALL",
+            CallKind::Restart => "RST",
+            CallKind::Interrupt => "Interrupt",
+        };
+        let mut line = format!(
+            "#{} {}  {} {}",
+            depth + 1,
+            describe(symbols, frame.return_address),
+            kind,
+            describe(symbols, frame.target)
+        );
+        if frame.kind != CallKind::Interrupt {
+            write!(line, " at {}", describe(symbols, frame.call_site)).unwrap();
+        }
+        write!(line, ", SP={:04X}", frame.sp).unwrap();
+        lines.push(line);
+    }
+    if let Some(mismatch) = emulator.get_last_mismatch() {
+        lines.push(format!(
+            "Last mismatched return: RET at {} went to {} instead of {}, SP={:04X}",
+            describe(symbols, mismatch.address),
+            describe(symbols, mismatch.found),
+            describe(symbols, mismatch.expected),
+            mismatch.sp
+        ));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::emulator::Target;
+
+    #[test]
+    fn symbolised_frames() {
+        // 0000: LXI SP,0100H / 0003: CALL 0010H / 0006: HLT
+        // 0010: NOP / 0011: RST 3 / (0018: HLT)
+        let mut e = Emulator::new();
+        e.load_ram(vec![0x31, 0x00, 0x01, 0xcd, 0x10, 0x00, 0x76], 0);
+        e.get_ram_mut()[0x0010] = 0x00;
+        e.get_ram_mut()[0x0011] = 0xdf;
+        e.get_ram_mut()[0x0018] = 0x76;
+        let mut symbols = SymbolTable::new();
+        symbols.insert(0x0000, "MAIN");
+        symbols.insert(0x0010, "PRINT");
+        e.run(4).expect("Fuck");
+
+        let trace = backtrace(&e, &symbols);
+        let lines: Vec<&str> = trace.lines().collect();
+        assert_eq!(lines[0], "#0 0018 (PRINT+8)");
+        assert_eq!(
+            lines[1],
+            "#1 0012 (PRINT+2)  RST 0018 (PRINT+8) at 0011 (PRINT+1), SP=00FC"
+        );
+        assert_eq!(
+            lines[2],
+            "#2 0006 (MAIN+6)  CALL 0010 (PRINT) at 0003 (MAIN+3), SP=00FE"
+        );
+        assert_eq!(lines.len(), 3);
+    }
+}
@@ -0,0 +1,649 @@
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+use crate::core::emulator::{Access, AccessWatch, Cpu, Emulator, Stop, Target};
+
+const INTERRUPT: u8 = 0x03;
+const PACKET_SIZE: usize = 0x1000;
+// Instructions run between checks for a break from the client
+const BATCH: usize = 10_000;
+
+const SIGINT: &str = "S02";
+const SIGILL: &str = "S04";
+const SIGTRAP: &str = "S05";
+
+/*
+ * Register set as seen by gdb, the order of the g packet
+ */
+pub const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.gnu.gdb.i8080.core">
+    <reg name="a" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="f" bitsize="8" type="uint8"/>
+    <reg name="b" bitsize="8" type="uint8"/>
+    <reg name="c" bitsize="8" type="uint8"/>
+    <reg name="d" bitsize="8" type="uint8"/>
+    <reg name="e" bitsize="8" type="uint8"/>
+    <reg name="h" bitsize="8" type="uint8"/>
+    <reg name="l" bitsize="8" type="uint8"/>
+    <reg name="sp" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+const REGISTERS: [&str; 10] = ["a", "f", "b", "c", "d", "e", "h", "l", "sp", "pc"];
+
+/*
+ * What the connection has to do after a packet
+ */
+#[derive(Debug, PartialEq)]
+pub enum Reply {
+    Packet(String),
+    // Run until a stop, the stop reply follows
+    Continue,
+    Detach,
+}
+
+/*
+ * GDB remote serial protocol stub for a Target
+ *
+ * Supports register and memory access, stepping, continuing,
+ * breakpoints (Z0/Z1) and write, read and access watchpoints (Z2-Z4).
+ */
+pub struct GdbStub {
+    ack: bool,
+}
+
+impl Default for GdbStub {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GdbStub {
+    pub fn new() -> Self {
+        GdbStub { ack: true }
+    }
+
+    /*
+     * Accept a single debugger connection and serve it until it detaches
+     */
+    pub fn listen<A: ToSocketAddrs>(&mut self, target: &mut dyn Target, address: A) -> io::Result<()> {
+        let listener = TcpListener::bind(address)?;
+        let (stream, _) = listener.accept()?;
+        self.serve(target, stream)
+    }
+
+    pub fn serve(&mut self, target: &mut dyn Target, stream: TcpStream) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+        let mut connection = Connection::new(stream);
+        while let Some(packet) = connection.read_packet(self.ack)? {
+            let reply = match packet {
+                Incoming::Packet(packet) => self.handle(target, &packet),
+                // Interrupts only mean something while running
+                Incoming::Interrupt => continue,
+            };
+            match reply {
+                Reply::Packet(reply) => connection.write_packet(&reply)?,
+                Reply::Continue => {
+                    let reply = self.resume(target, &mut connection)?;
+                    connection.write_packet(&reply)?;
+                }
+                Reply::Detach => {
+                    connection.write_packet("OK")?;
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /*
+     * Answer a single packet (without framing)
+     */
+    pub fn handle(&mut self, target: &mut dyn Target, packet: &str) -> Reply {
+        let reply = match packet.split_at(packet.chars().next().map_or(0, char::len_utf8)) {
+            ("?", _) => SIGTRAP.to_string(),
+            ("g", _) => encode(&read_registers(target.emulator())),
+            ("G", data) => status(decode(data).and_then(|bytes| write_registers(target.emulator_mut(), &bytes))),
+            ("p", number) => match parse_hex(number).and_then(|number| read_register(target.emulator(), number)) {
+                Ok(bytes) => encode(&bytes),
+                Err(_) => error(),
+            },
+            ("P", assignment) => status(assignment.split_once('=').ok_or("Malformed packet").and_then(|(n, v)| {
+                write_register(target.emulator_mut(), parse_hex(n)?, &decode(v)?)
+            })),
+            ("m", range) => match parse_range(range) {
+                Ok((address, length)) => encode(&read_memory(target.emulator(), address, length)),
+                Err(_) => error(),
+            },
+            ("M", write) => status(write.split_once(':').ok_or("Malformed packet").and_then(|(range, data)| {
+                let (address, length) = parse_range(range)?;
+                let bytes = decode(data)?;
+                if bytes.len() != length as usize {
+                    return Err("Length does not match the data");
+                }
+                write_memory(target.emulator_mut(), address, &bytes);
+                Ok(())
+            })),
+            ("s", address) => {
+                if let Err(reply) = resume_at(target.emulator_mut(), address) {
+                    return Reply::Packet(reply);
+                }
+                match target.step() {
+                    Ok(()) => target.emulator_mut().check_stop().map_or(SIGTRAP.to_string(), stop_reply),
+                    Err(_) => SIGILL.to_string(),
+                }
+            }
+            ("c", address) => {
+                if let Err(reply) = resume_at(target.emulator_mut(), address) {
+                    return Reply::Packet(reply);
+                }
+                return Reply::Continue;
+            }
+            ("Z", point) => status(parse_point(point).map(|point| {
+                let emulator = target.emulator_mut();
+                match point {
+                    Point::Breakpoint(address) => emulator.add_breakpoint(address),
+                    Point::Watchpoint(watch) => emulator.add_access_watch(watch),
+                }
+            })),
+            ("z", point) => status(parse_point(point).map(|point| {
+                let emulator = target.emulator_mut();
+                match point {
+                    Point::Breakpoint(address) => emulator.remove_breakpoint(address),
+                    Point::Watchpoint(watch) => emulator.remove_access_watch(&watch),
+                };
+            })),
+            ("D", _) | ("k", _) => return Reply::Detach,
+            ("H", _) => String::from("OK"),
+            _ => self.query(packet),
+        };
+        Reply::Packet(reply)
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return format!("PacketSize={:x};qXfer:features:read+;QStartNoAckMode+", PACKET_SIZE);
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            return match parse_range(range) {
+                Ok((offset, length)) => {
+                    let xml = TARGET_XML.as_bytes();
+                    let start = (offset as usize).min(xml.len());
+                    let end = (start + length as usize).min(xml.len());
+                    let more = if end < xml.len() { 'm' } else { 'l' };
+                    format!("{}{}", more, escape(&xml[start..end]))
+                }
+                Err(_) => error(),
+            };
+        }
+        match packet {
+            "QStartNoAckMode" => {
+                self.ack = false;
+                String::from("OK")
+            }
+            "qAttached" => String::from("1"),
+            "qC" => String::from("QC1"),
+            "qfThreadInfo" => String::from("m1"),
+            "qsThreadInfo" => String::from("l"),
+            // Everything else is unsupported, which gdb expects as an empty reply
+            _ => String::new(),
+        }
+    }
+
+    /*
+     * Run in batches until a stop or a break from the client,
+     * logpoint messages go to the gdb console
+     */
+    fn resume(&mut self, target: &mut dyn Target, connection: &mut Connection) -> io::Result<String> {
+        loop {
+            let stop = target.run(BATCH);
+            for message in target.emulator_mut().take_log() {
+                connection.write_packet(&format!("O{}", encode(format!("{}\n", message).as_bytes())))?;
+            }
+            match stop {
+                Ok(Stop::Limit) => {}
+                Ok(stop) => return Ok(stop_reply(stop)),
+                Err(_) => return Ok(SIGILL.to_string()),
+            }
+            if connection.interrupted()? {
+                return Ok(SIGINT.to_string());
+            }
+        }
+    }
+}
+
+enum Point {
+    Breakpoint(u16),
+    Watchpoint(AccessWatch),
+}
+
+/*
+ * "type,address,kind", software and hardware breakpoints are the same
+ * here, the kind of a watchpoint is the length of the watched range
+ */
+fn parse_point(point: &str) -> Result<Point, &'static str> {
+    let fields: Vec<&str> = point.split(',').collect();
+    let (kind, address, length) = match fields.as_slice() {
+        [kind, address, length, ..] => (*kind, parse_hex(address)? as u16, parse_hex(length)? as u16),
+        _ => return Err("Malformed packet"),
+    };
+    let accesses: &[Access] = match kind {
+        "0" | "1" => return Ok(Point::Breakpoint(address)),
+        "2" => &[Access::Write],
+        "3" => &[Access::Read],
+        "4" => &[Access::Read, Access::Write],
+        _ => return Err("Unsupported breakpoint type"),
+    };
+    let end = address.saturating_add(length.max(1) - 1);
+    Ok(Point::Watchpoint(AccessWatch::new(address, accesses).with_end(end)))
+}
+
+/*
+ * HLT stops with SIGTRAP as well, so the final state can still be inspected
+ */
+fn stop_reply(stop: Stop) -> String {
+    match stop {
+        Stop::Access(access) => match access.access {
+            Access::Write => format!("T05watch:{:x};", access.address),
+            Access::Read => format!("T05rwatch:{:x};", access.address),
+            Access::Execute => SIGTRAP.to_string(),
+        },
+        _ => SIGTRAP.to_string(),
+    }
+}
+
+fn resume_at(emulator: &mut Emulator, address: &str) -> Result<(), String> {
+    if !address.is_empty() {
+        let address = parse_hex(address).map_err(|_| error())?;
+        emulator.set_pc(address as u16);
+    }
+    Ok(())
+}
+
+fn flags(emulator: &Emulator) -> u8 {
+    match emulator.get_cpu() {
+        Cpu::I8080 => emulator.get_registers().get_flags(),
+        Cpu::I8085 => emulator.get_registers().get_flags_8085(),
+    }
+}
+
+fn read_register(emulator: &Emulator, number: u32) -> Result<Vec<u8>, &'static str> {
+    let registers = emulator.get_registers();
+    let bytes = match REGISTERS.get(number as usize).ok_or("Unknown register")? {
+        &"f" => vec![flags(emulator)],
+        &"sp" => emulator.get_sp().to_le_bytes().to_vec(),
+        &"pc" => emulator.get_pc().to_le_bytes().to_vec(),
+        name => vec![registers[name.chars().next().unwrap()]],
+    };
+    Ok(bytes)
+}
+
+fn write_register(emulator: &mut Emulator, number: u32, bytes: &[u8]) -> Result<(), &'static str> {
+    let name = *REGISTERS.get(number as usize).ok_or("Unknown register")?;
+    match (name, bytes) {
+        ("f", &[flags]) => emulator.get_registers_mut().set_flags(flags),
+        ("sp", &[low, high]) => emulator.set_sp(u16::from_le_bytes([low, high])),
+        ("pc", &[low, high]) => emulator.set_pc(u16::from_le_bytes([low, high])),
+        (name, &[value]) if name.len() == 1 => emulator.get_registers_mut()[name.chars().next().unwrap()] = value,
+        _ => return Err("Register value has the wrong size"),
+    }
+    Ok(())
+}
+
+fn read_registers(emulator: &Emulator) -> Vec<u8> {
+    (0..REGISTERS.len() as u32)
+        .flat_map(|number| read_register(emulator, number).unwrap_or_default())
+        .collect()
+}
+
+fn write_registers(emulator: &mut Emulator, bytes: &[u8]) -> Result<(), &'static str> {
+    if bytes.len() != read_registers(emulator).len() {
+        return Err("Register data has the wrong size");
+    }
+    let mut offset = 0;
+    for number in 0..REGISTERS.len() as u32 {
+        let size = read_register(emulator, number)?.len();
+        write_register(emulator, number, &bytes[offset..offset + size])?;
+        offset += size;
+    }
+    Ok(())
+}
+
+fn read_memory(emulator: &Emulator, address: u16, length: u16) -> Vec<u8> {
+    let ram = emulator.get_ram();
+    (0..length).map(|offset| ram[address.wrapping_add(offset)]).collect()
+}
+
+fn write_memory(emulator: &mut Emulator, address: u16, bytes: &[u8]) {
+    let ram = emulator.get_ram_mut();
+    for (offset, byte) in bytes.iter().enumerate() {
+        ram[address.wrapping_add(offset as u16)] = *byte;
+    }
+}
+
+/*
+ * "address,length" in hex
+ */
+fn parse_range(range: &str) -> Result<(u16, u16), &'static str> {
+    let (address, length) = range.split_once(',').ok_or("Malformed packet")?;
+    let length = parse_hex(length)?.min(PACKET_SIZE as u32 / 2);
+    Ok((parse_hex(address)? as u16, length as u16))
+}
+
+fn parse_hex(text: &str) -> Result<u32, &'static str> {
+    u32::from_str_radix(text, 16).map_err(|_| "Invalid hex number")
+}
+
+fn status(result: Result<(), &'static str>) -> String {
+    match result {
+        Ok(()) => String::from("OK"),
+        Err(_) => error(),
+    }
+}
+
+fn error() -> String {
+    String::from("E01")
+}
+
+fn encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode(text: &str) -> Result<Vec<u8>, &'static str> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return Err("Odd number of hex digits");
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&text[index..index + 2], 16).map_err(|_| "Invalid hex digit"))
+        .collect()
+}
+
+/*
+ * Binary data in packets escapes $, #, } and * with } and xor 0x20
+ */
+fn escape(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    for &byte in bytes {
+        if b"$#}*".contains(&byte) {
+            text.push('}');
+            text.push((byte ^ 0x20) as char);
+        } else {
+            text.push(byte as char);
+        }
+    }
+    text
+}
+
+fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte))
+}
+
+enum Incoming {
+    Packet(String),
+    Interrupt,
+}
+
+/*
+ * Packet framing on top of the TCP stream
+ */
+struct Connection {
+    stream: TcpStream,
+    pending: VecDeque<u8>,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> Self {
+        Connection { stream, pending: VecDeque::new() }
+    }
+
+    fn byte(&mut self) -> io::Result<Option<u8>> {
+        if let Some(byte) = self.pending.pop_front() {
+            return Ok(Some(byte));
+        }
+        let mut buffer = [0; 1];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(buffer[0])),
+                Err(error) if error.kind() == ErrorKind::Interrupted => {}
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /*
+     * Next packet or break, None once the client disconnected
+     * Packets with a wrong checksum are rejected and read again
+     */
+    fn read_packet(&mut self, ack: bool) -> io::Result<Option<Incoming>> {
+        loop {
+            match self.byte()? {
+                None => return Ok(None),
+                Some(INTERRUPT) => return Ok(Some(Incoming::Interrupt)),
+                Some(b'$') => {}
+                // Acknowledgements and noise between packets
+                Some(_) => continue,
+            }
+            let mut data = Vec::new();
+            loop {
+                match self.byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                }
+            }
+            let mut digits = [0; 2];
+            for digit in digits.iter_mut() {
+                *digit = match self.byte()? {
+                    Some(byte) => byte,
+                    None => return Ok(None),
+                };
+            }
+            let data = String::from_utf8_lossy(&data).into_owned();
+            let valid = std::str::from_utf8(&digits)
+                .ok()
+                .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                == Some(checksum(&data));
+            if ack {
+                self.stream.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid || !ack {
+                return Ok(Some(Incoming::Packet(data)));
+            }
+        }
+    }
+
+    fn write_packet(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum(data));
+        self.stream.write_all(packet.as_bytes())
+    }
+
+    /*
+     * Whether the client sent a break, without waiting for one
+     */
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut buffer = [0; 64];
+        let result = loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => break Ok(()),
+                Ok(count) => self.pending.extend(&buffer[..count]),
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break Ok(()),
+                Err(error) if error.kind() == ErrorKind::Interrupted => {}
+                Err(error) => break Err(error),
+            }
+        };
+        self.stream.set_nonblocking(false)?;
+        result?;
+        let interrupted = self.pending.contains(&INTERRUPT);
+        self.pending.retain(|byte| *byte != INTERRUPT);
+        Ok(interrupted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::emulator::Breakpoint;
+    use std::thread;
+
+    // 0000: LXI H,0100H / 0003: INR M / 0004: JMP 0003H / 0007: HLT
+    const COUNTER: [u8; 8] = [0x21, 0x00, 0x01, 0x34, 0xc3, 0x03, 0x00, 0x76];
+
+    fn setup() -> (GdbStub, Emulator) {
+        let mut emulator = Emulator::new();
+        emulator.load_ram(COUNTER.to_vec(), 0);
+        (GdbStub::new(), emulator)
+    }
+
+    fn packet(reply: &str) -> Reply {
+        Reply::Packet(reply.to_string())
+    }
+
+    #[test]
+    fn registers() {
+        let (mut stub, mut e) = setup();
+        assert_eq!(stub.handle(&mut e, "g"), packet("000200000000000000000000"));
+        assert_eq!(stub.handle(&mut e, "G3ad70102030405060010ff00"), packet("OK"));
+        assert_eq!(e.get_registers()['a'], 0x3a);
+        assert_eq!(e.get_registers()["bc"], 0x0102);
+        assert_eq!(e.get_sp(), 0x1000);
+        assert_eq!(e.get_pc(), 0x00ff);
+        assert_eq!(stub.handle(&mut e, "g"), packet("3ad70102030405060010ff00"));
+        assert_eq!(stub.handle(&mut e, "p9"), packet("ff00"));
+        assert_eq!(stub.handle(&mut e, "P8=3412"), packet("OK"));
+        assert_eq!(e.get_sp(), 0x1234);
+        assert_eq!(stub.handle(&mut e, "Pa=00"), packet("E01"));
+        assert_eq!(stub.handle(&mut e, "G00"), packet("E01"));
+    }
+
+    #[test]
+    fn memory() {
+        let (mut stub, mut e) = setup();
+        assert_eq!(stub.handle(&mut e, "m0,4"), packet("21000134"));
+        assert_eq!(stub.handle(&mut e, "M100,2:aabb"), packet("OK"));
+        assert_eq!(stub.handle(&mut e, "mff,3"), packet("00aabb"));
+        assert_eq!(stub.handle(&mut e, "M100,2:aa"), packet("E01"));
+        assert_eq!(stub.handle(&mut e, "mzz,1"), packet("E01"));
+    }
+
+    #[test]
+    fn stepping_and_breakpoints() {
+        let (mut stub, mut e) = setup();
+        assert_eq!(stub.handle(&mut e, "s"), packet("S05"));
+        assert_eq!(e.get_pc(), 0x0003);
+        assert_eq!(stub.handle(&mut e, "Z0,4,1"), packet("OK"));
+        assert_eq!(stub.handle(&mut e, "c"), Reply::Continue);
+        assert_eq!(e.run(100), Ok(Stop::Breakpoint(0x0004)));
+        assert_eq!(stub.handle(&mut e, "z0,4,1"), packet("OK"));
+        assert!(e.get_breakpoints().is_empty());
+        assert_eq!(stub.handle(&mut e, "Z2,100,1"), packet("OK"));
+        assert_eq!(stub.handle(&mut e, "s"), packet("S05"));
+        assert_eq!(stub.handle(&mut e, "s"), packet("T05watch:100;"));
+        assert_eq!(stub.handle(&mut e, "z2,100,1"), packet("OK"));
+        assert!(e.get_access_watches().is_empty());
+        assert_eq!(stub.handle(&mut e, "Z3,ff,2"), packet("OK"));
+        assert_eq!(stub.handle(&mut e, "c"), Reply::Continue);
+        assert_eq!(e.run(100).map(stop_reply), Ok(String::from("T05rwatch:100;")));
+        assert_eq!(stub.handle(&mut e, "Z5,100,1"), packet("E01"));
+        assert_eq!(stub.handle(&mut e, "s7"), packet("S05"));
+        assert!(!e.is_running());
+    }
+
+    #[test]
+    fn queries() {
+        let (mut stub, mut e) = setup();
+        assert_eq!(stub.handle(&mut e, "?"), packet("S05"));
+        assert_eq!(stub.handle(&mut e, "vMustReplyEmpty"), packet(""));
+        let reply = match stub.handle(&mut e, "qXfer:features:read:target.xml:0,ffff") {
+            Reply::Packet(reply) => reply,
+            reply => panic!("Unexpected reply {:?}", reply),
+        };
+        assert!(reply.starts_with("l<?xml"));
+        assert!(reply.contains("name=\"pc\""));
+        let reply = stub.handle(&mut e, "qXfer:features:read:target.xml:0,10");
+        assert_eq!(reply, packet("m<?xml version=\"1"));
+        assert_eq!(stub.handle(&mut e, "D"), Reply::Detach);
+    }
+
+    fn send(stream: &mut TcpStream, data: &str) {
+        write!(stream, "${}#{:02x}", data, checksum(data)).expect("Fuck");
+    }
+
+    // Reads the acknowledgement (if any) and the reply of a packet
+    fn receive(stream: &mut TcpStream, ack: bool) -> String {
+        let mut byte = [0; 1];
+        if ack {
+            stream.read_exact(&mut byte).expect("Fuck");
+            assert_eq!(byte[0], b'+');
+        }
+        let mut reply = Vec::new();
+        loop {
+            stream.read_exact(&mut byte).expect("Fuck");
+            if byte[0] == b'#' {
+                break;
+            }
+            reply.push(byte[0]);
+        }
+        let mut sum = [0; 2];
+        stream.read_exact(&mut sum).expect("Fuck");
+        let reply = String::from_utf8(reply).expect("Fuck");
+        let reply = reply.trim_start_matches('$').to_string();
+        assert_eq!(u8::from_str_radix(std::str::from_utf8(&sum).expect("Fuck"), 16), Ok(checksum(&reply)));
+        if ack {
+            stream.write_all(b"+").expect("Fuck");
+        }
+        reply
+    }
+
+    #[test]
+    fn scripted_client() {
+        let (mut stub, mut e) = setup();
+        let logpoint = Breakpoint::new().with_condition("M == 1").expect("Fuck").with_message("count {M}");
+        e.set_breakpoint(0x0004, logpoint);
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Fuck");
+        let address = listener.local_addr().expect("Fuck");
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).expect("Fuck");
+            // A corrupted packet is rejected and has to be resent
+            stream.write_all(b"+$?#00").expect("Fuck");
+            let mut byte = [0; 1];
+            stream.read_exact(&mut byte).expect("Fuck");
+            assert_eq!(byte[0], b'-');
+            send(&mut stream, "?");
+            assert_eq!(receive(&mut stream, true), "S05");
+            send(&mut stream, "QStartNoAckMode");
+            assert_eq!(receive(&mut stream, true), "OK");
+            send(&mut stream, "Z0,3,1");
+            assert_eq!(receive(&mut stream, false), "OK");
+            send(&mut stream, "c");
+            assert_eq!(receive(&mut stream, false), "S05");
+            send(&mut stream, "p9");
+            assert_eq!(receive(&mut stream, false), "0300");
+            send(&mut stream, "z0,3,1");
+            assert_eq!(receive(&mut stream, false), "OK");
+            // The counter loop never ends on its own
+            send(&mut stream, "c");
+            stream.write_all(&[INTERRUPT]).expect("Fuck");
+            assert_eq!(receive(&mut stream, false), format!("O{}", encode(b"count 01\n")));
+            let mut reply = receive(&mut stream, false);
+            while reply.starts_with('O') {
+                reply = receive(&mut stream, false);
+            }
+            assert_eq!(reply, "S02");
+            send(&mut stream, "p9");
+            assert!(["0300", "0400"].contains(&receive(&mut stream, false).as_str()));
+            send(&mut stream, "D");
+            assert_eq!(receive(&mut stream, false), "OK");
+        });
+        let (stream, _) = listener.accept().expect("Fuck");
+        stub.serve(&mut e, stream).expect("Fuck");
+        client.join().expect("Fuck");
+    }
+}
@@ -1 +1,7 @@
+pub mod backtrace;
 pub mod disassembler;
+pub mod gdb;
+pub mod monitor;
+pub mod profile;
+pub mod recursive;
+pub mod symbols;
@@ -0,0 +1,761 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+
+use super::backtrace::backtrace;
+use super::disassembler::Disassembler;
+use super::profile::{annotate, folded, report};
+use super::symbols::SymbolTable;
+use crate::core::emulator::{Access, AccessWatch, Breakpoint, Emulator, Stop, Target};
+use crate::kreator::parser::substitute_symbols;
+
+const DEFAULT_LIMIT: usize = 10_000_000;
+const DUMP_LENGTH: u16 = 0x40;
+const LIST_LINES: usize = 10;
+
+pub const HELP: &str = "\
+s, step [COUNT]         execute COUNT instructions (default 1)
+c, continue             run until a breakpoint, watchpoint or HLT
+g, until ADDRESS        run to ADDRESS
+b, break [ADDRESS]      set a breakpoint or list all breakpoints
+b, break ADDRESS if CONDITION
+                        stop only if CONDITION holds, e.g. A == 0FFH AND [HL] > 10
+ignore ADDRESS COUNT    let a breakpoint pass COUNT times before it stops
+log ADDRESS MESSAGE     print MESSAGE instead of stopping, {EXPRESSION} is replaced
+                        by its value
+bd, delete ADDRESS      clear a breakpoint
+w, watch [ADDRESS]      stop when the byte at ADDRESS changes, or list watchpoints
+w, watch RANGE MODE [VALUE]
+                        stop on accesses to ADDRESS[..END], MODE being any of
+                        r (read), w (write) and x (execute), optionally only
+                        when the byte is VALUE
+wd, unwatch ADDRESS     clear the watchpoints at ADDRESS
+r, regs                 show registers and flags
+bt, backtrace           show the calls that have not returned yet
+smash on|off            stop when a RET does not return to its call
+set REGISTER VALUE      change A-L, BC, DE, HL, SP, PC or FLAGS
+m, dump [ADDRESS] [LEN] hex dump, continues where the last dump ended
+e, edit ADDRESS BYTE... write bytes to memory
+l, list [ADDRESS] [N]   disassemble N instructions, around PC by default
+sym FILE                load a symbol file
+profile on|off|clear    start, pause or discard the execution profile
+profile                 show where the cycles went by function, symbol and address
+profile list [ADDRESS] [N]
+                        disassemble with hit and cycle counts
+profile folded FILE     write the call stacks for a flame graph
+i, input TEXT           queue console input for the program
+q, quit                 leave the monitor
+Numbers are hexadecimal, symbols can be used for addresses.
+An empty line repeats the last step, continue or dump.";
+
+/*
+ * DDT-like monitor: one command line in, text out
+ */
+pub struct Monitor {
+    symbols: SymbolTable,
+    limit: usize,
+    last: String,
+    dump_address: u16,
+}
+
+impl Default for Monitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Monitor {
+    pub fn new() -> Self {
+        Monitor {
+            symbols: SymbolTable::new(),
+            limit: DEFAULT_LIMIT,
+            last: String::new(),
+            dump_address: 0,
+        }
+    }
+
+    pub fn with_symbols(mut self, symbols: SymbolTable) -> Self {
+        self.symbols = symbols;
+        self
+    }
+
+    /*
+     * Instructions continue and until run before giving up
+     */
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /*
+     * Execute a command line, None once the monitor is quit
+     */
+    pub fn execute(&mut self, target: &mut dyn Target, line: &str) -> Result<Option<String>, &'static str> {
+        let line = match line.trim() {
+            "" => self.last.clone(),
+            line => line.to_string(),
+        };
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command.to_lowercase(),
+            None => return Ok(Some(String::new())),
+        };
+        let args: Vec<&str> = words.collect();
+        if ["s", "step", "c", "continue", "m", "dump"].contains(&command.as_str()) {
+            self.last = command.clone();
+        }
+
+        let output = match command.as_str() {
+            "s" | "step" => {
+                let count = args.first().map_or(Ok(1), |count| self.number(count))?;
+                self.step(target, count as usize)?
+            }
+            "c" | "continue" => {
+                let stop = target.run(self.limit);
+                self.report(target, stop)?
+            }
+            "g" | "until" => {
+                let address = self.address(args.first().ok_or("Missing address")?)?;
+                let emulator = target.emulator_mut();
+                let temporary = !emulator.get_breakpoints().contains(&address);
+                emulator.add_breakpoint(address);
+                let stop = target.run(self.limit);
+                if temporary {
+                    target.emulator_mut().remove_breakpoint(address);
+                }
+                self.report(target, stop)?
+            }
+            "b" | "break" => match args.as_slice() {
+                [] => self.list_breakpoints(target.emulator()),
+                [address] => {
+                    let address = self.address(address)?;
+                    target.emulator_mut().add_breakpoint(address);
+                    format!("Breakpoint at {}", self.describe(address))
+                }
+                [address, keyword, _, ..] if keyword.eq_ignore_ascii_case("if") => {
+                    let address = self.address(address)?;
+                    let condition = self.substitute(skip_words(&line, 3));
+                    target.emulator_mut().set_breakpoint(address, Breakpoint::new().with_condition(&condition)?);
+                    format!("Breakpoint at {} if {}", self.describe(address), condition)
+                }
+                _ => return Err("Usage: break ADDRESS [if CONDITION]"),
+            },
+            "ignore" => {
+                let (address, count) = match args.as_slice() {
+                    [address, count] => (self.address(address)?, self.number(count)?),
+                    _ => return Err("Usage: ignore ADDRESS COUNT"),
+                };
+                let emulator = target.emulator_mut();
+                let breakpoint = emulator.get_breakpoint_mut(address).ok_or("No breakpoint at this address")?;
+                breakpoint.set_ignore_count(count as u32);
+                format!("Ignoring the next {:X} hits at {}", count, self.describe(address))
+            }
+            "log" => {
+                let address = self.address(args.first().ok_or("Missing address")?)?;
+                let message = self.substitute_message(skip_words(&line, 2));
+                let logpoint = Breakpoint::new().with_message(&message);
+                target.emulator_mut().set_breakpoint(address, logpoint);
+                format!("Logpoint at {}: {}", self.describe(address), message)
+            }
+            "bd" | "delete" => {
+                let address = self.address(args.first().ok_or("Missing address")?)?;
+                if !target.emulator_mut().remove_breakpoint(address) {
+                    return Err("No breakpoint at this address");
+                }
+                String::new()
+            }
+            "w" | "watch" => match args.as_slice() {
+                [] => self.list_watchpoints(target.emulator()),
+                [address] => {
+                    let address = self.address(address)?;
+                    target.emulator_mut().add_watchpoint(address);
+                    format!("Watchpoint at {}", self.describe(address))
+                }
+                [range, mode, value @ ..] => {
+                    let watch = self.access_watch(range, mode, value)?;
+                    let description = self.describe_watch(&watch);
+                    target.emulator_mut().add_access_watch(watch);
+                    format!("Watchpoint at {}", description)
+                }
+            },
+            "wd" | "unwatch" => {
+                let address = self.address(args.first().ok_or("Missing address")?)?;
+                let emulator = target.emulator_mut();
+                let watches: Vec<AccessWatch> =
+                    emulator.get_access_watches().iter().filter(|watch| watch.start == address).cloned().collect();
+                if watches.is_empty() {
+                    return Err("No watchpoint at this address");
+                }
+                for watch in watches {
+                    emulator.remove_access_watch(&watch);
+                }
+                String::new()
+            }
+            "r" | "regs" => self.registers(target.emulator()),
+            "bt" | "backtrace" => backtrace(target.emulator(), &self.symbols),
+            "smash" => {
+                let stop = match args.first().map(|word| word.to_lowercase()).as_deref() {
+                    Some("on") => true,
+                    Some("off") => false,
+                    _ => return Err("Usage: smash on|off"),
+                };
+                target.emulator_mut().set_stop_on_mismatch(stop);
+                format!("Stopping on mismatched returns is {}", if stop { "on" } else { "off" })
+            }
+            "set" => {
+                let (register, value) = match args.as_slice() {
+                    [register, value] => (register.to_lowercase(), self.address(value)?),
+                    _ => return Err("Usage: set REGISTER VALUE"),
+                };
+                set_register(target.emulator_mut(), &register, value)?;
+                self.registers(target.emulator())
+            }
+            "m" | "dump" => {
+                if let Some(address) = args.first() {
+                    self.dump_address = self.address(address)?;
+                }
+                let length = args.get(1).map_or(Ok(DUMP_LENGTH), |length| self.number(length))?;
+                self.dump(target.emulator(), length)
+            }
+            "e" | "edit" => {
+                let (address, bytes) = args.split_first().ok_or("Missing address")?;
+                let address = self.address(address)?;
+                let emulator = target.emulator_mut();
+                for (offset, byte) in bytes.iter().enumerate() {
+                    let value = self.number(byte)?;
+                    if value > 0xff {
+                        return Err("Bytes must be below 100H");
+                    }
+                    emulator.get_ram_mut()[address.wrapping_add(offset as u16)] = value as u8;
+                }
+                String::new()
+            }
+            "l" | "list" => {
+                let emulator = target.emulator();
+                let lines = args.get(1).map_or(Ok(LIST_LINES as u16), |lines| self.number(lines))? as usize;
+                match args.first() {
+                    Some(address) => self.list(emulator, self.address(address)?, lines),
+                    None => self.around(emulator, emulator.get_pc(), lines / 2, lines - lines / 2),
+                }
+            }
+            "sym" => {
+                let path = args.first().ok_or("Missing file name")?;
+                self.symbols = SymbolTable::load_file(path).map_err(|_| "Could not load symbol file")?;
+                String::new()
+            }
+            "profile" => self.profile(target.emulator_mut(), &args)?,
+            "i" | "input" => {
+                let text = line.split_once(char::is_whitespace).map_or("", |(_, text)| text).trim_start();
+                target.send_input(format!("{}\r", text).as_bytes());
+                String::new()
+            }
+            "h" | "help" | "?" => String::from(HELP),
+            "q" | "quit" => return Ok(None),
+            _ => return Err("Unknown command, try help"),
+        };
+        Ok(Some(output))
+    }
+
+    /*
+     * Hexadecimal with optional H suffix or 0x prefix
+     */
+    fn number(&self, text: &str) -> Result<u16, &'static str> {
+        let upper = text.to_uppercase();
+        let digits = upper.strip_prefix("0X").or_else(|| upper.strip_suffix('H')).unwrap_or(&upper);
+        u16::from_str_radix(digits, 16).map_err(|_| "Invalid number")
+    }
+
+    fn address(&self, text: &str) -> Result<u16, &'static str> {
+        match self.symbols.address(text) {
+            Some(address) => Ok(address),
+            None => self.number(text),
+        }
+    }
+
+    /*
+     * Address with its symbol, e.g. "0105 (LOOP)"
+     */
+    fn describe(&self, address: u16) -> String {
+        match self.symbols.name(address) {
+            Some(name) => format!("{:04X} ({})", address, name),
+            None => format!("{:04X}", address),
+        }
+    }
+
+    /*
+     * Labels in a condition replaced by their addresses
+     */
+    fn substitute(&self, expression: &str) -> String {
+        let symbols: HashMap<String, u16> =
+            self.symbols.names().map(|(address, name)| (name.to_uppercase(), address)).collect();
+        substitute_symbols(expression, &symbols)
+    }
+
+    /*
+     * Same for the expressions in braces of a logpoint message
+     */
+    fn substitute_message(&self, message: &str) -> String {
+        let mut result = String::new();
+        let mut in_braces = false;
+        for part in message.split_inclusive(['{', '}']) {
+            if in_braces {
+                result.push_str(&self.substitute(part));
+            } else {
+                result.push_str(part);
+            }
+            in_braces = part.ends_with('{');
+        }
+        result
+    }
+
+    fn list_breakpoints(&self, emulator: &Emulator) -> String {
+        let mut lines = Vec::new();
+        for address in emulator.get_breakpoints() {
+            let breakpoint = emulator.get_breakpoint(address).unwrap();
+            let mut line = self.describe(address);
+            if let Some(condition) = breakpoint.get_condition() {
+                write!(line, " if {}", condition).unwrap();
+            }
+            if let Some(message) = breakpoint.get_message() {
+                write!(line, " log {}", message).unwrap();
+            }
+            if breakpoint.get_ignore_count() > 0 {
+                write!(line, ", ignore {:X}", breakpoint.get_ignore_count()).unwrap();
+            }
+            write!(line, ", hits {:X}", breakpoint.get_hits()).unwrap();
+            lines.push(line);
+        }
+        if lines.is_empty() {
+            return String::from("No breakpoints");
+        }
+        lines.join("\n")
+    }
+
+    fn list_watchpoints(&self, emulator: &Emulator) -> String {
+        let lines: Vec<String> = emulator.get_access_watches().iter().map(|watch| self.describe_watch(watch)).collect();
+        if lines.is_empty() {
+            return String::from("No watchpoints");
+        }
+        lines.join("\n")
+    }
+
+    /*
+     * Watch from "ADDRESS[..END]", a mode like "rw" and an optional value
+     */
+    fn access_watch(&self, range: &str, mode: &str, value: &[&str]) -> Result<AccessWatch, &'static str> {
+        let (start, end) = match range.split_once("..") {
+            Some((start, end)) => (self.address(start)?, self.address(end)?),
+            None => (self.address(range)?, self.address(range)?),
+        };
+        if end < start {
+            return Err("Range ends before it starts");
+        }
+        let mut accesses = Vec::new();
+        for letter in mode.to_lowercase().chars() {
+            accesses.push(match letter {
+                'r' => Access::Read,
+                'w' => Access::Write,
+                'x' => Access::Execute,
+                _ => return Err("Watch mode must be made of r, w and x"),
+            });
+        }
+        let mut watch = AccessWatch::new(start, &accesses).with_end(end);
+        match value {
+            [] => {}
+            [value] => match self.number(value)? {
+                value if value <= 0xff => watch = watch.with_value(value as u8),
+                _ => return Err("Bytes must be below 100H"),
+            },
+            _ => return Err("Usage: watch ADDRESS[..END] MODE [VALUE]"),
+        }
+        Ok(watch)
+    }
+
+    /*
+     * e.g. "0100 (COUNT)..0101 rw = 05"
+     */
+    fn describe_watch(&self, watch: &AccessWatch) -> String {
+        let mut description = self.describe(watch.start);
+        if watch.changes {
+            return description;
+        }
+        if watch.end != watch.start {
+            write!(description, "..{}", self.describe(watch.end)).unwrap();
+        }
+        description.push(' ');
+        for access in &watch.accesses {
+            description.push(match access {
+                Access::Read => 'r',
+                Access::Write => 'w',
+                Access::Execute => 'x',
+            });
+        }
+        if let Some(value) = watch.value {
+            write!(description, " = {:02X}", value).unwrap();
+        }
+        description
+    }
+
+    fn profile(&self, emulator: &mut Emulator, args: &[&str]) -> Result<String, &'static str> {
+        let command = args.first().map(|word| word.to_lowercase());
+        match command.as_deref() {
+            Some("on") => {
+                emulator.start_profiling();
+                return Ok(String::from("Profiling"));
+            }
+            Some("off") => {
+                emulator.stop_profiling();
+                return Ok(String::from("Profiling paused"));
+            }
+            Some("clear") => {
+                emulator.clear_profile();
+                return Ok(String::new());
+            }
+            _ => {}
+        }
+        let profile = emulator.get_profile().ok_or("No profile, start one with profile on")?;
+        match (command.as_deref(), args.get(1..).unwrap_or_default()) {
+            (None, _) => Ok(report(profile, &self.symbols, LIST_LINES).trim_end().to_string()),
+            (Some("list"), rest) if rest.len() <= 2 => {
+                let lines = rest.get(1).map_or(Ok(LIST_LINES as u16), |lines| self.number(lines))? as usize;
+                let start = rest.first().map_or(Ok(emulator.get_pc()), |address| self.address(address))?;
+                let listing = Disassembler::from_ram(emulator.get_ram(), start, lines * 3 + 3)
+                    .with_symbols(self.symbols.clone())
+                    .listing();
+                let listing: Vec<&str> = listing.lines().take(lines).collect();
+                Ok(annotate(profile, &listing.join("\n")))
+            }
+            (Some("folded"), [path]) => {
+                fs::write(path, folded(profile, &self.symbols)).map_err(|_| "Could not write the profile")?;
+                Ok(String::new())
+            }
+            _ => Err("Usage: profile [on|off|clear|list [ADDRESS] [N]|folded FILE]"),
+        }
+    }
+
+    fn step(&mut self, target: &mut dyn Target, count: usize) -> Result<String, &'static str> {
+        for _ in 0..count {
+            target.step()?;
+            if let Some(stop) = target.emulator_mut().check_stop() {
+                return self.report(target, Ok(stop));
+            }
+        }
+        Ok(self.status(target))
+    }
+
+    /*
+     * Why a run ended, followed by registers and the next instruction
+     */
+    fn report(&mut self, target: &mut dyn Target, stop: Result<Stop, &'static str>) -> Result<String, &'static str> {
+        let reason = match stop {
+            Ok(Stop::Halted) => String::from("Halted"),
+            Ok(Stop::Breakpoint(address)) => format!("Breakpoint at {}", self.describe(address)),
+            Ok(Stop::Watchpoint { address, old, new }) => {
+                format!("Watchpoint at {}: {:02X} -> {:02X}", self.describe(address), old, new)
+            }
+            Ok(Stop::Access(access)) => match access.access {
+                Access::Read => format!("Read from {}: {:02X}", self.describe(access.address), access.value),
+                Access::Write => format!("Write to {}: {:02X}", self.describe(access.address), access.value),
+                Access::Execute => format!("Execute at {}", self.describe(access.address)),
+            },
+            Ok(Stop::ReturnMismatch(mismatch)) => format!(
+                "RET at {} went to {} instead of {}",
+                self.describe(mismatch.address),
+                self.describe(mismatch.found),
+                self.describe(mismatch.expected)
+            ),
+            Ok(Stop::Limit) => format!("Stopped after {} instructions", self.limit),
+            Err(message) => format!("Error: {}", message),
+        };
+        Ok(format!("{}\n{}", reason, self.status(target)))
+    }
+
+    fn status(&mut self, target: &mut dyn Target) -> String {
+        let output = target.take_output();
+        let log = target.emulator_mut().take_log();
+        let emulator = target.emulator();
+        let mut status = String::new();
+        for message in log {
+            writeln!(status, "{}", message).unwrap();
+        }
+        if !output.is_empty() {
+            let text: String = output.iter().map(|&b| (b & 0x7f) as char).collect();
+            writeln!(status, "{}", text.trim_end_matches(['\r', '\n'])).unwrap();
+        }
+        write!(status, "{}\n{}", self.registers(emulator), self.list(emulator, emulator.get_pc(), 1)).unwrap();
+        status
+    }
+
+    pub fn registers(&self, emulator: &Emulator) -> String {
+        let reg = emulator.get_registers();
+        let flags: Vec<String> = [("S", "sign"), ("Z", "zero"), ("AC", "aux"), ("P", "parity"), ("CY", "carry")]
+            .iter()
+            .map(|(short, name)| format!("{}={}", short, reg.get_flag(name) as u8))
+            .collect();
+        format!(
+            "A={:02X} BC={:04X} DE={:04X} HL={:04X} SP={:04X} PC={:04X}  {}  CYCLES={}",
+            reg['a'],
+            reg["bc"],
+            reg["de"],
+            reg["hl"],
+            emulator.get_sp(),
+            emulator.get_pc(),
+            flags.join(" "),
+            emulator.get_cycles()
+        )
+    }
+
+    fn dump(&mut self, emulator: &Emulator, length: u16) -> String {
+        let ram = emulator.get_ram();
+        let mut lines = Vec::new();
+        let mut offset = 0;
+        while offset < length {
+            let address = self.dump_address.wrapping_add(offset);
+            let count = (length - offset).min(16);
+            let bytes: Vec<u8> = (0..count).map(|index| ram[address.wrapping_add(index)]).collect();
+            let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+            let text: String = bytes
+                .iter()
+                .map(|&byte| if (0x20..0x7f).contains(&byte) { byte as char } else { '.' })
+                .collect();
+            lines.push(format!("{:04X}  {:<48} {}", address, hex.join(" "), text));
+            offset += count;
+        }
+        self.dump_address = self.dump_address.wrapping_add(length);
+        lines.join("\n")
+    }
+
+    fn list(&self, emulator: &Emulator, start: u16, count: usize) -> String {
+        let listing = Disassembler::from_ram(emulator.get_ram(), start, count * 3 + 3)
+            .with_symbols(self.symbols.clone())
+            .listing();
+        self.mark(listing.lines().take(count), emulator.get_pc())
+    }
+
+    /*
+     * Up to before instructions leading to address, found by starting the
+     * linear sweep as far back as possible while still landing on address,
+     * and after instructions from address on
+     */
+    fn around(&self, emulator: &Emulator, address: u16, before: usize, after: usize) -> String {
+        let ram = emulator.get_ram();
+        for back in (1..=(before * 3).min(address as usize)).rev() {
+            let start = address - back as u16;
+            let mut disassembler = Disassembler::from_ram(ram, start, back).with_symbols(self.symbols.clone());
+            let count = disassembler.by_ref().count();
+            if disassembler.address() == address {
+                let skip = count.saturating_sub(before);
+                let listing = Disassembler::from_ram(ram, start, back + after * 3 + 3)
+                    .with_symbols(self.symbols.clone())
+                    .listing();
+                return self.mark(listing.lines().skip(skip).take(count - skip + after), emulator.get_pc());
+            }
+        }
+        self.list(emulator, address, after)
+    }
+
+    fn mark<'a>(&self, lines: impl Iterator<Item = &'a str>, pc: u16) -> String {
+        let pc = format!("{:04X}", pc);
+        let lines: Vec<String> = lines
+            .map(|line| format!("{} {}", if line.starts_with(&pc) { "=>" } else { "  " }, line))
+            .collect();
+        lines.join("\n")
+    }
+}
+
+/*
+ * What follows the first count words of a line
+ */
+fn skip_words(line: &str, count: usize) -> &str {
+    let mut rest = line.trim_start();
+    for _ in 0..count {
+        rest = rest.split_once(char::is_whitespace).map_or("", |(_, rest)| rest).trim_start();
+    }
+    rest
+}
+
+fn set_register(emulator: &mut Emulator, register: &str, value: u16) -> Result<(), &'static str> {
+    let byte = || if value <= 0xff { Ok(value as u8) } else { Err("Value does not fit into an 8 bit register") };
+    match register {
+        "pc" => emulator.set_pc(value),
+        "sp" => emulator.set_sp(value),
+        "bc" | "de" | "hl" => emulator.get_registers_mut()[register] = value,
+        "flags" => emulator.get_registers_mut().set_flags(byte()?),
+        "a" | "b" | "c" | "d" | "e" | "h" | "l" => {
+            let name = register.chars().next().unwrap();
+            emulator.get_registers_mut()[name] = byte()?;
+        }
+        _ => return Err("Unknown register"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 0000: LXI H,0100H / 0003: INR M / 0004: JMP 0003H / 0007: HLT
+    const COUNTER: [u8; 8] = [0x21, 0x00, 0x01, 0x34, 0xc3, 0x03, 0x00, 0x76];
+
+    fn setup() -> (Monitor, Emulator) {
+        let mut emulator = Emulator::new();
+        emulator.load_ram(COUNTER.to_vec(), 0);
+        let mut symbols = SymbolTable::new();
+        symbols.insert(0x0003, "LOOP");
+        (Monitor::new().with_symbols(symbols).with_limit(1000), emulator)
+    }
+
+    fn run(monitor: &mut Monitor, emulator: &mut Emulator, line: &str) -> String {
+        monitor.execute(emulator, line).expect("Fuck").expect("Fuck")
+    }
+
+    #[test]
+    fn stepping() {
+        let (mut monitor, mut emulator) = setup();
+        let output = run(&mut monitor, &mut emulator, "s");
+        assert!(output.starts_with("A=00 BC=0000 DE=0000 HL=0100 SP=0000 PC=0003"));
+        assert!(output.ends_with("=> 0003  34        LOOP:       INR M"));
+
+        run(&mut monitor, &mut emulator, "step 2");
+        assert_eq!(emulator.get_pc(), 0x0003);
+        run(&mut monitor, &mut emulator, "");
+        run(&mut monitor, &mut emulator, "");
+        assert_eq!(emulator.get_pc(), 0x0003);
+        assert_eq!(emulator.get_ram()[0x0100], 2);
+    }
+
+    #[test]
+    fn breakpoints_and_watchpoints() {
+        let (mut monitor, mut emulator) = setup();
+        assert_eq!(run(&mut monitor, &mut emulator, "b loop"), "Breakpoint at 0003 (LOOP)");
+        assert!(run(&mut monitor, &mut emulator, "c").starts_with("Breakpoint at 0003 (LOOP)"));
+        assert!(run(&mut monitor, &mut emulator, "c").starts_with("Breakpoint at 0003 (LOOP)"));
+        assert_eq!(run(&mut monitor, &mut emulator, "b"), "0003 (LOOP), hits 2");
+        run(&mut monitor, &mut emulator, "bd 3");
+        assert_eq!(monitor.execute(&mut emulator, "bd 3"), Err("No breakpoint at this address"));
+
+        run(&mut monitor, &mut emulator, "w 100");
+        let output = run(&mut monitor, &mut emulator, "c");
+        assert!(output.starts_with("Watchpoint at 0100: 01 -> 02"));
+        run(&mut monitor, &mut emulator, "wd 100H");
+        assert!(run(&mut monitor, &mut emulator, "c").starts_with("Stopped after 1000 instructions"));
+
+        assert_eq!(run(&mut monitor, &mut emulator, "w 0FF..101 rw 10"), "Watchpoint at 00FF..0101 rw = 10");
+        assert_eq!(run(&mut monitor, &mut emulator, "w"), "00FF..0101 rw = 10");
+        let output = run(&mut monitor, &mut emulator, "c");
+        assert!(output.starts_with("Write to 0100: 10"));
+        assert_eq!(emulator.get_pc(), 0x0004);
+        run(&mut monitor, &mut emulator, "wd ff");
+        assert_eq!(run(&mut monitor, &mut emulator, "w loop x"), "Watchpoint at 0003 (LOOP) x");
+        assert!(run(&mut monitor, &mut emulator, "c").starts_with("Execute at 0003 (LOOP)"));
+        assert_eq!(monitor.execute(&mut emulator, "w 101..100 r"), Err("Range ends before it starts"));
+        assert_eq!(monitor.execute(&mut emulator, "w 100 q"), Err("Watch mode must be made of r, w and x"));
+
+        run(&mut monitor, &mut emulator, "set pc 7");
+        assert!(run(&mut monitor, &mut emulator, "until 0").starts_with("Halted"));
+        assert!(emulator.get_breakpoints().is_empty());
+    }
+
+    #[test]
+    fn conditional_breakpoints() {
+        let (mut monitor, mut emulator) = setup();
+        assert_eq!(run(&mut monitor, &mut emulator, "b 4 if [HL] == 3"), "Breakpoint at 0004 if [HL] == 3");
+        assert!(run(&mut monitor, &mut emulator, "c").starts_with("Breakpoint at 0004"));
+        assert_eq!(emulator.get_ram()[0x0100], 3);
+        run(&mut monitor, &mut emulator, "b 4 if pc == loop + 1");
+        assert_eq!(run(&mut monitor, &mut emulator, "ignore 4 2"), "Ignoring the next 2 hits at 0004");
+        run(&mut monitor, &mut emulator, "c");
+        assert_eq!(emulator.get_ram()[0x0100], 6);
+        assert_eq!(run(&mut monitor, &mut emulator, "b"), "0004 if pc == 3 + 1, ignore 2, hits 3");
+
+        run(&mut monitor, &mut emulator, "bd 4");
+        let output = run(&mut monitor, &mut emulator, "log loop count={M} at {loop}");
+        assert_eq!(output, "Logpoint at 0003 (LOOP): count={M} at {3}");
+        run(&mut monitor, &mut emulator, "b 4 if M == 8");
+        let output = run(&mut monitor, &mut emulator, "c");
+        assert!(output.starts_with("Breakpoint at 0004\ncount=06 at 03\ncount=07 at 03\nA=00"));
+        assert_eq!(monitor.execute(&mut emulator, "b 4 if"), Err("Usage: break ADDRESS [if CONDITION]"));
+        assert_eq!(monitor.execute(&mut emulator, "b 4 if (1"), Err("Unbalanced parentheses"));
+        assert_eq!(monitor.execute(&mut emulator, "ignore 5 1"), Err("No breakpoint at this address"));
+    }
+
+    #[test]
+    fn call_stack() {
+        // 0000: LXI SP,0200H / 0003: CALL 0009H / 0006: HLT / 0007: HLT / 0008: NOP
+        // 0009: INX SP / 000A: INX SP / 000B: LXI H,0007H / 000E: PUSH H / 000F: RET
+        let program = [0x31, 0x00, 0x02, 0xcd, 0x09, 0x00, 0x76, 0x76, 0x00, 0x33, 0x33, 0x21, 0x07, 0x00, 0xe5, 0xc9];
+        let mut emulator = Emulator::new();
+        emulator.load_ram(program.to_vec(), 0);
+        let mut symbols = SymbolTable::new();
+        symbols.insert(0x0009, "SKIP");
+        let mut monitor = Monitor::new().with_symbols(symbols);
+
+        run(&mut monitor, &mut emulator, "s 3");
+        let trace = run(&mut monitor, &mut emulator, "bt");
+        assert_eq!(trace, "#0 000A (SKIP+1)\n#1 0006  CALL 0009 (SKIP) at 0003, SP=01FE");
+        assert_eq!(run(&mut monitor, &mut emulator, "smash on"), "Stopping on mismatched returns is on");
+        let output = run(&mut monitor, &mut emulator, "c");
+        assert!(output.starts_with("RET at 000F went to 0007 instead of 0006\n"));
+        assert_eq!(run(&mut monitor, &mut emulator, "bt").lines().count(), 2);
+        assert_eq!(monitor.execute(&mut emulator, "smash"), Err("Usage: smash on|off"));
+    }
+
+    #[test]
+    fn profiling() {
+        let (mut monitor, mut emulator) = setup();
+        assert_eq!(monitor.execute(&mut emulator, "profile"), Err("No profile, start one with profile on"));
+        run(&mut monitor, &mut emulator, "profile on");
+        run(&mut monitor, &mut emulator, "s 5");
+        run(&mut monitor, &mut emulator, "profile off");
+        run(&mut monitor, &mut emulator, "s 5");
+        let report = run(&mut monitor, &mut emulator, "profile");
+        assert!(report.starts_with("Instructions 5, cycles 50\n"));
+        assert!(report.contains(&format!("{:<24} {:>10} {:>10} {:>6.1}%", "LOOP", 4, 40, 80.0)));
+
+        let listing = run(&mut monitor, &mut emulator, "profile list 0 3");
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines[0], "       1         10  0000  21 00 01              LXI H,100H");
+        assert_eq!(lines[1], "       2         20  0003  34        LOOP:       INR M");
+        assert_eq!(lines.len(), 3);
+
+        let path = std::env::temp_dir().join("monitor_profile.folded");
+        run(&mut monitor, &mut emulator, &format!("profile folded {}", path.display()));
+        assert_eq!(fs::read_to_string(&path).expect("Fuck"), "0000 10\nLOOP 40\n");
+        fs::remove_file(path).expect("Fuck");
+        run(&mut monitor, &mut emulator, "profile clear");
+        assert!(emulator.get_profile().is_none());
+    }
+
+    #[test]
+    fn memory() {
+        let (mut monitor, mut emulator) = setup();
+        run(&mut monitor, &mut emulator, "e 200 48 49 0");
+        let dump = run(&mut monitor, &mut emulator, "m 200 4");
+        assert_eq!(dump, format!("0200  {:<48} HI..", "48 49 00 00"));
+        assert!(run(&mut monitor, &mut emulator, "").starts_with("0204"));
+        assert_eq!(monitor.execute(&mut emulator, "e 200 100"), Err("Bytes must be below 100H"));
+
+        run(&mut monitor, &mut emulator, "set a 41");
+        run(&mut monitor, &mut emulator, "set hl 1234");
+        assert_eq!(emulator.get_registers()['a'], 0x41);
+        assert_eq!(emulator.get_registers()["hl"], 0x1234);
+        assert_eq!(monitor.execute(&mut emulator, "set a 100"), Err("Value does not fit into an 8 bit register"));
+        assert_eq!(monitor.execute(&mut emulator, "set x 1"), Err("Unknown register"));
+    }
+
+    #[test]
+    fn listing() {
+        let (mut monitor, mut emulator) = setup();
+        emulator.set_pc(0x0004);
+        let listing = run(&mut monitor, &mut emulator, "l");
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines[0], "   0000  21 00 01              LXI H,100H");
+        assert_eq!(lines[1], "   0003  34        LOOP:       INR M");
+        assert_eq!(lines[2], "=> 0004  C3 03 00              JMP LOOP");
+
+        let listing = run(&mut monitor, &mut emulator, "list loop 2");
+        assert_eq!(listing.lines().count(), 2);
+        assert_eq!(monitor.execute(&mut emulator, "q"), Ok(None));
+        assert_eq!(monitor.execute(&mut emulator, "frobnicate"), Err("Unknown command, try help"));
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write;
+
+use super::symbols::SymbolTable;
+use crate::core::emulator::Profiler;
+
+const TOP_LEVEL: &str = "(top level)";
+const NO_SYMBOL: &str = "(no symbol)";
+
+fn percent(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    part as f64 * 100.0 / total as f64
+}
+
+/*
+ * Functions are named after the target of their call
+ */
+fn function(symbols: &SymbolTable, target: u16) -> String {
+    symbols.label(target).unwrap_or_else(|| format!("{:04X}", target))
+}
+
+fn describe(symbols: &SymbolTable, address: u16) -> String {
+    match symbols.label(address) {
+        Some(label) => format!("{:04X} ({})", address, label),
+        None => format!("{:04X}", address),
+    }
+}
+
+/*
+ * Largest values first, ties in key order
+ */
+fn sorted<K: Ord, V: Copy>(map: impl IntoIterator<Item = (K, V)>, value: impl Fn(V) -> u64) -> Vec<(K, V)> {
+    let mut entries: Vec<(K, V)> = map.into_iter().collect();
+    entries.sort_by(|(a, x), (b, y)| value(*y).cmp(&value(*x)).then(a.cmp(b)));
+    entries
+}
+
+/*
+ * Cycles per function, by itself and with its callees, per symbol and
+ * for the hottest addresses, each table limited to count lines
+ */
+pub fn report(profile: &Profiler, symbols: &SymbolTable, count: usize) -> String {
+    let total = profile.get_total_cycles();
+    let mut report = format!("Instructions {}, cycles {}\n", profile.get_instructions(), total);
+
+    let mut functions: HashMap<String, (u64, u64)> = HashMap::new();
+    for (stack, cycles) in profile.stacks() {
+        let frames = &stack[..stack.len() - 1];
+        let own = frames.last().map_or_else(|| TOP_LEVEL.to_string(), |&target| function(symbols, target));
+        functions.entry(own).or_default().0 += cycles;
+        let callers: BTreeSet<String> = frames.iter().map(|&target| function(symbols, target)).collect();
+        for name in callers.into_iter().chain([TOP_LEVEL.to_string()]) {
+            functions.entry(name).or_default().1 += cycles;
+        }
+    }
+    writeln!(report, "\n{:<24} {:>10} {:>7} {:>10} {:>7}", "Function", "Self", "", "Total", "").unwrap();
+    for (name, (own, all)) in sorted(functions, |(own, _)| own).into_iter().take(count) {
+        let (own_percent, all_percent) = (percent(own, total), percent(all, total));
+        writeln!(report, "{:<24} {:>10} {:>6.1}% {:>10} {:>6.1}%", name, own, own_percent, all, all_percent).unwrap();
+    }
+
+    let mut names: BTreeMap<&str, (u64, u64)> = BTreeMap::new();
+    for (address, hits, cycles) in profile.addresses() {
+        let name = symbols.nearest(address).map_or(NO_SYMBOL, |(_, name)| name);
+        let entry = names.entry(name).or_default();
+        entry.0 += hits;
+        entry.1 += cycles;
+    }
+    writeln!(report, "\n{:<24} {:>10} {:>10} {:>7}", "Symbol", "Hits", "Cycles", "").unwrap();
+    for (name, (hits, cycles)) in sorted(names, |(_, cycles)| cycles).into_iter().take(count) {
+        writeln!(report, "{:<24} {:>10} {:>10} {:>6.1}%", name, hits, cycles, percent(cycles, total)).unwrap();
+    }
+
+    let addresses = profile.addresses().map(|(address, hits, cycles)| (address, (hits, cycles)));
+    writeln!(report, "\n{:<24} {:>10} {:>10} {:>7}", "Address", "Hits", "Cycles", "").unwrap();
+    for (address, (hits, cycles)) in sorted(addresses, |(_, cycles)| cycles).into_iter().take(count) {
+        let address = describe(symbols, address);
+        writeln!(report, "{:<24} {:>10} {:>10} {:>6.1}%", address, hits, cycles, percent(cycles, total)).unwrap();
+    }
+    report
+}
+
+/*
+ * One "OUTER;INNER;LABEL CYCLES" line per call stack, the input
+ * format of flamegraph.pl and most flame graph viewers, LABEL being
+ * the symbol of the instructions if it is not the function itself
+ */
+pub fn folded(profile: &Profiler, symbols: &SymbolTable) -> String {
+    let mut lines: BTreeMap<String, u64> = BTreeMap::new();
+    for (stack, cycles) in profile.stacks() {
+        let (&address, frames) = stack.split_last().unwrap();
+        let mut names: Vec<String> = frames.iter().map(|&target| function(symbols, target)).collect();
+        let leaf = match symbols.nearest(address) {
+            Some((_, name)) => name.to_string(),
+            None => format!("{:04X}", address),
+        };
+        if names.last() != Some(&leaf) {
+            names.push(leaf);
+        }
+        *lines.entry(names.join(";")).or_default() += cycles;
+    }
+    lines.iter().map(|(stack, cycles)| format!("{} {}\n", stack, cycles)).collect()
+}
+
+/*
+ * Listing lines that start with an address prefixed by the
+ * hits and cycles of that address, blank if it never ran
+ */
+pub fn annotate(profile: &Profiler, listing: &str) -> String {
+    let lines: Vec<String> = listing
+        .lines()
+        .map(|line| {
+            let address = line.get(..4).and_then(|digits| u16::from_str_radix(digits, 16).ok());
+            match address.filter(|&address| profile.get_hits(address) > 0) {
+                Some(address) => {
+                    format!("{:>8} {:>10}  {}", profile.get_hits(address), profile.get_cycles(address), line)
+                }
+                None => format!("{:>8} {:>10}  {}", "", "", line),
+            }
+        })
+        .collect();
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::emulator::{Emulator, Target};
+    use crate::terminator::disassembler::Disassembler;
+
+    // 0000: LXI SP,0100H / 0003: MVI B,2
+    // 0005: CALL PRINT / 0008: DCR B / 0009: JNZ 0005H / 000C: HLT
+    // 000D: PRINT: CALL PUT / 0010: RET / 0011: PUT: RET
+    const PROGRAM: [u8; 18] = [
+        0x31, 0x00, 0x01, 0x06, 0x02, 0xcd, 0x0d, 0x00, 0x05, 0xc2, 0x05, 0x00, 0x76, 0xcd, 0x11, 0x00, 0xc9, 0xc9,
+    ];
+
+    fn setup() -> (Emulator, SymbolTable) {
+        let mut e = Emulator::new();
+        e.load_ram(PROGRAM.to_vec(), 0);
+        e.start_profiling();
+        e.run(100).expect("Fuck");
+        let symbols = SymbolTable::parse("0000 MAIN\n000D PRINT\n0011 PUT").expect("Fuck");
+        (e, symbols)
+    }
+
+    #[test]
+    fn sorted_report() {
+        let (e, symbols) = setup();
+        let report = report(e.get_profile().expect("Fuck"), &symbols, 2);
+        let lines: Vec<&str> = report.lines().collect();
+        // MAIN 10 + 7 + 2 * (17 + 5 + 10) + 7, PRINT 2 * (17 + 10), PUT 2 * 10
+        assert_eq!(lines[0], "Instructions 15, cycles 162");
+        assert_eq!(lines[3], format!("{:<24} {:>10} {:>6.1}% {:>10} {:>6.1}%", "(top level)", 88, 54.3, 162, 100.0));
+        assert_eq!(lines[4], format!("{:<24} {:>10} {:>6.1}% {:>10} {:>6.1}%", "PRINT", 54, 33.3, 74, 45.7));
+        assert_eq!(lines[7], format!("{:<24} {:>10} {:>10} {:>6.1}%", "MAIN", 9, 88, 54.3));
+        assert_eq!(lines[8], format!("{:<24} {:>10} {:>10} {:>6.1}%", "PRINT", 4, 54, 33.3));
+        assert_eq!(lines[11], format!("{:<24} {:>10} {:>10} {:>6.1}%", "0005 (MAIN+5)", 2, 34, 21.0));
+        assert_eq!(lines[12], format!("{:<24} {:>10} {:>10} {:>6.1}%", "000D (PRINT)", 2, 34, 21.0));
+        assert_eq!(lines.len(), 13);
+    }
+
+    #[test]
+    fn folded_stacks() {
+        let (e, symbols) = setup();
+        let folded = folded(e.get_profile().expect("Fuck"), &symbols);
+        assert_eq!(folded, "MAIN 88\nPRINT 54\nPRINT;PUT 20\n");
+        let folded = super::folded(e.get_profile().expect("Fuck"), &SymbolTable::new());
+        assert!(folded.starts_with("0000 10\n"));
+        assert!(folded.ends_with("000D;0011 20\n"));
+    }
+
+    #[test]
+    fn annotated_listing() {
+        let (e, symbols) = setup();
+        let listing = Disassembler::from_ram(e.get_ram(), 0x0009, 8).with_symbols(symbols).listing();
+        let annotated = annotate(e.get_profile().expect("Fuck"), &listing);
+        let lines: Vec<&str> = annotated.lines().collect();
+        assert_eq!(lines[0], "       2         20  0009  C2 05 00              JNZ 5H");
+        assert_eq!(lines[1], "       1          7  000C  76                    HLT");
+        assert_eq!(lines[2], "       2         34  000D  CD 11 00  PRINT:      CALL PUT");
+        assert_eq!(lines.len(), 4);
+    }
+}
@@ -0,0 +1,342 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::disassembler::{DecodedInstruction, Disassembler};
+use crate::kreator::assembler::is_label_name;
+use super::symbols::{DataType, SymbolTable};
+
+const RST_VECTORS: [u16; 8] = [0x00, 0x08, 0x10, 0x18, 0x20, 0x28, 0x30, 0x38];
+
+/*
+ * Disassembler that follows the code flow from a set of entry points
+ * instead of sweeping linearly, bytes that are never reached are data.
+ * Branch targets get labels (L1234), the generated source assembles
+ * back to the original image.
+ *
+ * A symbol table provides names for addresses and forces
+ * ranges to be treated as data. Names the assembler would reject
+ * get generated labels and are kept as comments.
+ */
+pub struct RecursiveDisassembler {
+    bytes: Vec<u8>,
+    origin: u16,
+    entry_points: Vec<u16>,
+    symbols: SymbolTable,
+}
+
+impl RecursiveDisassembler {
+    /*
+     * The reset and RST vectors inside the image are entry points by default
+     */
+    pub fn new(bytes: &[u8], origin: u16) -> Self {
+        let mut disassembler = Self {
+            bytes: bytes.to_vec(),
+            origin,
+            entry_points: Vec::new(),
+            symbols: SymbolTable::new(),
+        };
+        for vector in RST_VECTORS.iter() {
+            if disassembler.offset(*vector).is_some() {
+                disassembler.entry_points.push(*vector);
+            }
+        }
+        disassembler
+    }
+
+    pub fn with_symbols(mut self, symbols: SymbolTable) -> Self {
+        self.symbols = symbols;
+        self
+    }
+
+    pub fn add_entry_point(&mut self, address: u16) {
+        if !self.entry_points.contains(&address) {
+            self.entry_points.push(address);
+        }
+    }
+
+    fn offset(&self, address: u16) -> Option<usize> {
+        let offset = address.wrapping_sub(self.origin) as usize;
+        if offset < self.bytes.len() {
+            Some(offset)
+        } else {
+            None
+        }
+    }
+
+    fn decode_at(&self, offset: usize) -> DecodedInstruction {
+        let end = (offset + 3).min(self.bytes.len());
+        let address = self.origin.wrapping_add(offset as u16);
+        Disassembler::new(&self.bytes[offset..end], address).decode()
+    }
+
+    /*
+     * Follow all paths from the entry points
+     * Returns the reached instructions by address
+     */
+    pub fn trace(&self) -> BTreeMap<u16, DecodedInstruction> {
+        let mut claimed = vec![false; self.bytes.len()];
+        let mut code = BTreeMap::new();
+        let mut pending = self.entry_points.clone();
+
+        while let Some(address) = pending.pop() {
+            let offset = match self.offset(address) {
+                Some(offset) => offset,
+                None => continue,
+            };
+            if code.contains_key(&address) || self.symbols.data_range(address).is_some() {
+                continue;
+            }
+            let instruction = self.decode_at(offset);
+            if instruction.mnemonic == "DB" || claimed[offset..offset + instruction.length].contains(&true) {
+                continue;
+            }
+            for byte in claimed[offset..offset + instruction.length].iter_mut() {
+                *byte = true;
+            }
+
+            if let Some(target) = branch_target(&instruction) {
+                pending.push(target);
+            }
+            if falls_through(instruction.bytes[0]) {
+                pending.push(address.wrapping_add(instruction.length as u16));
+            }
+            code.insert(address, instruction);
+        }
+        code
+    }
+
+    /*
+     * Split the image into instructions and data lines
+     */
+    fn lines(&self, code: &BTreeMap<u16, DecodedInstruction>) -> Vec<DecodedInstruction> {
+        let mut lines = Vec::new();
+        let mut offset = 0;
+        while offset < self.bytes.len() {
+            let address = self.origin.wrapping_add(offset as u16);
+            if let Some(instruction) = code.get(&address) {
+                offset += instruction.length;
+                lines.push(instruction.clone());
+                continue;
+            }
+            let range = self.symbols.data_range(address).copied();
+            let kind = range.map(|range| range.kind).unwrap_or(DataType::Byte);
+
+            // Data lines end before code, named addresses and range boundaries
+            let mut length = 1;
+            while offset + length < self.bytes.len() {
+                let next = address.wrapping_add(length as u16);
+                if code.contains_key(&next)
+                    || self.symbols.name(next).is_some()
+                    || self.symbols.data_range(next).copied() != range
+                {
+                    break;
+                }
+                length += 1;
+            }
+            let mut d = Disassembler::new(&self.bytes[offset..offset + length], address);
+            while d.address() != address.wrapping_add(length as u16) {
+                lines.push(d.decode_data(kind, length));
+            }
+            offset += length;
+        }
+        lines
+    }
+
+    /*
+     * Generate assembler source, unreached bytes become DB lines
+     */
+    pub fn to_source(&self) -> String {
+        let code = self.trace();
+        let targets: BTreeSet<u16> = code
+            .values()
+            .filter_map(branch_target)
+            .filter(|target| code.contains_key(target))
+            .collect();
+        let lines = self.lines(&code);
+        let starts: BTreeSet<u16> = lines.iter().map(|line| line.address).collect();
+
+        let name = |address: u16| self.symbols.name(address).filter(|name| is_label_name(name));
+        let label = |address: u16| -> Option<String> {
+            match name(address) {
+                Some(name) => Some(name.to_string()),
+                None if targets.contains(&address) || self.symbols.name(address).is_some() => {
+                    Some(label_name(address))
+                }
+                None => None,
+            }
+        };
+
+        let mut equates = BTreeMap::new();
+        let mut source = Vec::new();
+        for mut line in lines {
+            let declaration = match label(line.address) {
+                Some(name) => format!("{}:", name),
+                None => String::new(),
+            };
+            if line.mnemonic != "DB" && line.mnemonic != "DW" && line.length == 3 {
+                let target = u16::from_le_bytes([line.bytes[1], line.bytes[2]]);
+                let is_branch = branch_target(&line).is_some();
+                let name = if starts.contains(&target) && (is_branch || self.symbols.name(target).is_some()) {
+                    label(target)
+                } else if self.offset(target).is_none() {
+                    name(target).map(String::from)
+                } else {
+                    None
+                };
+                if let Some(name) = name {
+                    if self.offset(target).is_none() {
+                        equates.insert(target, name.clone());
+                    }
+                    *line.operands.last_mut().unwrap() = name;
+                }
+            }
+            let mut comment = self.symbols.comment(line.address).map(String::from).or(line.comment.take());
+            if let Some(symbol) = self.symbols.name(line.address).filter(|symbol| !is_label_name(symbol)) {
+                comment = Some(match comment {
+                    Some(comment) => format!("{} - {}", symbol, comment),
+                    None => symbol.to_string(),
+                });
+            }
+            let text = format!("{:<7} {}", declaration, line);
+            match comment {
+                Some(comment) => source.push(format!("{:<32}; {}", text, comment)),
+                None => source.push(text),
+            }
+        }
+
+        let mut header = Vec::new();
+        for (address, name) in equates {
+            header.push(format!("{} EQU {}", name, Disassembler::fmt_hex::<u16>(address)));
+        }
+        header.push(format!("{:<8}ORG {}", "", Disassembler::fmt_hex::<u16>(self.origin)));
+        header.extend(source);
+        header.push(format!("{:<8}END", ""));
+        header.join("\n")
+    }
+}
+
+fn label_name(address: u16) -> String {
+    format!("L{:04X}", address)
+}
+
+/*
+ * Target of a jump, call or RST
+ */
+fn branch_target(instruction: &DecodedInstruction) -> Option<u16> {
+    let opcode = instruction.bytes[0];
+    match opcode {
+        0xc3 | 0xcd => {}
+        0xc2..=0xff if opcode & 0x07 == 0x02 || opcode & 0x07 == 0x04 => {}
+        0xc7..=0xff if opcode & 0x07 == 0x07 => return Some((opcode & 0x38) as u16),
+        _ => return None,
+    }
+    Some(u16::from_le_bytes([instruction.bytes[1], instruction.bytes[2]]))
+}
+
+/*
+ * Execution can continue with the next instruction
+ */
+fn falls_through(opcode: u8) -> bool {
+    !matches!(opcode, 0xc3 | 0xc9 | 0xe9)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kreator::assembler::Assembler;
+    use std::fs;
+
+    const PROGRAM: [u8; 20] = [
+        0x31, 0x00, 0x20, // 0100: LXI SP,2000H
+        0xcd, 0x0c, 0x01, // 0103: CALL 010CH
+        0xc2, 0x03, 0x01, // 0106: JNZ 0103H
+        0xc3, 0x00, 0x01, // 0109: JMP 0100H
+        0x3e, 0x08, // 010C: MVI A,08H
+        0xc9, // 010E: RET
+        0x48, 0x49, 0x08, 0xcb, 0x00, // 010F: data
+    ];
+
+    #[test]
+    fn follows_code_flow() {
+        let mut d = RecursiveDisassembler::new(&PROGRAM, 0x100);
+        d.add_entry_point(0x100);
+        let code = d.trace();
+
+        assert_eq!(
+            code.keys().copied().collect::<Vec<u16>>(),
+            vec![0x100, 0x103, 0x106, 0x109, 0x10c, 0x10e]
+        );
+
+        let source = d.to_source();
+        assert!(source.contains("L0100:  LXI SP,2000H"));
+        assert!(source.contains("L0103:  CALL L010C"));
+        assert!(source.contains("        JNZ L0103"));
+        assert!(source.contains("        DB 48H,49H,8H,0cbH,0H"));
+    }
+
+    #[test]
+    fn reassembles_program() {
+        let mut d = RecursiveDisassembler::new(&PROGRAM, 0x100);
+        d.add_entry_point(0x100);
+
+        let bytes = Assembler::new(&d.to_source()).assemble().expect("Fuck");
+        assert_eq!(bytes, PROGRAM.to_vec());
+    }
+
+    #[test]
+    fn applies_symbols() {
+        let symbols = SymbolTable::parse(
+            "010C Subr ; subroutine\n0111-0113 TEXT Msg\n2000 Stack\n",
+        )
+        .expect("Fuck");
+        let mut d = RecursiveDisassembler::new(&PROGRAM, 0x100).with_symbols(symbols);
+        d.add_entry_point(0x100);
+        let source = d.to_source();
+
+        assert!(source.starts_with("Stack EQU 2000H\n"));
+        assert!(source.contains("L0100:  LXI SP,Stack"));
+        assert!(source.contains("L0103:  CALL Subr"));
+        assert!(source.contains("Subr:   MVI A,8H                ; subroutine"));
+        assert!(source.contains("        DB 48H,49H\n"));
+        assert!(source.contains("Msg:    DB 8H,0cbH,0H           ; '...'"));
+
+        // Short names survive reassembly
+        let symbols = SymbolTable::parse("010C Subr\n0111-0113 TEXT Msg\n").expect("Fuck");
+        let mut d = RecursiveDisassembler::new(&PROGRAM, 0x100).with_symbols(symbols);
+        d.add_entry_point(0x100);
+        let bytes = Assembler::new(&d.to_source()).assemble().expect("Fuck");
+        assert_eq!(bytes, PROGRAM.to_vec());
+    }
+
+    #[test]
+    fn long_names() {
+        let symbols = SymbolTable::parse(
+            "010C DrawSprite ; draw it\n0111-0113 TEXT Message\n2000 StackTop\n0106 MOV\n",
+        )
+        .expect("Fuck");
+        let mut d = RecursiveDisassembler::new(&PROGRAM, 0x100).with_symbols(symbols);
+        d.add_entry_point(0x100);
+        let source = d.to_source();
+
+        assert!(source.starts_with("        ORG 100H\n"));
+        assert!(source.contains("L0100:  LXI SP,2000H"));
+        assert!(source.contains("L0103:  CALL L010C"));
+        assert!(source.contains("L010C:  MVI A,8H                ; DrawSprite - draw it"));
+        assert!(source.contains("L0111:  DB 8H,0cbH,0H           ; Message"));
+        assert!(source.contains("L0106:  JNZ L0103               ; MOV"));
+        let bytes = Assembler::new(&source).assemble().expect("Fuck");
+        assert_eq!(bytes, PROGRAM.to_vec());
+    }
+
+    #[test]
+    fn reassembles_invaders() {
+        let mut rom = Vec::new();
+        for part in ["h", "g", "f", "e"].iter() {
+            rom.extend(fs::read(format!("../roms/invaders.{}", part)).expect("Fuck"));
+        }
+        let d = RecursiveDisassembler::new(&rom, 0);
+        let source = d.to_source();
+
+        let bytes = Assembler::new(&source).assemble().expect("Fuck");
+        assert_eq!(bytes, rom);
+    }
+}
@@ -0,0 +1,297 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+
+use super::disassembler::DecodedInstruction;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DataType {
+    Byte,
+    Word,
+    Text,
+}
+
+impl DataType {
+    fn parse(name: &str) -> Option<Self> {
+        match name.to_uppercase().as_str() {
+            "BYTE" | "BYTES" | "DB" => Some(DataType::Byte),
+            "WORD" | "WORDS" | "DW" => Some(DataType::Word),
+            "TEXT" => Some(DataType::Text),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            DataType::Byte => "BYTE",
+            DataType::Word => "WORD",
+            DataType::Text => "TEXT",
+        }
+    }
+}
+
+/*
+ * Inclusive address range holding data instead of code
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DataRange {
+    pub start: u16,
+    pub end: u16,
+    pub kind: DataType,
+}
+
+impl DataRange {
+    pub fn contains(&self, address: u16) -> bool {
+        self.start <= address && address <= self.end
+    }
+}
+
+/*
+ * Names, comments and data ranges for addresses
+ *
+ * Symbol files have one entry per line, addresses are hexadecimal:
+ *
+ *   ; comment
+ *   1439 DrawSprite        ; optional comment
+ *   1a5c-1a6b BYTE         ; byte table
+ *   1b00-1b0f WORD Aliens  ; word table with a name
+ *   1c00-1c0f TEXT
+ */
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SymbolTable {
+    names: BTreeMap<u16, String>,
+    comments: BTreeMap<u16, String>,
+    ranges: Vec<DataRange>,
+}
+
+fn parse_address(text: &str) -> Result<u16, &'static str> {
+    let text = text.trim_end_matches(['H', 'h']);
+    u16::from_str_radix(text, 16).map_err(|_| "Invalid address in symbol file")
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn parse(input: &str) -> Result<Self, &'static str> {
+        let mut table = SymbolTable::new();
+
+        for line in input.lines() {
+            let (entry, comment) = match line.split_once(";") {
+                Some((entry, comment)) => (entry.trim(), Some(comment.trim())),
+                None => (line.trim(), None),
+            };
+            if entry.is_empty() {
+                continue;
+            }
+            let mut fields = entry.split_whitespace();
+            let address = fields.next().unwrap();
+            let start = match address.split_once("-") {
+                Some((start, end)) => {
+                    let kind = fields
+                        .next()
+                        .and_then(DataType::parse)
+                        .ok_or("Invalid data type in symbol file")?;
+                    let range = DataRange {
+                        start: parse_address(start)?,
+                        end: parse_address(end)?,
+                        kind,
+                    };
+                    if range.end < range.start {
+                        return Err("Invalid address range in symbol file");
+                    }
+                    table.add_range(range);
+                    range.start
+                }
+                None => parse_address(address)?,
+            };
+            if let Some(name) = fields.next() {
+                table.insert(start, name);
+            }
+            if fields.next().is_some() {
+                return Err("Too many fields in symbol file");
+            }
+            if let Some(comment) = comment.filter(|comment| !comment.is_empty()) {
+                table.set_comment(start, comment);
+            }
+        }
+        Ok(table)
+    }
+
+    pub fn load_file(path: &str) -> io::Result<Self> {
+        let input = fs::read_to_string(path)?;
+        SymbolTable::parse(&input).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn insert(&mut self, address: u16, name: &str) {
+        self.names.insert(address, name.to_string());
+    }
+
+    pub fn set_comment(&mut self, address: u16, comment: &str) {
+        self.comments.insert(address, comment.to_string());
+    }
+
+    pub fn add_range(&mut self, range: DataRange) {
+        self.ranges.push(range);
+    }
+
+    pub fn name(&self, address: u16) -> Option<&str> {
+        self.names.get(&address).map(String::as_str)
+    }
+
+    /*
+     * Closest name at or below address with its address
+     */
+    pub fn nearest(&self, address: u16) -> Option<(u16, &str)> {
+        let (start, name) = self.names.range(..=address).next_back()?;
+        Some((*start, name.as_str()))
+    }
+
+    /*
+     * Nearest name at or below address, e.g. "PRINT+3"
+     */
+    pub fn label(&self, address: u16) -> Option<String> {
+        let (start, name) = self.nearest(address)?;
+        match address - start {
+            0 => Some(name.to_string()),
+            offset => Some(format!("{}+{:X}", name, offset)),
+        }
+    }
+
+    pub fn address(&self, name: &str) -> Option<u16> {
+        self.names
+            .iter()
+            .find(|(_, symbol)| symbol.eq_ignore_ascii_case(name))
+            .map(|(address, _)| *address)
+    }
+
+    pub fn comment(&self, address: u16) -> Option<&str> {
+        self.comments.get(&address).map(String::as_str)
+    }
+
+    pub fn data_range(&self, address: u16) -> Option<&DataRange> {
+        self.ranges.iter().find(|range| range.contains(address))
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = (u16, &str)> {
+        self.names.iter().map(|(address, name)| (*address, name.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.comments.is_empty() && self.ranges.is_empty()
+    }
+
+    /*
+     * Replace 16 bit operands by the name of the address and attach comments
+     */
+    pub fn apply(&self, instruction: &mut DecodedInstruction) {
+        if let Some(comment) = self.comment(instruction.address) {
+            instruction.comment = Some(comment.to_string());
+        }
+        if instruction.mnemonic == "DB" || instruction.mnemonic == "DW" || instruction.length != 3 {
+            return;
+        }
+        let address = u16::from_le_bytes([instruction.bytes[1], instruction.bytes[2]]);
+        if let Some(name) = self.name(address) {
+            if let Some(operand) = instruction.operands.last_mut() {
+                *operand = name.to_string();
+            }
+        }
+    }
+}
+
+/*
+ * Export in the symbol file format
+ */
+impl fmt::Display for SymbolTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for range in &self.ranges {
+            write!(f, "{:04X}-{:04X} {}", range.start, range.end, range.kind.name())?;
+            if let Some(name) = self.name(range.start) {
+                write!(f, " {}", name)?;
+            }
+            if let Some(comment) = self.comment(range.start) {
+                write!(f, " ; {}", comment)?;
+            }
+            writeln!(f)?;
+        }
+        for (address, name) in &self.names {
+            if self.ranges.iter().any(|range| range.start == *address) {
+                continue;
+            }
+            write!(f, "{:04X} {}", address, name)?;
+            if let Some(comment) = self.comment(*address) {
+                write!(f, " ; {}", comment)?;
+            }
+            writeln!(f)?;
+        }
+        for (address, comment) in &self.comments {
+            if self.names.contains_key(address) || self.ranges.iter().any(|range| range.start == *address) {
+                continue;
+            }
+            writeln!(f, "{:04X} ; {}", address, comment)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminator::disassembler::Disassembler;
+
+    const SYMBOLS: &str = "; Space Invaders\n\
+                           0000 Reset\n\
+                           1439 DrawSprite ; Draw sprite at HL\n\
+                           1a5c-1a6bH BYTE ; alien sprite\n\
+                           1b00-1b0f word Aliens\n\
+                           20c0 ; ISR delay counter\n";
+
+    #[test]
+    fn parse_symbol_file() {
+        let table = SymbolTable::parse(SYMBOLS).expect("Fuck");
+
+        assert_eq!(table.name(0x1439), Some("DrawSprite"));
+        assert_eq!(table.address("drawsprite"), Some(0x1439));
+        assert_eq!(table.comment(0x1439), Some("Draw sprite at HL"));
+        assert_eq!(table.comment(0x20c0), Some("ISR delay counter"));
+        assert_eq!(table.name(0x1b00), Some("Aliens"));
+        assert_eq!(table.data_range(0x1a60).unwrap().kind, DataType::Byte);
+        assert_eq!(table.data_range(0x1b0f).unwrap().kind, DataType::Word);
+        assert_eq!(table.data_range(0x1b10), None);
+        assert_eq!(table.label(0x1439).as_deref(), Some("DrawSprite"));
+        assert_eq!(table.label(0x1450).as_deref(), Some("DrawSprite+17"));
+        assert_eq!(SymbolTable::new().label(0x1450), None);
+
+        assert_eq!(SymbolTable::parse("xyz Foo"), Err("Invalid address in symbol file"));
+        assert_eq!(SymbolTable::parse("10-20 FLOAT"), Err("Invalid data type in symbol file"));
+        assert_eq!(SymbolTable::parse("20-10 BYTE"), Err("Invalid address range in symbol file"));
+    }
+
+    #[test]
+    fn export_roundtrip() {
+        let table = SymbolTable::parse(SYMBOLS).expect("Fuck");
+        let exported = table.to_string();
+
+        assert!(exported.contains("1439 DrawSprite ; Draw sprite at HL\n"));
+        assert!(exported.contains("1B00-1B0F WORD Aliens\n"));
+        assert_eq!(SymbolTable::parse(&exported), Ok(table));
+    }
+
+    #[test]
+    fn apply_to_operands() {
+        let table = SymbolTable::parse(SYMBOLS).expect("Fuck");
+        // CALL 1439H; LXI H,1B00H
+        let mut d = Disassembler::new(&[0xcd, 0x39, 0x14, 0x21, 0x00, 0x1b], 0x1439);
+        let mut call = d.decode();
+        let mut lxi = d.decode();
+        table.apply(&mut call);
+        table.apply(&mut lxi);
+
+        assert_eq!(call.to_string(), "CALL DrawSprite");
+        assert_eq!(call.comment.as_deref(), Some("Draw sprite at HL"));
+        assert_eq!(lxi.to_string(), "LXI H,Aliens");
+    }
+}
@@ -1,8 +1,10 @@
 use crate::core::emulator::Emulator;
 use crate::kreator::assembler::Assembler;
+use crate::kreator::include::FileSystem;
 use std::{
     fs::*,
     io::{self, Read},
+    path::Path,
 };
 
 pub fn set_panic_hook() {
@@ -20,7 +22,8 @@ pub fn load_asm_file(emulator: &mut Emulator, path: &str) -> io::Result<()> {
     let mut file = File::open(path)?;
     let mut buf = String::new();
     file.read_to_string(&mut buf)?;
-    let asmblr = Assembler::new(&buf);
+    let directory = Path::new(path).parent().unwrap_or_else(|| Path::new("."));
+    let asmblr = Assembler::new(&buf).with_resolver(FileSystem::new(directory));
     let mc = asmblr.assemble().expect("Fuck");
     emulator.load_ram(mc, 0);
     Ok(())
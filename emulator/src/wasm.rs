@@ -0,0 +1,249 @@
+use wasm_bindgen::prelude::*;
+
+use crate::core::machines::trainer::Trainer;
+use crate::core::opcodes::{Opcode, OPCODES};
+use crate::kreator::assembler::Assembler;
+use crate::kreator::dialect::Dialect;
+use crate::kreator::include::MemoryFiles;
+use crate::terminator::backtrace::backtrace;
+use crate::terminator::disassembler::Disassembler;
+use crate::terminator::profile::{annotate, folded, report};
+use crate::terminator::symbols::SymbolTable;
+
+/*
+ * Disassembly listing of a memory image, symbols use the symbol file format
+ */
+#[wasm_bindgen]
+pub fn disassemble(bytes: &[u8], origin: u16, symbols: &str) -> Result<String, JsValue> {
+    let symbols = SymbolTable::parse(symbols).map_err(JsValue::from)?;
+    Ok(Disassembler::new(bytes, origin).with_symbols(symbols).listing())
+}
+
+/*
+ * Files of a multi-file program, INCLUDE looks them up by name
+ */
+#[wasm_bindgen(js_name = Project)]
+#[derive(Default)]
+pub struct WasmProject {
+    files: Vec<(String, String)>,
+    dialect: Dialect,
+}
+
+#[wasm_bindgen(js_class = Project)]
+impl WasmProject {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> WasmProject {
+        WasmProject::default()
+    }
+
+    pub fn add_file(&mut self, name: &str, contents: &str) {
+        self.files.push((name.to_string(), contents.to_string()));
+    }
+
+    /*
+     * "8080", "z80" or "8085"
+     */
+    pub fn set_dialect(&mut self, name: &str) -> Result<(), JsValue> {
+        self.dialect = Dialect::from_name(name).map_err(JsValue::from)?;
+        Ok(())
+    }
+
+    pub fn assemble(&self, code: &str) -> Result<Vec<u8>, JsValue> {
+        let mut files = MemoryFiles::new();
+        for (name, contents) in &self.files {
+            files.insert(name, contents);
+        }
+        Assembler::new(code)
+            .with_resolver(files)
+            .with_dialect(self.dialect)
+            .assemble()
+            .map_err(JsValue::from)
+    }
+}
+
+/*
+ * Opcode reference for the web UI, one entry per opcode
+ */
+#[wasm_bindgen(js_name = Opcode)]
+pub struct WasmOpcode {
+    info: &'static Opcode,
+}
+
+#[wasm_bindgen]
+pub fn opcode(value: u8) -> WasmOpcode {
+    WasmOpcode {
+        info: &OPCODES[value as usize],
+    }
+}
+
+#[wasm_bindgen(js_class = Opcode)]
+impl WasmOpcode {
+    pub fn opcode(&self) -> u8 {
+        self.info.opcode
+    }
+
+    pub fn mnemonic(&self) -> String {
+        self.info.mnemonic.to_string()
+    }
+
+    /*
+     * Instruction with operand kinds, e.g. "MVI A,D8"
+     */
+    pub fn template(&self) -> String {
+        self.info.to_string()
+    }
+
+    pub fn length(&self) -> usize {
+        self.info.length
+    }
+
+    pub fn cycles(&self) -> u8 {
+        self.info.cycles
+    }
+
+    pub fn cycles_taken(&self) -> u8 {
+        self.info.cycles_taken
+    }
+
+    /*
+     * Affected flags separated by spaces, e.g. "S Z AC P CY"
+     */
+    pub fn flags(&self) -> String {
+        self.info.flag_names().join(" ")
+    }
+
+    pub fn documented(&self) -> bool {
+        self.info.documented
+    }
+}
+
+/*
+ * Lab trainer for the web UI, the pin getters and setters
+ * let the page render LEDs and displays and toggle switches
+ */
+#[wasm_bindgen(js_name = Trainer)]
+pub struct WasmTrainer {
+    trainer: Trainer,
+}
+
+#[wasm_bindgen(js_class = Trainer)]
+impl WasmTrainer {
+    #[wasm_bindgen(constructor)]
+    pub fn new(ppi_port: u8) -> WasmTrainer {
+        WasmTrainer {
+            trainer: Trainer::new(ppi_port),
+        }
+    }
+
+    pub fn load(&mut self, data: &[u8], start: u16) {
+        self.trainer.load(data.to_vec(), start);
+    }
+
+    pub fn boot(&mut self, address: u16) {
+        self.trainer.boot(address);
+    }
+
+    pub fn run(&mut self, max_instructions: usize) -> Result<usize, JsValue> {
+        self.trainer.run(max_instructions).map_err(JsValue::from)
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.trainer.emulator().is_running()
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.trainer.emulator().get_pc()
+    }
+
+    pub fn disassemble(&self, start: u16, length: usize, symbols: &str) -> Result<String, JsValue> {
+        let symbols = SymbolTable::parse(symbols).map_err(JsValue::from)?;
+        let ram = self.trainer.emulator().get_ram();
+        Ok(Disassembler::from_ram(ram, start, length).with_symbols(symbols).listing())
+    }
+
+    /*
+     * Calls that have not returned yet, innermost first
+     */
+    pub fn backtrace(&self, symbols: &str) -> Result<String, JsValue> {
+        let symbols = SymbolTable::parse(symbols).map_err(JsValue::from)?;
+        Ok(backtrace(self.trainer.emulator(), &symbols))
+    }
+
+    pub fn start_profiling(&mut self) {
+        self.trainer.emulator_mut().start_profiling();
+    }
+
+    pub fn stop_profiling(&mut self) {
+        self.trainer.emulator_mut().stop_profiling();
+    }
+
+    pub fn clear_profile(&mut self) {
+        self.trainer.emulator_mut().clear_profile();
+    }
+
+    /*
+     * Cycles by function, symbol and address, count lines per table
+     */
+    pub fn profile_report(&self, symbols: &str, count: usize) -> Result<String, JsValue> {
+        let symbols = SymbolTable::parse(symbols).map_err(JsValue::from)?;
+        let profile = self.trainer.emulator().get_profile().ok_or("No profile")?;
+        Ok(report(profile, &symbols, count))
+    }
+
+    /*
+     * Call stacks in the folded format of flame graph tools
+     */
+    pub fn folded_stacks(&self, symbols: &str) -> Result<String, JsValue> {
+        let symbols = SymbolTable::parse(symbols).map_err(JsValue::from)?;
+        let profile = self.trainer.emulator().get_profile().ok_or("No profile")?;
+        Ok(folded(profile, &symbols))
+    }
+
+    /*
+     * Disassembly listing with hit and cycle counts
+     */
+    pub fn profile_listing(&self, start: u16, length: usize, symbols: &str) -> Result<String, JsValue> {
+        let profile = self.trainer.emulator().get_profile().ok_or("No profile")?;
+        Ok(annotate(profile, &self.disassemble(start, length, symbols)?))
+    }
+
+    pub fn port_a(&self) -> u8 {
+        self.trainer.ppi().borrow().port_a()
+    }
+
+    pub fn port_b(&self) -> u8 {
+        self.trainer.ppi().borrow().port_b()
+    }
+
+    pub fn port_c(&self) -> u8 {
+        self.trainer.ppi().borrow().port_c()
+    }
+
+    pub fn set_port_a(&mut self, value: u8) {
+        self.trainer.ppi().borrow_mut().set_port_a(value);
+    }
+
+    pub fn set_port_b(&mut self, value: u8) {
+        self.trainer.ppi().borrow_mut().set_port_b(value);
+    }
+
+    pub fn set_port_c(&mut self, value: u8) {
+        self.trainer.ppi().borrow_mut().set_port_c(value);
+    }
+
+    pub fn strobe_a(&mut self, byte: u8) {
+        self.trainer.ppi().borrow_mut().strobe_a(byte);
+    }
+
+    pub fn strobe_b(&mut self, byte: u8) {
+        self.trainer.ppi().borrow_mut().strobe_b(byte);
+    }
+
+    pub fn acknowledge_a(&mut self) -> Option<u8> {
+        self.trainer.ppi().borrow_mut().acknowledge_a()
+    }
+
+    pub fn acknowledge_b(&mut self) -> Option<u8> {
+        self.trainer.ppi().borrow_mut().acknowledge_b()
+    }
+}